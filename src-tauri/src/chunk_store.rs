@@ -0,0 +1,414 @@
+use anyhow::{Context, Result};
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression as FlateCompression};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// A chunk store keeps backups of the same files cheap when they change a little between runs.
+// Files are cut into chunks at content-defined boundaries, so an edit only moves the boundaries
+// around it, and each chunk is stored once under its hash. On disk:
+//
+//   <store>/chunks/ab/abcdef...   one zlib-compressed chunk, named by the SHA-256 of its
+//                                  uncompressed bytes and sharded by the first two hex digits
+//   <store>/snapshots/<name>.json  one backup: every file's path, size, modification time and
+//                                  the hashes of its chunks in order
+//
+// Chunks are never rewritten, so a snapshot stays restorable as long as its chunks are kept.
+
+const CHUNKS_DIR: &str = "chunks";
+const SNAPSHOTS_DIR: &str = "snapshots";
+const SNAPSHOT_VERSION: u32 = 1;
+
+// Chunk sizes follow FastCDC: no cut before MIN_CHUNK_SIZE, a stricter mask until
+// AVG_CHUNK_SIZE and a looser one after it so sizes cluster around the average, and a forced
+// cut at MAX_CHUNK_SIZE
+const MIN_CHUNK_SIZE: usize = 16 * 1024;
+const AVG_CHUNK_SIZE: usize = 64 * 1024;
+const MAX_CHUNK_SIZE: usize = 256 * 1024;
+// The top bits of the gear hash depend on the last 64 bytes; the low ones only on the last few
+const MASK_STRICT: u64 = !0u64 << (64 - 18);
+const MASK_LOOSE: u64 = !0u64 << (64 - 14);
+
+// Fixed pseudo-random values per byte (splitmix64), so boundaries are the same on every run
+const fn gear_table() -> [u64; 256] {
+    let mut table = [0u64; 256];
+    let mut state: u64 = 0;
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+}
+
+static GEAR: [u64; 256] = gear_table();
+
+// Length of the chunk at the start of `data`, which holds at least MAX_CHUNK_SIZE bytes unless
+// it is the end of the file
+fn cut_point(data: &[u8]) -> usize {
+    if data.len() <= MIN_CHUNK_SIZE {
+        return data.len();
+    }
+    let end = data.len().min(MAX_CHUNK_SIZE);
+    let normal = AVG_CHUNK_SIZE.min(end);
+
+    let mut hash = 0u64;
+    for (i, &byte) in data.iter().enumerate().take(end).skip(MIN_CHUNK_SIZE) {
+        hash = (hash << 1).wrapping_add(GEAR[byte as usize]);
+        let mask = if i < normal { MASK_STRICT } else { MASK_LOOSE };
+        if hash & mask == 0 {
+            return i + 1;
+        }
+    }
+    end
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SnapshotFile {
+    pub path: String, // relative to the snapshot root, '/' separated
+    pub size: u64,
+    pub modified: Option<u64>, // unix seconds
+    pub chunks: Vec<String>, // lowercase hex SHA-256 of each chunk, in file order
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Snapshot {
+    pub version: u32,
+    pub name: String,
+    pub created: u64,
+    pub directories: Vec<String>,
+    pub files: Vec<SnapshotFile>,
+}
+
+// What a backup added to the store; new_* against total_* shows how much was deduplicated
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct BackupStats {
+    pub files: usize,
+    pub total_chunks: usize,
+    pub new_chunks: usize,
+    pub total_bytes: u64,
+    pub new_bytes: u64,
+}
+
+pub struct ChunkStore {
+    root: PathBuf,
+}
+
+impl ChunkStore {
+    // Creates the store's folders if this is its first backup
+    pub fn open(root: &Path) -> Result<Self> {
+        std::fs::create_dir_all(root.join(CHUNKS_DIR))
+            .with_context(|| format!("Cannot create a chunk store in {}", root.display()))?;
+        std::fs::create_dir_all(root.join(SNAPSHOTS_DIR))?;
+        Ok(Self { root: root.to_path_buf() })
+    }
+
+    fn chunk_path(&self, hash: &str) -> PathBuf {
+        self.root.join(CHUNKS_DIR).join(&hash[..2]).join(hash)
+    }
+
+    fn snapshot_path(&self, name: &str) -> Result<PathBuf> {
+        if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+            return Err(anyhow::anyhow!("'{}' is not a valid snapshot name", name));
+        }
+        Ok(self.root.join(SNAPSHOTS_DIR).join(format!("{}.json", name)))
+    }
+
+    // Every snapshot in the store, oldest first
+    pub fn snapshots(&self) -> Result<Vec<Snapshot>> {
+        let mut snapshots = Vec::new();
+        for entry in std::fs::read_dir(self.root.join(SNAPSHOTS_DIR))? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "json") {
+                snapshots.push(read_snapshot(&path)?);
+            }
+        }
+        snapshots.sort_by(|a, b| a.created.cmp(&b.created).then_with(|| a.name.cmp(&b.name)));
+        Ok(snapshots)
+    }
+
+    pub fn snapshot(&self, name: &str) -> Result<Snapshot> {
+        let path = self.snapshot_path(name)?;
+        if !path.exists() {
+            return Err(anyhow::anyhow!("No snapshot named '{}' in {}", name, self.root.display()));
+        }
+        read_snapshot(&path)
+    }
+
+    // Store the inputs as snapshot `name`. Folders are stored under their own name, like the
+    // other formats do; only chunks the store doesn't have yet are written. Symbolic links are
+    // skipped rather than followed, so a link back up the tree can't make the walk go forever.
+    pub fn backup(&self, inputs: &[PathBuf], name: &str) -> Result<BackupStats> {
+        let snapshot_path = self.snapshot_path(name)?;
+        if snapshot_path.exists() {
+            return Err(anyhow::anyhow!("A snapshot named '{}' already exists", name));
+        }
+
+        let mut stats = BackupStats::default();
+        let mut snapshot = Snapshot {
+            version: SNAPSHOT_VERSION,
+            name: name.to_string(),
+            created: SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0),
+            directories: Vec::new(),
+            files: Vec::new(),
+        };
+
+        for input in inputs {
+            let base = input.parent().unwrap_or_else(|| Path::new(""));
+            self.backup_path(base, input, &mut snapshot, &mut stats)?;
+        }
+
+        // The snapshot goes last so an interrupted backup leaves only unreferenced chunks behind
+        let content = serde_json::to_string_pretty(&snapshot)?;
+        write_atomically(&snapshot_path, content.as_bytes())?;
+        Ok(stats)
+    }
+
+    fn backup_path(&self, base: &Path, path: &Path, snapshot: &mut Snapshot, stats: &mut BackupStats) -> Result<()> {
+        let relative = relative_name(base, path);
+        let metadata = std::fs::symlink_metadata(path)?;
+        if metadata.file_type().is_symlink() {
+            tracing::warn!(path = %path.display(), "Skipping symbolic link");
+            return Ok(());
+        }
+        if metadata.is_dir() {
+            snapshot.directories.push(relative);
+            let mut entries = std::fs::read_dir(path)?.collect::<std::io::Result<Vec<_>>>()?;
+            entries.sort_by_key(|entry| entry.file_name());
+            for entry in entries {
+                self.backup_path(base, &entry.path(), snapshot, stats)?;
+            }
+            return Ok(());
+        }
+
+        let chunks = self.store_file(path, stats)
+            .with_context(|| format!("Failed to back up {}", path.display()))?;
+        stats.files += 1;
+        snapshot.files.push(SnapshotFile {
+            path: relative,
+            size: metadata.len(),
+            modified: metadata.modified().ok()
+                .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                .map(|d| d.as_secs()),
+            chunks,
+        });
+        Ok(())
+    }
+
+    // Chunk one file and write the chunks that aren't stored yet, returning their hashes
+    fn store_file(&self, path: &Path, stats: &mut BackupStats) -> Result<Vec<String>> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut buffer: Vec<u8> = Vec::with_capacity(MAX_CHUNK_SIZE * 2);
+        let mut hashes = Vec::new();
+        let mut at_end = false;
+
+        loop {
+            // Keep a full maximum chunk in view so cut_point sees every possible boundary
+            while !at_end && buffer.len() < MAX_CHUNK_SIZE {
+                let filled = buffer.len();
+                buffer.resize(MAX_CHUNK_SIZE, 0);
+                let read = reader.read(&mut buffer[filled..])?;
+                buffer.truncate(filled + read);
+                at_end = read == 0;
+            }
+            if buffer.is_empty() {
+                break;
+            }
+
+            let length = cut_point(&buffer);
+            let chunk = &buffer[..length];
+            let hash: String = Sha256::digest(chunk).iter().map(|byte| format!("{:02x}", byte)).collect();
+            stats.total_chunks += 1;
+            stats.total_bytes += length as u64;
+
+            let chunk_path = self.chunk_path(&hash);
+            if !chunk_path.exists() {
+                let mut encoder = ZlibEncoder::new(Vec::new(), FlateCompression::default());
+                encoder.write_all(chunk)?;
+                std::fs::create_dir_all(chunk_path.parent().unwrap())?;
+                write_atomically(&chunk_path, &encoder.finish()?)?;
+                stats.new_chunks += 1;
+                stats.new_bytes += length as u64;
+            }
+            hashes.push(hash);
+            buffer.drain(..length);
+        }
+        Ok(hashes)
+    }
+
+    // Recreate snapshot `name` under `output_dir`, checking every chunk against its hash. Unless
+    // `overwrite` is set, nothing is written if any of the snapshot's files already exists.
+    pub fn restore(&self, name: &str, output_dir: &Path, overwrite: bool) -> Result<usize> {
+        let snapshot = self.snapshot(name)?;
+        for relative in snapshot.directories.iter().chain(snapshot.files.iter().map(|file| &file.path)) {
+            if !crate::compression::is_safe_relative_path(Path::new(relative)) {
+                return Err(anyhow::anyhow!("Snapshot '{}' has an unsafe path: {}", name, relative));
+            }
+        }
+        if !overwrite {
+            if let Some(existing) = snapshot.files.iter().map(|file| output_dir.join(&file.path)).find(|path| path.exists()) {
+                return Err(anyhow::anyhow!("{} already exists; restore with overwrite to replace it", existing.display()));
+            }
+        }
+
+        for dir in &snapshot.directories {
+            std::fs::create_dir_all(output_dir.join(dir))?;
+        }
+        for file in &snapshot.files {
+            let output_path = output_dir.join(&file.path);
+            if let Some(parent) = output_path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            let mut output = BufWriter::new(File::create(&output_path)?);
+            for hash in &file.chunks {
+                output.write_all(&self.read_chunk(hash)?)?;
+            }
+            output.flush()?;
+            drop(output);
+
+            let written = std::fs::metadata(&output_path)?.len();
+            if written != file.size {
+                return Err(anyhow::anyhow!("{} was restored with {} bytes instead of {}", file.path, written, file.size));
+            }
+            if let Some(modified) = file.modified {
+                let _ = crate::file_utils::set_modified_time(&output_path, modified as i64, 0);
+            }
+        }
+        Ok(snapshot.files.len())
+    }
+
+    fn read_chunk(&self, hash: &str) -> Result<Vec<u8>> {
+        if hash.len() != 64 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(anyhow::anyhow!("'{}' is not a chunk hash", hash));
+        }
+        let chunk_path = self.chunk_path(hash);
+        let compressed = File::open(&chunk_path)
+            .with_context(|| format!("Chunk {} is missing from the store", hash))?;
+        let mut data = Vec::new();
+        ZlibDecoder::new(compressed).read_to_end(&mut data)
+            .with_context(|| format!("Chunk {} is damaged", hash))?;
+
+        let actual: String = Sha256::digest(&data).iter().map(|byte| format!("{:02x}", byte)).collect();
+        if !actual.eq_ignore_ascii_case(hash) {
+            return Err(anyhow::anyhow!("Chunk {} is damaged: its content hashes to {}", hash, actual));
+        }
+        Ok(data)
+    }
+}
+
+fn read_snapshot(path: &Path) -> Result<Snapshot> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let snapshot: Snapshot = serde_json::from_str(&content)
+        .with_context(|| format!("Invalid snapshot: {}", path.display()))?;
+    if snapshot.version > SNAPSHOT_VERSION {
+        return Err(anyhow::anyhow!("{} was written by a newer version of TauZip", path.display()));
+    }
+    Ok(snapshot)
+}
+
+fn relative_name(base: &Path, path: &Path) -> String {
+    let relative = path.strip_prefix(base).unwrap_or(path);
+    let parts: Vec<String> = relative.components()
+        .filter(|component| matches!(component, std::path::Component::Normal(_)))
+        .map(|component| component.as_os_str().to_string_lossy().into_owned())
+        .collect();
+    parts.join("/")
+}
+
+// Write to a temporary name first so a crash never leaves a partial chunk or snapshot that a
+// later run would take for a complete one
+fn write_atomically(path: &Path, content: &[u8]) -> Result<()> {
+    let mut temp_name = path.as_os_str().to_os_string();
+    temp_name.push(format!(".tmp{}", std::process::id()));
+    let temp_path = PathBuf::from(temp_name);
+    std::fs::write(&temp_path, content)?;
+    if let Err(e) = std::fs::rename(&temp_path, path) {
+        let _ = std::fs::remove_file(&temp_path);
+        return Err(e.into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{noise, read_tree, write_file, TempDir};
+
+    #[test]
+    fn backups_restore_byte_identical() {
+        let dir = TempDir::new();
+        let source = dir.join("project");
+        write_file(&source.join("big.bin"), noise(1024 * 1024, 1));
+        write_file(&source.join("notes").join("todo.txt"), "ship it");
+        write_file(&source.join("empty.txt"), "");
+        std::fs::create_dir_all(source.join("nothing_here")).unwrap();
+
+        let store = ChunkStore::open(&dir.join("store")).unwrap();
+        let stats = store.backup(&[source.clone()], "first").unwrap();
+        assert_eq!(stats.files, 3);
+
+        let output_dir = dir.join("restored");
+        assert_eq!(store.restore("first", &output_dir, false).unwrap(), 3);
+        assert_eq!(read_tree(&output_dir.join("project")), read_tree(&source));
+        assert!(output_dir.join("project").join("nothing_here").is_dir());
+    }
+
+    #[test]
+    fn a_small_edit_stores_few_new_chunks() {
+        let dir = TempDir::new();
+        let file = dir.join("data.bin");
+        let mut data = noise(4 * 1024 * 1024, 2);
+        write_file(&file, &data);
+        let store = ChunkStore::open(&dir.join("store")).unwrap();
+        let first = store.backup(&[file.clone()], "first").unwrap();
+        assert!(first.total_chunks > 20);
+
+        data.splice(2_000_000..2_000_010, b"edited!".iter().copied());
+        write_file(&file, &data);
+        let second = store.backup(&[file.clone()], "second").unwrap();
+        assert!(second.new_chunks <= 3, "{} of {} chunks stored again", second.new_chunks, second.total_chunks);
+
+        let output_dir = dir.join("restored");
+        store.restore("second", &output_dir, false).unwrap();
+        assert_eq!(std::fs::read(output_dir.join("data.bin")).unwrap(), data);
+    }
+
+    #[test]
+    fn restore_leaves_existing_files_alone_unless_overwriting() {
+        let dir = TempDir::new();
+        write_file(&dir.join("a.txt"), "from the backup");
+        let store = ChunkStore::open(&dir.join("store")).unwrap();
+        store.backup(&[dir.join("a.txt")], "first").unwrap();
+
+        let output_dir = dir.join("restored");
+        write_file(&output_dir.join("a.txt"), "newer work");
+        assert!(store.restore("first", &output_dir, false).is_err());
+        assert_eq!(std::fs::read(output_dir.join("a.txt")).unwrap(), b"newer work");
+        store.restore("first", &output_dir, true).unwrap();
+        assert_eq!(std::fs::read(output_dir.join("a.txt")).unwrap(), b"from the backup");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn backup_skips_symlinks_instead_of_following_them() {
+        let dir = TempDir::new();
+        let source = dir.join("tree");
+        write_file(&source.join("a.txt"), "a");
+        std::os::unix::fs::symlink(&source, source.join("loop")).unwrap();
+        std::os::unix::fs::symlink(source.join("a.txt"), source.join("link.txt")).unwrap();
+
+        let store = ChunkStore::open(&dir.join("store")).unwrap();
+        let stats = store.backup(&[source], "first").unwrap();
+        assert_eq!(stats.files, 1);
+        let snapshot = store.snapshot("first").unwrap();
+        assert_eq!(snapshot.files.iter().map(|file| file.path.as_str()).collect::<Vec<_>>(), vec!["tree/a.txt"]);
+    }
+}
\ No newline at end of file
@@ -1,15 +1,21 @@
 use anyhow::{Context, Result};
 use flate2::{write::GzEncoder, Compression as FlateCompression, GzBuilder};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs::File;
 use std::io::{BufReader, BufWriter, Write, Read};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
 use tar::Builder as TarBuilder;
 use zip::{write::FileOptions, ZipWriter};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum CompressionType {
     Zip,
+    Tar,
     TarGz,
     TarBr,
     Gz,
@@ -19,9 +25,29 @@ pub enum CompressionType {
 }
 
 impl CompressionType {
+    // Every variant, in the order formats are offered to the user
+    pub fn all() -> &'static [CompressionType] {
+        &[
+            CompressionType::Zip,
+            CompressionType::Tar,
+            CompressionType::TarGz,
+            CompressionType::TarBr,
+            CompressionType::Gz,
+            CompressionType::Br,
+            CompressionType::Gzip,
+            CompressionType::Bzip2,
+        ]
+    }
+
+    // The extensions archives can be created with, for "supported formats" messages
+    pub fn supported_extensions() -> Vec<&'static str> {
+        Self::all().iter().map(|compression_type| compression_type.extension()).collect()
+    }
+
     pub fn extension(&self) -> &'static str {
         match self {
             CompressionType::Zip => ".zip",
+            CompressionType::Tar => ".tar",
             CompressionType::TarGz => ".tar.gz",
             CompressionType::TarBr => ".tar.br",
             CompressionType::Gz => ".gz",
@@ -31,812 +57,7058 @@ impl CompressionType {
         }
     }
 
+    // Accepts the extension with or without its leading dot, in any case. Spellings are looked up
+    // in ARCHIVE_SUFFIXES so this agrees with archive_extension and is_compressed_file.
     pub fn from_extension(ext: &str) -> Option<Self> {
-        match ext.to_lowercase().as_str() {
-            ".zip" => Some(CompressionType::Zip),
-            ".tar.gz" | ".tgz" => Some(CompressionType::TarGz),
-            ".tar.br" => Some(CompressionType::TarBr),
-            ".gz" => Some(CompressionType::Gz),
-            ".br" => Some(CompressionType::Br),
-            ".gzip" => Some(CompressionType::Gzip),
-            ".bz2" | ".bzip2" => Some(CompressionType::Bzip2),
+        let suffix = format!(".{}", ext.trim_start_matches('.'));
+        let (matched, extension) = match_archive_suffix(&suffix)?;
+        if matched.len() != suffix.len() {
+            return None;
+        }
+        match (matched, extension) {
+            (".gzip", _) => Some(CompressionType::Gzip),
+            (_, "zip") => Some(CompressionType::Zip),
+            (_, "tar") => Some(CompressionType::Tar),
+            (_, "tar.gz") => Some(CompressionType::TarGz),
+            (_, "tar.br") => Some(CompressionType::TarBr),
+            (_, "gz") => Some(CompressionType::Gz),
+            (_, "br") => Some(CompressionType::Br),
+            (_, "bz2") => Some(CompressionType::Bzip2),
             _ => None,
         }
     }
 
+    // Infer the type from a file name, checking multi-dot extensions like ".tar.gz" before ".gz"
+    pub fn from_file_name(path: &Path) -> Option<Self> {
+        let (suffix, _) = match_archive_suffix(path.file_name()?.to_str()?)?;
+        Self::from_extension(suffix)
+    }
+
     pub fn supports_multiple_files(&self) -> bool {
         match self {
-            CompressionType::Zip | CompressionType::TarGz | CompressionType::TarBr => true,
+            CompressionType::Zip | CompressionType::Tar | CompressionType::TarGz | CompressionType::TarBr => true,
             CompressionType::Gz | CompressionType::Br | CompressionType::Gzip | CompressionType::Bzip2 => false,
         }
     }
 }
 
-pub async fn compress_files(
-    files: &[PathBuf],
-    output_path: &Path,
-    compression_type: CompressionType,
-) -> Result<()> {
-    compress_files_with_progress(files, output_path, compression_type, |_, _| {}).await
-}
+// Every archive suffix we recognise and the canonical extension it is handled as. Multi-dot
+// suffixes come first so "x.tar.gz" isn't taken for a plain ".gz".
+const ARCHIVE_SUFFIXES: &[(&str, &str)] = &[
+    (".tar.gz.age", "tar.gz.age"),
+    (".tar.gz", "tar.gz"),
+    (".tgz", "tar.gz"),
+    (".tar.br", "tar.br"),
+    (".tar", "tar"),
+    (".zip", "zip"),
+    (".gz", "gz"),
+    (".gzip", "gz"),
+    (".br", "br"),
+    (".bz2", "bz2"),
+    (".bzip2", "bz2"),
+    (".rar", "rar"),
+    (".7z", "7z"),
+];
 
-pub async fn compress_files_with_progress<F>(
-    files: &[PathBuf],
-    output_path: &Path,
-    compression_type: CompressionType,
-    mut progress_callback: F,
-) -> Result<()>
-where
-    F: FnMut(f64, String),
-{
-    if !compression_type.supports_multiple_files() && files.len() > 1 {
-        return Err(anyhow::anyhow!(
-            "Compression type {:?} does not support multiple files",
-            compression_type
-        ));
-    }
+// The matching (suffix, canonical extension) pair for a file name, ignoring case
+fn match_archive_suffix(file_name: &str) -> Option<(&'static str, &'static str)> {
+    let name = file_name.as_bytes();
+    ARCHIVE_SUFFIXES.iter().copied().find(|(suffix, _)| {
+        name.len() >= suffix.len() && name[name.len() - suffix.len()..].eq_ignore_ascii_case(suffix.as_bytes())
+    })
+}
 
-    match compression_type {
-        CompressionType::Zip => compress_zip_with_progress(files, output_path, progress_callback).await,
-        CompressionType::TarGz => compress_tar_gz_with_progress(files, output_path, progress_callback).await,
-        CompressionType::TarBr => compress_tar_br_with_progress(files, output_path, progress_callback).await,
-        CompressionType::Gz | CompressionType::Gzip => {
-            let filename = files[0].file_name().unwrap_or_default().to_string_lossy().to_string();
-            compress_gz_with_progress(&files[0], output_path, move |progress| {
-                progress_callback(progress, filename.clone())
-            }).await
-        },
-        CompressionType::Br => {
-            let filename = files[0].file_name().unwrap_or_default().to_string_lossy().to_string();
-            compress_br_with_progress(&files[0], output_path, move |progress| {
-                progress_callback(progress, filename.clone())
-            }).await
-        },
-        CompressionType::Bzip2 => {
-            let filename = files[0].file_name().unwrap_or_default().to_string_lossy().to_string();
-            compress_bzip2_with_progress(&files[0], output_path, move |progress| {
-                progress_callback(progress, filename.clone())
-            }).await
+// "backup.tar.gz" -> "backup"; paths without a recognised archive suffix are returned as they are
+pub fn strip_archive_suffix(path: &Path) -> PathBuf {
+    let file_name = match path.file_name().and_then(|name| name.to_str()) {
+        Some(file_name) => file_name,
+        None => return path.to_path_buf(),
+    };
+    match match_archive_suffix(file_name) {
+        Some((suffix, _)) if suffix.len() < file_name.len() => {
+            path.with_file_name(&file_name[..file_name.len() - suffix.len()])
         },
+        _ => path.to_path_buf(),
     }
 }
 
-async fn compress_zip_with_progress<F>(files: &[PathBuf], output_path: &Path, mut progress_callback: F) -> Result<()>
-where
-    F: FnMut(f64, String),
-{
-    let file = File::create(output_path)
-        .with_context(|| format!("Failed to create output file: {}", output_path.display()))?;
-    
-    let mut zip = ZipWriter::new(BufWriter::new(file));
-    let options = FileOptions::default()
-        .compression_method(zip::CompressionMethod::Deflated)
-        .unix_permissions(0o755);
+// Every suffix that can be opened, including read-only formats like .rar and .7z
+pub fn readable_extensions() -> Vec<&'static str> {
+    ARCHIVE_SUFFIXES.iter().map(|(suffix, _)| *suffix).collect()
+}
 
-    // Calculate the common base directory for all files
-    let base_dir = if files.len() == 1 {
-        // For a single file, use its parent directory
-        files[0].parent().unwrap_or_else(|| Path::new(".")).to_path_buf()
-    } else {
-        // For multiple files, find the common parent directory
-        find_common_base_dir(files).unwrap_or_else(|| PathBuf::from("."))
-    };
+// Canonical extension used to pick a decoder: "tar.gz" for .tgz, "gz" for .gzip, "bz2" for .bzip2
+pub fn archive_extension(path: &Path) -> Option<&'static str> {
+    let file_name = path.file_name()?.to_str()?;
+    match_archive_suffix(file_name).map(|(_, extension)| extension)
+}
 
-    println!("Using base directory: {}", base_dir.display());
+// Shared flag used to abort a running compress/decompress from another thread
+pub type CancellationToken = Arc<AtomicBool>;
 
-    // Calculate total size for progress tracking
-    let total_size = calculate_total_size(files)?;
-    let mut processed_size = 0u64;
+// Failures of the public compression functions that callers may want to tell apart. Anything
+// without a variant of its own keeps its full anyhow context in Other.
+#[derive(Debug)]
+pub enum CompressionError {
+    Io(std::io::Error),
+    UnsupportedFormat(String),
+    Cancelled,
+    // An encrypted zip entry had no password
+    PasswordRequired { entry: String },
+    // The password supplied for an encrypted zip entry didn't decrypt it
+    WrongPassword { entry: String },
+    // The archive's data is damaged or ends early
+    Corrupt(String),
+    // An output path would land outside the output directory
+    PathTraversal(String),
+    // The output volume has less free space than an extraction is expected to need
+    InsufficientSpace { needed: u64, available: u64 },
+    // Extraction was stopped as a likely decompression bomb; see OutputLimit
+    DecompressionLimitExceeded(String),
+    Other(anyhow::Error),
+}
 
-    for (index, file_path) in files.iter().enumerate() {
-        let current_filename = file_path.file_name()
-            .unwrap_or_default()
-            .to_string_lossy()
-            .to_string();
-        
-        println!("Adding file: {}", file_path.display());
-        
-        // Update progress before processing each file
-        let file_progress = if total_size > 0 {
-            (processed_size as f64 / total_size as f64) * 100.0
-        } else {
-            (index as f64 / files.len() as f64) * 100.0
-        };
-        progress_callback(file_progress, current_filename.clone());
-        
-        add_to_zip_with_progress(&mut zip, file_path, &base_dir, &options, &mut processed_size, &mut progress_callback, total_size).await?;
+impl std::fmt::Display for CompressionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(error) => std::fmt::Display::fmt(error, f),
+            Self::UnsupportedFormat(name) => write!(f, "Unsupported file format: {}", name),
+            Self::Cancelled => write!(f, "Operation cancelled"),
+            Self::PasswordRequired { entry } => write!(f, "'{}' is encrypted; a password is required", entry),
+            Self::WrongPassword { entry } => write!(f, "Wrong password for '{}'", entry),
+            Self::Corrupt(detail) => write!(f, "The archive is corrupt: {}", detail),
+            Self::PathTraversal(path) => write!(f, "'{}' would be written outside the output directory", path),
+            Self::InsufficientSpace { needed, available } => {
+                write!(f, "Not enough disk space: extraction needs about {} bytes but only {} bytes are free", needed, available)
+            },
+            Self::DecompressionLimitExceeded(detail) => write!(f, "Extraction stopped as a likely decompression bomb: {}", detail),
+            // Forwarding keeps {:#} printing the whole context chain
+            Self::Other(error) => std::fmt::Display::fmt(error, f),
+        }
     }
+}
 
-    progress_callback(100.0, "Complete".to_string());
-    zip.finish()?;
-    Ok(())
+impl std::error::Error for CompressionError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(error) => error.source(),
+            Self::Other(error) => error.source(),
+            _ => None,
+        }
+    }
 }
 
-fn calculate_total_size(files: &[PathBuf]) -> Result<u64> {
-    let mut total = 0u64;
-    for file_path in files {
-        total += calculate_path_size(file_path)?;
+impl From<std::io::Error> for CompressionError {
+    fn from(error: std::io::Error) -> Self {
+        // A cancel raised inside a reader or writer arrives wrapped in an io::Error
+        if matches!(error.get_ref().and_then(|inner| inner.downcast_ref()), Some(CompressionError::Cancelled)) {
+            return Self::Cancelled;
+        }
+        // flate2 reports a damaged deflate stream as InvalidInput rather than InvalidData
+        match error.kind() {
+            std::io::ErrorKind::InvalidData | std::io::ErrorKind::InvalidInput | std::io::ErrorKind::UnexpectedEof => {
+                Self::Corrupt(error.to_string())
+            },
+            _ => Self::Io(error),
+        }
     }
-    Ok(total)
 }
 
-fn calculate_path_size(path: &Path) -> Result<u64> {
-    let mut total = 0u64;
-    if path.is_file() {
-        total += std::fs::metadata(path)?.len();
-    } else if path.is_dir() {
-        for entry in std::fs::read_dir(path)? {
-            let entry = entry?;
-            total += calculate_path_size(&entry.path())?;
+impl From<zip::result::ZipError> for CompressionError {
+    fn from(error: zip::result::ZipError) -> Self {
+        match error {
+            zip::result::ZipError::Io(error) => error.into(),
+            zip::result::ZipError::InvalidArchive(detail) => Self::Corrupt(detail.to_string()),
+            error => Self::Other(error.into()),
         }
     }
-    Ok(total)
 }
 
-fn find_common_base_dir(files: &[PathBuf]) -> Option<PathBuf> {
-    if files.is_empty() {
-        return None;
+impl From<anyhow::Error> for CompressionError {
+    fn from(error: anyhow::Error) -> Self {
+        let error = match error.downcast::<CompressionError>() {
+            Ok(error) => return error,
+            Err(error) => error,
+        };
+        // io and zip errors are only narrowed when no context was added on the way up, since
+        // the context says more than the variant would
+        let is_bare = |inner: &dyn std::fmt::Display| inner.to_string() == error.to_string();
+        if error.downcast_ref::<std::io::Error>().is_some_and(|inner| is_bare(inner)) {
+            return error.downcast::<std::io::Error>().map_or_else(Self::Other, Self::from);
+        }
+        if error.downcast_ref::<zip::result::ZipError>().is_some_and(|inner| is_bare(inner)) {
+            return error.downcast::<zip::result::ZipError>().map_or_else(Self::Other, Self::from);
+        }
+        Self::Other(error)
     }
+}
 
-    // Start with the first file's parent directory
-    let mut common_base = files[0].parent()?.to_path_buf();
+// Picks the password for each encrypted zip entry by name; None leaves the entry without one
+#[derive(Clone)]
+pub struct PasswordResolver(Arc<dyn Fn(&str) -> Option<String> + Send + Sync>);
 
-    // Find the common ancestor of all file paths
-    for file in &files[1..] {
-        if let Some(file_parent) = file.parent() {
-            // Find the common path between current common_base and this file's parent
-            if let Some(new_common) = find_common_path(&common_base, file_parent) {
-                common_base = new_common;
-            } else {
-                // No common path found, use current directory
-                return Some(PathBuf::from("."));
-            }
-        }
+impl PasswordResolver {
+    pub fn new(resolve: impl Fn(&str) -> Option<String> + Send + Sync + 'static) -> Self {
+        Self(Arc::new(resolve))
     }
 
-    Some(common_base)
+    // The same password for every entry
+    pub fn single(password: String) -> Self {
+        Self::new(move |_| Some(password.clone()))
+    }
+
+    pub fn password_for(&self, entry: &str) -> Option<String> {
+        (self.0)(entry)
+    }
 }
 
-fn find_common_path(path1: &Path, path2: &Path) -> Option<PathBuf> {
-    let components1: Vec<_> = path1.components().collect();
-    let components2: Vec<_> = path2.components().collect();
-    
-    let mut common_components = Vec::new();
-    
-    for (comp1, comp2) in components1.iter().zip(components2.iter()) {
-        if comp1 == comp2 {
-            common_components.push(comp1.as_os_str());
-        } else {
-            break;
-        }
+impl std::fmt::Debug for PasswordResolver {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("PasswordResolver")
     }
-    
-    if common_components.is_empty() {
-        None
-    } else {
-        // Reconstruct the path from components
-        let mut result = PathBuf::new();
-        for component in common_components {
-            result.push(component);
+}
+
+// Optional behaviour for compress_files_with_progress; the defaults match plain compress_files
+#[derive(Debug, Clone, Default)]
+pub struct CompressOptions {
+    // Deflate zip entries on a rayon thread pool
+    pub parallel: bool,
+    // Store zip entries relative to `root` (or the filesystem root when unset) instead of the
+    // inputs' common parent, so a single nested file keeps the folders it lives in
+    pub preserve_paths: bool,
+    pub root: Option<PathBuf>,
+    // Drop this many leading components of each input's own path when storing zip entries,
+    // e.g. 2 stores /a/b/c/d as c/d/...; takes precedence over the common-base and root logic
+    pub input_base_depth: Option<usize>,
+    // Cut the finished archive into exactly this many volumes of nearly equal size
+    // (output.001, output.002, ...); the unsplit archive is removed
+    pub split_into: Option<usize>,
+    // Cut the finished archive into volumes of this many bytes, the last one taking what is
+    // left. Like split_into this is a plain byte split, not zip's own spanning format: the
+    // volumes only make sense joined again, which decompress_files_with_progress does when
+    // given the .001 file.
+    pub split_size: Option<u64>,
+    // Glob patterns selecting which files are stored; exclusions win over inclusions.
+    // An empty include list keeps every file that isn't excluded.
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    pub symlink_policy: SymlinkPolicy,
+    // How zip entries are compressed; ignored by the other formats
+    pub zip_method: ZipMethod,
+    // How hard every encoder works; see CompressionPreset::level
+    pub preset: CompressionPreset,
+    // Brotli quality (0-11) for .br and .tar.br in place of the preset's; clamped to that range
+    pub brotli_quality: Option<u32>,
+    // Brotli window as a power of two (10-24, clamped), BROTLI_WINDOW_BITS when unset. A larger
+    // window finds repeats further back, and costs the encoder and decoder that much memory.
+    pub brotli_window: Option<u32>,
+    // Store files that are already compressed (jpg, mp4, zip...) instead of using zip_method
+    pub auto_store: bool,
+    // Give zip entries the file's own modification time, including the Info-ZIP "UT" and NTFS
+    // extra fields so it survives with sub-second precision and an explicit UTC timezone
+    pub precise_timestamps: bool,
+    // Add entries in path order (inputs by path, directory contents by name) instead of the
+    // order the filesystem lists them in, so archives of the same tree list identically
+    pub sort_entries: bool,
+    // What the zip progress percentage counts; see ProgressMode
+    pub progress_mode: ProgressMode,
+    // Also write the zip's central directory to `<output>.cd` for clients reading the archive
+    // with range requests; see write_central_directory_sidecar. Ignored by the other formats.
+    pub central_directory_sidecar: bool,
+    // Write `<archive>.sha256` with the finished archive's SHA-256 (one per volume when split);
+    // see write_checksum_sidecar
+    pub write_checksum: bool,
+    // Store each distinct file content in a zip once; see ZipDedup. Ignored by the other formats.
+    pub dedup: bool,
+    // Add a MANIFEST.json at the archive's root with every file's size and SHA-256, which
+    // `decompress --verify-manifest` checks the extracted files against; see archive_manifest
+    pub manifest: bool,
+    // Replace an existing file at the output path. Otherwise the archive is written next to it
+    // as "archive (2).zip" and so on; compress_files_with_progress returns the name it used.
+    pub overwrite: bool,
+}
+
+// Bytes suit a few large files; for folders of many tiny files per-file overhead dominates and
+// counting finished entries tracks elapsed time more closely
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum ProgressMode {
+    #[default]
+    Bytes,
+    Entries,
+}
+
+impl ProgressMode {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "bytes" => Some(ProgressMode::Bytes),
+            "entries" | "files" => Some(ProgressMode::Entries),
+            _ => None,
         }
-        Some(result)
     }
 }
 
-fn add_to_zip_sync_with_progress<F>(
-    zip: &mut ZipWriter<BufWriter<File>>,
-    file_path: &Path,
-    base_dir: &Path,
-    options: &FileOptions,
-    processed_size: &mut u64,
-    progress_callback: &mut F,
-    total_size: u64,
-) -> Result<()>
-where
-    F: FnMut(f64, String),
-{
-    if file_path.is_file() {
-        // Calculate relative path from base directory
-        let relative_path = if let Ok(rel_path) = file_path.strip_prefix(base_dir) {
-            rel_path
-        } else {
-            // If strip_prefix fails, just use the filename
-            Path::new(file_path.file_name().unwrap_or_default())
-        };
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum ZipMethod {
+    #[default]
+    Deflated,
+    // No compression, just copies the data
+    Stored,
+    Bzip2,
+}
 
-        let current_filename = file_path.file_name()
-            .unwrap_or_default()
-            .to_string_lossy()
-            .to_string();
+impl ZipMethod {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "deflated" | "deflate" => Some(ZipMethod::Deflated),
+            "stored" | "store" => Some(ZipMethod::Stored),
+            "bzip2" => Some(ZipMethod::Bzip2),
+            _ => None,
+        }
+    }
 
-        println!("Adding file with relative path: {}", relative_path.display());
-        
-        // Ensure we use forward slashes for zip paths (cross-platform compatibility)
-        let zip_path = relative_path.to_string_lossy().replace('\\', "/");
-        
-        zip.start_file(&zip_path, *options)?;
-        
-        let mut file = File::open(file_path)?;
-        let bytes_copied = std::io::copy(&mut file, zip)?;
-        *processed_size += bytes_copied;
-        
-        // Update progress after processing this file
-        let current_progress = if total_size > 0 {
-            (*processed_size as f64 / total_size as f64) * 100.0
-        } else {
-            100.0
-        };
-        progress_callback(current_progress, current_filename);
-        
-    } else if file_path.is_dir() {
-        // For directories, recursively add all files
-        for entry in std::fs::read_dir(file_path)? {
-            let entry = entry?;
-            add_to_zip_sync_with_progress(zip, &entry.path(), base_dir, options, processed_size, progress_callback, total_size)?;
+    fn compression_method(self) -> zip::CompressionMethod {
+        match self {
+            ZipMethod::Deflated => zip::CompressionMethod::Deflated,
+            ZipMethod::Stored => zip::CompressionMethod::Stored,
+            ZipMethod::Bzip2 => zip::CompressionMethod::Bzip2,
         }
     }
-    Ok(())
 }
 
-async fn add_to_zip_with_progress<F>(
-    zip: &mut ZipWriter<BufWriter<File>>,
-    file_path: &Path,
-    base_dir: &Path,
-    options: &FileOptions,
-    processed_size: &mut u64,
-    progress_callback: &mut F,
-    total_size: u64,
-) -> Result<()>
-where
-    F: FnMut(f64, String),
-{
-    add_to_zip_sync_with_progress(zip, file_path, base_dir, options, processed_size, progress_callback, total_size)
+// Named stand-ins for the numeric levels, which differ in range from format to format
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum CompressionPreset {
+    Fastest,
+    #[default]
+    Default,
+    Best,
 }
 
-async fn compress_tar_gz_with_progress<F>(files: &[PathBuf], output_path: &Path, mut progress_callback: F) -> Result<()>
-where
-    F: FnMut(f64, String),
-{
-    let file = File::create(output_path)?;
-    let gz_encoder = GzEncoder::new(BufWriter::new(file), FlateCompression::default());
-    let mut tar = TarBuilder::new(gz_encoder);
+impl CompressionPreset {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "fastest" | "fast" => Some(CompressionPreset::Fastest),
+            "default" | "normal" => Some(CompressionPreset::Default),
+            "best" => Some(CompressionPreset::Best),
+            _ => None,
+        }
+    }
 
-    let total_size = calculate_total_size(files)?;
-    let mut processed_size = 0u64;
+    // The encoder level for `compression_type`: the bottom or top of its range for Fastest and
+    // Best (flate2 and bzip2 1 and 9, brotli 0 and 11), DEFAULT_COMPRESSION_LEVEL otherwise.
+    // Plain tar has no levels.
+    pub fn level(self, compression_type: &CompressionType) -> Option<u32> {
+        let levels = compression_levels(compression_type)?;
+        Some(match self {
+            CompressionPreset::Fastest => *levels.start(),
+            CompressionPreset::Default => DEFAULT_COMPRESSION_LEVEL,
+            CompressionPreset::Best => *levels.end(),
+        })
+    }
+}
 
-    for (index, file_path) in files.iter().enumerate() {
-        let current_filename = file_path.file_name()
-            .unwrap_or_default()
-            .to_string_lossy()
-            .to_string();
-        
-        let file_progress = if total_size > 0 {
-            (processed_size as f64 / total_size as f64) * 100.0
+// Formats that barely shrink when compressed again, stored as-is with auto_store
+const DEFAULT_INCOMPRESSIBLE_EXTENSIONS: &[&str] = &[
+    "jpg", "jpeg", "png", "gif", "webp", "heic", "avif",
+    "mp3", "m4a", "aac", "ogg", "opus", "flac",
+    "mp4", "m4v", "mkv", "mov", "webm", "avi",
+    "docx", "xlsx", "pptx", "odt", "epub", "jar", "apk",
+    "xz", "zst", "tgz",
+];
+
+// Lower-cased extensions without the leading dot, so "JPG" and ".jpg" match alike
+#[derive(Debug, Clone, Default)]
+pub struct IncompressibleExtensions {
+    extensions: HashSet<String>,
+}
+
+impl IncompressibleExtensions {
+    pub fn builtin() -> Self {
+        let mut extensions = Self::default();
+        extensions.extend(DEFAULT_INCOMPRESSIBLE_EXTENSIONS.iter().copied());
+        extensions
+    }
+
+    // The built-in list merged with the user's additions, or only the additions when replacing
+    pub fn from_config(config: &crate::config::Config) -> Self {
+        let mut extensions = if config.replace_incompressible_defaults {
+            Self::default()
         } else {
-            (index as f64 / files.len() as f64) * 100.0
+            Self::builtin()
         };
-        progress_callback(file_progress, current_filename.clone());
-        
-        add_to_tar_with_progress(&mut tar, file_path, &mut processed_size).await?;
+        extensions.extend(config.incompressible_extensions.iter().map(String::as_str));
+        extensions
     }
 
-    progress_callback(100.0, "Complete".to_string());
-    tar.finish()?;
-    Ok(())
+    pub fn extend<'a>(&mut self, extensions: impl IntoIterator<Item = &'a str>) {
+        for extension in extensions {
+            let extension = normalize_extension(extension);
+            if !extension.is_empty() {
+                self.extensions.insert(extension);
+            }
+        }
+    }
+
+    pub fn contains(&self, extension: &str) -> bool {
+        self.extensions.contains(&normalize_extension(extension))
+    }
 }
 
-async fn compress_tar_br_with_progress<F>(files: &[PathBuf], output_path: &Path, mut progress_callback: F) -> Result<()>
-where
-    F: FnMut(f64, String),
-{
-    let file = File::create(output_path)?;
-    let br_encoder = brotli::CompressorWriter::new(BufWriter::new(file), 4096, 6, 22);
-    let mut tar = TarBuilder::new(br_encoder);
+fn normalize_extension(extension: &str) -> String {
+    extension.trim().trim_start_matches('.').to_lowercase()
+}
 
-    let total_size = calculate_total_size(files)?;
-    let mut processed_size = 0u64;
+// Loaded from the config file once per process
+fn incompressible_extensions() -> &'static IncompressibleExtensions {
+    static EXTENSIONS: OnceLock<IncompressibleExtensions> = OnceLock::new();
+    EXTENSIONS.get_or_init(|| IncompressibleExtensions::from_config(&crate::config::load_config()))
+}
 
-    for (index, file_path) in files.iter().enumerate() {
-        let current_filename = file_path.file_name()
-            .unwrap_or_default()
-            .to_string_lossy()
-            .to_string();
-        
-        let file_progress = if total_size > 0 {
-            (processed_size as f64 / total_size as f64) * 100.0
-        } else {
-            (index as f64 / files.len() as f64) * 100.0
-        };
-        progress_callback(file_progress, current_filename.clone());
-        
-        add_to_tar_with_progress(&mut tar, file_path, &mut processed_size).await?;
-    }
+pub fn is_incompressible_extension(extension: &str) -> bool {
+    incompressible_extensions().contains(extension)
+}
 
-    progress_callback(100.0, "Complete".to_string());
-    tar.finish()?;
-    Ok(())
+fn is_precompressed(path: &Path) -> bool {
+    let extension = path.extension()
+        .map(|ext| ext.to_string_lossy().to_string())
+        .unwrap_or_default();
+    is_incompressible_extension(&extension) || is_compressed_file(path)
 }
 
-async fn add_to_tar_with_progress<W: Write>(tar: &mut TarBuilder<W>, file_path: &Path, processed_size: &mut u64) -> Result<()> {
-    if file_path.is_file() {
-        // For tar files, we can use the filename directly
-        let filename = file_path.file_name().unwrap_or_default();
-        tar.append_path_with_name(file_path, filename)?;
-        
-        // Update processed size
-        if let Ok(metadata) = std::fs::metadata(file_path) {
-            *processed_size += metadata.len();
-        }
-    } else if file_path.is_dir() {
-        tar.append_dir_all(file_path.file_name().unwrap(), file_path)?;
-        
-        // Update processed size for directory
-        if let Ok(size) = calculate_path_size(file_path) {
-            *processed_size += size;
+// Bytes read from the start of a file for the entropy check; shorter files aren't judged,
+// since a small sample can't reach a high entropy even when it is random
+const ENTROPY_SAMPLE_SIZE: u64 = 64 * 1024;
+const MIN_ENTROPY_SAMPLE_SIZE: usize = 4 * 1024;
+// Bits per byte above which a sample counts as already compressed; compressed and encrypted
+// data come out just under 8, text around 4 to 5
+const INCOMPRESSIBLE_ENTROPY: f64 = 7.5;
+
+// Which of a selection is worth compressing, as returned by filter_compressible
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CompressibilitySplit {
+    pub compressible: Vec<String>,
+    pub already_compressed: Vec<String>,
+}
+
+// Split files into those worth compressing and those already compressed, by extension (the
+// incompressible list and archive formats) and, with `check_entropy`, by the entropy of their
+// first bytes, which also catches compressed data under an unrelated extension. Directories
+// and unreadable files count as compressible.
+pub fn filter_compressible(files: &[PathBuf], check_entropy: bool) -> CompressibilitySplit {
+    let mut split = CompressibilitySplit::default();
+    for path in files {
+        let already_compressed = path.is_file() && (is_precompressed(path)
+            || (check_entropy && sample_entropy(path).ok().flatten().is_some_and(|entropy| entropy > INCOMPRESSIBLE_ENTROPY)));
+        if already_compressed {
+            split.already_compressed.push(path.display().to_string());
+        } else {
+            split.compressible.push(path.display().to_string());
         }
     }
-    Ok(())
+    split
 }
 
-// Progress tracking writer wrapper for compression with filename tracking
-struct ProgressWriter<W, F> {
-    inner: W,
-    progress_callback: F,
-    total_size: u64,
-    bytes_written: u64,
-    filename: String,
+// Shannon entropy in bits per byte of the start of a file, None when it is too short to tell
+fn sample_entropy(path: &Path) -> std::io::Result<Option<f64>> {
+    let mut sample = Vec::new();
+    File::open(path)?.take(ENTROPY_SAMPLE_SIZE).read_to_end(&mut sample)?;
+    if sample.len() < MIN_ENTROPY_SAMPLE_SIZE {
+        return Ok(None);
+    }
+
+    let mut counts = [0u64; 256];
+    for &byte in &sample {
+        counts[byte as usize] += 1;
+    }
+    let len = sample.len() as f64;
+    Ok(Some(counts.iter()
+        .filter(|&&count| count > 0)
+        .map(|&count| {
+            let p = count as f64 / len;
+            -p * p.log2()
+        })
+        .sum()))
 }
 
-impl<W: Write, F: FnMut(f64)> ProgressWriter<W, F> {
-    fn new(inner: W, total_size: u64, filename: String, progress_callback: F) -> Self {
-        Self {
-            inner,
-            progress_callback,
-            total_size,
-            bytes_written: 0,
-            filename,
+// Options shared by every zip entry, plus the per-entry switch to Stored under auto_store
+#[derive(Clone, Copy)]
+struct ZipEntryOptions {
+    base: FileOptions,
+    auto_store: bool,
+    precise_timestamps: bool,
+}
+
+impl ZipEntryOptions {
+    fn for_path(&self, path: &Path) -> FileOptions {
+        let options = if self.auto_store && is_precompressed(path) {
+            self.base.compression_method(zip::CompressionMethod::Stored).compression_level(None)
+        } else {
+            self.base
+        };
+        match unix_permissions(path) {
+            Some(mode) => options.unix_permissions(mode),
+            None => options,
         }
     }
 }
 
-impl<W: Write, F: FnMut(f64)> Write for ProgressWriter<W, F> {
-    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-        let bytes = self.inner.write(buf)?;
-        self.bytes_written += bytes as u64;
-        
-        if self.total_size > 0 {
-            let progress = (self.bytes_written as f64 / self.total_size as f64) * 100.0;
-            (self.progress_callback)(progress.min(100.0));
-        }
-        
-        Ok(bytes)
+// The file's own permission bits on unix, so executables and private files keep their mode;
+// elsewhere entries keep the 0o755 default
+fn unix_permissions(path: &Path) -> Option<u32> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::metadata(path).ok().map(|metadata| metadata.permissions().mode() & 0o777)
     }
-    
-    fn flush(&mut self) -> std::io::Result<()> {
-        self.inner.flush()
+    #[cfg(not(unix))]
+    {
+        let _ = path;
+        None
     }
 }
 
-async fn compress_gz_with_progress<F>(file_path: &Path, output_path: &Path, progress_callback: F) -> Result<()>
-where
-    F: FnMut(f64),
-{
-    let input = File::open(file_path)?;
-    let output = File::create(output_path)?;
-    let file_size = std::fs::metadata(file_path)?.len();
-    
-    let filename = file_path.file_name()
-        .unwrap_or_default()
-        .to_string_lossy()
-        .to_string();
-    
-    let progress_output = ProgressWriter::new(output, file_size, filename, progress_callback);
-    
-    // Create encoder with optional filename in header
-    let mut encoder = match file_path.file_name().and_then(|name| name.to_str()) {
-        Some(filename_str) => {
-            // Store the original filename in the gzip header
-            GzBuilder::new()
-                .filename(filename_str)
-                .write(BufWriter::new(progress_output), FlateCompression::default())
-        }
-        None => {
-            // No filename available, create without header filename
-            GzBuilder::new().write(BufWriter::new(progress_output), FlateCompression::default())
-        }
-    };
-    
-    let mut reader = BufReader::new(input);
-    std::io::copy(&mut reader, &mut encoder)?;
-    encoder.finish()?;
-    Ok(())
-}
+// Begin the entry for `path`, writing its timestamp extra fields when precise_timestamps is set
+fn start_zip_file<W: Write + std::io::Seek>(zip: &mut ZipWriter<W>, zip_path: &str, path: &Path, options: &ZipEntryOptions) -> Result<()> {
+    let file_options = options.for_path(path);
+    let modified = std::fs::metadata(path)?.modified()?
+        .duration_since(std::time::UNIX_EPOCH)
+        .ok();
 
-async fn compress_br_with_progress<F>(file_path: &Path, output_path: &Path, progress_callback: F) -> Result<()>
-where
-    F: FnMut(f64),
-{
-    let input = File::open(file_path)?;
-    let output = File::create(output_path)?;
-    let file_size = std::fs::metadata(file_path)?.len();
-    
-    let filename = file_path.file_name()
-        .unwrap_or_default()
-        .to_string_lossy()
-        .to_string();
-    
-    let progress_output = ProgressWriter::new(output, file_size, filename, progress_callback);
-    let mut encoder = brotli::CompressorWriter::new(BufWriter::new(progress_output), 4096, 6, 22);
-    
-    let mut reader = BufReader::new(input);
-    std::io::copy(&mut reader, &mut encoder)?;
-    encoder.flush()?;
+    match modified {
+        Some(modified) if options.precise_timestamps => {
+            // The DOS field has no timezone; like extraction, treat it as UTC
+            let file_options = match zip_datetime_from_unix(modified.as_secs() as i64) {
+                Some(mtime) => file_options.last_modified_time(mtime),
+                None => file_options,
+            };
+            zip.start_file_with_extra_data(zip_path, file_options)?;
+            zip.write_all(&timestamp_extra_fields(modified))?;
+            zip.end_extra_data()?;
+        },
+        _ => zip.start_file(zip_path, file_options)?,
+    }
     Ok(())
 }
 
-async fn compress_bzip2_with_progress<F>(file_path: &Path, output_path: &Path, progress_callback: F) -> Result<()>
-where
-    F: FnMut(f64),
-{
-    let input = File::open(file_path)?;
-    let output = File::create(output_path)?;
-    let file_size = std::fs::metadata(file_path)?.len();
-    
-    let filename = file_path.file_name()
-        .unwrap_or_default()
-        .to_string_lossy()
-        .to_string();
-    
-    let progress_output = ProgressWriter::new(output, file_size, filename, progress_callback);
-    let mut encoder = bzip2::write::BzEncoder::new(BufWriter::new(progress_output), bzip2::Compression::default());
-    
-    let mut reader = BufReader::new(input);
-    std::io::copy(&mut reader, &mut encoder)?;
-    encoder.finish()?;
-    Ok(())
-}
+const EXTENDED_TIMESTAMP_TAG: u16 = 0x5455;
+const NTFS_TAG: u16 = 0x000a;
+// 100ns intervals between 1601-01-01 (the NTFS epoch) and 1970-01-01
+const NTFS_UNIX_EPOCH_OFFSET: u64 = 116_444_736_000_000_000;
 
-// Standard decompression without progress (backward compatibility)
-pub async fn decompress_files(file_path: &Path, output_dir: &Path) -> Result<()> {
-    decompress_files_with_progress(file_path, output_dir, |_, _| {}).await
-}
+// Info-ZIP "UT" (whole seconds, modification time only) followed by the NTFS field
+// (100ns resolution, with the access and creation times set to the modification time)
+fn timestamp_extra_fields(modified: std::time::Duration) -> Vec<u8> {
+    let mut extra = Vec::with_capacity(9 + 36);
 
-// New decompression function with filename-aware progress callback
-pub async fn decompress_files_with_progress<F>(
-    file_path: &Path, 
-    output_dir: &Path,
-    mut progress_callback: F
-) -> Result<()> 
-where
-    F: FnMut(f64, String),
-{
-    let extension = file_path.extension()
-        .and_then(|ext| ext.to_str())
-        .unwrap_or("");
+    extra.extend_from_slice(&EXTENDED_TIMESTAMP_TAG.to_le_bytes());
+    extra.extend_from_slice(&5u16.to_le_bytes());
+    extra.push(0x01); // mtime present
+    extra.extend_from_slice(&(modified.as_secs() as u32).to_le_bytes());
 
-    let file_name = file_path.file_name()
-        .and_then(|name| name.to_str())
-        .unwrap_or("");
+    let ntfs_time = NTFS_UNIX_EPOCH_OFFSET + modified.as_secs() * 10_000_000 + u64::from(modified.subsec_nanos() / 100);
+    extra.extend_from_slice(&NTFS_TAG.to_le_bytes());
+    extra.extend_from_slice(&32u16.to_le_bytes());
+    extra.extend_from_slice(&0u32.to_le_bytes()); // reserved
+    extra.extend_from_slice(&1u16.to_le_bytes()); // attribute tag 1: times
+    extra.extend_from_slice(&24u16.to_le_bytes());
+    for _ in 0..3 {
+        extra.extend_from_slice(&ntfs_time.to_le_bytes());
+    }
+    extra
+}
 
-    // Get file size for progress calculation
-    let file_size = std::fs::metadata(file_path)?.len();
-    
-    let archive_name = file_path.file_name()
-        .unwrap_or_default()
-        .to_string_lossy()
-        .to_string();
+// Modification time from a zip entry's extra fields as (unix seconds, nanoseconds). The NTFS
+// field is preferred for its precision, then the "UT" field.
+fn extended_mtime(mut extra: &[u8]) -> Option<(i64, u32)> {
+    let mut extended = None;
+    let mut ntfs = None;
 
-    if file_name.ends_with(".tar.gz") || file_name.ends_with(".tgz") {
-        decompress_tar_gz_with_progress(file_path, output_dir, file_size, move |progress, _| {
-            progress_callback(progress, archive_name.clone())
-        }).await
-    } else if file_name.ends_with(".tar.br") {
-        decompress_tar_br_with_progress(file_path, output_dir, file_size, move |progress, _| {
-            progress_callback(progress, archive_name.clone())
-        }).await
-    } else {
-        match extension {
-            "zip" => decompress_zip_with_progress(file_path, output_dir, move |progress, _| {
-                progress_callback(progress, archive_name.clone())
-            }).await,
-            "gz" | "gzip" => decompress_gz_with_progress(file_path, output_dir, file_size, move |progress, _| {
-                progress_callback(progress, archive_name.clone())
-            }).await,
-            "br" => decompress_br_with_progress(file_path, output_dir, file_size, move |progress, _| {
-                progress_callback(progress, archive_name.clone())
-            }).await,
-            "bz2" | "bzip2" => decompress_bzip2_with_progress(file_path, output_dir, file_size, move |progress, _| {
-                progress_callback(progress, archive_name.clone())
-            }).await,
-            #[cfg(feature = "rar-support")]
-            "rar" => decompress_rar(file_path, output_dir).await,
-            _ => Err(anyhow::anyhow!("Unsupported file format: {}", extension)),
+    while extra.len() >= 4 {
+        let tag = u16::from_le_bytes([extra[0], extra[1]]);
+        let size = u16::from_le_bytes([extra[2], extra[3]]) as usize;
+        let data = extra.get(4..4 + size)?;
+
+        if tag == EXTENDED_TIMESTAMP_TAG && data.len() >= 5 && data[0] & 0x01 != 0 {
+            extended = Some((u32::from_le_bytes([data[1], data[2], data[3], data[4]]) as i64, 0));
+        } else if tag == NTFS_TAG {
+            // Attributes follow 4 reserved bytes; tag 1 holds mtime, atime and ctime
+            let mut attributes = data.get(4..)?;
+            while attributes.len() >= 4 {
+                let attribute_tag = u16::from_le_bytes([attributes[0], attributes[1]]);
+                let attribute_size = u16::from_le_bytes([attributes[2], attributes[3]]) as usize;
+                let value = attributes.get(4..4 + attribute_size)?;
+                if attribute_tag == 1 && value.len() >= 8 {
+                    let ticks = u64::from_le_bytes(value[..8].try_into().ok()?);
+                    let since_unix = ticks.checked_sub(NTFS_UNIX_EPOCH_OFFSET)?;
+                    ntfs = Some(((since_unix / 10_000_000) as i64, (since_unix % 10_000_000) as u32 * 100));
+                }
+                attributes = &attributes[4 + attribute_size..];
+            }
         }
+        extra = &extra[4 + size..];
     }
+    ntfs.or(extended)
 }
 
-// Progress tracking reader wrapper for decompression with filename tracking
-struct ProgressReader<R, F> {
-    inner: R,
-    progress_callback: F,
-    total_size: u64,
-    bytes_read: u64,
-    filename: String,
+// Info-ZIP Unicode Path field: version 1, CRC-32 of the entry's raw name, then the UTF-8 name
+const UNICODE_PATH_TAG: u16 = 0x7075;
+
+// A zip entry's name, decoded the way the tool that wrote it meant it. The zip crate honours the
+// UTF-8 flag (general purpose bit 11) and reads everything else as CP437, but Info-ZIP on Unix,
+// macOS and many Windows tools store UTF-8 names without setting the flag, which CP437 turns
+// into mojibake. So: a Unicode Path field that still matches the raw name, then a raw name that
+// is valid UTF-8, and only then the crate's own decoding.
+fn zip_entry_name(file: &zip::read::ZipFile) -> String {
+    let raw = file.name_raw();
+    if let Some(name) = unicode_path_name(raw, file.extra_data()) {
+        return name;
+    }
+    match std::str::from_utf8(raw) {
+        Ok(name) => name.to_string(),
+        Err(_) => file.name().to_string(),
+    }
 }
 
-impl<R: Read, F: FnMut(f64, String)> ProgressReader<R, F> {
-    fn new(inner: R, total_size: u64, filename: String, progress_callback: F) -> Self {
-        Self {
-            inner,
-            progress_callback,
-            total_size,
-            bytes_read: 0,
-            filename,
+// The name from an Info-ZIP Unicode Path extra field, if there is one that still matches `raw`
+fn unicode_path_name(raw: &[u8], mut extra: &[u8]) -> Option<String> {
+    while extra.len() >= 4 {
+        let tag = u16::from_le_bytes([extra[0], extra[1]]);
+        let size = u16::from_le_bytes([extra[2], extra[3]]) as usize;
+        let Some(data) = extra.get(4..4 + size) else { break };
+        if tag == UNICODE_PATH_TAG && data.len() > 5 && data[0] == 1 {
+            // A tool that renamed the entry without knowing the field leaves a stale CRC behind
+            let mut crc = flate2::Crc::new();
+            crc.update(raw);
+            if crc.sum().to_le_bytes() == data[1..5] {
+                if let Ok(name) = std::str::from_utf8(&data[5..]) {
+                    return Some(name.to_string());
+                }
+            }
         }
+        extra = &extra[4 + size..];
     }
+    None
 }
 
-impl<R: Read, F: FnMut(f64, String)> Read for ProgressReader<R, F> {
-    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        let bytes = self.inner.read(buf)?;
-        self.bytes_read += bytes as u64;
-        
-        if self.total_size > 0 {
-            let progress = (self.bytes_read as f64 / self.total_size as f64) * 100.0;
-            (self.progress_callback)(progress.min(100.0), self.filename.clone());
+// What compression does with symbolic links (and, on Windows, directory junctions)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum SymlinkPolicy {
+    // Archive what the link points to; a directory that was already walked (through a cycle
+    // or another link) is skipped, so each one is stored once and cycles can't recurse forever
+    #[default]
+    Follow,
+    // Archive the link itself: a zip entry with unix mode 0o120000 or a tar symlink header
+    Store,
+    // Leave links out of the archive
+    Skip,
+}
+
+impl SymlinkPolicy {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "follow" => Some(SymlinkPolicy::Follow),
+            "store" => Some(SymlinkPolicy::Store),
+            "skip" => Some(SymlinkPolicy::Skip),
+            _ => None,
         }
-        
-        Ok(bytes)
     }
 }
 
-async fn decompress_zip_with_progress<F>(
-    file_path: &Path, 
-    output_dir: &Path, 
-    mut progress_callback: F
-) -> Result<()>
-where
-    F: FnMut(f64, String),
-{
-    let file = File::open(file_path)?;
-    let mut archive = zip::ZipArchive::new(BufReader::new(file))?;
-    let archive_name = file_path.file_name().unwrap_or_default().to_string_lossy().to_string();
-    
-    std::fs::create_dir_all(output_dir)?;
+// Compiled include/exclude globs. A pattern matches an entry's stored path (e.g.
+// "project/src/main.rs") or just its name, so "node_modules" and "*.log" apply at any depth.
+// Excluded directories are not walked at all; include patterns only select files.
+#[derive(Debug, Clone, Default)]
+pub struct PathFilter {
+    include: Option<GlobSet>,
+    exclude: Option<GlobSet>,
+    // One per input directory that has a .tauzipignore at its root
+    ignore_files: Vec<Gitignore>,
+    symlinks: SymlinkPolicy,
+    // Walk directory contents in name order
+    sorted: bool,
+    // Walks already made with this filter, replayed instead of reading the disk again
+    walks: Option<Arc<SharedWalks>>,
+}
 
-    let total_files = archive.len();
-    
-    for i in 0..archive.len() {
-        // Update progress based on file count
-        let progress = (i as f64 / total_files as f64) * 100.0;
-        progress_callback(progress, archive_name.clone());
-        
-        let mut file = archive.by_index(i)?;
-        let outpath = output_dir.join(file.name());
+// The entries walk_input found for each (input, base) pair, in walk order. Shared by the
+// candidates of compress_best_of so every format archives the same walk of the inputs.
+#[derive(Debug, Default)]
+struct SharedWalks(Mutex<HashMap<(PathBuf, PathBuf), Arc<Vec<(PathBuf, WalkEntry)>>>>);
 
-        if file.is_dir() {
-            std::fs::create_dir_all(&outpath)?;
-        } else {
-            if let Some(p) = outpath.parent() {
-                std::fs::create_dir_all(p)?;
+// Read from the root of each directory being compressed; uses .gitignore syntax with patterns
+// relative to that directory
+pub const IGNORE_FILE_NAME: &str = ".tauzipignore";
+
+impl PathFilter {
+    pub fn new(include: &[String], exclude: &[String]) -> Result<Self> {
+        Ok(Self {
+            include: build_glob_set(include)?,
+            exclude: build_glob_set(exclude)?,
+            ignore_files: Vec::new(),
+            symlinks: SymlinkPolicy::default(),
+            sorted: false,
+            walks: None,
+        })
+    }
+
+    fn with_shared_walks(mut self, walks: Option<Arc<SharedWalks>>) -> Self {
+        self.walks = walks;
+        self
+    }
+
+    pub fn with_sorted_entries(mut self, sorted: bool) -> Self {
+        self.sorted = sorted;
+        self
+    }
+
+    pub fn with_symlink_policy(mut self, symlinks: SymlinkPolicy) -> Self {
+        self.symlinks = symlinks;
+        self
+    }
+
+    // Parse the .tauzipignore of every input directory once, before any walk starts
+    pub fn with_ignore_files(mut self, files: &[PathBuf]) -> Result<Self> {
+        for dir in files.iter().filter(|path| path.is_dir()) {
+            let ignore_path = dir.join(IGNORE_FILE_NAME);
+            if !ignore_path.is_file() {
+                continue;
             }
-            let mut outfile = File::create(&outpath)?;
-            std::io::copy(&mut file, &mut outfile)?;
-        }
 
-        // Set file permissions if available
-        #[cfg(unix)]
-        if let Some(mode) = file.unix_mode() {
-            use std::os::unix::fs::PermissionsExt;
-            std::fs::set_permissions(&outpath, std::fs::Permissions::from_mode(mode))?;
+            let mut builder = GitignoreBuilder::new(dir);
+            if let Some(err) = builder.add(&ignore_path) {
+                tracing::warn!(file = %ignore_path.display(), error = %err, "Problem in ignore file");
+            }
+            let ignore_file = builder.build()
+                .with_context(|| format!("Invalid ignore file: {}", ignore_path.display()))?;
+            tracing::debug!(file = %ignore_path.display(), patterns = ignore_file.num_ignores(), "Using ignore file");
+            self.ignore_files.push(ignore_file);
         }
+        Ok(self)
     }
 
-    progress_callback(100.0, archive_name);
-    Ok(())
+    // `base` is the directory the entry's stored path is relative to
+    fn allows(&self, path: &Path, base: &Path) -> bool {
+        let name = Path::new(path.file_name().unwrap_or_default());
+        let stored = stored_path(path, base);
+        let matches = |set: &GlobSet| set.is_match(stored) || set.is_match(name);
+        let is_dir = path.is_dir();
+
+        if self.exclude.as_ref().is_some_and(matches) {
+            return false;
+        }
+        let ignored = self.ignore_files.iter().any(|ignore_file| {
+            path.starts_with(ignore_file.path()) && ignore_file.matched(path, is_dir).is_ignore()
+        });
+        if ignored {
+            return false;
+        }
+        is_dir || self.include.as_ref().is_none_or(matches)
+    }
 }
 
-async fn decompress_tar_gz_with_progress<F>(
-    file_path: &Path, 
-    output_dir: &Path, 
-    file_size: u64,
-    progress_callback: F
-) -> Result<()>
-where
-    F: FnMut(f64, String),
-{
-    let file = File::open(file_path)?;
-    let filename = file_path.file_name().unwrap_or_default().to_string_lossy().to_string();
-    let progress_reader = ProgressReader::new(file, file_size, filename, progress_callback);
-    let gz_decoder = flate2::read::GzDecoder::new(BufReader::new(progress_reader));
-    let mut archive = tar::Archive::new(gz_decoder);
-    
-    std::fs::create_dir_all(output_dir)?;
-    archive.unpack(output_dir)?;
-    Ok(())
+fn build_glob_set(patterns: &[String]) -> Result<Option<GlobSet>> {
+    if patterns.is_empty() {
+        return Ok(None);
+    }
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(Glob::new(pattern).with_context(|| format!("Invalid glob pattern '{}'", pattern))?);
+    }
+    Ok(Some(builder.build()?))
 }
 
-async fn decompress_tar_br_with_progress<F>(
-    file_path: &Path, 
-    output_dir: &Path, 
-    file_size: u64,
-    progress_callback: F
-) -> Result<()>
-where
-    F: FnMut(f64, String),
-{
-    let file = File::open(file_path)?;
-    let filename = file_path.file_name().unwrap_or_default().to_string_lossy().to_string();
-    let progress_reader = ProgressReader::new(file, file_size, filename, progress_callback);
-    let br_decoder = brotli::Decompressor::new(BufReader::new(progress_reader), 4096);
-    let mut archive = tar::Archive::new(br_decoder);
-    
-    std::fs::create_dir_all(output_dir)?;
-    archive.unpack(output_dir)?;
-    Ok(())
+// What to do when an extracted file already exists on disk
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum ConflictPolicy {
+    #[default]
+    Overwrite,
+    Skip,
+    Rename,
 }
 
-async fn decompress_gz_with_progress<F>(
-    file_path: &Path, 
-    output_dir: &Path, 
-    file_size: u64,
-    progress_callback: F
-) -> Result<()>
-where
-    F: FnMut(f64, String),
-{
-    let input = File::open(file_path)?;
-    let filename = file_path.file_name().unwrap_or_default().to_string_lossy().to_string();
-    let progress_reader = ProgressReader::new(input, file_size, filename, progress_callback);
-    let mut decoder = flate2::read::GzDecoder::new(BufReader::new(progress_reader));
-    
-    std::fs::create_dir_all(output_dir)?;
-    
-    // Try to get the original filename from the gzip header first
-    let output_name = if let Some(filename_bytes) = decoder.header().and_then(|h| h.filename()) {
-        // Convert bytes to string and create OsString
-        match std::str::from_utf8(filename_bytes) {
-            Ok(filename_str) => std::ffi::OsString::from(filename_str),
-            Err(_) => {
-                // If UTF-8 conversion fails, fall back to the compressed filename
-                fallback_filename_from_compressed(file_path)
-            }
+impl ConflictPolicy {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "overwrite" => Some(ConflictPolicy::Overwrite),
+            "skip" => Some(ConflictPolicy::Skip),
+            "rename" => Some(ConflictPolicy::Rename),
+            _ => None,
         }
-    } else {
-        // If no filename in header, try to infer from the compressed filename
-        fallback_filename_from_compressed(file_path)
-    };
-    
-    let output_path = output_dir.join(output_name);
-    let mut output = File::create(output_path)?;
-    
-    std::io::copy(&mut decoder, &mut output)?;
-    Ok(())
+    }
 }
 
-fn fallback_filename_from_compressed(file_path: &Path) -> std::ffi::OsString {
-    let filename = file_path.file_name().unwrap_or_default().to_string_lossy();
-    
-    // Handle different compression formats
-    let (base_name, stripped) = if filename.ends_with(".gz") {
-        (&filename[..filename.len() - 3], true)
-    } else if filename.ends_with(".gzip") {
-        (&filename[..filename.len() - 5], true)
-    } else if filename.ends_with(".br") {
-        (&filename[..filename.len() - 3], true)
-    } else if filename.ends_with(".bz2") {
-        (&filename[..filename.len() - 4], true)
-    } else if filename.ends_with(".bzip2") {
-        (&filename[..filename.len() - 6], true)
-    } else {
-        (filename.as_ref(), false)
-    };
-    
-    if stripped && !base_name.is_empty() {
-        // If we stripped a compression extension and there's still a base name
-        if base_name.contains('.') {
-            // Base name already has an extension, use it as-is
-            std::ffi::OsString::from(base_name)
+// What the extractors are handed for existing files: the policy, and how a file it overwrites
+// is backed up first, if at all
+#[derive(Debug, Clone, Copy)]
+struct ConflictHandling {
+    policy: ConflictPolicy,
+    backup_on_overwrite: Option<OverwriteBackup>,
+}
+
+// Saves a file the Overwrite policy is about to replace; an error leaves the file in place and
+// fails the extraction
+pub type OverwriteBackup = fn(&Path) -> std::io::Result<()>;
+
+// The usual OverwriteBackup, from which the overwrite can be undone
+pub fn move_to_trash(path: &Path) -> std::io::Result<()> {
+    trash::delete(path).map_err(|e| std::io::Error::other(format!("Could not move '{}' to the trash: {}", path.display(), e)))
+}
+
+// Optional behaviour for decompress_files_with_progress
+#[derive(Debug, Clone, Default)]
+pub struct DecompressOptions {
+    // Overrides the inferred file name for single-stream formats (gz, br, bz2)
+    pub output_name: Option<String>,
+    pub conflict_policy: ConflictPolicy,
+    // Back up each file the Overwrite policy would replace before replacing it, e.g. with
+    // move_to_trash so the overwrite can be undone from the system trash
+    pub backup_on_overwrite: Option<OverwriteBackup>,
+    // Report the planned output paths through the progress callback without writing anything;
+    // the summary then holds the planned file count and size
+    pub dry_run: bool,
+    // Passwords for encrypted zip entries
+    pub password: Option<PasswordResolver>,
+    // (glob pattern, target directory) rules sending matching entries of zip, tar and 7z
+    // archives elsewhere, e.g. ("*.jpg", "images"); see EntryRouter
+    pub routes: Vec<(String, PathBuf)>,
+    // Write every file of zip, tar and 7z archives straight into its target directory, dropping
+    // the archive's folders; files that end up with the same name are numbered
+    pub flatten: bool,
+    // Also extract the archives found among the extracted files, and the ones inside those,
+    // each into a folder next to it; see extract_nested_archives
+    pub recursive: bool,
+    // How many levels deep recursive extraction goes (DEFAULT_NESTED_DEPTH when unset), so an
+    // archive that unpacks into copies of itself can't go on forever
+    pub max_nested_depth: Option<usize>,
+    // Stop once the extraction has decompressed this many bytes, nested archives included, and
+    // refuse zip entries claiming an implausible compression ratio; see OutputLimit
+    pub max_output_bytes: Option<u64>,
+    // Extract even when the archive records more data than the output volume has room for
+    pub skip_space_check: bool,
+}
+
+// Sends extracted entries to per-pattern directories. Rules are tried in order and, like include
+// and exclude patterns, match the entry's path or just its name; entries no rule matches stay in
+// the output directory. Relative targets are inside the output directory, and an entry keeps its
+// own path below whichever directory it goes to, unless flattening leaves only its file name.
+struct EntryRouter {
+    routes: Vec<(globset::GlobMatcher, PathBuf)>,
+    default_dir: PathBuf,
+    flatten: bool,
+    // Paths already handed out by claim, so flattened entries don't overwrite each other
+    claimed: Mutex<HashSet<PathBuf>>,
+}
+
+impl EntryRouter {
+    fn new(routes: &[(String, PathBuf)], output_dir: &Path, flatten: bool) -> Result<Self> {
+        let routes = routes.iter()
+            .map(|(pattern, target)| {
+                let glob = Glob::new(pattern).with_context(|| format!("Invalid glob pattern '{}'", pattern))?;
+                Ok((glob.compile_matcher(), output_dir.join(target)))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self { routes, default_dir: output_dir.to_path_buf(), flatten, claimed: Mutex::new(HashSet::new()) })
+    }
+
+    // Whether entries can land somewhere other than their own path in the output directory
+    fn rewrites_paths(&self) -> bool {
+        !self.routes.is_empty() || self.flatten
+    }
+
+    // `relative_path` must already be known to stay inside the directory it is joined to
+    fn output_path(&self, relative_path: &Path) -> PathBuf {
+        let name = Path::new(relative_path.file_name().unwrap_or_default());
+        let dir = self.routes.iter()
+            .find(|(matcher, _)| matcher.is_match(relative_path) || matcher.is_match(name))
+            .map_or(&self.default_dir, |(_, target)| target);
+        if self.flatten {
+            dir.join(name)
         } else {
-            // No extension in base name, assume it's a text file
-            // You could make this more sophisticated by analyzing file content
-            std::ffi::OsString::from(format!("{}.txt", base_name))
+            dir.join(relative_path)
         }
-    } else {
-        // Fallback to file stem if we couldn't parse the format
-        file_path.file_stem().unwrap_or_default().to_os_string()
+    }
+
+    // Whether `path` (as returned by output_path) lies below a symbolic link inside the directory
+    // its entry was sent to, as after a tar entry "d -> /home/user" when "d/.bashrc" comes next.
+    // Writing there would land outside that directory, which tar's own unpack_in refuses too.
+    // Links in the output directory's own path are the user's and are followed as usual.
+    fn passes_through_symlink(&self, path: &Path) -> bool {
+        let base = self.routes.iter()
+            .map(|(_, target)| target)
+            .chain(std::iter::once(&self.default_dir))
+            .filter(|dir| path.starts_with(dir))
+            .max_by_key(|dir| dir.components().count());
+        let Some((base, relative)) = base.and_then(|base| Some((base, path.strip_prefix(base).ok()?))) else {
+            return true;
+        };
+        let mut current = base.clone();
+        relative.components().any(|component| {
+            current.push(component);
+            current.symlink_metadata().is_ok_and(|metadata| metadata.file_type().is_symlink())
+        })
+    }
+
+    // Reserve `outpath` for a file of this extraction. When flattening, a path an earlier entry
+    // already took (a/x.txt and b/x.txt both becoming x.txt) is numbered like a rename instead.
+    fn claim(&self, outpath: PathBuf) -> PathBuf {
+        if !self.flatten {
+            return outpath;
+        }
+        let mut claimed = self.claimed.lock().unwrap();
+        let outpath = if claimed.contains(&outpath) {
+            let reserved: Vec<PathBuf> = claimed.iter().cloned().collect();
+            crate::file_utils::get_unique_name_among(&outpath, &reserved)
+        } else {
+            outpath
+        };
+        claimed.insert(outpath.clone());
+        outpath
     }
 }
 
-async fn decompress_br_with_progress<F>(
-    file_path: &Path, 
-    output_dir: &Path, 
-    file_size: u64,
-    progress_callback: F
-) -> Result<()>
+// Same rule as tar's own unpack: only plain relative paths stay inside the output directory
+pub(crate) fn is_safe_relative_path(path: &Path) -> bool {
+    path.components().all(|c| matches!(c, std::path::Component::Normal(_) | std::path::Component::CurDir))
+}
+
+// What an extraction would write, as returned by preview_extraction
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ExtractionPreview {
+    pub entries: Vec<String>,
+    pub total_size: u64,
+    pub would_overwrite: Vec<String>,
+}
+
+// Files that hit an existing path during extraction
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ExtractSummary {
+    pub skipped: usize,
+    pub renamed: usize,
+    // Overwritten files that were backed up (usually to the trash) first
+    pub trashed: usize,
+    // Archives found inside the archive and extracted along with it (recursive extraction)
+    pub nested: usize,
+    // Files and bytes a dry run would have written
+    pub planned_files: usize,
+    pub planned_size: u64,
+    // Files written where nothing was before, so a stopped extraction can take back just those
+    // from an output directory that already held other files
+    #[serde(skip)]
+    pub created: Vec<PathBuf>,
+}
+
+// Where an extracted file should be written under `conflicts`, or None to leave the existing file
+// alone. An io::Error so it can be raised from inside the 7z entry callback too.
+fn resolve_conflict(path: &Path, conflicts: ConflictHandling, summary: &mut ExtractSummary) -> std::io::Result<Option<PathBuf>> {
+    if !path.exists() {
+        summary.created.push(path.to_path_buf());
+        return Ok(Some(path.to_path_buf()));
+    }
+    match conflicts.policy {
+        ConflictPolicy::Overwrite => {
+            // A file that can't be backed up isn't overwritten
+            if let Some(backup) = conflicts.backup_on_overwrite {
+                backup(path)?;
+                tracing::info!(path = %path.display(), "Backed up existing file");
+                summary.trashed += 1;
+            }
+            Ok(Some(path.to_path_buf()))
+        },
+        ConflictPolicy::Skip => {
+            tracing::info!(path = %path.display(), "Skipping existing file");
+            summary.skipped += 1;
+            Ok(None)
+        },
+        ConflictPolicy::Rename => {
+            summary.renamed += 1;
+            let renamed = crate::file_utils::get_unique_name(path);
+            summary.created.push(renamed.clone());
+            Ok(Some(renamed))
+        },
+    }
+}
+
+// File and byte counters reported alongside the overall percentage while compressing
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ProgressCounts {
+    pub files_done: usize,
+    pub files_total: usize,
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+    pub mode: ProgressMode,
+}
+
+impl ProgressCounts {
+    fn update_single_file(&mut self, progress: f64) {
+        self.bytes_done = ((progress / 100.0) * self.bytes_total as f64) as u64;
+        if progress >= 100.0 {
+            self.files_done = 1;
+        }
+    }
+
+    fn percent(&self) -> f64 {
+        if self.mode == ProgressMode::Entries && self.files_total > 0 {
+            (self.files_done as f64 / self.files_total as f64) * 100.0
+        } else if self.bytes_total > 0 {
+            (self.bytes_done as f64 / self.bytes_total as f64) * 100.0
+        } else if self.files_total > 0 {
+            (self.files_done as f64 / self.files_total as f64) * 100.0
+        } else {
+            100.0
+        }
+    }
+}
+
+fn check_cancelled(cancel: &CancellationToken) -> Result<()> {
+    if cancel.load(Ordering::SeqCst) {
+        return Err(CompressionError::Cancelled.into());
+    }
+    Ok(())
+}
+
+// Zip entries larger than this may expand at most MAX_ZIP_ENTRY_RATIO times when a limit is
+// set. Deflate tops out near 1032:1, so only other methods or doctored headers get past it.
+const MAX_ZIP_ENTRY_RATIO: u64 = 2000;
+const ZIP_RATIO_CHECK_MIN_SIZE: u64 = 1024 * 1024;
+
+// The max_output_bytes budget of one extraction. Every decoder counts what it decompresses
+// through LimitedReader, so the cap holds whatever the archive's headers claim; clones share
+// the count. The first reason extraction was stopped is kept for the error.
+#[derive(Clone, Default)]
+struct OutputLimit {
+    max: Option<u64>,
+    used: Arc<AtomicU64>,
+    tripped: Arc<Mutex<Option<String>>>,
+}
+
+impl OutputLimit {
+    fn new(max: Option<u64>) -> Self {
+        Self { max, ..Default::default() }
+    }
+
+    fn reader<R: Read>(&self, inner: R) -> LimitedReader<R> {
+        LimitedReader { inner, limit: self.clone() }
+    }
+
+    fn trip(&self, reason: String) -> std::io::Error {
+        self.tripped.lock().unwrap().get_or_insert(reason.clone());
+        std::io::Error::new(std::io::ErrorKind::Other, CompressionError::DecompressionLimitExceeded(reason))
+    }
+
+    fn tripped(&self) -> Option<String> {
+        self.tripped.lock().unwrap().clone()
+    }
+
+    fn count(&self, bytes: usize) -> std::io::Result<()> {
+        let Some(max) = self.max else { return Ok(()) };
+        let used = self.used.fetch_add(bytes as u64, Ordering::SeqCst) + bytes as u64;
+        if used > max {
+            return Err(self.trip(format!("more than the {}-byte output limit", max)));
+        }
+        Ok(())
+    }
+
+    // Fail before writing a zip entry whose header already gives it away
+    fn check_zip_entry(&self, name: &str, size: u64, compressed_size: u64) -> std::io::Result<()> {
+        let Some(max) = self.max else { return Ok(()) };
+        if size > max.saturating_sub(self.used.load(Ordering::SeqCst)) {
+            return Err(self.trip(format!("'{}' alone would take the output past the {}-byte limit", name, max)));
+        }
+        if size > ZIP_RATIO_CHECK_MIN_SIZE && size / compressed_size.max(1) > MAX_ZIP_ENTRY_RATIO {
+            return Err(self.trip(format!(
+                "'{}' claims to expand {}:1 ({} -> {} bytes), more than the {}:1 allowed",
+                name, size / compressed_size.max(1), compressed_size, size, MAX_ZIP_ENTRY_RATIO
+            )));
+        }
+        Ok(())
+    }
+}
+
+struct LimitedReader<R> {
+    inner: R,
+    limit: OutputLimit,
+}
+
+impl<R: Read> Read for LimitedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let bytes = self.inner.read(buf)?;
+        self.limit.count(bytes)?;
+        Ok(bytes)
+    }
+}
+
+fn cancelled_io_error() -> std::io::Error {
+    // ErrorKind::Interrupted would make std::io::copy retry, so use Other
+    std::io::Error::new(std::io::ErrorKind::Other, CompressionError::Cancelled)
+}
+
+pub async fn compress_files(
+    files: &[PathBuf],
+    output_path: &Path,
+    compression_type: CompressionType,
+) -> Result<PathBuf, CompressionError> {
+    let cancel = CancellationToken::default();
+    compress_files_with_progress(files, output_path, compression_type, &CompressOptions::default(), &cancel, |_, _, _| {}).await
+}
+
+#[tracing::instrument(skip_all, fields(output = %output_path.display(), format = ?compression_type))]
+pub async fn compress_files_with_progress<F>(
+    files: &[PathBuf],
+    output_path: &Path,
+    compression_type: CompressionType,
+    options: &CompressOptions,
+    cancel: &CancellationToken,
+    progress_callback: F,
+) -> Result<PathBuf, CompressionError>
 where
-    F: FnMut(f64, String),
+    F: FnMut(f64, String, &ProgressCounts),
 {
-    let input = File::open(file_path)?;
-    let filename = file_path.file_name().unwrap_or_default().to_string_lossy().to_string();
-    let progress_reader = ProgressReader::new(input, file_size, filename, progress_callback);
-    let mut decoder = brotli::Decompressor::new(BufReader::new(progress_reader), 4096);
-    
-    std::fs::create_dir_all(output_dir)?;
-    
-    // Use improved filename logic
-    let output_name = fallback_filename_from_compressed(file_path);
-    let output_path = output_dir.join(output_name);
-    let mut output = File::create(output_path)?;
-    
-    std::io::copy(&mut decoder, &mut output)?;
-    Ok(())
+    compress_files_with_walks(files, output_path, compression_type, options, None, cancel, progress_callback).await
 }
 
-async fn decompress_bzip2_with_progress<F>(
-    file_path: &Path, 
-    output_dir: &Path, 
-    file_size: u64,
-    progress_callback: F
-) -> Result<()>
+// compress_files_with_progress, replaying the walks in `walks` when given instead of walking
+// the inputs again
+async fn compress_files_with_walks<F>(
+    files: &[PathBuf],
+    output_path: &Path,
+    compression_type: CompressionType,
+    options: &CompressOptions,
+    walks: Option<Arc<SharedWalks>>,
+    cancel: &CancellationToken,
+    mut progress_callback: F,
+) -> Result<PathBuf, CompressionError>
 where
-    F: FnMut(f64, String),
+    F: FnMut(f64, String, &ProgressCounts),
 {
-    let input = File::open(file_path)?;
-    let filename = file_path.file_name().unwrap_or_default().to_string_lossy().to_string();
-    let progress_reader = ProgressReader::new(input, file_size, filename, progress_callback);
-    let mut decoder = bzip2::read::BzDecoder::new(BufReader::new(progress_reader));
-    
-    std::fs::create_dir_all(output_dir)?;
-    
-    // Use improved filename logic
-    let output_name = fallback_filename_from_compressed(file_path);
-    let output_path = output_dir.join(output_name);
-    let mut output = File::create(output_path)?;
-    
-    std::io::copy(&mut decoder, &mut output)?;
-    Ok(())
+    if !compression_type.supports_multiple_files() && files.len() > 1 {
+        return Err(anyhow::anyhow!(
+            "Compression type {:?} does not support multiple files",
+            compression_type
+        ).into());
+    }
+
+    if options.manifest && !compression_type.supports_multiple_files() {
+        return Err(anyhow::anyhow!("{} holds a single file and has no room for a manifest", compression_type.extension()).into());
+    }
+
+    let special_output = is_special_output(output_path);
+    if special_output && matches!(compression_type, CompressionType::Zip) {
+        return Err(anyhow::anyhow!("Zip archives need a seekable output file; use .tar.gz or .tar.br to write to a pipe").into());
+    }
+    let splits = options.split_into.is_some() || options.split_size.is_some();
+    if options.split_into.is_some() && options.split_size.is_some() {
+        return Err(anyhow::anyhow!("Split into a number of volumes or by volume size, not both").into());
+    }
+    if special_output && splits {
+        return Err(anyhow::anyhow!("A pipe or device output cannot be split into volumes").into());
+    }
+
+    // A split archive only leaves its volumes behind, so those count as the name being taken
+    let taken = |path: &Path| path.exists() || (splits && volume_path(path, 1).exists());
+    let unique_path;
+    let output_path = if options.overwrite || special_output || !taken(output_path) {
+        output_path
+    } else {
+        let mut passed_over = vec![output_path.to_path_buf()];
+        let mut candidate = crate::file_utils::get_unique_name_among(output_path, &passed_over);
+        while taken(&candidate) {
+            passed_over.push(candidate);
+            candidate = crate::file_utils::get_unique_name_among(output_path, &passed_over);
+        }
+        unique_path = candidate;
+        tracing::info!(requested = %output_path.display(), output = %unique_path.display(), "Output already exists; writing a numbered name instead");
+        unique_path.as_path()
+    };
+
+    let sorted_files;
+    let files = if options.sort_entries {
+        sorted_files = sorted_inputs(files);
+        &sorted_files
+    } else {
+        files
+    };
+    let filter = PathFilter::new(&options.include, &options.exclude)?
+        .with_ignore_files(files)?
+        .with_symlink_policy(options.symlink_policy)
+        .with_sorted_entries(options.sort_entries)
+        .with_shared_walks(walks);
+
+    // Every format but plain tar has levels, and tar ignores this
+    let level = options.preset.level(&compression_type).unwrap_or(DEFAULT_COMPRESSION_LEVEL);
+    let (brotli_quality, brotli_window) = brotli_params(options, level);
+    let result = match compression_type {
+        CompressionType::Zip => compress_zip_with_progress(files, output_path, options, &filter, cancel, progress_callback).await,
+        CompressionType::Tar => compress_tar_with_progress(files, output_path, &filter, options.manifest, cancel, progress_callback).await,
+        CompressionType::TarGz => compress_tar_gz_with_progress(files, output_path, level, &filter, options.manifest, cancel, progress_callback).await,
+        CompressionType::TarBr => compress_tar_br_with_progress(files, output_path, (brotli_quality, brotli_window), &filter, options.manifest, cancel, progress_callback).await,
+        CompressionType::Gz | CompressionType::Gzip => {
+            let filename = files[0].file_name().unwrap_or_default().to_string_lossy().to_string();
+            let mut counts = single_file_counts(&files[0]);
+            compress_gz_with_progress(&files[0], output_path, level, cancel, move |progress| {
+                counts.update_single_file(progress);
+                progress_callback(progress, filename.clone(), &counts)
+            }).await
+        },
+        CompressionType::Br => {
+            let filename = files[0].file_name().unwrap_or_default().to_string_lossy().to_string();
+            let mut counts = single_file_counts(&files[0]);
+            compress_br_with_progress(&files[0], output_path, brotli_quality, brotli_window, cancel, move |progress| {
+                counts.update_single_file(progress);
+                progress_callback(progress, filename.clone(), &counts)
+            }).await
+        },
+        CompressionType::Bzip2 => {
+            let filename = files[0].file_name().unwrap_or_default().to_string_lossy().to_string();
+            let mut counts = single_file_counts(&files[0]);
+            compress_bzip2_with_progress(&files[0], output_path, level, cancel, move |progress| {
+                counts.update_single_file(progress);
+                progress_callback(progress, filename.clone(), &counts)
+            }).await
+        },
+    };
+
+    if result.is_err() && cancel.load(Ordering::SeqCst) {
+        // Don't leave a truncated archive behind
+        if !special_output {
+            let _ = std::fs::remove_file(output_path);
+        }
+        return Err(CompressionError::Cancelled);
+    }
+    result?;
+
+    // Offsets in the sidecar are into the whole archive, so it is written before any split
+    if options.central_directory_sidecar && matches!(compression_type, CompressionType::Zip) && !special_output {
+        let sidecar = write_central_directory_sidecar(output_path)?;
+        tracing::info!(path = %sidecar.display(), "Central directory written");
+    }
+
+    let volumes = match (options.split_into, options.split_size) {
+        (Some(parts), _) => Some(split_into_volumes(output_path, parts)?),
+        (None, Some(volume_size)) => Some(split_by_size(output_path, volume_size)?),
+        (None, None) => None,
+    };
+    if let Some(volumes) = volumes {
+        for (path, size) in volumes {
+            tracing::info!(path = %path.display(), bytes = size, "Volume written");
+            if options.write_checksum {
+                write_checksum_sidecar(&path)?;
+            }
+        }
+    } else if options.write_checksum && !special_output {
+        let sidecar = write_checksum_sidecar(output_path)?;
+        tracing::info!(path = %sidecar.display(), "Checksum written");
+    }
+    Ok(output_path.to_path_buf())
 }
 
-#[cfg(feature = "rar-support")]
-async fn decompress_rar(file_path: &Path, output_dir: &Path) -> Result<()> {
-    use unrar::Archive;
-    
-    std::fs::create_dir_all(output_dir)?;
-    
-    // Convert to owned strings to avoid lifetime issues
-    let file_path_str = file_path.to_str()
-        .ok_or_else(|| anyhow::anyhow!("Invalid file path"))?
-        .to_string();
-    let output_dir_str = output_dir.to_str()
-        .ok_or_else(|| anyhow::anyhow!("Invalid output directory path"))?
-        .to_string();
-    
-    // Handle the unrar specific error types using spawn_blocking for thread safety
-    tokio::task::spawn_blocking(move || {
-        Archive::new(file_path_str)
-            .extract_to(output_dir_str)
-            .map_err(|e| anyhow::anyhow!("RAR extraction failed: {:?}", e))?
-            .process()
-            .map_err(|e| anyhow::anyhow!("RAR processing failed: {:?}", e))?;
-        Ok::<(), anyhow::Error>(())
-    }).await??;
-    
-    Ok(())
+// The archive compress_best_of kept, and the size each format came to
+#[derive(Debug)]
+pub struct BestOfResult {
+    pub output_path: PathBuf,
+    pub compression_type: CompressionType,
+    pub sizes: Vec<(CompressionType, u64)>,
 }
 
-pub fn is_compressed_file(path: &Path) -> bool {
-    let file_name = path.file_name()
-        .and_then(|name| name.to_str())
-        .unwrap_or("");
+// Compress the inputs once in each format and keep only the smallest archive, written to
+// `output_stem` plus that format's extension. Ties go to the format listed first. Candidates are
+// written under temporary names and only the winner is renamed into place, so the losers (and
+// all of them if any format fails) are deleted without touching files that were already there.
+// The inputs are walked once and every format archives that same list of entries; progress is
+// reported across all of them.
+pub async fn compress_best_of<F>(
+    files: &[PathBuf],
+    output_stem: &Path,
+    compression_types: &[CompressionType],
+    options: &CompressOptions,
+    cancel: &CancellationToken,
+    mut progress_callback: F,
+) -> Result<BestOfResult, CompressionError>
+where
+    F: FnMut(f64, String, &ProgressCounts),
+{
+    if compression_types.is_empty() {
+        return Err(anyhow::anyhow!("No formats given to compare").into());
+    }
+    if options.split_into.is_some() || options.split_size.is_some() {
+        return Err(anyhow::anyhow!("Best-of archives cannot be split into volumes").into());
+    }
+    let bundles = files.len() > 1 || files.iter().any(|file_path| file_path.is_dir());
+    if let Some(single) = compression_types.iter().find(|t| bundles && !t.supports_multiple_files()) {
+        return Err(anyhow::anyhow!("{} compresses a single file and cannot hold these inputs", single.extension()).into());
+    }
 
-    if file_name.ends_with(".tar.gz") || file_name.ends_with(".tgz") || file_name.ends_with(".tar.br") {
-        return true;
+    // "tgz" and "tar.gz" are the same candidate
+    let mut requested: Vec<(CompressionType, PathBuf)> = Vec::new();
+    for compression_type in compression_types {
+        let mut name = output_stem.as_os_str().to_os_string();
+        name.push(compression_type.extension());
+        let output_path = PathBuf::from(name);
+        if !requested.iter().any(|(_, path)| *path == output_path) {
+            requested.push((compression_type.clone(), output_path));
+        }
     }
+    // Unless overwriting, the kept archive's name is numbered around existing files up front
+    let mut candidates: Vec<(CompressionType, PathBuf)> = requested.into_iter()
+        .map(|(compression_type, output_path)| match options.overwrite {
+            true => (compression_type, output_path),
+            false => (compression_type, crate::file_utils::get_unique_name(&output_path)),
+        })
+        .collect();
 
-    let extension = path.extension()
-        .and_then(|ext| ext.to_str())
-        .unwrap_or("");
+    // Only the archive that is kept gets a checksum or central directory sidecar
+    let candidate_options = CompressOptions { write_checksum: false, central_directory_sidecar: false, overwrite: true, ..options.clone() };
+    let walks = Arc::new(SharedWalks::default());
+    let total = candidates.len() as f64;
+    let mut sizes = Vec::with_capacity(candidates.len());
+    for (index, (compression_type, output_path)) in candidates.iter().enumerate() {
+        tracing::info!(output = %output_path.display(), "Compressing best-of candidate");
+        let temp_path = per_item_temp_path(output_path, index);
+        let result = compress_files_with_walks(files, &temp_path, compression_type.clone(), &candidate_options, Some(walks.clone()), cancel, |progress, current_filename, counts| {
+            progress_callback((index as f64 + progress / 100.0) / total * 100.0, current_filename, counts)
+        }).await.and_then(|_| Ok(std::fs::metadata(&temp_path)?.len()));
+
+        match result {
+            Ok(size) => sizes.push((compression_type.clone(), size)),
+            Err(e) => {
+                for (written, (_, output_path)) in candidates[..=index].iter().enumerate() {
+                    let _ = std::fs::remove_file(per_item_temp_path(output_path, written));
+                }
+                // Only the catch-all gains the format; the other variants are what callers match on
+                return Err(match e {
+                    CompressionError::Other(e) => CompressionError::Other(e.context(format!("Compressing to {} failed", compression_type.extension()))),
+                    e => e,
+                });
+            },
+        }
+    }
+
+    // min_by_key keeps the first of equal sizes
+    let winner = (0..candidates.len()).min_by_key(|&index| sizes[index].1).unwrap_or(0);
+    for (index, (_, output_path)) in candidates.iter().enumerate() {
+        if index != winner {
+            std::fs::remove_file(per_item_temp_path(output_path, index))?;
+        }
+    }
+
+    let (compression_type, output_path) = candidates.swap_remove(winner);
+    std::fs::rename(per_item_temp_path(&output_path, winner), &output_path)?;
+    if options.central_directory_sidecar && matches!(compression_type, CompressionType::Zip) {
+        write_central_directory_sidecar(&output_path)?;
+    }
+    if options.write_checksum {
+        write_checksum_sidecar(&output_path)?;
+    }
+    Ok(BestOfResult {
+        output_path,
+        compression_type,
+        sizes,
+    })
+}
+
+// How one input fared in compress_per_item
+#[derive(Debug)]
+pub struct PerItemResult {
+    pub input: PathBuf,
+    pub result: Result<PathBuf, CompressionError>,
+}
+
+// Compress every input into its own archive next to it, running up to `max_concurrent` of them
+// at once. Each archive is written under a temporary name unique to its task and only renamed
+// into place once complete. Progress is reported as the sum over all items.
+pub async fn compress_per_item<F>(
+    files: &[PathBuf],
+    compression_type: CompressionType,
+    options: &CompressOptions,
+    max_concurrent: usize,
+    cancel: &CancellationToken,
+    progress_callback: F,
+) -> Result<Vec<PerItemResult>, CompressionError>
+where
+    F: FnMut(f64, String, &ProgressCounts) + Send + 'static,
+{
+    if options.split_into.is_some() || options.split_size.is_some() {
+        return Err(anyhow::anyhow!("Per-item archives cannot be split into volumes").into());
+    }
+    // Any format can wrap one input, but a single-stream one only a file
+    if !compression_type.supports_multiple_files() {
+        if let Some(folder) = files.iter().find(|file_path| file_path.is_dir()) {
+            return Err(anyhow::anyhow!(
+                "'{}' is a folder; {} compresses single files, so use an archive format such as .zip or .tar.gz for folders",
+                folder.display(), compression_type.extension()
+            ).into());
+        }
+    }
 
-    matches!(extension, "zip" | "gz" | "br" | "gzip" | "bzip2" | "bz2" | "rar")
+    // Names are picked up front so two inputs with the same stem can't race for one archive.
+    // Like a single archive, an existing file is only replaced when overwriting; an archive
+    // written earlier in this run never is.
+    let mut outputs: Vec<PathBuf> = Vec::with_capacity(files.len());
+    for file_path in files {
+        let output = per_item_output_path(file_path, &compression_type);
+        let output = match options.overwrite && !outputs.contains(&output) {
+            true => output,
+            false => crate::file_utils::get_unique_name_among(&output, &outputs),
+        };
+        outputs.push(output);
+    }
+
+    // Seed every item's totals before any starts so the combined percentage doesn't jump back
+    // as later items begin
+    let filter = PathFilter::new(&options.include, &options.exclude)?
+        .with_ignore_files(files)?
+        .with_symlink_policy(options.symlink_policy)
+        .with_sorted_entries(options.sort_entries);
+    let bases = tar_input_bases(files);
+    let mut initial_counts = Vec::with_capacity(files.len());
+    for (file_path, base) in files.iter().zip(&bases) {
+        initial_counts.push(calculate_total_counts(std::slice::from_ref(file_path), std::slice::from_ref(base), &filter)?);
+    }
+
+    let semaphore = Arc::new(tokio::sync::Semaphore::new(max_concurrent.max(1)));
+    let item_counts = Arc::new(Mutex::new(initial_counts));
+    let progress_callback = Arc::new(Mutex::new(progress_callback));
+    let mut tasks = Vec::with_capacity(files.len());
+
+    for (index, (file_path, output_path)) in files.iter().zip(&outputs).enumerate() {
+        // Waits here until one of the running items finishes
+        let permit = semaphore.clone().acquire_owned().await.map_err(anyhow::Error::from)?;
+        let file_path = file_path.clone();
+        let output_path = output_path.clone();
+        let compression_type = compression_type.clone();
+        // The checksum has to name the final archive, so it is written after the rename
+        let write_checksum = options.write_checksum;
+        // The temporary archive is renamed into place, so it has to be written under its own name
+        let options = CompressOptions { write_checksum: false, overwrite: true, ..options.clone() };
+        let cancel = cancel.clone();
+        let item_counts = item_counts.clone();
+        let progress_callback = progress_callback.clone();
+
+        // Archive writing is blocking I/O, so each item gets a blocking thread of its own
+        tasks.push(tokio::task::spawn_blocking(move || {
+            let _permit = permit;
+            let temp_path = per_item_temp_path(&output_path, index);
+            let files = [file_path];
+            let result = tokio::runtime::Handle::current().block_on(compress_files_with_progress(
+                &files, &temp_path, compression_type, &options, &cancel,
+                |_, current_file, counts| {
+                    let total = {
+                        let mut all_counts = item_counts.lock().unwrap();
+                        all_counts[index] = counts.clone();
+                        all_counts.iter().fold(ProgressCounts::default(), |mut total, item| {
+                            total.files_done += item.files_done;
+                            total.files_total += item.files_total;
+                            total.bytes_done += item.bytes_done;
+                            total.bytes_total += item.bytes_total;
+                            total.mode = item.mode;
+                            total
+                        })
+                    };
+                    (progress_callback.lock().unwrap())(total.percent(), current_file, &total);
+                },
+            ));
+
+            let [file_path] = files;
+            let result = result
+                .and_then(|_| Ok(std::fs::rename(&temp_path, &output_path)?))
+                .and_then(|()| match write_checksum {
+                    true => write_checksum_sidecar(&output_path).map(|_| ()),
+                    false => Ok(()),
+                })
+                .map(|()| output_path);
+            if result.is_err() {
+                let _ = std::fs::remove_file(&temp_path);
+            }
+            PerItemResult { input: file_path, result }
+        }));
+    }
+
+    let mut results = Vec::with_capacity(tasks.len());
+    for task in tasks {
+        results.push(task.await.map_err(anyhow::Error::from)?);
+    }
+    Ok(results)
+}
+
+// "photos/" -> "photos.zip", "notes.txt" -> "notes.zip", next to the input. Single-stream
+// formats keep the whole name ("notes.txt" -> "notes.txt.gz"), as only .gz records it inside.
+fn per_item_output_path(file_path: &Path, compression_type: &CompressionType) -> PathBuf {
+    let name = if file_path.is_dir() || !compression_type.supports_multiple_files() {
+        file_path.file_name()
+    } else {
+        file_path.file_stem()
+    };
+    let name = name.unwrap_or_default().to_string_lossy();
+    let parent = file_path.parent().unwrap_or_else(|| Path::new("."));
+    parent.join(format!("{}{}", name, compression_type.extension()))
+}
+
+// "notes.zip" -> "notes.zip.1234-0.tmp"; the process id and item index keep concurrent
+// tasks (and concurrent tauzip processes) from sharing a temporary file
+fn per_item_temp_path(output_path: &Path, index: usize) -> PathBuf {
+    let mut name = output_path.as_os_str().to_os_string();
+    name.push(format!(".{}-{}.tmp", std::process::id(), index));
+    PathBuf::from(name)
+}
+
+const ZIP_EOCD_SIGNATURE: u32 = 0x0605_4b50;
+const ZIP64_EOCD_SIGNATURE: u32 = 0x0606_4b50;
+const ZIP64_EOCD_LOCATOR_SIGNATURE: u32 = 0x0706_4b50;
+const ZIP_EOCD_SIZE: u64 = 22;
+const ZIP64_EOCD_LOCATOR_SIZE: u64 = 20;
+const ZIP64_EOCD_SIZE: u64 = 56;
+
+fn le_u16(bytes: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([bytes[at], bytes[at + 1]])
+}
+
+fn le_u32(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+}
+
+fn le_u64(bytes: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap())
+}
+
+fn read_at(file: &mut File, offset: u64, len: u64) -> Result<Vec<u8>> {
+    use std::io::{Seek, SeekFrom};
+
+    let mut buffer = vec![0u8; len as usize];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut buffer)?;
+    Ok(buffer)
+}
+
+// Offset and contents of a zip's end of central directory record. It is the last thing in the
+// file, followed only by a comment of up to 64 KiB whose length it records. None when the file
+// doesn't end with one, e.g. because it is cut short.
+fn find_zip_end_record(file: &mut File) -> Result<Option<(u64, Vec<u8>)>> {
+    let len = file.metadata()?.len();
+    if len < ZIP_EOCD_SIZE {
+        return Ok(None);
+    }
+    let search = len.min(ZIP_EOCD_SIZE + u16::MAX as u64);
+    let tail = read_at(file, len - search, search)?;
+
+    for start in (0..=tail.len() - ZIP_EOCD_SIZE as usize).rev() {
+        if le_u32(&tail, start) == ZIP_EOCD_SIGNATURE
+            && start + ZIP_EOCD_SIZE as usize + le_u16(&tail, start + 20) as usize == tail.len()
+        {
+            return Ok(Some((len - search + start as u64, tail[start..start + ZIP_EOCD_SIZE as usize].to_vec())));
+        }
+    }
+    Ok(None)
+}
+
+// Where a zip's central directory starts and how long it is, from the end records; the zip64
+// ones when the plain record's fields are saturated
+fn zip_central_directory_range(file: &mut File) -> Result<(u64, u64)> {
+    let (end_offset, end_record) = find_zip_end_record(file)?
+        .ok_or_else(|| anyhow::anyhow!("No zip end of central directory record found"))?;
+    let size = le_u32(&end_record, 12);
+    let offset = le_u32(&end_record, 16);
+    if size != u32::MAX && offset != u32::MAX {
+        return Ok((offset as u64, size as u64));
+    }
+
+    let locator_offset = end_offset.checked_sub(ZIP64_EOCD_LOCATOR_SIZE)
+        .ok_or_else(|| anyhow::anyhow!("Missing zip64 end of central directory locator"))?;
+    let locator = read_at(file, locator_offset, ZIP64_EOCD_LOCATOR_SIZE)?;
+    if le_u32(&locator, 0) != ZIP64_EOCD_LOCATOR_SIGNATURE {
+        return Err(anyhow::anyhow!("Missing zip64 end of central directory locator"));
+    }
+    let zip64_record = read_at(file, le_u64(&locator, 8), ZIP64_EOCD_SIZE)?;
+    if le_u32(&zip64_record, 0) != ZIP64_EOCD_SIGNATURE {
+        return Err(anyhow::anyhow!("Invalid zip64 end of central directory record"));
+    }
+    Ok((le_u64(&zip64_record, 48), le_u64(&zip64_record, 40)))
+}
+
+// "archive.zip" -> "archive.zip.cd"
+pub fn central_directory_sidecar_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(".cd");
+    PathBuf::from(name)
+}
+
+// Copy everything from the start of a zip's central directory to the end of the file (the
+// directory, any zip64 end records, the end record and the comment) into `<archive>.cd`. A
+// client that range-reads the archive can fetch this up front instead of probing for the end
+// record, and the offsets it holds point straight into the archive.
+//
+// Our zips are written to a seekable file, so each local header already carries the entry's
+// sizes and CRC and the data needs no descriptors after it: a streaming reader can also take
+// the entries in order from the start without the central directory. The zip crate can't
+// write descriptor-based archives, which only matter when the output can't be seeked.
+pub fn write_central_directory_sidecar(archive_path: &Path) -> Result<PathBuf, CompressionError> {
+    let mut file = File::open(archive_path)?;
+    let (offset, _) = zip_central_directory_range(&mut file)?;
+    let len = file.metadata()?.len();
+    if offset > len {
+        return Err(anyhow::anyhow!("The central directory offset points past the end of {}", archive_path.display()).into());
+    }
+    let tail = read_at(&mut file, offset, len - offset)?;
+
+    let sidecar = central_directory_sidecar_path(archive_path);
+    std::fs::write(&sidecar, tail)?;
+    Ok(sidecar)
+}
+
+// "archive.zip" -> "archive.zip.sha256"
+pub fn checksum_sidecar_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(".sha256");
+    PathBuf::from(name)
+}
+
+// Hash the finished archive and write `<archive>.sha256` in the `<hex>  <filename>` form that
+// `sha256sum -c` reads, naming the archive without its directory so the pair can be moved
+pub fn write_checksum_sidecar(archive_path: &Path) -> Result<PathBuf, CompressionError> {
+    let hash = crate::manifest::sha256_file(archive_path)?;
+    let name = archive_path.file_name().unwrap_or_default().to_string_lossy();
+    let sidecar = checksum_sidecar_path(archive_path);
+    std::fs::write(&sidecar, format!("{}  {}\n", hash, name))?;
+    Ok(sidecar)
+}
+
+// Recompute the archive's SHA-256 and compare it with `<archive>.sha256`. The sidecar may
+// come from other tools, so the binary-mode "*name" marker and uppercase hex are accepted.
+pub fn verify_checksum(archive: &Path) -> Result<bool, CompressionError> {
+    let sidecar = checksum_sidecar_path(archive);
+    let content = std::fs::read_to_string(&sidecar)
+        .with_context(|| format!("Failed to read {}", sidecar.display()))?;
+    let expected = content.split_whitespace().next()
+        .filter(|hash| hash.len() == 64 && hash.chars().all(|c| c.is_ascii_hexdigit()))
+        .ok_or_else(|| anyhow::anyhow!("{} does not hold a SHA-256 checksum", sidecar.display()))?;
+    Ok(crate::manifest::sha256_file(archive)?.eq_ignore_ascii_case(expected))
+}
+
+// "archive.zip" -> "archive.zip.001" for index 1
+pub fn volume_path(path: &Path, index: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(format!(".{:03}", index));
+    PathBuf::from(name)
+}
+
+// Split a finished file into exactly `parts` volumes whose sizes differ by at most one byte.
+// Returns each volume's path and actual size.
+pub fn split_into_volumes(path: &Path, parts: usize) -> Result<Vec<(PathBuf, u64)>, CompressionError> {
+    let total_size = std::fs::metadata(path)?.len();
+    if parts == 0 {
+        return Err(anyhow::anyhow!("The number of volumes must be at least 1").into());
+    }
+    if parts as u64 > total_size {
+        return Err(anyhow::anyhow!(
+            "'{}' is only {} bytes and cannot be split into {} volumes",
+            path.display(), total_size, parts
+        ).into());
+    }
+
+    // The first `total_size % parts` volumes take one extra byte
+    let base_size = total_size / parts as u64;
+    let larger_volumes = total_size % parts as u64;
+    let limits: Vec<u64> = (1..=parts as u64).map(|index| base_size + u64::from(index <= larger_volumes)).collect();
+    write_volumes(path, &limits)
+}
+
+// Split a finished file into volumes of `volume_size` bytes; the last holds the remainder, and
+// a file no larger than one volume becomes a single .001
+pub fn split_by_size(path: &Path, volume_size: u64) -> Result<Vec<(PathBuf, u64)>, CompressionError> {
+    let total_size = std::fs::metadata(path)?.len();
+    if volume_size == 0 {
+        return Err(anyhow::anyhow!("The volume size must be at least 1 byte").into());
+    }
+
+    let full_volumes = total_size / volume_size;
+    let mut limits = vec![volume_size; full_volumes as usize];
+    if total_size % volume_size > 0 || limits.is_empty() {
+        limits.push(total_size % volume_size);
+    }
+    write_volumes(path, &limits)
+}
+
+// Copy consecutive byte ranges of `path` into path.001, path.002... and remove the original
+fn write_volumes(path: &Path, limits: &[u64]) -> Result<Vec<(PathBuf, u64)>, CompressionError> {
+    let mut input = BufReader::new(File::open(path)?);
+    let mut volumes = Vec::with_capacity(limits.len());
+    for (index, &limit) in limits.iter().enumerate() {
+        let volume = volume_path(path, index + 1);
+        let mut output = BufWriter::new(File::create(&volume)?);
+        let written = std::io::copy(&mut (&mut input).take(limit), &mut output)?;
+        output.flush()?;
+        volumes.push((volume, written));
+    }
+
+    std::fs::remove_file(path)?;
+    Ok(volumes)
+}
+
+// "backup.zip.001" -> "backup.zip"; None for any other name
+pub fn first_volume_base(path: &Path) -> Option<PathBuf> {
+    let name = path.file_name()?.to_str()?;
+    match name.strip_suffix(".001") {
+        Some(base) if !base.is_empty() => Some(path.with_file_name(base)),
+        _ => None,
+    }
+}
+
+// Reads archive.001, archive.002... as one continuous file, so volumes written by split_by_size
+// or split_into_volumes decode without being joined on disk first. Any path that isn't a first
+// volume reads as just itself. Seeking is supported for zip and 7z, which read from the end.
+pub struct VolumeReader {
+    volumes: Vec<File>,
+    // Offset of each volume's first byte within the joined archive
+    starts: Vec<u64>,
+    total_size: u64,
+    position: u64,
+    current: usize,
+}
+
+impl VolumeReader {
+    pub fn open(path: &Path) -> std::io::Result<Self> {
+        let paths = match first_volume_base(path) {
+            // The set ends at the first missing number
+            Some(base) => (1..).map(|index| volume_path(&base, index)).take_while(|volume| volume.exists()).collect(),
+            None => vec![path.to_path_buf()],
+        };
+
+        let mut volumes = Vec::with_capacity(paths.len());
+        let mut starts = Vec::with_capacity(paths.len());
+        let mut total_size = 0;
+        for volume in paths {
+            let file = File::open(&volume)?;
+            starts.push(total_size);
+            total_size += file.metadata()?.len();
+            volumes.push(file);
+        }
+        Ok(Self { volumes, starts, total_size, position: 0, current: 0 })
+    }
+
+    pub fn len(&self) -> u64 {
+        self.total_size
+    }
+}
+
+impl Read for VolumeReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.current < self.volumes.len() {
+            let read = self.volumes[self.current].read(buf)?;
+            if read > 0 || buf.is_empty() {
+                self.position += read as u64;
+                return Ok(read);
+            }
+            // This volume is used up; the next one is read from its start
+            self.current += 1;
+            if let Some(next) = self.volumes.get_mut(self.current) {
+                std::io::Seek::seek(next, std::io::SeekFrom::Start(0))?;
+            }
+        }
+        Ok(0)
+    }
+}
+
+impl std::io::Seek for VolumeReader {
+    fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
+        let target = match pos {
+            std::io::SeekFrom::Start(offset) => Some(offset),
+            std::io::SeekFrom::End(delta) => self.total_size.checked_add_signed(delta),
+            std::io::SeekFrom::Current(delta) => self.position.checked_add_signed(delta),
+        }.ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Seek before the start of the archive"))?;
+
+        // The last volume whose start is at or before the target; past the end reads nothing
+        self.current = self.starts.partition_point(|&start| start <= target).saturating_sub(1);
+        if let Some(volume) = self.volumes.get_mut(self.current) {
+            volume.seek(std::io::SeekFrom::Start(target - self.starts[self.current]))?;
+        }
+        self.position = target;
+        Ok(target)
+    }
+}
+
+// True when the output already exists as a FIFO, socket or device. Such targets are written
+// in place like a stream and must never be removed on cancel.
+fn is_special_output(path: &Path) -> bool {
+    std::fs::metadata(path)
+        .map(|metadata| !metadata.is_file() && !metadata.is_dir())
+        .unwrap_or(false)
+}
+
+async fn compress_zip_with_progress<F>(files: &[PathBuf], output_path: &Path, compress_options: &CompressOptions, filter: &PathFilter, cancel: &CancellationToken, mut progress_callback: F) -> Result<()>
+where
+    F: FnMut(f64, String, &ProgressCounts),
+{
+    let file = File::create(output_path)
+        .with_context(|| format!("Failed to create output file: {}", output_path.display()))?;
+    
+    let mut zip = ZipWriter::new(BufWriter::new(file));
+
+    // Calculate the common base directory for all files
+    let base_dir = if compress_options.preserve_paths {
+        // Keep each input's path below the chosen root
+        compress_options.root.clone().unwrap_or_else(|| {
+            files[0].ancestors().last().unwrap_or_else(|| Path::new(".")).to_path_buf()
+        })
+    } else if files.len() == 1 {
+        // For a single file, use its parent directory
+        files[0].parent().unwrap_or_else(|| Path::new(".")).to_path_buf()
+    } else {
+        // For multiple files, find the common parent directory
+        find_common_base_dir(files).unwrap_or_else(|| PathBuf::from("."))
+    };
+
+    tracing::debug!(base = %base_dir.display(), "Using base directory");
+
+    // Each input may have its own base when a depth is requested
+    let input_bases: Vec<PathBuf> = files.iter()
+        .map(|file_path| match compress_options.input_base_depth {
+            Some(depth) => input_base_dir(file_path, depth),
+            None => base_dir.clone(),
+        })
+        .collect();
+
+    // Count files and bytes up front for progress tracking
+    let mut counts = calculate_total_counts(files, &input_bases, filter)?;
+    counts.mode = compress_options.progress_mode;
+
+    // Past 4 GiB of input or 65535 entries the 32-bit zip fields overflow, so every entry gets
+    // zip64 extra fields. Smaller archives leave them out for older unzip tools.
+    let needs_zip64 = counts.bytes_total >= u32::MAX as u64 || counts.files_total > u16::MAX as usize;
+    if needs_zip64 {
+        tracing::info!(bytes = counts.bytes_total, files = counts.files_total, "Writing a zip64 archive");
+    }
+    // Stored entries take no level at all
+    let level = match compress_options.zip_method {
+        ZipMethod::Stored => None,
+        ZipMethod::Deflated | ZipMethod::Bzip2 => compress_options.preset.level(&CompressionType::Zip),
+    };
+    let options = ZipEntryOptions {
+        base: FileOptions::default()
+            .compression_method(compress_options.zip_method.compression_method())
+            .compression_level(level.map(|level| level as i32))
+            .unix_permissions(0o755)
+            .large_file(needs_zip64),
+        auto_store: compress_options.auto_store,
+        precise_timestamps: compress_options.precise_timestamps,
+    };
+
+    // raw_copy_file drops extra fields, so precise timestamps need the sequential writer
+    if compress_options.parallel && compress_options.precise_timestamps {
+        tracing::warn!("Precise timestamps are written sequentially; ignoring --parallel");
+    }
+    // Duplicates are found in the order entries are written, which the parallel writer doesn't keep
+    if compress_options.parallel && compress_options.dedup {
+        tracing::warn!("Deduplicated archives are written sequentially; ignoring --parallel");
+    }
+    let mut dedup = compress_options.dedup.then(ZipDedup::default);
+    if compress_options.parallel && !compress_options.precise_timestamps && dedup.is_none() {
+        let level = level.unwrap_or(DEFAULT_COMPRESSION_LEVEL);
+        add_to_zip_parallel(&mut zip, files, &input_bases, &options, level, filter, &mut counts, &mut progress_callback, cancel)?;
+    } else {
+        for (file_path, input_base) in files.iter().zip(&input_bases) {
+            check_cancelled(cancel)?;
+            let current_filename = file_path.file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string();
+            
+            tracing::debug!(path = %file_path.display(), "Adding input");
+            
+            // Update progress before processing each file
+            progress_callback(counts.percent(), current_filename.clone(), &counts);
+            
+            add_to_zip_with_progress(&mut zip, file_path, input_base, &options, dedup.as_mut(), filter, &mut counts, &mut progress_callback, cancel).await?;
+        }
+    }
+
+    if let Some(dedup) = dedup.filter(|dedup| !dedup.manifest.aliases.is_empty()) {
+        tracing::info!(duplicates = dedup.manifest.aliases.len(), "Stored duplicate files as references");
+        zip.start_file(DEDUP_MANIFEST_NAME, options.base.compression_method(zip::CompressionMethod::Deflated))?;
+        serde_json::to_writer_pretty(&mut zip, &dedup.manifest)?;
+    }
+    if compress_options.manifest {
+        let manifest = archive_manifest(files, &input_bases, filter)?;
+        zip.start_file(crate::manifest::MANIFEST_FILE_NAME, options.base.compression_method(zip::CompressionMethod::Deflated))?;
+        serde_json::to_writer_pretty(&mut zip, &manifest)?;
+    }
+
+    progress_callback(100.0, "Complete".to_string(), &counts);
+    zip.finish()?;
+    Ok(())
+}
+
+// The leading part of `path` made of its first `depth` normal components. The depth is clamped
+// so the input's own name is always kept in the stored path.
+fn input_base_dir(path: &Path, depth: usize) -> PathBuf {
+    let normal_components = path.components()
+        .filter(|c| matches!(c, std::path::Component::Normal(_)))
+        .count();
+    let depth = depth.min(normal_components.saturating_sub(1));
+
+    let mut base = PathBuf::new();
+    let mut dropped = 0;
+    for component in path.components() {
+        if let std::path::Component::Normal(_) = component {
+            if dropped == depth {
+                break;
+            }
+            dropped += 1;
+        }
+        base.push(component);
+    }
+    base
+}
+
+fn single_file_counts(file_path: &Path) -> ProgressCounts {
+    ProgressCounts {
+        files_total: 1,
+        bytes_total: std::fs::metadata(file_path).map(|m| m.len()).unwrap_or(0),
+        ..Default::default()
+    }
+}
+
+// `bases` holds the directory each input's stored paths are relative to
+fn calculate_total_counts(files: &[PathBuf], bases: &[PathBuf], filter: &PathFilter) -> Result<ProgressCounts> {
+    let mut counts = ProgressCounts::default();
+    for (file_path, base) in files.iter().zip(bases) {
+        walk_input(file_path, base, filter, &mut |entry_path, kind| {
+            match kind {
+                WalkEntry::File => {
+                    counts.files_total += 1;
+                    counts.bytes_total += std::fs::metadata(entry_path)?.len();
+                },
+                WalkEntry::Symlink(_) => counts.files_total += 1,
+                WalkEntry::Dir => {},
+            }
+            Ok(())
+        })?;
+    }
+    Ok(counts)
+}
+
+// The path an entry is stored under: relative to `base`, or just its name outside of it
+fn stored_path<'a>(path: &'a Path, base: &Path) -> &'a Path {
+    path.strip_prefix(base)
+        .unwrap_or_else(|_| Path::new(path.file_name().unwrap_or_default()))
+}
+
+#[derive(Debug, Clone)]
+enum WalkEntry {
+    File,
+    Dir,
+    // A link kept as-is under SymlinkPolicy::Store, with its target
+    Symlink(PathBuf),
+}
+
+// Visit `path` and everything below it that `filter` allows, each directory before its
+// contents. Every archive writer and the up-front counting share this walk so they agree on
+// which entries exist. With shared walks the disk is only read the first time for each input
+// and base; later calls replay what that walk found.
+fn walk_input<F>(path: &Path, base: &Path, filter: &PathFilter, visit: &mut F) -> Result<()>
+where
+    F: FnMut(&Path, WalkEntry) -> Result<()>,
+{
+    let Some(walks) = &filter.walks else {
+        return walk_path(path, base, filter, &mut HashSet::new(), visit);
+    };
+
+    let key = (path.to_path_buf(), base.to_path_buf());
+    let walked = walks.0.lock().unwrap().get(&key).cloned();
+    let entries = match walked {
+        Some(entries) => entries,
+        None => {
+            let mut entries = Vec::new();
+            walk_path(path, base, filter, &mut HashSet::new(), &mut |entry_path, kind| {
+                entries.push((entry_path.to_path_buf(), kind));
+                Ok(())
+            })?;
+            let entries = Arc::new(entries);
+            walks.0.lock().unwrap().insert(key, entries.clone());
+            entries
+        },
+    };
+    for (entry_path, kind) in entries.iter() {
+        visit(entry_path, kind.clone())?;
+    }
+    Ok(())
+}
+
+// Identifies a directory however it was reached: device and inode on unix, the canonical
+// path elsewhere
+#[cfg(unix)]
+type DirId = (u64, u64);
+#[cfg(not(unix))]
+type DirId = PathBuf;
+
+fn dir_id(path: &Path) -> Result<DirId> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let metadata = std::fs::metadata(path)?;
+        Ok((metadata.dev(), metadata.ino()))
+    }
+    #[cfg(not(unix))]
+    Ok(path.canonicalize()?)
+}
+
+// `visited` holds every directory walked so far. Followed symlinks can reach a directory
+// again, either from inside it (a cycle) or from a sibling link; its contents are only
+// stored the first time.
+fn walk_path<F>(path: &Path, base: &Path, filter: &PathFilter, visited: &mut HashSet<DirId>, visit: &mut F) -> Result<()>
+where
+    F: FnMut(&Path, WalkEntry) -> Result<()>,
+{
+    if !filter.allows(path, base) {
+        tracing::debug!(path = %path.display(), "Skipping filtered path");
+        return Ok(());
+    }
+
+    let is_symlink = std::fs::symlink_metadata(path)
+        .map(|metadata| metadata.file_type().is_symlink())
+        .unwrap_or(false);
+    if is_symlink {
+        match filter.symlinks {
+            SymlinkPolicy::Skip => {
+                tracing::debug!(path = %path.display(), "Skipping symlink");
+                return Ok(());
+            },
+            SymlinkPolicy::Store => return visit(path, WalkEntry::Symlink(std::fs::read_link(path)?)),
+            SymlinkPolicy::Follow if !path.exists() => {
+                tracing::warn!(path = %path.display(), "Skipping broken symlink");
+                return Ok(());
+            },
+            SymlinkPolicy::Follow => {},
+        }
+    }
+
+    if path.is_file() {
+        visit(path, WalkEntry::File)?;
+    } else if path.is_dir() {
+        if !visited.insert(dir_id(path)?) {
+            tracing::warn!(path = %path.display(), "Skipping directory already reached through another path");
+            return Ok(());
+        }
+        visit(path, WalkEntry::Dir)?;
+
+        let mut children = std::fs::read_dir(path)?.collect::<std::io::Result<Vec<_>>>()?;
+        if filter.sorted {
+            children.sort_by_key(|entry| entry.file_name());
+        }
+        for entry in children {
+            if is_reparse_point(&entry.path()) && !entry.file_type()?.is_symlink() {
+                tracing::debug!(path = %entry.path().display(), "Skipping reparse point");
+                continue;
+            }
+            walk_path(&entry.path(), base, filter, visited, visit)?;
+        }
+    }
+    Ok(())
+}
+
+// Inputs in path order; Path compares component by component, so "a/b" sorts with "a" and
+// before "a.txt", matching the order the sorted walk gives a directory's contents
+pub fn sorted_inputs(files: &[PathBuf]) -> Vec<PathBuf> {
+    let mut sorted = files.to_vec();
+    sorted.sort();
+    sorted
+}
+
+// Tar entries are stored under each input's own name, so filters match relative to its parent
+fn tar_input_bases(files: &[PathBuf]) -> Vec<PathBuf> {
+    files.iter()
+        .map(|file_path| file_path.parent().unwrap_or_else(|| Path::new("")).to_path_buf())
+        .collect()
+}
+
+// Symlinks and junctions go through the SymlinkPolicy like links elsewhere; any other nested
+// reparse point (e.g. a cloud placeholder) is skipped while walking instead of being read.
+#[cfg(windows)]
+fn is_reparse_point(path: &Path) -> bool {
+    use std::os::windows::fs::MetadataExt;
+    const FILE_ATTRIBUTE_REPARSE_POINT: u32 = 0x400;
+
+    std::fs::symlink_metadata(path)
+        .map(|metadata| metadata.file_attributes() & FILE_ATTRIBUTE_REPARSE_POINT != 0)
+        .unwrap_or(false)
+}
+
+#[cfg(not(windows))]
+fn is_reparse_point(_path: &Path) -> bool {
+    false
+}
+
+fn find_common_base_dir(files: &[PathBuf]) -> Option<PathBuf> {
+    if files.is_empty() {
+        return None;
+    }
+
+    // Start with the first file's parent directory
+    let mut common_base = files[0].parent()?.to_path_buf();
+
+    // Find the common ancestor of all file paths
+    for file in &files[1..] {
+        if let Some(file_parent) = file.parent() {
+            // Find the common path between current common_base and this file's parent
+            if let Some(new_common) = find_common_path(&common_base, file_parent) {
+                common_base = new_common;
+            } else {
+                // No common path found, use current directory
+                return Some(PathBuf::from("."));
+            }
+        }
+    }
+
+    Some(common_base)
+}
+
+fn find_common_path(path1: &Path, path2: &Path) -> Option<PathBuf> {
+    let components1: Vec<_> = path1.components().collect();
+    let components2: Vec<_> = path2.components().collect();
+    
+    let mut common_components = Vec::new();
+    
+    for (comp1, comp2) in components1.iter().zip(components2.iter()) {
+        if comp1 == comp2 {
+            common_components.push(comp1.as_os_str());
+        } else {
+            break;
+        }
+    }
+    
+    if common_components.is_empty() {
+        None
+    } else {
+        // Reconstruct the path from components
+        let mut result = PathBuf::new();
+        for component in common_components {
+            result.push(component);
+        }
+        Some(result)
+    }
+}
+
+fn add_to_zip_sync_with_progress<F>(
+    zip: &mut ZipWriter<BufWriter<File>>,
+    file_path: &Path,
+    base_dir: &Path,
+    options: &ZipEntryOptions,
+    mut dedup: Option<&mut ZipDedup>,
+    filter: &PathFilter,
+    counts: &mut ProgressCounts,
+    progress_callback: &mut F,
+    cancel: &CancellationToken,
+) -> Result<()>
+where
+    F: FnMut(f64, String, &ProgressCounts),
+{
+    walk_input(file_path, base_dir, filter, &mut |entry_path, kind| {
+        check_cancelled(cancel)?;
+        if let WalkEntry::Dir = kind {
+            return Ok(());
+        }
+
+        let relative_path = stored_path(entry_path, base_dir);
+        let current_filename = entry_path.file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+
+        tracing::debug!(entry = %relative_path.display(), "Adding file");
+        
+        // Ensure we use forward slashes for zip paths (cross-platform compatibility)
+        let zip_path = relative_path.to_string_lossy().replace('\\', "/");
+        
+        let link_target = match kind {
+            WalkEntry::Symlink(target) => Some(target.to_string_lossy().replace('\\', "/")),
+            _ => None,
+        };
+        let stored_as = match (&link_target, dedup.as_deref_mut()) {
+            (None, Some(dedup)) => dedup.find_or_record(entry_path, &zip_path)?,
+            _ => None,
+        };
+        counts.bytes_done += match stored_as {
+            Some(original) => {
+                tracing::debug!(entry = %zip_path, original = %original, "Storing a reference to identical contents");
+                start_zip_file(zip, &zip_path, entry_path, options)?;
+                std::fs::metadata(entry_path)?.len()
+            },
+            None => write_zip_entry(zip, entry_path, &zip_path, link_target.as_deref(), options, cancel)?,
+        };
+        counts.files_done += 1;
+        
+        // Update progress after processing this file
+        progress_callback(counts.percent(), current_filename, counts);
+        Ok(())
+    })
+}
+
+// Write one walked entry: a symlink as a link, anything else with its file contents.
+// Returns the number of content bytes read.
+fn write_zip_entry<W: Write + std::io::Seek>(
+    zip: &mut ZipWriter<W>,
+    path: &Path,
+    zip_path: &str,
+    link_target: Option<&str>,
+    options: &ZipEntryOptions,
+    cancel: &CancellationToken,
+) -> Result<u64> {
+    if let Some(target) = link_target {
+        zip.add_symlink(zip_path, target, options.base)?;
+        return Ok(0);
+    }
+
+    start_zip_file(zip, zip_path, path, options)?;
+    let file = File::open(path)?;
+    let declared = file.metadata()?.len();
+    let mut reader = ProgressReader::new(file, 0, String::new(), cancel.clone(), |_, _| {});
+    let read = std::io::copy(&mut reader, zip)?;
+    warn_if_changed(path, declared, read);
+    Ok(read)
+}
+
+// Zip entries record the sizes of the bytes actually written, so a file that changed while it
+// was read (e.g. a live log) still makes a valid entry; it just doesn't match any one version
+// of the file
+fn warn_if_changed(path: &Path, declared: u64, read: u64) {
+    if read != declared {
+        tracing::warn!(path = %path.display(), declared, stored = read, "File changed while it was read");
+    }
+}
+
+// Written last in a deduplicated zip; maps each empty stand-in entry to the entry holding its
+// contents
+pub const DEDUP_MANIFEST_NAME: &str = ".tauzip-dedup.json";
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct DedupManifest {
+    aliases: BTreeMap<String, String>,
+}
+
+// Zip has no way for two entries to share data, so a repeated file is stored as an empty entry
+// and listed in DEDUP_MANIFEST_NAME; extraction here copies the original back in its place.
+// Other unzip tools leave those files empty and extract the manifest as a plain file. Files
+// are only hashed once another of the same size turns up, so inputs without repeats are not
+// read twice.
+#[derive(Default)]
+struct ZipDedup {
+    by_size: HashMap<u64, Vec<StoredContent>>,
+    manifest: DedupManifest,
+}
+
+struct StoredContent {
+    path: PathBuf,
+    zip_path: String,
+    sha256: Option<String>,
+}
+
+impl ZipDedup {
+    // The entry already holding the contents of `path`, which is then recorded as its alias.
+    // Otherwise None, and `path` is remembered as stored under `zip_path`.
+    fn find_or_record(&mut self, path: &Path, zip_path: &str) -> Result<Option<String>> {
+        let size = std::fs::metadata(path)?.len();
+        // An empty entry costs the same as a reference
+        if size == 0 {
+            return Ok(None);
+        }
+        let stored = self.by_size.entry(size).or_default();
+        let mut sha256 = None;
+        if !stored.is_empty() {
+            let hash = crate::manifest::sha256_file(path)?;
+            for earlier in stored.iter_mut() {
+                if earlier.sha256.is_none() {
+                    earlier.sha256 = Some(crate::manifest::sha256_file(&earlier.path)?);
+                }
+                if earlier.sha256.as_deref() == Some(hash.as_str()) {
+                    self.manifest.aliases.insert(zip_path.to_string(), earlier.zip_path.clone());
+                    return Ok(Some(earlier.zip_path.clone()));
+                }
+            }
+            sha256 = Some(hash);
+        }
+        stored.push(StoredContent { path: path.to_path_buf(), zip_path: zip_path.to_string(), sha256 });
+        Ok(None)
+    }
+}
+
+// The aliases of a zip written with dedup, or None when it has no DEDUP_MANIFEST_NAME entry
+fn read_dedup_manifest<R: Read + std::io::Seek>(archive: &mut zip::ZipArchive<R>) -> Result<Option<BTreeMap<String, String>>> {
+    let entry = match archive.by_name(DEDUP_MANIFEST_NAME) {
+        Ok(entry) => entry,
+        Err(zip::result::ZipError::FileNotFound) => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let manifest: DedupManifest = serde_json::from_reader(entry)
+        .with_context(|| format!("Invalid {} in the archive", DEDUP_MANIFEST_NAME))?;
+    Ok(Some(manifest.aliases))
+}
+
+async fn add_to_zip_with_progress<F>(
+    zip: &mut ZipWriter<BufWriter<File>>,
+    file_path: &Path,
+    base_dir: &Path,
+    options: &ZipEntryOptions,
+    dedup: Option<&mut ZipDedup>,
+    filter: &PathFilter,
+    counts: &mut ProgressCounts,
+    progress_callback: &mut F,
+    cancel: &CancellationToken,
+) -> Result<()>
+where
+    F: FnMut(f64, String, &ProgressCounts),
+{
+    add_to_zip_sync_with_progress(zip, file_path, base_dir, options, dedup, filter, counts, progress_callback, cancel)
+}
+
+// Every encoder here runs at its library's default level
+pub const DEFAULT_COMPRESSION_LEVEL: u32 = 6;
+const BROTLI_MAX_QUALITY: u32 = 11;
+const BROTLI_WINDOW_BITS: u32 = 22;
+const BROTLI_MIN_WINDOW_BITS: u32 = 10;
+const BROTLI_MAX_WINDOW_BITS: u32 = 24;
+
+// The brotli quality and window for a run: the explicit options when set, otherwise the
+// preset's `level` and the default window, within the ranges the encoder accepts
+fn brotli_params(options: &CompressOptions, level: u32) -> (u32, u32) {
+    let quality = options.brotli_quality.unwrap_or(level).min(BROTLI_MAX_QUALITY);
+    let window = options.brotli_window.unwrap_or(BROTLI_WINDOW_BITS).clamp(BROTLI_MIN_WINDOW_BITS, BROTLI_MAX_WINDOW_BITS);
+    (quality, window)
+}
+// Finished entries each parallel zip worker may hold in memory before they are written out
+const PARALLEL_ENTRIES_PER_WORKER: usize = 4;
+// Files above this are streamed straight into the archive instead of being buffered by a worker
+const PARALLEL_MAX_ENTRY_BYTES: u64 = 64 * 1024 * 1024;
+// The BufReader/BufWriter pair around every stream
+const STREAM_BUFFER_BYTES: u64 = 2 * 8 * 1024;
+
+// Rough peak memory of a compression run with `threads` encoders working at once, so callers
+// can warn or cut parallelism before running out of memory. Per encoder:
+//   deflate (zip, gz, tar.gz): zlib's (1 << (windowBits + 2)) + (1 << (memLevel + 9)) = 256 KiB
+//   brotli (br, tar.br):       the 2^lgwin ring buffer, hash tables of 4 * 2^lgwin from quality 5
+//                              (4 * 2^16 below) and 16 bytes per window byte for the
+//                              Zopfli search at quality 10 and 11
+//   bzip2:                     400 KB + 8 * block size, the block being level * 100 KB
+//   tar:                       nothing beyond the stream buffers
+// With more than one zip worker the finished entries are also buffered (see
+// add_to_zip_parallel), which is counted as the largest bufferable inputs at their full size.
+pub fn estimate_memory(files: &[PathBuf], compression_type: &CompressionType, level: u32, threads: usize) -> u64 {
+    estimate_memory_for_sizes(&largest_first(input_file_sizes(files)), compression_type, level, threads)
+}
+
+// Memory the OS can hand out right now; 0 when it can't be determined
+pub fn available_memory() -> u64 {
+    let mut system = sysinfo::System::new();
+    system.refresh_memory();
+    system.available_memory()
+}
+
+fn estimate_memory_for_sizes(sizes_largest_first: &[u64], compression_type: &CompressionType, level: u32, threads: usize) -> u64 {
+    let threads = threads.max(1);
+    let per_encoder = encoder_memory(compression_type, level) + STREAM_BUFFER_BYTES;
+    let mut estimate = per_encoder * threads as u64;
+
+    if matches!(compression_type, CompressionType::Zip) && threads > 1 {
+        estimate += sizes_largest_first.iter()
+            .filter(|&&size| size <= PARALLEL_MAX_ENTRY_BYTES)
+            .take(threads * PARALLEL_ENTRIES_PER_WORKER)
+            .sum::<u64>();
+    }
+    estimate
+}
+
+fn encoder_memory(compression_type: &CompressionType, level: u32) -> u64 {
+    match compression_type {
+        CompressionType::Tar => 0,
+        CompressionType::Zip | CompressionType::TarGz | CompressionType::Gz | CompressionType::Gzip => {
+            (1 << (15 + 2)) + (1 << (8 + 9))
+        },
+        CompressionType::Br | CompressionType::TarBr => {
+            let window = 1u64 << BROTLI_WINDOW_BITS;
+            let hash_tables = if level >= 5 { 4 * window } else { 4 << 16 };
+            let zopfli = if level >= 10 { 16 * window } else { 0 };
+            window + hash_tables + zopfli
+        },
+        CompressionType::Bzip2 => 400_000 + 8 * u64::from(level.clamp(1, 9)) * 100_000,
+    }
+}
+
+// The most encoders, up to `threads`, whose estimate fits in `budget` bytes; never below one
+fn threads_within_memory(sizes_largest_first: &[u64], compression_type: &CompressionType, level: u32, threads: usize, budget: u64) -> usize {
+    (1..=threads.max(1))
+        .rev()
+        .find(|&n| estimate_memory_for_sizes(sizes_largest_first, compression_type, level, n) <= budget)
+        .unwrap_or(1)
+}
+
+fn input_file_sizes(files: &[PathBuf]) -> Vec<u64> {
+    let mut sizes = Vec::new();
+    for file_path in files {
+        let _ = walk_input(file_path, file_path, &PathFilter::default(), &mut |entry_path, kind| {
+            if let WalkEntry::File = kind {
+                sizes.push(std::fs::metadata(entry_path)?.len());
+            }
+            Ok(())
+        });
+    }
+    sizes
+}
+
+fn largest_first(mut sizes: Vec<u64>) -> Vec<u64> {
+    sizes.sort_unstable_by(|a, b| b.cmp(a));
+    sizes
+}
+
+// A level sweep compresses at most this much input per level, so it stays quick on any selection
+const LEVEL_SWEEP_SAMPLE_BYTES: usize = 4 * 1024 * 1024;
+// Taken from the start of each file in turn, so one large file doesn't make up the whole sample
+const LEVEL_SWEEP_CHUNK_BYTES: usize = 256 * 1024;
+
+// One point of level_sweep's size/time curve
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LevelResult {
+    pub level: u32,
+    pub size: u64,     // compressed size of the sample
+    pub seconds: f64,  // time to compress the sample
+    pub sample_size: u64,
+}
+
+// Compress a sample of the inputs at each level the format offers, to show how much smaller a
+// higher level makes the output and what it costs in time. Only the sample is compressed, so the
+// sizes are relative: what matters is how they change from level to level.
+pub fn level_sweep(files: &[PathBuf], compression_type: &CompressionType) -> Result<Vec<LevelResult>, CompressionError> {
+    let levels = compression_levels(compression_type)
+        .ok_or_else(|| anyhow::anyhow!("{} has no compression levels", compression_type.extension()))?;
+    let sample = read_level_sweep_sample(files)?;
+
+    levels.map(|level| {
+        let started = std::time::Instant::now();
+        let size = compressed_sample_size(&sample, compression_type, level)?;
+        Ok(LevelResult {
+            level,
+            size,
+            seconds: started.elapsed().as_secs_f64(),
+            sample_size: sample.len() as u64,
+        })
+    })
+    .collect()
+}
+
+fn compression_levels(compression_type: &CompressionType) -> Option<std::ops::RangeInclusive<u32>> {
+    match compression_type {
+        CompressionType::Tar => None,
+        CompressionType::Zip | CompressionType::TarGz | CompressionType::Gz | CompressionType::Gzip => Some(1..=9),
+        CompressionType::Br | CompressionType::TarBr => Some(0..=11),
+        CompressionType::Bzip2 => Some(1..=9),
+    }
+}
+
+fn read_level_sweep_sample(files: &[PathBuf]) -> Result<Vec<u8>> {
+    let mut sample = Vec::new();
+    for file_path in files {
+        walk_input(file_path, file_path, &PathFilter::default(), &mut |entry_path, kind| {
+            let remaining = LEVEL_SWEEP_SAMPLE_BYTES - sample.len();
+            if let (WalkEntry::File, true) = (kind, remaining > 0) {
+                let limit = remaining.min(LEVEL_SWEEP_CHUNK_BYTES) as u64;
+                File::open(entry_path)?.take(limit).read_to_end(&mut sample)?;
+            }
+            Ok(())
+        })?;
+    }
+    if sample.is_empty() {
+        return Err(anyhow::anyhow!("No file content to sample"));
+    }
+    Ok(sample)
+}
+
+// The same encoders and settings the compressors use, apart from the level
+fn compressed_sample_size(sample: &[u8], compression_type: &CompressionType, level: u32) -> Result<u64> {
+    let compressed = match compression_type {
+        CompressionType::Tar => sample.to_vec(),
+        CompressionType::Zip => {
+            let mut encoder = flate2::write::DeflateEncoder::new(Vec::new(), FlateCompression::new(level));
+            encoder.write_all(sample)?;
+            encoder.finish()?
+        },
+        CompressionType::TarGz | CompressionType::Gz | CompressionType::Gzip => {
+            let mut encoder = GzEncoder::new(Vec::new(), FlateCompression::new(level));
+            encoder.write_all(sample)?;
+            encoder.finish()?
+        },
+        CompressionType::Br | CompressionType::TarBr => {
+            let mut encoder = brotli::CompressorWriter::new(Vec::new(), 4096, level, BROTLI_WINDOW_BITS);
+            encoder.write_all(sample)?;
+            encoder.flush()?;
+            encoder.into_inner()
+        },
+        CompressionType::Bzip2 => {
+            let mut encoder = bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::new(level));
+            encoder.write_all(sample)?;
+            encoder.finish()?
+        },
+    };
+    Ok(compressed.len() as u64)
+}
+
+// Deflate entries concurrently into single-entry in-memory zips, then copy the compressed
+// data into the output sequentially so entry order matches the sequential path. Links and
+// files above PARALLEL_MAX_ENTRY_BYTES are written in place by the sequential writer.
+fn add_to_zip_parallel<F>(
+    zip: &mut ZipWriter<BufWriter<File>>,
+    files: &[PathBuf],
+    input_bases: &[PathBuf],
+    options: &ZipEntryOptions,
+    level: u32,
+    filter: &PathFilter,
+    counts: &mut ProgressCounts,
+    progress_callback: &mut F,
+    cancel: &CancellationToken,
+) -> Result<()>
+where
+    F: FnMut(f64, String, &ProgressCounts),
+{
+    use rayon::prelude::*;
+
+    let mut entries = Vec::new();
+    for (file_path, input_base) in files.iter().zip(input_bases) {
+        collect_zip_entries(file_path, input_base, filter, &mut entries)?;
+    }
+
+    // Use fewer workers when their buffered entries would not fit in a quarter-spared free memory
+    let sizes = largest_first(entries.iter()
+        .filter(|(_, _, link_target)| link_target.is_none())
+        .filter_map(|(file_path, _, _)| std::fs::metadata(file_path).ok())
+        .map(|metadata| metadata.len())
+        .collect());
+    let budget = available_memory() / 4 * 3;
+    let mut threads = rayon::current_num_threads();
+    if budget > 0 {
+        let fitting = threads_within_memory(&sizes, &CompressionType::Zip, level, threads, budget);
+        if fitting < threads {
+            tracing::info!(threads = fitting, "Limiting parallel compression to fit in available memory");
+            threads = fitting;
+        }
+    }
+    let pool = rayon::ThreadPoolBuilder::new().num_threads(threads).build()?;
+
+    // Only a few entries per worker are held in memory at a time
+    let batch_size = threads * PARALLEL_ENTRIES_PER_WORKER;
+    for batch in entries.chunks(batch_size) {
+        check_cancelled(cancel)?;
+        let buffers: Vec<Result<Option<Vec<u8>>>> = pool.install(|| batch
+            .par_iter()
+            .map(|(file_path, zip_path, link_target)| {
+                // Links are written directly below: raw_copy_file keeps only the permission
+                // bits of the mode, which would turn them into regular files
+                if link_target.is_some() || std::fs::metadata(file_path)?.len() > PARALLEL_MAX_ENTRY_BYTES {
+                    return Ok(None);
+                }
+                compress_zip_entry_to_memory(file_path, zip_path, options.for_path(file_path), cancel).map(Some)
+            })
+            .collect());
+
+        for ((file_path, zip_path, link_target), buffer) in batch.iter().zip(buffers) {
+            tracing::debug!(entry = %zip_path, "Adding file");
+            match buffer? {
+                Some(buffer) => {
+                    let mut entry_archive = zip::ZipArchive::new(std::io::Cursor::new(buffer))?;
+                    let entry = entry_archive.by_index_raw(0)?;
+                    counts.bytes_done += entry.size();
+                    zip.raw_copy_file(entry)?;
+                },
+                None => {
+                    counts.bytes_done += write_zip_entry(zip, file_path, zip_path, link_target.as_deref(), options, cancel)?;
+                },
+            }
+            counts.files_done += 1;
+
+            let current_filename = file_path.file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string();
+            progress_callback(counts.percent(), current_filename, counts);
+        }
+    }
+    Ok(())
+}
+
+// Source path, stored zip path and, for a stored symlink, the link target
+type ZipEntrySource = (PathBuf, String, Option<String>);
+
+fn collect_zip_entries(file_path: &Path, base_dir: &Path, filter: &PathFilter, entries: &mut Vec<ZipEntrySource>) -> Result<()> {
+    walk_input(file_path, base_dir, filter, &mut |entry_path, kind| {
+        let zip_path = stored_path(entry_path, base_dir).to_string_lossy().replace('\\', "/");
+        match kind {
+            WalkEntry::File => entries.push((entry_path.to_path_buf(), zip_path, None)),
+            WalkEntry::Symlink(target) => {
+                entries.push((entry_path.to_path_buf(), zip_path, Some(target.to_string_lossy().replace('\\', "/"))));
+            },
+            WalkEntry::Dir => {},
+        }
+        Ok(())
+    })
+}
+
+fn compress_zip_entry_to_memory(file_path: &Path, zip_path: &str, options: FileOptions, cancel: &CancellationToken) -> Result<Vec<u8>> {
+    let mut entry_zip = ZipWriter::new(std::io::Cursor::new(Vec::new()));
+    entry_zip.start_file(zip_path, options)?;
+    let file = File::open(file_path)?;
+    let declared = file.metadata()?.len();
+    let mut reader = ProgressReader::new(file, 0, String::new(), cancel.clone(), |_, _| {});
+    let read = std::io::copy(&mut reader, &mut entry_zip)?;
+    warn_if_changed(file_path, declared, read);
+    Ok(entry_zip.finish()?.into_inner())
+}
+
+async fn compress_tar_with_progress<F>(files: &[PathBuf], output_path: &Path, filter: &PathFilter, manifest: bool, cancel: &CancellationToken, mut progress_callback: F) -> Result<()>
+where
+    F: FnMut(f64, String, &ProgressCounts),
+{
+    let file = File::create(output_path)?;
+    let file = ProgressWriter::new(file, 0, String::new(), cancel.clone(), |_| {});
+    let mut tar = TarBuilder::new(BufWriter::new(file));
+
+    add_files_to_tar_with_progress(&mut tar, files, filter, manifest, cancel, &mut progress_callback).await?;
+    tar.into_inner()?.flush()?;
+    Ok(())
+}
+
+async fn compress_tar_gz_with_progress<F>(files: &[PathBuf], output_path: &Path, level: u32, filter: &PathFilter, manifest: bool, cancel: &CancellationToken, mut progress_callback: F) -> Result<()>
+where
+    F: FnMut(f64, String, &ProgressCounts),
+{
+    let file = File::create(output_path)?;
+    // The writer only watches the cancellation flag; progress is reported per file below
+    let file = ProgressWriter::new(file, 0, String::new(), cancel.clone(), |_| {});
+    let gz_encoder = GzEncoder::new(BufWriter::new(file), FlateCompression::new(level));
+    let mut tar = TarBuilder::new(gz_encoder);
+
+    add_files_to_tar_with_progress(&mut tar, files, filter, manifest, cancel, &mut progress_callback).await?;
+    tar.finish()?;
+    Ok(())
+}
+
+async fn compress_tar_br_with_progress<F>(files: &[PathBuf], output_path: &Path, (quality, window): (u32, u32), filter: &PathFilter, manifest: bool, cancel: &CancellationToken, mut progress_callback: F) -> Result<()>
+where
+    F: FnMut(f64, String, &ProgressCounts),
+{
+    let file = File::create(output_path)?;
+    let file = ProgressWriter::new(file, 0, String::new(), cancel.clone(), |_| {});
+    let br_encoder = brotli::CompressorWriter::new(BufWriter::new(file), 4096, quality, window);
+    let mut tar = TarBuilder::new(br_encoder);
+
+    add_files_to_tar_with_progress(&mut tar, files, filter, manifest, cancel, &mut progress_callback).await?;
+    tar.finish()?;
+    Ok(())
+}
+
+// Key material for age-encrypted tar.gz archives (.tar.gz.age)
+#[derive(Debug, Clone)]
+pub enum AgeKey {
+    // Usable for both encryption and decryption
+    Passphrase(String),
+    // age1... public key, encryption only
+    Recipient(String),
+    // File containing AGE-SECRET-KEY-1... lines, decryption only
+    IdentityFile(PathBuf),
+}
+
+#[cfg(feature = "age-support")]
+pub async fn compress_tar_gz_age_with_progress<F>(
+    files: &[PathBuf],
+    output_path: &Path,
+    key: &AgeKey,
+    level: u32,
+    filter: &PathFilter,
+    cancel: &CancellationToken,
+    mut progress_callback: F,
+) -> Result<(), CompressionError>
+where
+    F: FnMut(f64, String, &ProgressCounts),
+{
+    let encryptor = match key {
+        AgeKey::Passphrase(passphrase) => {
+            age::Encryptor::with_user_passphrase(age::secrecy::SecretString::from(passphrase.clone()))
+        }
+        AgeKey::Recipient(recipient) => {
+            let recipient: age::x25519::Recipient = recipient.parse()
+                .map_err(|e| anyhow::anyhow!("Invalid age recipient '{}': {}", recipient, e))?;
+            age::Encryptor::with_recipients(std::iter::once(&recipient as &dyn age::Recipient))
+                .map_err(anyhow::Error::from)?
+        }
+        AgeKey::IdentityFile(_) => {
+            return Err(anyhow::anyhow!("An age identity file can only be used for decryption").into());
+        }
+    };
+
+    let special_output = is_special_output(output_path);
+    let result = async {
+        let file = File::create(output_path)
+            .with_context(|| format!("Failed to create output file: {}", output_path.display()))?;
+        let file = ProgressWriter::new(file, 0, String::new(), cancel.clone(), |_| {});
+        // tar -> gzip -> age -> file
+        let age_writer = encryptor.wrap_output(BufWriter::new(file))?;
+        let gz_encoder = GzEncoder::new(age_writer, FlateCompression::new(level));
+        let mut tar = TarBuilder::new(gz_encoder);
+
+        add_files_to_tar_with_progress(&mut tar, files, filter, false, cancel, &mut progress_callback).await?;
+        // Each layer must be finished explicitly so the age stream gets its final chunk
+        tar.into_inner()?.finish()?.finish()?.flush()?;
+        Ok::<(), anyhow::Error>(())
+    }.await;
+
+    if result.is_err() && cancel.load(Ordering::SeqCst) {
+        if !special_output {
+            let _ = std::fs::remove_file(output_path);
+        }
+        return Err(CompressionError::Cancelled);
+    }
+    result.map_err(CompressionError::from)
+}
+
+async fn add_files_to_tar_with_progress<W, F>(
+    tar: &mut TarBuilder<W>,
+    files: &[PathBuf],
+    filter: &PathFilter,
+    manifest: bool,
+    cancel: &CancellationToken,
+    progress_callback: &mut F,
+) -> Result<()>
+where
+    W: Write,
+    F: FnMut(f64, String, &ProgressCounts),
+{
+    let bases = tar_input_bases(files);
+    let mut counts = calculate_total_counts(files, &bases, filter)?;
+
+    for (file_path, base) in files.iter().zip(&bases) {
+        check_cancelled(cancel)?;
+        let current_filename = file_path.file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+        
+        progress_callback(counts.percent(), current_filename.clone(), &counts);
+        
+        add_to_tar_with_progress(tar, file_path, base, filter, &mut counts, cancel)?;
+    }
+    if manifest {
+        let content = serde_json::to_vec_pretty(&archive_manifest(files, &bases, filter)?)?;
+        let mut header = tar::Header::new_gnu();
+        header.set_size(content.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0));
+        tar.append_data(&mut header, crate::manifest::MANIFEST_FILE_NAME, content.as_slice())?;
+    }
+
+    progress_callback(100.0, "Complete".to_string(), &counts);
+    Ok(())
+}
+
+// The MANIFEST.json for an archive of `files`, each stored relative to its base: the path, size
+// and SHA-256 of every file, walked the way the writers walk them so it lists the same entries.
+// Links are left out, and so is an input's own MANIFEST.json at the root, which the new one
+// replaces on extraction.
+fn archive_manifest(files: &[PathBuf], bases: &[PathBuf], filter: &PathFilter) -> Result<crate::manifest::Manifest> {
+    let mut manifest = crate::manifest::Manifest::default();
+    for (file_path, base) in files.iter().zip(bases) {
+        walk_input(file_path, base, filter, &mut |entry_path, kind| {
+            let path = stored_path(entry_path, base).to_string_lossy().replace('\\', "/");
+            if matches!(kind, WalkEntry::File) && path != crate::manifest::MANIFEST_FILE_NAME {
+                let size = std::fs::metadata(entry_path)?.len();
+                manifest.files.push(crate::manifest::ManifestEntry { path, size, sha256: crate::manifest::sha256_file(entry_path)? });
+            }
+            Ok(())
+        })?;
+    }
+    Ok(manifest)
+}
+
+// Directories are walked by hand rather than with append_dir_all so filtered entries can be
+// left out; entries are stored relative to `base_dir`, the parent of the selected input
+fn add_to_tar_with_progress<W: Write>(
+    tar: &mut TarBuilder<W>,
+    file_path: &Path,
+    base_dir: &Path,
+    filter: &PathFilter,
+    counts: &mut ProgressCounts,
+    cancel: &CancellationToken,
+) -> Result<()> {
+    walk_input(file_path, base_dir, filter, &mut |entry_path, kind| {
+        check_cancelled(cancel)?;
+        let entry_name = stored_path(entry_path, base_dir);
+
+        match kind {
+            WalkEntry::File => {
+                counts.bytes_done += append_tar_file(tar, entry_path, &entry_name)?;
+                counts.files_done += 1;
+            },
+            WalkEntry::Symlink(target) => {
+                let mut header = tar_header(&std::fs::symlink_metadata(entry_path)?, tar::EntryType::Symlink);
+                tar.append_link(&mut header, entry_name, target)?;
+                counts.files_done += 1;
+            },
+            WalkEntry::Dir => {
+                let mut header = tar_header(&std::fs::metadata(entry_path)?, tar::EntryType::Directory);
+                tar.append_data(&mut header, entry_name, std::io::empty())?;
+            },
+        }
+        Ok(())
+    })
+}
+
+// Add a regular file, storing exactly the size its header declares. Returns that size.
+fn append_tar_file<W: Write>(tar: &mut TarBuilder<W>, path: &Path, name: &Path) -> Result<u64> {
+    let file = File::open(path)?;
+    let metadata = file.metadata()?;
+    let mut header = tar_header(&metadata, tar::EntryType::Regular);
+
+    let mut reader = DeclaredSizeReader::new(file, metadata.len());
+    tar.append_data(&mut header, name, &mut reader)?;
+
+    if reader.grew {
+        tracing::warn!(path = %path.display(), stored = reader.declared, "File grew while it was read; stored only its declared size");
+    } else if reader.read < reader.declared {
+        tracing::warn!(path = %path.display(), read = reader.read, declared = reader.declared, "File shrank while it was read; padded it with zeros");
+    }
+    Ok(metadata.len())
+}
+
+// A header with the entry's modification time, permissions and (on unix) owner filled in
+// explicitly, rather than through Header::set_metadata, whose handling of these differs between
+// platforms. Regular files get their size here; everything else is stored empty.
+fn tar_header(metadata: &std::fs::Metadata, entry_type: tar::EntryType) -> tar::Header {
+    let mut header = tar::Header::new_gnu();
+    header.set_entry_type(entry_type);
+    header.set_size(if entry_type.is_file() { metadata.len() } else { 0 });
+    // The header's time field is unsigned, so times before 1970 are stored as the epoch
+    let mtime = metadata.modified().ok()
+        .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+        .map_or(0, |since_epoch| since_epoch.as_secs());
+    header.set_mtime(mtime);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        header.set_mode(metadata.mode() & 0o7777);
+        header.set_uid(metadata.uid() as u64);
+        header.set_gid(metadata.gid() as u64);
+    }
+    #[cfg(not(unix))]
+    {
+        // Windows only knows the read-only attribute; everything else gets the usual defaults
+        let mode = if entry_type.is_file() { 0o644 } else { 0o755 };
+        header.set_mode(if metadata.permissions().readonly() { mode & !0o222 } else { mode });
+    }
+    header
+}
+
+// Yields exactly `declared` bytes of a file that may be changing: the tar header holding the size
+// is written before the data, and any other byte count would misalign every entry after it. A
+// file that shrank is padded with zeros, one that grew is cut off.
+struct DeclaredSizeReader<R> {
+    inner: R,
+    declared: u64,
+    position: u64, // bytes yielded, padding included
+    read: u64,     // bytes that came from the file
+    exhausted: bool,
+    grew: bool,
+}
+
+impl<R: Read> DeclaredSizeReader<R> {
+    fn new(inner: R, declared: u64) -> Self {
+        Self {
+            inner,
+            declared,
+            position: 0,
+            read: 0,
+            exhausted: false,
+            grew: false,
+        }
+    }
+}
+
+impl<R: Read> Read for DeclaredSizeReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.position >= self.declared {
+            // One byte past the declared size tells whether the file grew
+            if !self.exhausted {
+                self.exhausted = true;
+                self.grew = self.inner.read(&mut [0u8; 1])? > 0;
+            }
+            return Ok(0);
+        }
+
+        let wanted = buf.len().min((self.declared - self.position) as usize);
+        if !self.exhausted {
+            let n = self.inner.read(&mut buf[..wanted])?;
+            if n > 0 {
+                self.position += n as u64;
+                self.read += n as u64;
+                return Ok(n);
+            }
+            self.exhausted = true;
+        }
+
+        buf[..wanted].fill(0);
+        self.position += wanted as u64;
+        Ok(wanted)
+    }
+}
+
+// Progress tracking writer wrapper for compression with filename tracking
+struct ProgressWriter<W, F> {
+    inner: W,
+    progress_callback: F,
+    total_size: u64,
+    bytes_written: u64,
+    filename: String,
+    cancel: CancellationToken,
+}
+
+impl<W: Write, F: FnMut(f64)> ProgressWriter<W, F> {
+    fn new(inner: W, total_size: u64, filename: String, cancel: CancellationToken, progress_callback: F) -> Self {
+        Self {
+            inner,
+            progress_callback,
+            total_size,
+            bytes_written: 0,
+            filename,
+            cancel,
+        }
+    }
+}
+
+impl<W: Write, F: FnMut(f64)> Write for ProgressWriter<W, F> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.cancel.load(Ordering::SeqCst) {
+            return Err(cancelled_io_error());
+        }
+        let bytes = self.inner.write(buf)?;
+        self.bytes_written += bytes as u64;
+        
+        if self.total_size > 0 {
+            let progress = (self.bytes_written as f64 / self.total_size as f64) * 100.0;
+            (self.progress_callback)(progress.min(100.0));
+        }
+        
+        Ok(bytes)
+    }
+    
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+async fn compress_gz_with_progress<F>(file_path: &Path, output_path: &Path, level: u32, cancel: &CancellationToken, progress_callback: F) -> Result<()>
+where
+    F: FnMut(f64),
+{
+    let input = File::open(file_path)?;
+    let output = File::create(output_path)?;
+    let file_size = std::fs::metadata(file_path)?.len();
+    
+    let filename = file_path.file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string();
+    
+    let progress_output = ProgressWriter::new(output, file_size, filename, cancel.clone(), progress_callback);
+    
+    // Create encoder with optional filename in header
+    let mut encoder = match file_path.file_name().and_then(|name| name.to_str()) {
+        Some(filename_str) => {
+            // Store the original filename in the gzip header
+            GzBuilder::new()
+                .filename(filename_str)
+                .write(BufWriter::new(progress_output), FlateCompression::new(level))
+        }
+        None => {
+            // No filename available, create without header filename
+            GzBuilder::new().write(BufWriter::new(progress_output), FlateCompression::new(level))
+        }
+    };
+    
+    let mut reader = BufReader::new(input);
+    std::io::copy(&mut reader, &mut encoder)?;
+    encoder.finish()?;
+    Ok(())
+}
+
+async fn compress_br_with_progress<F>(file_path: &Path, output_path: &Path, quality: u32, window: u32, cancel: &CancellationToken, progress_callback: F) -> Result<()>
+where
+    F: FnMut(f64),
+{
+    let input = File::open(file_path)?;
+    let output = File::create(output_path)?;
+    let file_size = std::fs::metadata(file_path)?.len();
+    
+    let filename = file_path.file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string();
+    
+    let progress_output = ProgressWriter::new(output, file_size, filename, cancel.clone(), progress_callback);
+    let mut encoder = brotli::CompressorWriter::new(BufWriter::new(progress_output), 4096, quality, window);
+    
+    let mut reader = BufReader::new(input);
+    std::io::copy(&mut reader, &mut encoder)?;
+    encoder.flush()?;
+    Ok(())
+}
+
+async fn compress_bzip2_with_progress<F>(file_path: &Path, output_path: &Path, level: u32, cancel: &CancellationToken, progress_callback: F) -> Result<()>
+where
+    F: FnMut(f64),
+{
+    let input = File::open(file_path)?;
+    let output = File::create(output_path)?;
+    let file_size = std::fs::metadata(file_path)?.len();
+    
+    let filename = file_path.file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string();
+    
+    let progress_output = ProgressWriter::new(output, file_size, filename, cancel.clone(), progress_callback);
+    let mut encoder = bzip2::write::BzEncoder::new(BufWriter::new(progress_output), bzip2::Compression::new(level));
+    
+    let mut reader = BufReader::new(input);
+    std::io::copy(&mut reader, &mut encoder)?;
+    encoder.finish()?;
+    Ok(())
+}
+
+// Standard decompression without progress (backward compatibility)
+pub async fn decompress_files(file_path: &Path, output_dir: &Path) -> Result<(), CompressionError> {
+    let cancel = CancellationToken::default();
+    decompress_files_with_progress(file_path, output_dir, &DecompressOptions::default(), &cancel, |_, _| {}).await?;
+    Ok(())
+}
+
+// New decompression function with filename-aware progress callback
+#[tracing::instrument(skip_all, fields(archive = %file_path.display(), output = %output_dir.display()))]
+pub async fn decompress_files_with_progress<F>(
+    file_path: &Path, 
+    output_dir: &Path,
+    options: &DecompressOptions,
+    cancel: &CancellationToken,
+    mut progress_callback: F
+) -> Result<ExtractSummary, CompressionError> 
+where
+    F: FnMut(f64, String),
+{
+    if options.dry_run {
+        return Ok(dry_run_extraction(file_path, output_dir, options, progress_callback)?);
+    }
+
+    check_free_space(file_path, output_dir, options.skip_space_check)?;
+
+    // Only clean up the output directory on cancel if we were the ones to fill it.
+    // generate_output_dir creates it empty up front, so an empty directory counts as ours.
+    let output_existed = holds_anything(output_dir);
+    // Archives already in the output directory weren't written by this extraction
+    let mut visited = HashSet::new();
+    if options.recursive {
+        find_nested_archives(output_dir, &mut visited)?;
+        visited = visited.iter().filter_map(|path| std::fs::canonicalize(path).ok()).collect();
+    }
+    let mut summary = ExtractSummary::default();
+    let limit = OutputLimit::new(options.max_output_bytes);
+    let result = async {
+        decompress_dispatch(file_path, output_dir, options, &limit, &mut summary, cancel, &mut progress_callback).await?;
+        if options.recursive {
+            extract_nested_archives(output_dir, options, &limit, visited, &mut summary, cancel, &mut progress_callback).await?;
+        }
+        Ok::<(), anyhow::Error>(())
+    }.await;
+
+    if result.is_err() && cancel.load(Ordering::SeqCst) {
+        remove_partial_output(output_dir, output_existed, &summary);
+        return Err(CompressionError::Cancelled);
+    }
+    // Whichever decoder hit the limit, the partial output goes the same way as on cancel
+    if let (Err(_), Some(reason)) = (&result, limit.tripped()) {
+        remove_partial_output(output_dir, output_existed, &summary);
+        return Err(CompressionError::DecompressionLimitExceeded(reason));
+    }
+    result.map(|_| summary).map_err(CompressionError::from)
+}
+
+// Take back what a stopped extraction wrote: the whole output directory when it started out
+// empty, otherwise only the files the extraction created and the folders they leave empty.
+// Files it overwrote keep their new contents.
+fn remove_partial_output(output_dir: &Path, output_existed: bool, summary: &ExtractSummary) {
+    if !output_existed {
+        let _ = std::fs::remove_dir_all(output_dir);
+        return;
+    }
+    for path in &summary.created {
+        let _ = std::fs::remove_file(path);
+        // remove_dir only removes empty folders, so this stops at the first one still in use
+        for folder in path.ancestors().skip(1).take_while(|folder| folder.starts_with(output_dir) && *folder != output_dir) {
+            if std::fs::remove_dir(folder).is_err() {
+                break;
+            }
+        }
+    }
+}
+
+// Formats that can be extracted while they are read front to back, e.g. as they download;
+// zip and 7z keep their index at the end
+pub fn is_streamable(path: &Path) -> bool {
+    matches!(archive_extension(path), Some("tar" | "tar.gz" | "tar.br" | "gz" | "br" | "bz2"))
+}
+
+// Extract an archive arriving as a stream instead of from a file. `name` is the archive's file
+// name, which gives its format (one is_streamable accepts) and, for gz, br and bz2, the output
+// name. `total_size` is the stream's length if known, for progress. Nested archives are not
+// extracted. Partial output is removed on cancel and on hitting the output limit, as in
+// decompress_files_with_progress.
+pub fn decompress_stream<R, F>(
+    input: R,
+    name: &str,
+    output_dir: &Path,
+    options: &DecompressOptions,
+    total_size: u64,
+    cancel: &CancellationToken,
+    mut progress_callback: F,
+) -> Result<ExtractSummary, CompressionError>
+where
+    R: Read,
+    F: FnMut(f64, String),
+{
+    let extension = archive_extension(Path::new(name))
+        .filter(|_| is_streamable(Path::new(name)))
+        .ok_or_else(|| anyhow::anyhow!("'{}' can't be extracted as it is read; only tar, tar.gz, tar.br, gz, br and bz2 can", name))?;
+    let output_existed = holds_anything(output_dir);
+    let limit = OutputLimit::new(options.max_output_bytes);
+    let router = EntryRouter::new(&options.routes, output_dir, options.flatten)?;
+    let conflicts = ConflictHandling {
+        policy: options.conflict_policy,
+        backup_on_overwrite: options.backup_on_overwrite,
+    };
+    let mut summary = ExtractSummary::default();
+
+    let result = (|| -> Result<()> {
+        std::fs::create_dir_all(output_dir)?;
+        let reader = BufReader::new(ProgressReader::new(input, total_size, name.to_string(), cancel.clone(), &mut progress_callback));
+        match extension {
+            "gz" | "br" | "bz2" => {
+                let output_name = options.output_name.as_deref().map(std::ffi::OsString::from);
+                let sniff = output_name.is_none();
+                let output_name = output_name.unwrap_or_else(|| fallback_filename_from_compressed(Path::new(name)));
+                write_decoded_output(limit.reader(single_stream_decoder(reader, extension)), output_dir, output_name, sniff, conflicts, &mut summary)
+            },
+            _ => {
+                let decoder: Box<dyn Read + '_> = match extension {
+                    "tar.gz" => Box::new(flate2::read::GzDecoder::new(reader)),
+                    "tar.br" => Box::new(brotli::Decompressor::new(reader, 4096)),
+                    _ => Box::new(reader),
+                };
+                let mut archive = tar::Archive::new(limit.reader(decoder));
+                archive.set_preserve_mtime(true);
+                unpack_tar_with_policy(archive, output_dir, &router, conflicts, &mut summary)
+            },
+        }
+    })();
+
+    if result.is_err() && cancel.load(Ordering::SeqCst) {
+        remove_partial_output(output_dir, output_existed, &summary);
+        return Err(CompressionError::Cancelled);
+    }
+    if let (Err(_), Some(reason)) = (&result, limit.tripped()) {
+        remove_partial_output(output_dir, output_existed, &summary);
+        return Err(CompressionError::DecompressionLimitExceeded(reason));
+    }
+    result?;
+    // Tar stops reading at its end-of-archive blocks, short of the stream's length
+    progress_callback(100.0, name.to_string());
+    Ok(summary)
+}
+
+const DEFAULT_NESTED_DEPTH: usize = 3;
+
+// Recursive extraction, one level at a time: every archive among the files the previous level
+// wrote is extracted into a folder next to it (as generate_output_dir names them) and those
+// folders are scanned for the next level. The nested archives themselves are left in place.
+// Archives are remembered by canonical path so none is extracted twice, and the scan doesn't
+// follow links, so a link cycle can't keep it going. A nested archive that fails to extract
+// (corrupt, encrypted, not really an archive) is reported and skipped; only cancelling stops
+// the whole run. Archives routed outside the output directory are not looked at.
+async fn extract_nested_archives<F>(
+    output_dir: &Path,
+    options: &DecompressOptions,
+    limit: &OutputLimit,
+    mut visited: HashSet<PathBuf>,
+    summary: &mut ExtractSummary,
+    cancel: &CancellationToken,
+    progress_callback: &mut F,
+) -> Result<()>
+where
+    F: FnMut(f64, String),
+{
+    let max_depth = options.max_nested_depth.unwrap_or(DEFAULT_NESTED_DEPTH);
+    // Nested archives are extracted whole into their own folders
+    let nested_options = DecompressOptions {
+        output_name: None,
+        routes: Vec::new(),
+        recursive: false,
+        ..options.clone()
+    };
+    let mut scan_dirs = vec![output_dir.to_path_buf()];
+
+    for depth in 1..=max_depth {
+        let mut found = HashSet::new();
+        for dir in &scan_dirs {
+            find_nested_archives(dir, &mut found)?;
+        }
+        let mut archives: Vec<PathBuf> = found.into_iter()
+            .filter(|archive| std::fs::canonicalize(archive).map(|path| visited.insert(path)).unwrap_or(false))
+            .collect();
+        archives.sort();
+        if archives.is_empty() {
+            return Ok(());
+        }
+
+        scan_dirs.clear();
+        let count = archives.len();
+        for (index, archive) in archives.iter().enumerate() {
+            check_cancelled(cancel)?;
+            let name = archive.strip_prefix(output_dir).unwrap_or(archive).display().to_string();
+            tracing::info!(depth, archive = %name, "Extracting nested archive");
+            let nested_dir = crate::file_utils::generate_output_dir(archive, None)?;
+
+            let result = async {
+                check_free_space(archive, &nested_dir, nested_options.skip_space_check)?;
+                decompress_dispatch(archive, &nested_dir, &nested_options, limit, summary, cancel, |progress, _| {
+                    // Each level runs from 0 to 100 again, split evenly between its archives
+                    let level_progress = (index as f64 + progress / 100.0) / count as f64 * 100.0;
+                    progress_callback(level_progress, format!("{} (level {})", name, depth));
+                }).await
+            }.await;
+
+            match result {
+                Ok(()) => {
+                    summary.nested += 1;
+                    scan_dirs.push(nested_dir);
+                },
+                Err(e) if cancel.load(Ordering::SeqCst) || limit.tripped().is_some() => return Err(e),
+                Err(e) => {
+                    tracing::warn!(archive = %name, error = %format!("{:#}", e), "Skipping nested archive");
+                    // generate_output_dir created the folder for this archive alone
+                    let _ = std::fs::remove_dir_all(&nested_dir);
+                },
+            }
+        }
+    }
+
+    let mut deeper = HashSet::new();
+    for dir in &scan_dirs {
+        find_nested_archives(dir, &mut deeper)?;
+    }
+    if !deeper.is_empty() {
+        tracing::warn!(archives = deeper.len(), max_depth, "Left archives nested too deep unextracted");
+    }
+    Ok(())
+}
+
+// Every file under `dir` that is_compressed_file accepts; links are not followed
+fn find_nested_archives(dir: &Path, archives: &mut HashSet<PathBuf>) -> Result<()> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    for entry in entries {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        let path = entry.path();
+        if file_type.is_dir() {
+            find_nested_archives(&path, archives)?;
+        } else if file_type.is_file() && is_compressed_file(&path) {
+            archives.insert(path);
+        }
+    }
+    Ok(())
+}
+
+fn holds_anything(dir: &Path) -> bool {
+    std::fs::read_dir(dir).map(|mut entries| entries.next().is_some()).unwrap_or(false)
+}
+
+// Typical text compresses 3-4x; binaries and media much less, so this errs on the safe side
+const DEFAULT_EXPANSION_FACTOR: f64 = 5.0;
+
+// Fail before writing anything when the output volume can't hold the extracted files. Only the
+// output directory's volume is checked, not those of any route targets. A size the archive
+// records is trusted; a guess from the expansion factor only logs a warning, since it can be far
+// off either way. Archives whose size can't be read, and volumes whose free space can't be, are
+// let through, as is everything when `skip` is set.
+fn check_free_space(file_path: &Path, output_dir: &Path, skip: bool) -> Result<()> {
+    if skip {
+        return Ok(());
+    }
+    let available = match available_space(output_dir) {
+        Some(available) => available,
+        None => return Ok(()),
+    };
+
+    match recorded_extracted_size(file_path) {
+        Ok(Some(needed)) if needed > available => {
+            Err(CompressionError::InsufficientSpace { needed, available }.into())
+        },
+        Ok(Some(_)) => Ok(()),
+        Ok(None) => {
+            let guess = guessed_extracted_size(file_path)?;
+            if guess > available {
+                tracing::warn!(guess, available, "The extracted files may not fit on the output volume");
+            }
+            Ok(())
+        },
+        Err(_) => Ok(()),
+    }
+}
+
+// Uncompressed size of an archive's contents: the size it records where there is one, otherwise
+// its size multiplied by the expansion factor
+pub fn estimate_extracted_size(file_path: &Path) -> Result<u64, CompressionError> {
+    match recorded_extracted_size(file_path)? {
+        Some(size) => Ok(size),
+        None => Ok(guessed_extracted_size(file_path)?),
+    }
+}
+
+// Deflate can't shrink data more than about 1032:1, so below this size a gzip stream's ISIZE
+// trailer (the uncompressed size modulo 4 GiB) can't have wrapped around
+const MAX_UNWRAPPED_GZIP_SIZE: u64 = (1 << 32) / 1032;
+
+// The uncompressed size as the archive records it, read from the whole volume set. Zip and 7z
+// record it per entry; a plain tar is never smaller than what it holds; gzip records it in its
+// trailer, though for concatenated streams that only covers the last one. None for the formats
+// that don't record it without decoding the whole stream.
+fn recorded_extracted_size(file_path: &Path) -> Result<Option<u64>> {
+    let mut volumes = VolumeReader::open(file_path)?;
+    let archive_size = volumes.len();
+    let base = first_volume_base(file_path);
+
+    match archive_extension(base.as_deref().unwrap_or(file_path)).unwrap_or("") {
+        "zip" => {
+            let mut archive = zip::ZipArchive::new(BufReader::new(volumes))?;
+            let mut total = 0u64;
+            for i in 0..archive.len() {
+                total += archive.by_index_raw(i)?.size();
+            }
+            Ok(Some(total))
+        },
+        #[cfg(feature = "sevenz-support")]
+        "7z" => {
+            use sevenz_rust::{Password, SevenZReader};
+
+            let reader = SevenZReader::new(volumes, archive_size, Password::empty())
+                .map_err(|e| anyhow::anyhow!("Failed to open 7z archive: {}", e))?;
+            Ok(Some(reader.archive().files.iter().map(|entry| entry.size()).sum()))
+        },
+        "tar" => Ok(Some(archive_size)),
+        "gz" | "tar.gz" if (18..MAX_UNWRAPPED_GZIP_SIZE).contains(&archive_size) => {
+            use std::io::{Seek, SeekFrom};
+
+            let mut trailer = [0u8; 4];
+            volumes.seek(SeekFrom::End(-4))?;
+            volumes.read_exact(&mut trailer)?;
+            Ok(Some(u32::from_le_bytes(trailer) as u64))
+        },
+        _ => Ok(None),
+    }
+}
+
+fn guessed_extracted_size(file_path: &Path) -> Result<u64> {
+    let archive_size = VolumeReader::open(file_path)?.len();
+    Ok((archive_size as f64 * expansion_factor()) as u64)
+}
+
+// Loaded from the config file once per process; factors below 1 are ignored
+fn expansion_factor() -> f64 {
+    static FACTOR: OnceLock<f64> = OnceLock::new();
+    *FACTOR.get_or_init(|| crate::config::load_config()
+        .expansion_factor
+        .filter(|factor| factor.is_finite() && *factor >= 1.0)
+        .unwrap_or(DEFAULT_EXPANSION_FACTOR))
+}
+
+// Free space on the volume `path` is on, or would be created on; None when it can't be found
+pub fn available_space(path: &Path) -> Option<u64> {
+    let existing = path.ancestors().find(|ancestor| ancestor.exists())?;
+    let existing = existing.canonicalize().ok()?;
+
+    let disks = sysinfo::Disks::new_with_refreshed_list();
+    disks.iter()
+        .filter(|disk| existing.starts_with(disk.mount_point()))
+        .max_by_key(|disk| disk.mount_point().components().count())
+        .map(|disk| disk.available_space())
+}
+
+fn dry_run_extraction<F>(file_path: &Path, output_dir: &Path, options: &DecompressOptions, mut progress_callback: F) -> Result<ExtractSummary>
+where
+    F: FnMut(f64, String),
+{
+    let planned = list_planned_files(file_path, options.output_name.as_deref())?;
+    let router = EntryRouter::new(&options.routes, output_dir, options.flatten)?;
+    let total_entries = planned.len().max(1);
+    let mut summary = ExtractSummary { planned_files: planned.len(), ..Default::default() };
+
+    for (index, file) in planned.iter().enumerate() {
+        summary.planned_size += file.size;
+        let progress = ((index + 1) as f64 / total_entries as f64) * 100.0;
+        progress_callback(progress, router.claim(router.output_path(&file.path)).display().to_string());
+    }
+    Ok(summary)
+}
+
+// List the files an extraction into `output_dir` would create, without touching the disk
+pub fn preview_extraction(file_path: &Path, output_dir: &Path, output_name: Option<&str>, routes: &[(String, PathBuf)], flatten: bool) -> Result<ExtractionPreview, CompressionError> {
+    let mut preview = ExtractionPreview::default();
+    let router = EntryRouter::new(routes, output_dir, flatten)?;
+
+    for file in list_planned_files(file_path, output_name)? {
+        let outpath = router.claim(router.output_path(&file.path));
+        if outpath.exists() {
+            preview.would_overwrite.push(outpath.display().to_string());
+        }
+        preview.entries.push(outpath.display().to_string());
+        preview.total_size += file.size;
+    }
+    Ok(preview)
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ArchiveEntry {
+    pub name: String,          // '/' separated
+    pub size: u64,             // uncompressed
+    pub modified: Option<i64>, // unix seconds, when the archive records it
+    pub method: String,        // e.g. "Deflated"; for tar and single-file formats, the whole stream's
+    pub ratio: Option<f64>,    // compressed / uncompressed, for entries compressed on their own
+}
+
+// Every file stored in an archive, in archive order. Directory entries are left out.
+pub fn list_archive(file_path: &Path) -> Result<Vec<ArchiveEntry>, CompressionError> {
+    Ok(list_planned_files(file_path, None)?
+        .into_iter()
+        .map(|file| ArchiveEntry {
+            name: file.path.to_string_lossy().replace('\\', "/"),
+            size: file.size,
+            modified: file.modified,
+            method: file.method,
+            ratio: file.compressed_size
+                .filter(|_| file.size > 0)
+                .map(|compressed| compressed as f64 / file.size as f64),
+        })
+        .collect())
+}
+
+// A file in an archive, as listings and extraction previews see it
+struct PlannedFile {
+    path: PathBuf,                // relative
+    size: u64,                    // uncompressed
+    modified: Option<i64>,        // unix seconds, when the archive records it
+    method: String,
+    compressed_size: Option<u64>, // only for entries compressed on their own
+}
+
+// The files in an archive. Tar and single-stream formats don't index their contents, so those
+// are decoded into a null sink to find out.
+fn list_planned_files(file_path: &Path, output_name: Option<&str>) -> Result<Vec<PlannedFile>> {
+    let file_name = file_path.file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or("");
+
+    let extension = archive_extension(file_path).unwrap_or("");
+
+    let mut planned = Vec::new();
+
+    if extension == "tar.gz.age" {
+        return Err(anyhow::anyhow!("'{}' is age-encrypted and cannot be previewed without a key", file_name));
+    } else if extension == "tar.gz" {
+        let decoder = flate2::read::GzDecoder::new(BufReader::new(File::open(file_path)?));
+        list_tar_files(tar::Archive::new(decoder), "Deflated", &mut planned)?;
+    } else if extension == "tar.br" {
+        let decoder = brotli::Decompressor::new(BufReader::new(File::open(file_path)?), 4096);
+        list_tar_files(tar::Archive::new(decoder), "Brotli", &mut planned)?;
+    } else {
+        match extension {
+            "tar" => list_tar_files(tar::Archive::new(BufReader::new(File::open(file_path)?)), "Stored", &mut planned)?,
+            "zip" => {
+                let mut archive = zip::ZipArchive::new(BufReader::new(File::open(file_path)?))?;
+                for i in 0..archive.len() {
+                    let file = archive.by_index_raw(i)?;
+                    if !file.is_dir() {
+                        // The precise extra fields win over the DOS time, which is read as UTC
+                        let modified = extended_mtime(file.extra_data())
+                            .map(|(seconds, _)| seconds)
+                            .or_else(|| file.last_modified().to_time().ok().map(|t| t.unix_timestamp()));
+                        planned.push(PlannedFile {
+                            path: PathBuf::from(zip_entry_name(&file)),
+                            size: file.size(),
+                            modified,
+                            method: file.compression().to_string(),
+                            compressed_size: Some(file.compressed_size()),
+                        });
+                    }
+                }
+            },
+            "gz" | "br" | "bz2" => {
+                let header = if extension == "gz" {
+                    flate2::read::GzDecoder::new(BufReader::new(File::open(file_path)?))
+                        .header()
+                        .cloned()
+                } else {
+                    None
+                };
+                let header_name = header.as_ref()
+                    .and_then(|h| h.filename())
+                    .and_then(|bytes| std::str::from_utf8(bytes).ok())
+                    .map(std::ffi::OsString::from);
+                // 0 means the gzip header has no time
+                let modified = header.as_ref()
+                    .map(|h| h.mtime())
+                    .filter(|&mtime| mtime > 0)
+                    .map(i64::from);
+                let name = output_name.map(std::ffi::OsString::from)
+                    .or(header_name)
+                    .unwrap_or_else(|| fallback_filename_from_compressed(file_path));
+
+                let mut decoder = single_stream_decoder(File::open(file_path)?, extension);
+                let size = std::io::copy(&mut decoder, &mut std::io::sink())?;
+                let method = match extension {
+                    "gz" => "Deflated",
+                    "br" => "Brotli",
+                    _ => "Bzip2",
+                };
+                // The stream holds just this one file, so its ratio is the file's
+                planned.push(PlannedFile {
+                    path: PathBuf::from(name),
+                    size,
+                    modified,
+                    method: method.to_string(),
+                    compressed_size: Some(std::fs::metadata(file_path)?.len()),
+                });
+            },
+            #[cfg(feature = "sevenz-support")]
+            "7z" => {
+                use sevenz_rust::{Password, SevenZMethod, SevenZReader};
+
+                let reader = SevenZReader::open(file_path, Password::empty())
+                    .map_err(|e| anyhow::anyhow!("Failed to open 7z archive: {}", e))?;
+                let archive = reader.archive();
+                for (index, entry) in archive.files.iter().enumerate() {
+                    if !entry.is_directory() {
+                        let modified = entry.has_last_modified_date
+                            .then(|| entry.last_modified_date().to_unix_time());
+                        let folder = archive.stream_map.file_folder_index[index].map(|folder| &archive.folders[folder]);
+                        // Coders in decoding order, as 7z -slt shows them ("BCJ LZMA2")
+                        let method = folder.map(|folder| folder.ordered_coder_iter()
+                            .map(|(_, coder)| SevenZMethod::by_id(coder.decompression_method_id())
+                                .map_or("Unknown", |method| method.name()))
+                            .collect::<Vec<_>>()
+                            .join(" "))
+                            .unwrap_or_else(|| "Empty".to_string());
+                        // A solid block's packed size is credited to its first file, so a size
+                        // of the entry's own only exists when the block holds nothing else
+                        let compressed_size = folder
+                            .filter(|folder| folder.num_unpack_sub_streams == 1)
+                            .map(|_| entry.compressed_size);
+                        planned.push(PlannedFile {
+                            path: PathBuf::from(entry.name()),
+                            size: entry.size(),
+                            modified,
+                            method,
+                            compressed_size,
+                        });
+                    }
+                }
+            },
+            _ => return Err(anyhow::anyhow!("Preview is not supported for: {}", file_name)),
+        }
+    }
+
+    Ok(planned)
+}
+
+// `method` is the compression of the stream around the tar, which its entries share
+fn list_tar_files<R: Read>(mut archive: tar::Archive<R>, method: &str, planned: &mut Vec<PlannedFile>) -> Result<()> {
+    for entry in archive.entries()? {
+        let entry = entry?;
+        if entry.header().entry_type().is_file() {
+            let modified = entry.header().mtime().ok().map(|mtime| mtime as i64);
+            planned.push(PlannedFile {
+                path: entry.path()?.into_owned(),
+                size: entry.size(),
+                modified,
+                method: method.to_string(),
+                compressed_size: None,
+            });
+        }
+    }
+    Ok(())
+}
+
+// Remove one entry, or a directory and everything under it, from a zip archive. The other
+// entries are raw-copied into a temporary archive next to the original (no recompression),
+// which then replaces it, so a failure partway leaves the original untouched. Raw copies keep
+// the data and DOS time but not extra fields such as precise timestamps. In a deduplicated zip,
+// a deleted original's contents move to its first remaining alias and the manifest is
+// rewritten to match. Returns the number of entries removed.
+pub fn delete_entry(archive_path: &Path, entry_name: &str) -> Result<usize, CompressionError> {
+    if archive_extension(archive_path) != Some("zip") {
+        return Err(anyhow::anyhow!("Deleting entries is only supported for zip archives").into());
+    }
+
+    let name = entry_name.trim_start_matches("./").trim_end_matches('/');
+    let dir_prefix = format!("{}/", name);
+    let is_deleted = |entry: &str| entry == name || entry.starts_with(&dir_prefix);
+
+    let mut archive = zip::ZipArchive::new(BufReader::new(File::open(archive_path)?))?;
+    let removed = archive.file_names().filter(|entry| is_deleted(entry)).count();
+    if removed == 0 {
+        return Err(anyhow::anyhow!("No entry '{}' in {}", entry_name, archive_path.display()).into());
+    }
+
+    // Remaining aliases whose original is deleted; the first of each takes over its contents
+    let mut promoted = HashMap::new();
+    let manifest = match read_dedup_manifest(&mut archive)? {
+        Some(aliases) => {
+            if is_deleted(DEDUP_MANIFEST_NAME) && aliases.keys().any(|alias| !is_deleted(alias)) {
+                return Err(anyhow::anyhow!(
+                    "{} lists the duplicate files stored as empty entries in {}; deleting it would leave them empty",
+                    DEDUP_MANIFEST_NAME, archive_path.display()
+                ).into());
+            }
+            let mut kept = BTreeMap::new();
+            let mut holders: HashMap<String, String> = HashMap::new();
+            for (alias, original) in aliases.into_iter().filter(|(alias, _)| !is_deleted(alias)) {
+                if !is_deleted(&original) {
+                    kept.insert(alias, original);
+                } else if let Some(holder) = holders.get(&original) {
+                    kept.insert(alias, holder.clone());
+                } else {
+                    holders.insert(original.clone(), alias.clone());
+                    promoted.insert(alias, original);
+                }
+            }
+            Some(DedupManifest { aliases: kept })
+        },
+        None => None,
+    };
+    let names = (0..archive.len())
+        .map(|i| archive.by_index_raw(i).map(|entry| entry.name().to_string()))
+        .collect::<zip::result::ZipResult<Vec<_>>>()?;
+
+    let temp_path = archive_temp_path(archive_path);
+    let result = (|| -> Result<()> {
+        let mut output = ZipWriter::new(BufWriter::new(File::create(&temp_path)?));
+        output.set_raw_comment(archive.comment().to_vec());
+        for (i, name) in names.iter().enumerate() {
+            if is_deleted(name) {
+                tracing::info!(entry = %name, "Deleting entry");
+                continue;
+            }
+            if manifest.is_some() && name == DEDUP_MANIFEST_NAME {
+                continue;
+            }
+            match promoted.get(name).and_then(|original| names.iter().position(|other| other == original)) {
+                Some(original) => {
+                    tracing::info!(entry = %name, original = %names[original], "Moving the contents of a deleted original to its alias");
+                    output.raw_copy_file_rename(archive.by_index_raw(original)?, name)?;
+                },
+                None => copy_zip_entry_raw(&mut output, archive.by_index_raw(i)?)?,
+            }
+        }
+        if let Some(manifest) = manifest.as_ref().filter(|manifest| !manifest.aliases.is_empty()) {
+            output.start_file(DEDUP_MANIFEST_NAME, FileOptions::default().compression_method(zip::CompressionMethod::Deflated))?;
+            serde_json::to_writer_pretty(&mut output, manifest)?;
+        }
+        output.finish()?.flush()?;
+        Ok(())
+    })();
+
+    if let Err(e) = result {
+        let _ = std::fs::remove_file(&temp_path);
+        return Err(e.into());
+    }
+    drop(archive);
+    std::fs::rename(&temp_path, archive_path)?;
+    Ok(removed)
+}
+
+// Add `files` to an existing zip archive, stored under their own names like a fresh compress of
+// them would. An added name that is already in the archive replaces it, is left out, or gets a
+// numbered name, following `policy`. Returns how many entries were added.
+pub fn append_to_zip(archive_path: &Path, files: &[PathBuf], policy: ConflictPolicy) -> Result<usize, CompressionError> {
+    if archive_extension(archive_path) != Some("zip") {
+        return Err(anyhow::anyhow!("Appending is only supported for zip archives").into());
+    }
+
+    let archive_canonical = archive_path.canonicalize()
+        .with_context(|| format!("Archive not found: {}", archive_path.display()))?;
+    if let Some(file) = files.iter().find(|file| file.canonicalize().ok().as_ref() == Some(&archive_canonical)) {
+        return Err(anyhow::anyhow!("Cannot add {} to itself", file.display()).into());
+    }
+
+    // Absolute inputs give "." and ".." real names to store entries under
+    let files = files.iter()
+        .map(|file| std::path::absolute(file).map(|path| lexically_normalized(&path)))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    let filter = PathFilter::default().with_ignore_files(&files)?;
+    let mut added = Vec::new();
+    for (file_path, base) in files.iter().zip(tar_input_bases(&files)) {
+        collect_zip_entries(file_path, &base, &filter, &mut added)?;
+    }
+    // A directory being added may hold the archive itself
+    added.retain(|(path, _, _)| {
+        let is_archive = path.canonicalize().ok().as_ref() == Some(&archive_canonical);
+        if is_archive {
+            tracing::info!(path = %path.display(), "Skipping the archive itself");
+        }
+        !is_archive
+    });
+
+    let mut archive = zip::ZipArchive::new(BufReader::new(File::open(archive_path)?))?;
+    let existing: HashSet<String> = archive.file_names().map(str::to_string).collect();
+    let mut replaced = HashSet::new();
+    match policy {
+        ConflictPolicy::Overwrite => {
+            replaced = added.iter()
+                .map(|(_, zip_path, _)| zip_path.clone())
+                .filter(|zip_path| existing.contains(zip_path))
+                .collect();
+        },
+        ConflictPolicy::Skip => {
+            added.retain(|(_, zip_path, _)| {
+                let exists = existing.contains(zip_path);
+                if exists {
+                    tracing::info!(entry = %zip_path, "Skipping existing entry");
+                }
+                !exists
+            });
+        },
+        ConflictPolicy::Rename => {
+            let mut taken = existing.clone();
+            for (_, zip_path, _) in added.iter_mut() {
+                if taken.contains(zip_path.as_str()) {
+                    let renamed = unique_entry_name(zip_path, &taken);
+                    tracing::info!(entry = %zip_path, renamed = %renamed, "Adding under a new name");
+                    *zip_path = renamed;
+                }
+                taken.insert(zip_path.clone());
+            }
+        },
+    }
+
+    let added_bytes: u64 = added.iter()
+        .filter_map(|(path, _, _)| std::fs::metadata(path).ok())
+        .map(|metadata| metadata.len())
+        .sum();
+    let archive_bytes = std::fs::metadata(archive_path)?.len();
+    let needs_zip64 = archive_bytes + added_bytes >= u32::MAX as u64 || existing.len() + added.len() > u16::MAX as usize;
+    let options = ZipEntryOptions {
+        base: FileOptions::default()
+            .unix_permissions(0o755)
+            .large_file(needs_zip64),
+        auto_store: false,
+        precise_timestamps: false,
+    };
+    let cancel = CancellationToken::default();
+
+    let temp_path = archive_temp_path(archive_path);
+    let result = (|| -> Result<()> {
+        let mut output = ZipWriter::new(BufWriter::new(File::create(&temp_path)?));
+        output.set_raw_comment(archive.comment().to_vec());
+        for i in 0..archive.len() {
+            let entry = archive.by_index_raw(i)?;
+            if replaced.contains(entry.name()) {
+                tracing::info!(entry = entry.name(), "Replacing entry");
+                continue;
+            }
+            copy_zip_entry_raw(&mut output, entry)?;
+        }
+        for (path, zip_path, link_target) in &added {
+            tracing::debug!(entry = %zip_path, "Adding file");
+            write_zip_entry(&mut output, path, zip_path, link_target.as_deref(), &options, &cancel)?;
+        }
+        output.finish()?.flush()?;
+        Ok(())
+    })();
+
+    if let Err(e) = result {
+        let _ = std::fs::remove_file(&temp_path);
+        return Err(e.into());
+    }
+    drop(archive);
+    std::fs::rename(&temp_path, archive_path)?;
+    Ok(added.len())
+}
+
+// Resolve "." and ".." without touching the filesystem, so a symlink input stays a symlink
+fn lexically_normalized(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            std::path::Component::CurDir => {},
+            std::path::Component::ParentDir => {
+                normalized.pop();
+            },
+            other => normalized.push(other),
+        }
+    }
+    normalized
+}
+
+// Rewrites go next to the archive first so the original survives a failure midway
+fn archive_temp_path(archive_path: &Path) -> PathBuf {
+    let mut temp_name = archive_path.as_os_str().to_os_string();
+    temp_name.push(format!(".{}.tmp", std::process::id()));
+    PathBuf::from(temp_name)
+}
+
+fn copy_zip_entry_raw<W: Write + std::io::Seek>(output: &mut ZipWriter<W>, mut entry: zip::read::ZipFile<'_>) -> Result<()> {
+    // raw_copy_file keeps only the permission bits of the mode, which would turn a
+    // symlink into a regular file, so links are re-added from their target
+    let is_symlink = entry.unix_mode().is_some_and(|mode| mode & 0o170000 == 0o120000);
+    if is_symlink && entry.compression() == zip::CompressionMethod::Stored {
+        let mut target = String::new();
+        entry.read_to_string(&mut target)?;
+        let options = FileOptions::default().last_modified_time(entry.last_modified());
+        output.add_symlink(entry.name().to_string(), target, options)?;
+    } else {
+        output.raw_copy_file(entry)?;
+    }
+    Ok(())
+}
+
+// "docs/a.txt" -> "docs/a (2).txt", numbered like renamed files on disk
+fn unique_entry_name(name: &str, taken: &HashSet<String>) -> String {
+    let (dir, file_name) = match name.rfind('/') {
+        Some(index) => name.split_at(index + 1),
+        None => ("", name),
+    };
+    let (stem, extension) = match file_name.rfind('.') {
+        Some(index) if index > 0 => file_name.split_at(index),
+        _ => (file_name, ""),
+    };
+
+    (2..)
+        .map(|counter| format!("{}{} ({}){}", dir, stem, counter, extension))
+        .find(|candidate| !taken.contains(candidate))
+        .unwrap_or_else(|| name.to_string())
+}
+
+// The first `max_bytes` of one archived file, e.g. enough of an image for a thumbnail. Only that
+// entry is decompressed, and only as far as needed; tar streams are still decoded up to it.
+// Single-stream formats hold one file, so `entry_name` is ignored for them.
+pub fn preview_entry(file_path: &Path, entry_name: &str, max_bytes: usize) -> Result<Vec<u8>, CompressionError> {
+    let extension = archive_extension(file_path).unwrap_or("");
+    let wanted = entry_name.trim_start_matches("./");
+    let mut head = Vec::new();
+
+    match extension {
+        "zip" => {
+            let mut archive = zip::ZipArchive::new(BufReader::new(File::open(file_path)?))?;
+            let aliases = read_dedup_manifest(&mut archive)?.unwrap_or_default();
+            let is_empty = match archive.by_name(wanted) {
+                Err(zip::result::ZipError::FileNotFound) => {
+                    return Err(anyhow::anyhow!("No entry '{}' in {}", entry_name, file_path.display()).into());
+                },
+                result => result?.size() == 0,
+            };
+            // An alias in a deduplicated zip is empty; its contents live in the original
+            let source = aliases.get(wanted).filter(|_| is_empty).map(String::as_str).unwrap_or(wanted);
+            let entry = match archive.by_name(source) {
+                Err(zip::result::ZipError::FileNotFound) => {
+                    return Err(anyhow::anyhow!("No entry '{}' in {}", entry_name, file_path.display()).into());
+                },
+                result => result?,
+            };
+            entry.take(max_bytes as u64).read_to_end(&mut head)?;
+        },
+        "tar" | "tar.gz" | "tar.br" => {
+            let input = BufReader::new(File::open(file_path)?);
+            let reader: Box<dyn Read> = match extension {
+                "tar" => Box::new(input),
+                "tar.gz" => Box::new(flate2::read::MultiGzDecoder::new(input)),
+                _ => Box::new(brotli::Decompressor::new(input, 4096)),
+            };
+            let mut archive = tar::Archive::new(reader);
+            let mut found = false;
+            for entry in archive.entries()? {
+                let entry = entry?;
+                if entry.path()?.to_string_lossy().trim_start_matches("./") == wanted {
+                    entry.take(max_bytes as u64).read_to_end(&mut head)?;
+                    found = true;
+                    break;
+                }
+            }
+            if !found {
+                return Err(anyhow::anyhow!("No entry '{}' in {}", entry_name, file_path.display()).into());
+            }
+        },
+        "gz" | "br" | "bz2" => {
+            single_stream_decoder(File::open(file_path)?, extension)
+                .take(max_bytes as u64)
+                .read_to_end(&mut head)?;
+        },
+        #[cfg(feature = "sevenz-support")]
+        "7z" => {
+            use sevenz_rust::{Password, SevenZReader};
+
+            let mut reader = SevenZReader::open(file_path, Password::empty())
+                .map_err(|e| anyhow::anyhow!("Failed to open 7z archive: {}", e))?;
+            let mut found = false;
+            reader.for_each_entries(|entry, data| {
+                if entry.name() != wanted {
+                    return Ok(true);
+                }
+                data.take(max_bytes as u64).read_to_end(&mut head)?;
+                found = true;
+                Ok(false)
+            }).map_err(|e| anyhow::anyhow!("Failed to read 7z archive: {}", e))?;
+            if !found {
+                return Err(anyhow::anyhow!("No entry '{}' in {}", entry_name, file_path.display()).into());
+            }
+        },
+        _ => return Err(CompressionError::UnsupportedFormat(file_path.display().to_string())),
+    }
+
+    Ok(head)
+}
+
+// Extract only what lies at or below `prefix` in a zip or tar archive (e.g. just "docs/" out of
+// a big zip), for pulling one folder out of the GUI's tree view. With `strip_prefix` the
+// prefix's folders are dropped from the output paths, so docs/readme.md lands as readme.md.
+// Returns how many files were written; a prefix matching nothing is an error.
+pub fn extract_prefix(archive_path: &Path, output_dir: &Path, prefix: &str, strip_prefix: bool) -> Result<usize, CompressionError> {
+    let extension = archive_extension(archive_path).unwrap_or("");
+    let prefix = prefix.replace('\\', "/");
+    let prefix = prefix.trim_start_matches("./").trim_matches('/');
+    if prefix.is_empty() {
+        return Err(anyhow::anyhow!("An empty prefix matches the whole archive; extract it normally instead").into());
+    }
+    let mut matched = 0;
+    let mut written = 0;
+
+    match extension {
+        "zip" => {
+            let mut archive = zip::ZipArchive::new(BufReader::new(VolumeReader::open(archive_path)?))?;
+            let aliases = read_dedup_manifest(&mut archive)?.unwrap_or_default();
+
+            for i in 0..archive.len() {
+                let (name, is_dir, size) = {
+                    let raw = archive.by_index_raw(i)?;
+                    (zip_entry_name(&raw), raw.is_dir(), raw.size())
+                };
+                let Some(relative_path) = prefixed_relative_path(&name, prefix, strip_prefix, is_dir) else {
+                    continue;
+                };
+                matched += 1;
+                if name.contains('\0') || !is_safe_relative_path(&relative_path) {
+                    tracing::warn!(entry = %name, "Skipping unsafe zip entry");
+                    continue;
+                }
+                let outpath = output_dir.join(&relative_path);
+                if is_dir {
+                    std::fs::create_dir_all(&outpath)?;
+                    continue;
+                }
+                if let Some(p) = outpath.parent() {
+                    std::fs::create_dir_all(p)?;
+                }
+
+                // A deduplicated zip stores repeats as empty entries; their contents live in the
+                // original, which may well be outside the prefix
+                let contents = match aliases.get(&name).filter(|_| size == 0) {
+                    Some(original) => archive.by_name(original),
+                    None => archive.by_index(i),
+                };
+                let mut file = match contents {
+                    Err(zip::result::ZipError::UnsupportedArchive(zip::result::ZipError::PASSWORD_REQUIRED)) => {
+                        return Err(CompressionError::PasswordRequired { entry: name });
+                    },
+                    result => result?,
+                };
+                let mut outfile = File::create(&outpath)?;
+                std::io::copy(&mut file, &mut outfile)?;
+                drop(outfile);
+                written += 1;
+
+                let mtime = extended_mtime(file.extra_data())
+                    .or_else(|| file.last_modified().to_time().ok().map(|t| (t.unix_timestamp(), 0)));
+                if let Some((seconds, nanos)) = mtime {
+                    let _ = crate::file_utils::set_modified_time(&outpath, seconds, nanos);
+                }
+                #[cfg(unix)]
+                if let Some(mode) = file.unix_mode() {
+                    use std::os::unix::fs::PermissionsExt;
+                    std::fs::set_permissions(&outpath, std::fs::Permissions::from_mode(mode))?;
+                }
+            }
+        },
+        "tar" | "tar.gz" | "tar.br" => {
+            let input = BufReader::new(File::open(archive_path)?);
+            let reader: Box<dyn Read> = match extension {
+                "tar" => Box::new(input),
+                "tar.gz" => Box::new(flate2::read::MultiGzDecoder::new(input)),
+                _ => Box::new(brotli::Decompressor::new(input, 4096)),
+            };
+            let mut archive = tar::Archive::new(reader);
+
+            for entry in archive.entries()? {
+                let mut entry = entry?;
+                let name = entry.path()?.to_string_lossy().into_owned();
+                let entry_type = entry.header().entry_type();
+                let Some(relative_path) = prefixed_relative_path(&name, prefix, strip_prefix, entry_type.is_dir()) else {
+                    continue;
+                };
+                matched += 1;
+                if !is_safe_relative_path(&relative_path) {
+                    tracing::warn!(entry = %name, "Skipping unsafe tar entry");
+                    continue;
+                }
+                let outpath = output_dir.join(&relative_path);
+                if entry_type.is_dir() {
+                    std::fs::create_dir_all(&outpath)?;
+                    continue;
+                }
+                // Links may point at entries that were left out, so only plain files come along
+                if !entry_type.is_file() {
+                    tracing::warn!(entry = %name, "Skipping link or special entry in a prefix extraction");
+                    continue;
+                }
+                if let Some(p) = outpath.parent() {
+                    std::fs::create_dir_all(p)?;
+                }
+                entry.unpack(&outpath)?;
+                written += 1;
+            }
+        },
+        _ => return Err(CompressionError::UnsupportedFormat(archive_path.display().to_string())),
+    }
+
+    if matched == 0 {
+        return Err(anyhow::anyhow!("No entries under '{}' in {}", prefix, archive_path.display()).into());
+    }
+    tracing::info!(archive = %archive_path.display(), prefix, files = written, "Extracted entries by prefix");
+    Ok(written)
+}
+
+// Where an entry lands for extract_prefix, or None when it is not at or below `prefix`. Matching
+// goes by whole path components, so "doc" does not pick up "docs/". Stripping keeps an exactly
+// matched file's own name and turns an exactly matched folder into the output directory itself.
+fn prefixed_relative_path(name: &str, prefix: &str, strip_prefix: bool, is_dir: bool) -> Option<PathBuf> {
+    let name = name.trim_start_matches("./").trim_end_matches('/');
+    let rest = if name == prefix {
+        if is_dir {
+            ""
+        } else {
+            name.rsplit('/').next().unwrap_or(name)
+        }
+    } else {
+        name.strip_prefix(prefix)?.strip_prefix('/')?
+    };
+    Some(PathBuf::from(if strip_prefix { rest } else { name }))
+}
+
+// Best-effort recovery for a zip that ZipArchive cannot open at all, typically a partial
+// download that lost its central directory. Walks the local file headers instead and extracts
+// every entry whose data is complete and passes its CRC, skipping damaged entries and the cut-off
+// tail. Returns the names recovered, in archive order. Encrypted entries, and stored ones written
+// with a data descriptor (sizes only after the data, so nothing says where the data ends), cannot
+// be read this way and are skipped too.
+pub fn salvage_zip(archive_path: &Path, output_dir: &Path) -> Result<Vec<String>, CompressionError> {
+    use std::io::{Seek, SeekFrom};
+
+    let mut reader = BufReader::new(File::open(archive_path)?);
+    let mut recovered = Vec::new();
+    let mut offset = 0;
+
+    while let Some(header) = find_local_header(&mut reader, offset)? {
+        reader.seek(SeekFrom::Start(header))?;
+        // Where to scan on from if this turns out not to be an intact entry
+        offset = header + 4;
+
+        let mut fixed = [0u8; 30];
+        if reader.read_exact(&mut fixed).is_err() {
+            break;
+        }
+        let flags = u16::from_le_bytes([fixed[6], fixed[7]]);
+        let method = u16::from_le_bytes([fixed[8], fixed[9]]);
+        if flags & ZIP_DATA_DESCRIPTOR_FLAG != 0 && flags & ZIP_ENCRYPTED_FLAG == 0 && method == ZIP_DEFLATED {
+            match salvage_streamed_zip_entry(&mut reader, &fixed, output_dir) {
+                Ok(Some(name)) => {
+                    recovered.push(name);
+                    offset = reader.stream_position()?;
+                },
+                Ok(None) => {},
+                Err(e) => tracing::warn!(offset = header, error = %e, "Skipping damaged zip entry"),
+            }
+            continue;
+        }
+        reader.seek(SeekFrom::Start(header))?;
+        let mut file = match zip::read::read_zipfile_from_stream(&mut reader) {
+            Ok(Some(file)) => file,
+            Ok(None) => continue,
+            Err(e) => {
+                tracing::warn!(offset = header, error = %e, "Skipping unreadable zip entry");
+                continue;
+            },
+        };
+        let name = zip_entry_name(&file);
+        let relative_path = PathBuf::from(&name);
+        if name.contains('\0') || !is_safe_relative_path(&relative_path) {
+            tracing::warn!(entry = %name, "Skipping unsafe zip entry");
+            continue;
+        }
+        let outpath = output_dir.join(&relative_path);
+
+        if file.is_dir() {
+            std::fs::create_dir_all(&outpath)?;
+        } else {
+            if let Some(p) = outpath.parent() {
+                std::fs::create_dir_all(p)?;
+            }
+            let mut outfile = File::create(&outpath)?;
+            // Reading to the end checks the CRC, so a cut-off or corrupted entry fails here
+            if let Err(e) = std::io::copy(&mut file, &mut outfile) {
+                drop(outfile);
+                let _ = std::fs::remove_file(&outpath);
+                tracing::warn!(entry = %name, error = %e, "Skipping damaged zip entry");
+                continue;
+            }
+            drop(outfile);
+
+            let mtime = extended_mtime(file.extra_data())
+                .or_else(|| file.last_modified().to_time().ok().map(|t| (t.unix_timestamp(), 0)));
+            if let Some((seconds, nanos)) = mtime {
+                let _ = crate::file_utils::set_modified_time(&outpath, seconds, nanos);
+            }
+        }
+        drop(file);
+        recovered.push(name);
+        offset = reader.stream_position()?;
+    }
+
+    if recovered.is_empty() {
+        return Err(anyhow::anyhow!("No intact entries found in {}", archive_path.display()).into());
+    }
+    tracing::info!(archive = %archive_path.display(), entries = recovered.len(), "Salvaged zip entries");
+    Ok(recovered)
+}
+
+// General purpose flag bits and the compression method of a local file header
+const ZIP_ENCRYPTED_FLAG: u16 = 0x0001;
+const ZIP_DATA_DESCRIPTOR_FLAG: u16 = 0x0008;
+const ZIP_DEFLATED: u16 = 8;
+const ZIP64_EXTRA_TAG: u16 = 0x0001;
+
+// A deflated entry with a data descriptor, which read_zipfile_from_stream refuses. The deflate
+// stream marks its own end, so the data is inflated up to there, and the descriptor right after
+// it gives the CRC and size to check the output against. `reader` is just past the header's
+// fixed 30 bytes, `fixed`. Returns the entry's name, or None for an unsafe name; a damaged or
+// cut-off entry is an error and leaves no file behind.
+fn salvage_streamed_zip_entry<R: std::io::BufRead>(reader: &mut R, fixed: &[u8; 30], output_dir: &Path) -> Result<Option<String>> {
+    let field = |offset: usize| u16::from_le_bytes([fixed[offset], fixed[offset + 1]]);
+    let mut raw_name = vec![0u8; field(26) as usize];
+    reader.read_exact(&mut raw_name)?;
+    let mut extra = vec![0u8; field(28) as usize];
+    reader.read_exact(&mut extra)?;
+
+    let name = unicode_path_name(&raw_name, &extra).unwrap_or_else(|| String::from_utf8_lossy(&raw_name).into_owned());
+    let relative_path = PathBuf::from(&name);
+    if name.contains('\0') || !is_safe_relative_path(&relative_path) {
+        tracing::warn!(entry = %name, "Skipping unsafe zip entry");
+        return Ok(None);
+    }
+    let outpath = output_dir.join(&relative_path);
+    let is_dir = name.ends_with('/');
+    if let Some(p) = outpath.parent() {
+        std::fs::create_dir_all(p)?;
+    }
+
+    let result = (|| -> Result<()> {
+        let mut data = flate2::CrcReader::new(flate2::bufread::DeflateDecoder::new(&mut *reader));
+        if is_dir {
+            std::io::copy(&mut data, &mut std::io::sink())?;
+        } else {
+            std::io::copy(&mut data, &mut File::create(&outpath)?)?;
+        }
+        let (crc, size) = (data.crc().sum(), data.crc().amount());
+        drop(data);
+
+        // The descriptor's signature is optional, and its sizes are 8 bytes for zip64 entries
+        let mut word = [0u8; 4];
+        reader.read_exact(&mut word)?;
+        if word == *b"PK\x07\x08" {
+            reader.read_exact(&mut word)?;
+        }
+        let recorded_crc = u32::from_le_bytes(word);
+        let mut sizes = vec![0u8; if has_extra_field(&extra, ZIP64_EXTRA_TAG) { 16 } else { 8 }];
+        reader.read_exact(&mut sizes)?;
+        let recorded_size = match sizes.len() {
+            16 => u64::from_le_bytes(sizes[8..16].try_into().unwrap()),
+            _ => u32::from_le_bytes(sizes[4..8].try_into().unwrap()) as u64,
+        };
+        if recorded_crc != crc || recorded_size != size as u64 {
+            return Err(anyhow::anyhow!("CRC or size does not match the data descriptor"));
+        }
+        Ok(())
+    })();
+    if let Err(e) = result {
+        if !is_dir {
+            let _ = std::fs::remove_file(&outpath);
+        }
+        return Err(e);
+    }
+
+    if is_dir {
+        std::fs::create_dir_all(&outpath)?;
+    } else {
+        let mtime = extended_mtime(&extra)
+            .or_else(|| zip::DateTime::from_msdos(field(12), field(10)).to_time().ok().map(|t| (t.unix_timestamp(), 0)));
+        if let Some((seconds, nanos)) = mtime {
+            let _ = crate::file_utils::set_modified_time(&outpath, seconds, nanos);
+        }
+    }
+    Ok(Some(name))
+}
+
+fn has_extra_field(mut extra: &[u8], wanted: u16) -> bool {
+    while extra.len() >= 4 {
+        let tag = u16::from_le_bytes([extra[0], extra[1]]);
+        let size = u16::from_le_bytes([extra[2], extra[3]]) as usize;
+        if tag == wanted {
+            return true;
+        }
+        extra = extra.get(4 + size..).unwrap_or_default();
+    }
+    false
+}
+
+// Offset of the next local file header signature at or after `from`
+fn find_local_header<R: Read + std::io::Seek>(reader: &mut R, from: u64) -> std::io::Result<Option<u64>> {
+    const SIGNATURE: &[u8] = b"PK\x03\x04";
+
+    reader.seek(std::io::SeekFrom::Start(from))?;
+    let mut window = Vec::new();
+    let mut window_start = from;
+    let mut buffer = [0u8; 64 * 1024];
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            return Ok(None);
+        }
+        window.extend_from_slice(&buffer[..read]);
+        if let Some(position) = window.windows(SIGNATURE.len()).position(|w| w == SIGNATURE) {
+            return Ok(Some(window_start + position as u64));
+        }
+        // Keep the last few bytes in case the signature straddles two reads
+        let keep = window.len().min(SIGNATURE.len() - 1);
+        window_start += (window.len() - keep) as u64;
+        window.drain(..window.len() - keep);
+    }
+}
+
+async fn decompress_dispatch<F>(
+    file_path: &Path, 
+    output_dir: &Path,
+    options: &DecompressOptions,
+    limit: &OutputLimit,
+    summary: &mut ExtractSummary,
+    cancel: &CancellationToken,
+    mut progress_callback: F
+) -> Result<()> 
+where
+    F: FnMut(f64, String),
+{
+    // A first volume is decoded as the archive all its volumes make up together
+    let joined_path = first_volume_base(file_path);
+    let extension = archive_extension(joined_path.as_deref().unwrap_or(file_path)).unwrap_or("");
+
+    let file_name = file_path.file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or("");
+
+    // Get file size for progress calculation
+    let file_size = VolumeReader::open(file_path)?.len();
+    
+    let archive_name = file_path.file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string();
+
+    let output_name = options.output_name.as_deref();
+    let conflicts = ConflictHandling {
+        policy: options.conflict_policy,
+        backup_on_overwrite: options.backup_on_overwrite,
+    };
+    let router = EntryRouter::new(&options.routes, output_dir, options.flatten)?;
+
+    let is_single_stream = matches!(extension, "gz" | "br" | "bz2");
+    if output_name.is_some() && !is_single_stream {
+        return Err(anyhow::anyhow!("An output file name can only be set for single-file formats (.gz, .br, .bz2)"));
+    }
+
+    if joined_path.is_some() && matches!(extension, "tar.gz.age" | "rar") {
+        Err(anyhow::anyhow!("Split .{} archives can't be read in volumes; join them into one file first", extension))
+    } else if extension == "tar.gz.age" {
+        Err(anyhow::anyhow!("'{}' is age-encrypted; a passphrase or identity file is required", file_name))
+    } else if extension == "tar.gz" {
+        decompress_tar_gz_with_progress(file_path, output_dir, limit, &router, file_size, conflicts, summary, cancel, move |progress, _| {
+            progress_callback(progress, archive_name.clone())
+        }).await
+    } else if extension == "tar.br" {
+        decompress_tar_br_with_progress(file_path, output_dir, limit, &router, file_size, conflicts, summary, cancel, move |progress, _| {
+            progress_callback(progress, archive_name.clone())
+        }).await
+    } else {
+        match extension {
+            "zip" => decompress_zip_with_progress(file_path, output_dir, limit, &router, conflicts, options.password.as_ref(), summary, cancel, move |progress, _| {
+                progress_callback(progress, archive_name.clone())
+            }).await,
+            "tar" => decompress_tar_with_progress(file_path, output_dir, limit, &router, file_size, conflicts, summary, cancel, move |progress, _| {
+                progress_callback(progress, archive_name.clone())
+            }).await,
+            "gz" => decompress_gz_with_progress(file_path, output_dir, limit, output_name, file_size, conflicts, summary, cancel, move |progress, _| {
+                progress_callback(progress, archive_name.clone())
+            }).await,
+            "br" => decompress_br_with_progress(file_path, output_dir, limit, output_name, file_size, conflicts, summary, cancel, move |progress, _| {
+                progress_callback(progress, archive_name.clone())
+            }).await,
+            "bz2" => decompress_bzip2_with_progress(file_path, output_dir, limit, output_name, file_size, conflicts, summary, cancel, move |progress, _| {
+                progress_callback(progress, archive_name.clone())
+            }).await,
+            #[cfg(feature = "rar-support")]
+            "rar" => decompress_rar(file_path, output_dir).await,
+            #[cfg(feature = "sevenz-support")]
+            "7z" => decompress_7z(file_path, output_dir, limit, &router, conflicts, summary, cancel, move |progress, _| {
+                progress_callback(progress, archive_name.clone())
+            }).await,
+            _ => Err(anyhow::anyhow!("Unsupported file format: {}", file_name)),
+        }
+    }
+}
+
+// Progress tracking reader wrapper for decompression with filename tracking
+struct ProgressReader<R, F> {
+    inner: R,
+    progress_callback: F,
+    total_size: u64,
+    bytes_read: u64,
+    filename: String,
+    cancel: CancellationToken,
+}
+
+impl<R: Read, F: FnMut(f64, String)> ProgressReader<R, F> {
+    fn new(inner: R, total_size: u64, filename: String, cancel: CancellationToken, progress_callback: F) -> Self {
+        Self {
+            inner,
+            progress_callback,
+            total_size,
+            bytes_read: 0,
+            filename,
+            cancel,
+        }
+    }
+}
+
+impl<R: Read, F: FnMut(f64, String)> Read for ProgressReader<R, F> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.cancel.load(Ordering::SeqCst) {
+            return Err(cancelled_io_error());
+        }
+        let bytes = self.inner.read(buf)?;
+        self.bytes_read += bytes as u64;
+        
+        if self.total_size > 0 {
+            let progress = (self.bytes_read as f64 / self.total_size as f64) * 100.0;
+            (self.progress_callback)(progress.min(100.0), self.filename.clone());
+        }
+        
+        Ok(bytes)
+    }
+}
+
+async fn decompress_zip_with_progress<F>(
+    file_path: &Path, 
+    output_dir: &Path, 
+    limit: &OutputLimit,
+    router: &EntryRouter,
+    conflicts: ConflictHandling,
+    password: Option<&PasswordResolver>,
+    summary: &mut ExtractSummary,
+    cancel: &CancellationToken,
+    mut progress_callback: F
+) -> Result<()>
+where
+    F: FnMut(f64, String),
+{
+    let file = VolumeReader::open(file_path)?;
+    let mut archive = zip::ZipArchive::new(BufReader::new(file))?;
+    let archive_name = file_path.file_name().unwrap_or_default().to_string_lossy().to_string();
+    
+    std::fs::create_dir_all(output_dir)?;
+
+    // Where each original went, for the references of a deduplicated zip to be copied from
+    let aliases = read_dedup_manifest(&mut archive)?;
+    let mut extracted: HashMap<String, PathBuf> = HashMap::new();
+
+    let total_files = archive.len();
+    
+    for i in 0..archive.len() {
+        check_cancelled(cancel)?;
+        // Update progress based on file count
+        let progress = (i as f64 / total_files as f64) * 100.0;
+        progress_callback(progress, archive_name.clone());
+        
+        let name = zip_entry_name(&archive.by_index_raw(i)?);
+        if aliases.is_some() && name == DEDUP_MANIFEST_NAME {
+            continue;
+        }
+        let mut file = match password.and_then(|resolver| resolver.password_for(&name)) {
+            Some(password) => archive.by_index_decrypt(i, password.as_bytes())?
+                .map_err(|_| CompressionError::WrongPassword { entry: name.clone() })?,
+            None => match archive.by_index(i) {
+                Err(zip::result::ZipError::UnsupportedArchive(zip::result::ZipError::PASSWORD_REQUIRED)) => {
+                    return Err(CompressionError::PasswordRequired { entry: name }.into());
+                },
+                result => result?,
+            },
+        };
+        // Absolute paths and ".." would escape output_dir (Zip Slip)
+        let relative_path = PathBuf::from(&name);
+        if name.contains('\0') || !is_safe_relative_path(&relative_path) {
+            tracing::warn!(entry = %name, "Skipping unsafe zip entry");
+            continue;
+        }
+        let mut outpath = router.output_path(&relative_path);
+        #[cfg(unix)]
+        let unix_mode = file.unix_mode();
+
+        if file.is_dir() {
+            // When routing or flattening, directories only appear where their files are sent
+            if router.rewrites_paths() {
+                continue;
+            }
+            std::fs::create_dir_all(&outpath)?;
+        } else {
+            limit.check_zip_entry(&name, file.size(), file.compressed_size())?;
+            // Restore the archived modification time, preferring the precise extra fields over
+            // the DOS time (stored without a timezone, read as UTC)
+            let mtime = extended_mtime(file.extra_data())
+                .or_else(|| file.last_modified().to_time().ok().map(|t| (t.unix_timestamp(), 0)));
+            outpath = match resolve_conflict(&router.claim(outpath), conflicts, summary)? {
+                Some(path) => path,
+                None => continue,
+            };
+            if let Some(p) = outpath.parent() {
+                std::fs::create_dir_all(p)?;
+            }
+            let mut outfile = File::create(&outpath)?;
+            // The originals are always written before their references
+            let original = aliases.as_ref()
+                .and_then(|aliases| aliases.get(&name))
+                .filter(|_| file.size() == 0);
+            match original.map(|original| (original, extracted.get(original))) {
+                Some((_, Some(source))) => {
+                    let mut reader = ProgressReader::new(limit.reader(File::open(source)?), 0, archive_name.clone(), cancel.clone(), |_, _| {});
+                    std::io::copy(&mut reader, &mut outfile)?;
+                },
+                Some((original, None)) => {
+                    // The original wasn't written (skipped as already there, or unsafe), so its
+                    // contents are read from the archive again
+                    drop(file);
+                    let mut source = match password.and_then(|resolver| resolver.password_for(original)) {
+                        Some(password) => archive.by_name_decrypt(original, password.as_bytes())?
+                            .map_err(|_| CompressionError::WrongPassword { entry: original.clone() })?,
+                        None => archive.by_name(original)?,
+                    };
+                    let mut reader = ProgressReader::new(limit.reader(&mut source), 0, archive_name.clone(), cancel.clone(), |_, _| {});
+                    std::io::copy(&mut reader, &mut outfile)?;
+                },
+                None => {
+                    let mut reader = ProgressReader::new(limit.reader(&mut file), 0, archive_name.clone(), cancel.clone(), |_, _| {});
+                    std::io::copy(&mut reader, &mut outfile)?;
+                    if aliases.is_some() {
+                        extracted.insert(name.clone(), outpath.clone());
+                    }
+                },
+            }
+            drop(outfile);
+
+            if let Some((seconds, nanos)) = mtime {
+                let _ = crate::file_utils::set_modified_time(&outpath, seconds, nanos);
+            }
+        }
+
+        // Set file permissions if available
+        #[cfg(unix)]
+        if let Some(mode) = unix_mode {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&outpath, std::fs::Permissions::from_mode(mode))?;
+        }
+    }
+
+    progress_callback(100.0, archive_name);
+    Ok(())
+}
+
+async fn decompress_tar_with_progress<F>(
+    file_path: &Path,
+    output_dir: &Path,
+    limit: &OutputLimit,
+    router: &EntryRouter,
+    file_size: u64,
+    conflicts: ConflictHandling,
+    summary: &mut ExtractSummary,
+    cancel: &CancellationToken,
+    mut progress_callback: F
+) -> Result<()>
+where
+    F: FnMut(f64, String),
+{
+    let file = VolumeReader::open(file_path)?;
+    let filename = file_path.file_name().unwrap_or_default().to_string_lossy().to_string();
+    let progress_reader = ProgressReader::new(file, file_size, filename.clone(), cancel.clone(), &mut progress_callback);
+    let mut archive = tar::Archive::new(limit.reader(BufReader::new(progress_reader)));
+    archive.set_preserve_mtime(true);
+
+    std::fs::create_dir_all(output_dir)?;
+    unpack_tar_with_policy(archive, output_dir, router, conflicts, summary)?;
+    // tar stops at its end-of-archive blocks, before any record padding or trailing data
+    progress_callback(100.0, filename);
+    Ok(())
+}
+
+async fn decompress_tar_gz_with_progress<F>(
+    file_path: &Path, 
+    output_dir: &Path, 
+    limit: &OutputLimit,
+    router: &EntryRouter,
+    file_size: u64,
+    conflicts: ConflictHandling,
+    summary: &mut ExtractSummary,
+    cancel: &CancellationToken,
+    mut progress_callback: F
+) -> Result<()>
+where
+    F: FnMut(f64, String),
+{
+    let file = VolumeReader::open(file_path)?;
+    let filename = file_path.file_name().unwrap_or_default().to_string_lossy().to_string();
+    let progress_reader = ProgressReader::new(file, file_size, filename.clone(), cancel.clone(), &mut progress_callback);
+    let gz_decoder = flate2::read::GzDecoder::new(BufReader::new(progress_reader));
+    let mut archive = tar::Archive::new(limit.reader(gz_decoder));
+    archive.set_preserve_mtime(true);
+    
+    std::fs::create_dir_all(output_dir)?;
+    unpack_tar_with_policy(archive, output_dir, router, conflicts, summary)?;
+    // Compressed bytes are counted as they are read, and tar stops reading at its
+    // end-of-archive blocks: the rest of the stream (padding, the gzip trailer, any further
+    // members) is never read and the count would stall short of the file size
+    progress_callback(100.0, filename);
+    Ok(())
+}
+
+#[cfg(feature = "age-support")]
+pub async fn decompress_tar_gz_age_with_progress<F>(
+    file_path: &Path,
+    output_dir: &Path,
+    key: &AgeKey,
+    cancel: &CancellationToken,
+    progress_callback: F,
+) -> Result<(), CompressionError>
+where
+    F: FnMut(f64, String),
+{
+    let output_existed = holds_anything(output_dir);
+    let result = async {
+        let file = File::open(file_path)?;
+        let file_size = file.metadata()?.len();
+        let filename = file_path.file_name().unwrap_or_default().to_string_lossy().to_string();
+        let progress_reader = ProgressReader::new(file, file_size, filename, cancel.clone(), progress_callback);
+        let decryptor = age::Decryptor::new(BufReader::new(progress_reader))?;
+
+        let reader = match key {
+            AgeKey::Passphrase(passphrase) => {
+                let identity = age::scrypt::Identity::new(age::secrecy::SecretString::from(passphrase.clone()));
+                decryptor.decrypt(std::iter::once(&identity as &dyn age::Identity))?
+            }
+            AgeKey::IdentityFile(path) => {
+                let identities = age::IdentityFile::from_file(path.to_string_lossy().to_string())
+                    .with_context(|| format!("Failed to read age identity file: {}", path.display()))?
+                    .into_identities()?;
+                decryptor.decrypt(identities.iter().map(|identity| identity.as_ref() as &dyn age::Identity))?
+            }
+            AgeKey::Recipient(_) => {
+                return Err(anyhow::anyhow!("A recipient public key cannot decrypt; use the matching identity file"));
+            }
+        };
+
+        let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(reader));
+        archive.set_preserve_mtime(true);
+        std::fs::create_dir_all(output_dir)?;
+        // Plain overwriting extraction, through the same path as other tars for its hard links
+        let conflicts = ConflictHandling { policy: ConflictPolicy::Overwrite, backup_on_overwrite: None };
+        unpack_tar_with_policy(archive, output_dir, &EntryRouter::new(&[], output_dir, false)?, conflicts, &mut ExtractSummary::default())
+    }.await;
+
+    if result.is_err() && cancel.load(Ordering::SeqCst) {
+        if !output_existed {
+            let _ = std::fs::remove_dir_all(output_dir);
+        }
+        return Err(CompressionError::Cancelled);
+    }
+    result.map_err(CompressionError::from)
+}
+
+fn unpack_tar_with_policy<R: Read>(
+    mut archive: tar::Archive<R>,
+    output_dir: &Path,
+    router: &EntryRouter,
+    conflicts: ConflictHandling,
+    summary: &mut ExtractSummary,
+) -> Result<()> {
+    // Tar's own unpack replaces files without asking, so only trashing, skipping, renaming,
+    // routing and flattening need the conflict handling below
+    let plain_unpack = conflicts.policy == ConflictPolicy::Overwrite && conflicts.backup_on_overwrite.is_none() && !router.rewrites_paths();
+    // Where each entry went, for hard links to entries that were renamed or routed
+    let mut written: HashMap<PathBuf, PathBuf> = HashMap::new();
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let relative_path = entry.path()?.into_owned();
+        if !is_safe_relative_path(&relative_path) {
+            tracing::warn!(entry = %relative_path.display(), "Skipping unsafe tar entry");
+            continue;
+        }
+        let is_hard_link = entry.header().entry_type() == tar::EntryType::Link;
+        if plain_unpack && !is_hard_link {
+            // Noted as resolve_conflict does, since this path doesn't go through it
+            let outpath = output_dir.join(&relative_path);
+            if !entry.header().entry_type().is_dir() && std::fs::symlink_metadata(&outpath).is_err() {
+                summary.created.push(outpath);
+            }
+            entry.unpack_in(output_dir)?;
+            continue;
+        }
+
+        let outpath = router.output_path(&relative_path);
+        if entry.header().entry_type().is_dir() {
+            // When routing or flattening, directories only appear where their files are sent
+            if !router.rewrites_paths() {
+                if router.passes_through_symlink(&outpath) {
+                    tracing::warn!(entry = %relative_path.display(), "Skipping tar entry below a symbolic link");
+                    continue;
+                }
+                std::fs::create_dir_all(&outpath)?;
+            }
+            continue;
+        }
+
+        // Checked before resolve_conflict, which would otherwise trash or rename the file the link leads to
+        let outpath = router.claim(outpath);
+        if outpath.parent().is_some_and(|parent| router.passes_through_symlink(parent)) {
+            tracing::warn!(entry = %relative_path.display(), "Skipping tar entry below a symbolic link");
+            continue;
+        }
+        let link_source = match is_hard_link {
+            true => match hard_link_source(&entry, router, &written)? {
+                Some(source) => Some(source),
+                None => {
+                    tracing::warn!(entry = %relative_path.display(), "Skipping hard link whose target is not a file extracted here");
+                    continue;
+                },
+            },
+            false => None,
+        };
+        let outpath = match resolve_conflict(&outpath, conflicts, summary)? {
+            Some(path) => path,
+            None => continue,
+        };
+        if let Some(parent) = outpath.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        match link_source {
+            Some(source) => unpack_hard_link(&source, &outpath)?,
+            None => {
+                entry.unpack(&outpath)?;
+            },
+        }
+        written.insert(relative_path, outpath);
+    }
+    Ok(())
+}
+
+// Where the file a tar hard link points at was extracted, or None if that is not a plain file
+// inside the output directories. A symlink extracted earlier, as the target or on the way to
+// it ("d -> ~/.ssh" and then a link to "d/id_rsa"), would otherwise bring a file from outside
+// into the output by linking or copying it.
+fn hard_link_source<R: Read>(entry: &tar::Entry<R>, router: &EntryRouter, written: &HashMap<PathBuf, PathBuf>) -> Result<Option<PathBuf>> {
+    // Entry::unpack would resolve the target against the working directory
+    let target = entry.link_name()?
+        .map(|target| target.into_owned())
+        .filter(|target| is_safe_relative_path(target));
+    let Some(target) = target else {
+        return Ok(None);
+    };
+    let source = written.get(&target).cloned().unwrap_or_else(|| router.output_path(&target));
+    let plain_file = source.symlink_metadata().is_ok_and(|metadata| metadata.is_file());
+    Ok((plain_file && !router.passes_through_symlink(&source)).then_some(source))
+}
+
+// Recreate a tar hard link. Filesystems without hard links (FAT, exFAT, some network shares)
+// get a copy of the target instead, which reads the same.
+fn unpack_hard_link(source: &Path, outpath: &Path) -> Result<()> {
+    if source == outpath {
+        return Ok(());
+    }
+    // hard_link won't replace an existing file the way unpacking a regular entry does
+    if outpath.symlink_metadata().is_ok_and(|metadata| !metadata.is_dir()) {
+        std::fs::remove_file(outpath)?;
+    }
+    if let Err(e) = std::fs::hard_link(source, outpath) {
+        tracing::warn!(path = %outpath.display(), source = %source.display(), error = %e, "Could not hard link; copying instead");
+        std::fs::copy(source, outpath)
+            .with_context(|| format!("Failed to copy hard link target {}", source.display()))?;
+    }
+    Ok(())
+}
+
+async fn decompress_tar_br_with_progress<F>(
+    file_path: &Path, 
+    output_dir: &Path, 
+    limit: &OutputLimit,
+    router: &EntryRouter,
+    file_size: u64,
+    conflicts: ConflictHandling,
+    summary: &mut ExtractSummary,
+    cancel: &CancellationToken,
+    mut progress_callback: F
+) -> Result<()>
+where
+    F: FnMut(f64, String),
+{
+    let file = VolumeReader::open(file_path)?;
+    let filename = file_path.file_name().unwrap_or_default().to_string_lossy().to_string();
+    let progress_reader = ProgressReader::new(file, file_size, filename.clone(), cancel.clone(), &mut progress_callback);
+    let br_decoder = brotli::Decompressor::new(BufReader::new(progress_reader), 4096);
+    let mut archive = tar::Archive::new(limit.reader(br_decoder));
+    archive.set_preserve_mtime(true);
+    
+    std::fs::create_dir_all(output_dir)?;
+    unpack_tar_with_policy(archive, output_dir, router, conflicts, summary)?;
+    // Same as tar.gz: tar stops before the end of the stream
+    progress_callback(100.0, filename);
+    Ok(())
+}
+
+async fn decompress_gz_with_progress<F>(
+    file_path: &Path, 
+    output_dir: &Path, 
+    limit: &OutputLimit,
+    output_name: Option<&str>,
+    file_size: u64,
+    conflicts: ConflictHandling,
+    summary: &mut ExtractSummary,
+    cancel: &CancellationToken,
+    progress_callback: F
+) -> Result<()>
+where
+    F: FnMut(f64, String),
+{
+    let input = VolumeReader::open(file_path)?;
+    let filename = file_path.file_name().unwrap_or_default().to_string_lossy().to_string();
+    let progress_reader = ProgressReader::new(input, file_size, filename, cancel.clone(), progress_callback);
+    // MultiGzDecoder keeps reading past the first member so concatenated .gz files (cat a.gz b.gz) are fully extracted
+    let decoder = flate2::read::MultiGzDecoder::new(BufReader::new(progress_reader));
+    
+    std::fs::create_dir_all(output_dir)?;
+    
+    // An explicit name wins, then the original filename from the gzip header
+    let header_name = decoder.header()
+        .and_then(|h| h.filename())
+        .and_then(|filename_bytes| std::str::from_utf8(filename_bytes).ok())
+        .map(std::ffi::OsString::from);
+    
+    match output_name.map(std::ffi::OsString::from).or(header_name) {
+        Some(name) => write_decoded_output(limit.reader(decoder), output_dir, name, false, conflicts, summary),
+        // If no usable filename in header, infer it from the compressed filename
+        None => write_decoded_output(limit.reader(decoder), output_dir, fallback_filename_from_compressed(file_path), true, conflicts, summary),
+    }
+}
+
+// Write a decoded single-stream payload. With `sniff`, a name without an extension gets one
+// only if the first bytes match a well-known signature.
+fn write_decoded_output<R: Read>(
+    mut decoder: R,
+    output_dir: &Path,
+    mut output_name: std::ffi::OsString,
+    sniff: bool,
+    conflicts: ConflictHandling,
+    summary: &mut ExtractSummary,
+) -> Result<()> {
+    // The name may come from the gzip header, which is no more trusted than an archive entry
+    if !is_safe_relative_path(Path::new(&output_name)) {
+        return Err(CompressionError::PathTraversal(output_name.to_string_lossy().into_owned()).into());
+    }
+    let mut head = Vec::new();
+    if sniff && Path::new(&output_name).extension().is_none() {
+        (&mut decoder).take(512).read_to_end(&mut head)?;
+        if let Some(extension) = sniff_extension(&head) {
+            output_name.push(".");
+            output_name.push(extension);
+        }
+    }
+    
+    let output_path = match resolve_conflict(&output_dir.join(output_name), conflicts, summary)? {
+        Some(path) => path,
+        None => return Ok(()),
+    };
+    let mut output = File::create(output_path)?;
+    output.write_all(&head)?;
+    std::io::copy(&mut decoder, &mut output)?;
+    Ok(())
+}
+
+fn sniff_extension(head: &[u8]) -> Option<&'static str> {
+    if head.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("png")
+    } else if head.starts_with(b"\xff\xd8\xff") {
+        Some("jpg")
+    } else if head.starts_with(b"GIF87a") || head.starts_with(b"GIF89a") {
+        Some("gif")
+    } else if head.starts_with(b"%PDF-") {
+        Some("pdf")
+    } else if head.starts_with(b"PK\x03\x04") {
+        Some("zip")
+    } else if head.len() >= 262 && &head[257..262] == b"ustar" {
+        Some("tar")
+    } else {
+        None
+    }
+}
+
+fn fallback_filename_from_compressed(file_path: &Path) -> std::ffi::OsString {
+    let joined_path = first_volume_base(file_path);
+    let file_path = joined_path.as_deref().unwrap_or(file_path);
+    let filename = file_path.file_name().unwrap_or_default().to_string_lossy();
+    
+    // Handle different compression formats
+    let (base_name, stripped) = match match_archive_suffix(&filename) {
+        Some((suffix, "gz" | "br" | "bz2")) => (&filename[..filename.len() - suffix.len()], true),
+        _ => (filename.as_ref(), false),
+    };
+    
+    if stripped && !base_name.is_empty() {
+        // If we stripped a compression extension and there's still a base name, use it as-is
+        std::ffi::OsString::from(base_name)
+    } else {
+        // Fallback to file stem if we couldn't parse the format
+        file_path.file_stem().unwrap_or_default().to_os_string()
+    }
+}
+
+async fn decompress_br_with_progress<F>(
+    file_path: &Path, 
+    output_dir: &Path, 
+    limit: &OutputLimit,
+    output_name: Option<&str>,
+    file_size: u64,
+    conflicts: ConflictHandling,
+    summary: &mut ExtractSummary,
+    cancel: &CancellationToken,
+    progress_callback: F
+) -> Result<()>
+where
+    F: FnMut(f64, String),
+{
+    let input = VolumeReader::open(file_path)?;
+    let filename = file_path.file_name().unwrap_or_default().to_string_lossy().to_string();
+    let progress_reader = ProgressReader::new(input, file_size, filename, cancel.clone(), progress_callback);
+    let decoder = brotli::Decompressor::new(BufReader::new(progress_reader), 4096);
+    
+    std::fs::create_dir_all(output_dir)?;
+    
+    // Use improved filename logic unless a name was given
+    match output_name {
+        Some(name) => write_decoded_output(limit.reader(decoder), output_dir, name.into(), false, conflicts, summary),
+        None => write_decoded_output(limit.reader(decoder), output_dir, fallback_filename_from_compressed(file_path), true, conflicts, summary),
+    }
+}
+
+async fn decompress_bzip2_with_progress<F>(
+    file_path: &Path, 
+    output_dir: &Path, 
+    limit: &OutputLimit,
+    output_name: Option<&str>,
+    file_size: u64,
+    conflicts: ConflictHandling,
+    summary: &mut ExtractSummary,
+    cancel: &CancellationToken,
+    progress_callback: F
+) -> Result<()>
+where
+    F: FnMut(f64, String),
+{
+    let input = VolumeReader::open(file_path)?;
+    let filename = file_path.file_name().unwrap_or_default().to_string_lossy().to_string();
+    let progress_reader = ProgressReader::new(input, file_size, filename, cancel.clone(), progress_callback);
+    let decoder = bzip2::read::BzDecoder::new(BufReader::new(progress_reader));
+    
+    std::fs::create_dir_all(output_dir)?;
+    
+    // Use improved filename logic unless a name was given
+    match output_name {
+        Some(name) => write_decoded_output(limit.reader(decoder), output_dir, name.into(), false, conflicts, summary),
+        None => write_decoded_output(limit.reader(decoder), output_dir, fallback_filename_from_compressed(file_path), true, conflicts, summary),
+    }
+}
+
+#[cfg(feature = "rar-support")]
+async fn decompress_rar(file_path: &Path, output_dir: &Path) -> Result<()> {
+    use unrar::Archive;
+    
+    std::fs::create_dir_all(output_dir)?;
+    
+    // Convert to owned strings to avoid lifetime issues
+    let file_path_str = file_path.to_str()
+        .ok_or_else(|| anyhow::anyhow!("Invalid file path"))?
+        .to_string();
+    let output_dir_str = output_dir.to_str()
+        .ok_or_else(|| anyhow::anyhow!("Invalid output directory path"))?
+        .to_string();
+    
+    // Handle the unrar specific error types using spawn_blocking for thread safety
+    tokio::task::spawn_blocking(move || {
+        Archive::new(file_path_str)
+            .extract_to(output_dir_str)
+            .map_err(|e| anyhow::anyhow!("RAR extraction failed: {:?}", e))?
+            .process()
+            .map_err(|e| anyhow::anyhow!("RAR processing failed: {:?}", e))?;
+        Ok::<(), anyhow::Error>(())
+    }).await??;
+    
+    Ok(())
+}
+
+#[cfg(feature = "sevenz-support")]
+async fn decompress_7z<F>(
+    file_path: &Path,
+    output_dir: &Path,
+    limit: &OutputLimit,
+    router: &EntryRouter,
+    conflicts: ConflictHandling,
+    summary: &mut ExtractSummary,
+    cancel: &CancellationToken,
+    mut progress_callback: F
+) -> Result<()>
+where
+    F: FnMut(f64, String),
+{
+    use sevenz_rust::{Password, SevenZReader};
+
+    let archive_name = file_path.file_name().unwrap_or_default().to_string_lossy().to_string();
+    let volumes = VolumeReader::open(file_path)?;
+    let archive_size = volumes.len();
+    let mut reader = SevenZReader::new(volumes, archive_size, Password::empty())
+        .map_err(|e| anyhow::anyhow!("Failed to open 7z archive: {}", e))?;
+    
+    std::fs::create_dir_all(output_dir)?;
+
+    let total_entries = reader.archive().files.len().max(1);
+    let mut processed_entries = 0usize;
+
+    reader.for_each_entries(|entry, data| {
+        if cancel.load(Ordering::SeqCst) {
+            return Err(cancelled_io_error().into());
+        }
+
+        // Update progress based on entry count
+        let progress = (processed_entries as f64 / total_entries as f64) * 100.0;
+        progress_callback(progress, archive_name.clone());
+        processed_entries += 1;
+
+        let relative_path = Path::new(entry.name());
+        if !is_safe_relative_path(relative_path) {
+            tracing::warn!(entry = entry.name(), "Skipping unsafe 7z entry");
+            std::io::copy(data, &mut std::io::sink())?;
+            return Ok(true);
+        }
+
+        let outpath = router.output_path(relative_path);
+        if entry.is_directory() {
+            // When routing or flattening, directories only appear where their files are sent
+            if !router.rewrites_paths() {
+                std::fs::create_dir_all(&outpath)?;
+            }
+        } else if let Some(outpath) = resolve_conflict(&router.claim(outpath), conflicts, summary)? {
+            if let Some(p) = outpath.parent() {
+                std::fs::create_dir_all(p)?;
+            }
+            let mut outfile = File::create(&outpath)?;
+            std::io::copy(&mut limit.reader(data), &mut outfile)?;
+        } else {
+            // Solid archives still need the skipped entry's data consumed
+            std::io::copy(data, &mut std::io::sink())?;
+        }
+        Ok(true)
+    }).map_err(|e| anyhow::anyhow!("7z extraction failed: {}", e))?;
+
+    progress_callback(100.0, archive_name);
+    Ok(())
+}
+
+const TAR_BLOCK_SIZE: u64 = 512;
+// A tar archive closes with two zero-filled blocks
+const TAR_END_SIZE: usize = 2 * TAR_BLOCK_SIZE as usize;
+const SEVENZ_SIGNATURE: &[u8] = b"7z\xBC\xAF\x27\x1C";
+const SEVENZ_START_HEADER_SIZE: u64 = 32;
+
+// Whether an archive is all there, so a partial download can be flagged before extracting it.
+// Zip and 7z are judged by whether the directory their headers point to fits in the file, and a
+// plain tar by its closing blocks, all without reading the contents. Compressed streams have no
+// index, so they are decoded to check they end where their format says; gzip also checks its
+// CRC and ISIZE trailer. Ok(false) means the data stops early. Damaged data and formats that
+// can't be checked are errors.
+pub fn is_complete_archive(path: &Path) -> Result<bool, CompressionError> {
+    let file_name = path.file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or("");
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len();
+
+    match archive_extension(path).unwrap_or("") {
+        "zip" => {
+            let (end_offset, _) = match find_zip_end_record(&mut file)? {
+                Some(end) => end,
+                None => return Ok(false),
+            };
+            let (offset, size) = zip_central_directory_range(&mut file)?;
+            Ok(offset.checked_add(size).is_some_and(|directory_end| directory_end <= end_offset))
+        },
+        "7z" => {
+            if len < SEVENZ_START_HEADER_SIZE {
+                return Ok(false);
+            }
+            let start_header = read_at(&mut file, 0, SEVENZ_START_HEADER_SIZE)?;
+            if !start_header.starts_with(SEVENZ_SIGNATURE) {
+                return Err(anyhow::anyhow!("'{}' is not a 7z archive", file_name).into());
+            }
+            let next_header_end = SEVENZ_START_HEADER_SIZE
+                .checked_add(le_u64(&start_header, 12))
+                .and_then(|end| end.checked_add(le_u64(&start_header, 20)));
+            Ok(next_header_end.is_some_and(|end| end <= len))
+        },
+        "tar" => {
+            if len < TAR_END_SIZE as u64 || len % TAR_BLOCK_SIZE != 0 {
+                return Ok(false);
+            }
+            let tail = read_at(&mut file, len - TAR_END_SIZE as u64, TAR_END_SIZE as u64)?;
+            Ok(tail.iter().all(|&byte| byte == 0))
+        },
+        extension @ ("tar.gz" | "tar.br" | "gz" | "br" | "bz2") => {
+            let input_ended = Arc::new(AtomicBool::new(false));
+            let input = EndTrackingReader { inner: file, ended: input_ended.clone() };
+            let decoder: Box<dyn Read> = match extension {
+                "tar.gz" => Box::new(flate2::read::GzDecoder::new(BufReader::new(input))),
+                "tar.br" => Box::new(brotli::Decompressor::new(BufReader::new(input), 4096)),
+                _ => single_stream_decoder(input, extension),
+            };
+            Ok(match decode_to_end(decoder, &input_ended)? {
+                Some((decoded, tail)) if extension.starts_with("tar.") => ends_like_tar(decoded, &tail),
+                Some(_) => true,
+                None => false,
+            })
+        },
+        "tar.gz.age" => Err(anyhow::anyhow!("'{}' is age-encrypted and cannot be checked without a key", file_name).into()),
+        _ => Err(CompressionError::UnsupportedFormat(file_name.to_string())),
+    }
+}
+
+// Records when its input has run out. Brotli reports a stream cut short as invalid data, the same
+// as damage, so an error after the end of the input is what tells truncation apart.
+struct EndTrackingReader<R> {
+    inner: R,
+    ended: Arc<AtomicBool>,
+}
+
+impl<R: Read> Read for EndTrackingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if n == 0 && !buf.is_empty() {
+            self.ended.store(true, Ordering::SeqCst);
+        }
+        Ok(n)
+    }
+}
+
+// Decode a whole stream, returning its decoded length and last two tar blocks' worth of data,
+// or None when the input runs out before the stream ends
+fn decode_to_end<R: Read>(mut decoder: R, input_ended: &AtomicBool) -> Result<Option<(u64, Vec<u8>)>> {
+    let mut buffer = vec![0u8; 64 * 1024];
+    let mut tail = Vec::with_capacity(TAR_END_SIZE + buffer.len());
+    let mut decoded = 0u64;
+    loop {
+        let n = match decoder.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof || input_ended.load(Ordering::SeqCst) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        decoded += n as u64;
+        tail.extend_from_slice(&buffer[..n]);
+        if tail.len() > TAR_END_SIZE {
+            tail.drain(..tail.len() - TAR_END_SIZE);
+        }
+    }
+    Ok(Some((decoded, tail)))
+}
+
+fn ends_like_tar(decoded: u64, tail: &[u8]) -> bool {
+    decoded % TAR_BLOCK_SIZE == 0 && tail.len() == TAR_END_SIZE && tail.iter().all(|&byte| byte == 0)
+}
+
+// Read every entry through a null sink so decoders and CRC checks run without writing to disk
+pub fn verify_archive(path: &Path) -> Result<(), CompressionError> {
+    let file_name = path.file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or("");
+
+    let extension = archive_extension(path).unwrap_or("");
+
+    let mut sink = std::io::sink();
+
+    if extension == "tar.gz.age" {
+        return Err(anyhow::anyhow!("'{}' is age-encrypted and cannot be verified without a key", file_name).into());
+    } else if extension == "tar.gz" {
+        let decoder = flate2::read::GzDecoder::new(BufReader::new(File::open(path)?));
+        verify_tar_entries(tar::Archive::new(decoder))?;
+    } else if extension == "tar.br" {
+        let decoder = brotli::Decompressor::new(BufReader::new(File::open(path)?), 4096);
+        verify_tar_entries(tar::Archive::new(decoder))?;
+    } else {
+        match extension {
+            "tar" => verify_tar_entries(tar::Archive::new(BufReader::new(File::open(path)?)))?,
+            "zip" => {
+                let mut archive = zip::ZipArchive::new(File::open(path)?)?;
+                for i in 0..archive.len() {
+                    let mut entry = archive.by_index(i)?;
+                    let name = entry.name().to_string();
+                    // The zip reader checks the CRC once the entry is read to the end
+                    std::io::copy(&mut entry, &mut sink)
+                        .with_context(|| format!("Entry '{}' is corrupt", name))?;
+                }
+            },
+            "gz" => {
+                let mut decoder = flate2::read::MultiGzDecoder::new(BufReader::new(File::open(path)?));
+                std::io::copy(&mut decoder, &mut sink)?;
+            },
+            "br" => {
+                let mut decoder = brotli::Decompressor::new(BufReader::new(File::open(path)?), 4096);
+                std::io::copy(&mut decoder, &mut sink)?;
+            },
+            "bz2" => {
+                let mut decoder = bzip2::read::BzDecoder::new(BufReader::new(File::open(path)?));
+                std::io::copy(&mut decoder, &mut sink)?;
+            },
+            #[cfg(feature = "sevenz-support")]
+            "7z" => {
+                use sevenz_rust::{Password, SevenZReader};
+
+                let mut reader = SevenZReader::open(path, Password::empty())
+                    .map_err(|e| anyhow::anyhow!("Failed to open 7z archive: {}", e))?;
+                reader.for_each_entries(|_, data| {
+                    std::io::copy(data, &mut sink)?;
+                    Ok(true)
+                }).map_err(|e| anyhow::anyhow!("7z verification failed: {}", e))?;
+            },
+            _ => return Err(CompressionError::UnsupportedFormat(file_name.to_string())),
+        }
+    }
+
+    Ok(())
+}
+
+fn verify_tar_entries<R: Read>(mut archive: tar::Archive<R>) -> Result<()> {
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let name = entry.path()?.display().to_string();
+        std::io::copy(&mut entry, &mut std::io::sink())
+            .with_context(|| format!("Entry '{}' is corrupt", name))?;
+    }
+    Ok(())
+}
+
+// Repackage an archive into another format, streaming entries from the source decoder into the
+// target encoder so nothing is extracted to disk. Formats only the extractors can read (7z, rar)
+// go through a temporary directory instead. `preset` picks the target's compression level.
+pub async fn convert_archive<F>(
+    input_path: &Path,
+    output_path: &Path,
+    target_type: CompressionType,
+    preset: CompressionPreset,
+    cancel: &CancellationToken,
+    progress_callback: F,
+) -> Result<(), CompressionError>
+where
+    F: FnMut(f64, String),
+{
+    if is_same_file_path(input_path, output_path) {
+        return Err(anyhow::anyhow!("Input and output must be different files").into());
+    }
+
+    let special_output = is_special_output(output_path);
+    if special_output && matches!(target_type, CompressionType::Zip) {
+        return Err(anyhow::anyhow!("Zip archives need a seekable output file; use .tar.gz or .tar.br to write to a pipe").into());
+    }
+
+    let result = if matches!(archive_extension(input_path), Some("7z" | "rar")) {
+        convert_via_temp_dir(input_path, output_path, target_type, preset, cancel, progress_callback).await
+    } else {
+        convert_archive_entries(input_path, output_path, &target_type, preset, cancel, progress_callback)
+    };
+
+    if result.is_err() {
+        // Don't leave a half-written archive behind
+        if !special_output {
+            let _ = std::fs::remove_file(output_path);
+        }
+        if cancel.load(Ordering::SeqCst) {
+            return Err(CompressionError::Cancelled);
+        }
+    }
+    result.map_err(CompressionError::from)
+}
+
+// Whether two paths name the same file once `.`, `..` and symlinks are resolved. The output
+// usually doesn't exist yet, so its parent is resolved and the file name joined back on.
+fn is_same_file_path(a: &Path, b: &Path) -> bool {
+    fn resolve(path: &Path) -> PathBuf {
+        if let Ok(path) = path.canonicalize() {
+            return path;
+        }
+        match (path.parent(), path.file_name()) {
+            (Some(parent), Some(name)) => {
+                let parent = if parent.as_os_str().is_empty() { Path::new(".") } else { parent };
+                parent.canonicalize().map(|parent| parent.join(name)).unwrap_or_else(|_| path.to_path_buf())
+            },
+            _ => path.to_path_buf(),
+        }
+    }
+    a == b || resolve(a) == resolve(b)
+}
+
+// Extract into a private temporary directory, then compress what came out: a lone extracted
+// file becomes the only entry, so single-stream targets work for one-file sources. The
+// directory is removed whether or not the conversion succeeds.
+async fn convert_via_temp_dir<F>(
+    input_path: &Path,
+    output_path: &Path,
+    target_type: CompressionType,
+    preset: CompressionPreset,
+    cancel: &CancellationToken,
+    mut progress_callback: F,
+) -> Result<()>
+where
+    F: FnMut(f64, String),
+{
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or_default();
+    let temp_dir = std::env::temp_dir().join(format!("tauzip-convert-{}-{}", std::process::id(), nanos));
+    std::fs::create_dir_all(&temp_dir)?;
+
+    let result = async {
+        // Each half of the work gets half of the progress bar
+        decompress_files_with_progress(input_path, &temp_dir, &DecompressOptions::default(), cancel, |progress, name| {
+            progress_callback(progress / 2.0, name)
+        }).await?;
+
+        let mut entries = std::fs::read_dir(&temp_dir)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<std::io::Result<Vec<_>>>()?;
+        entries.sort();
+        if entries.is_empty() {
+            return Err(anyhow::anyhow!("{} has no entries to convert", input_path.display()));
+        }
+        if !target_type.supports_multiple_files() && (entries.len() > 1 || entries[0].is_dir()) {
+            return Err(anyhow::anyhow!(
+                "{} files hold a single file, but {} has {} entries",
+                target_type.extension(), input_path.display(), entries.len()
+            ));
+        }
+
+        let options = CompressOptions { overwrite: true, preset, ..Default::default() };
+        compress_files_with_progress(&entries, output_path, target_type, &options, cancel, |progress, name, _| {
+            progress_callback(50.0 + progress / 2.0, name)
+        }).await?;
+        Ok(())
+    }.await;
+
+    let _ = std::fs::remove_dir_all(&temp_dir);
+    result
+}
+
+fn convert_archive_entries<F>(
+    input_path: &Path,
+    output_path: &Path,
+    target_type: &CompressionType,
+    preset: CompressionPreset,
+    cancel: &CancellationToken,
+    mut progress_callback: F,
+) -> Result<()>
+where
+    F: FnMut(f64, String),
+{
+    let file_name = input_path.file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or("");
+
+    let extension = archive_extension(input_path).unwrap_or("");
+
+    let archive_name = file_name.to_string();
+    let file_size = std::fs::metadata(input_path)?.len();
+
+    if extension == "tar.gz.age" {
+        return Err(anyhow::anyhow!("Converting age-encrypted archives is not supported"));
+    }
+
+    let mut sink = ConvertSink::create(output_path, target_type, preset)?;
+
+    if extension == "tar.gz" {
+        let reader = ProgressReader::new(File::open(input_path)?, file_size, archive_name, cancel.clone(), progress_callback);
+        let decoder = flate2::read::MultiGzDecoder::new(BufReader::new(reader));
+        convert_tar_entries(tar::Archive::new(decoder), &mut sink)?;
+    } else if extension == "tar.br" {
+        let reader = ProgressReader::new(File::open(input_path)?, file_size, archive_name, cancel.clone(), progress_callback);
+        let decoder = brotli::Decompressor::new(BufReader::new(reader), 4096);
+        convert_tar_entries(tar::Archive::new(decoder), &mut sink)?;
+    } else if extension == "tar" {
+        let reader = ProgressReader::new(File::open(input_path)?, file_size, archive_name, cancel.clone(), progress_callback);
+        convert_tar_entries(tar::Archive::new(BufReader::new(reader)), &mut sink)?;
+    } else if extension == "zip" {
+        // ZipArchive needs to seek, so progress is reported per entry instead of per byte
+        let mut archive = zip::ZipArchive::new(BufReader::new(File::open(input_path)?))?;
+        let aliases = read_dedup_manifest(&mut archive)?;
+        let total_entries = archive.len().max(1);
+        for i in 0..archive.len() {
+            check_cancelled(cancel)?;
+            progress_callback((i as f64 / total_entries as f64) * 100.0, archive_name.clone());
+
+            let mut file = archive.by_index(i)?;
+            let name = zip_entry_name(&file);
+            // The target gets every alias as a full copy, so the manifest has nothing to list
+            if aliases.is_some() && name == DEDUP_MANIFEST_NAME {
+                continue;
+            }
+            let original = aliases.as_ref()
+                .and_then(|aliases| aliases.get(&name))
+                .filter(|_| file.size() == 0)
+                .cloned();
+            let mut entry = ConvertEntry {
+                name,
+                is_dir: file.is_dir(),
+                mode: file.unix_mode(),
+                mtime: extended_mtime(file.extra_data())
+                    .or_else(|| file.last_modified().to_time().ok().map(|t| (t.unix_timestamp(), 0))),
+                size: Some(file.size()),
+            };
+            // A deduplicated zip stores repeats as empty entries; their contents live in the original
+            match original {
+                Some(original) => {
+                    drop(file);
+                    let mut contents = archive.by_name(&original)
+                        .with_context(|| format!("'{}' refers to the missing entry '{}'", entry.name, original))?;
+                    entry.size = Some(contents.size());
+                    sink.add(&entry, &mut contents)?;
+                },
+                None => sink.add(&entry, &mut file)?,
+            }
+        }
+    } else if matches!(extension, "gz" | "br" | "bz2") {
+        // A single-stream source becomes one entry named like its decompressed file
+        let header_name = if extension == "gz" {
+            flate2::read::GzDecoder::new(BufReader::new(File::open(input_path)?))
+                .header()
+                .and_then(|h| h.filename())
+                .and_then(|bytes| std::str::from_utf8(bytes).ok())
+                .map(|name| name.to_string())
+        } else {
+            None
+        };
+        let name = header_name
+            .unwrap_or_else(|| fallback_filename_from_compressed(input_path).to_string_lossy().to_string());
+
+        // Tar headers need the size up front, which single-stream formats don't record; other
+        // targets go without it rather than decode the source twice
+        let size = if sink.needs_size() {
+            let mut decoder = single_stream_decoder(File::open(input_path)?, extension);
+            Some(std::io::copy(&mut decoder, &mut std::io::sink())?)
+        } else {
+            None
+        };
+
+        let reader = ProgressReader::new(File::open(input_path)?, file_size, archive_name, cancel.clone(), progress_callback);
+        let mut decoder = single_stream_decoder(reader, extension);
+        let entry = ConvertEntry {
+            name,
+            is_dir: false,
+            mode: None,
+            mtime: std::fs::metadata(input_path)?.modified().ok()
+                .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+                .map(|d| (d.as_secs() as i64, d.subsec_nanos())),
+            size,
+        };
+        sink.add(&entry, &mut decoder)?;
+    } else {
+        return Err(anyhow::anyhow!("Unsupported source format for conversion: {}", file_name));
+    }
+
+    sink.finish()
+}
+
+// Decode a single-stream file (.gz, .br, .bz2) into `writer`, e.g. stdout, and return the
+// decoded size. Zip and tar archives hold several entries and are rejected.
+pub fn decompress_to_writer<W: Write>(file_path: &Path, writer: &mut W) -> Result<u64, CompressionError> {
+    let extension = archive_extension(file_path).unwrap_or("");
+    if !matches!(extension, "gz" | "br" | "bz2") {
+        return Err(anyhow::anyhow!(
+            "'{}' is not a single-stream file; only .gz, .br and .bz2 can be written to one output",
+            file_path.display()
+        ).into());
+    }
+
+    let mut decoder = single_stream_decoder(File::open(file_path)?, extension);
+    Ok(std::io::copy(&mut decoder, writer)?)
+}
+
+fn single_stream_decoder<'a, R: Read + 'a>(reader: R, extension: &str) -> Box<dyn Read + 'a> {
+    let reader = BufReader::new(reader);
+    match extension {
+        "gz" => Box::new(flate2::read::MultiGzDecoder::new(reader)),
+        "br" => Box::new(brotli::Decompressor::new(reader, 4096)),
+        _ => Box::new(bzip2::read::BzDecoder::new(reader)),
+    }
+}
+
+// Gzip a buffer in memory, for callers that don't write the result to a file (e.g. base64
+// text on stdout)
+pub fn compress_bytes(data: &[u8], level: u32) -> Result<Vec<u8>, CompressionError> {
+    let mut encoder = GzEncoder::new(Vec::new(), FlateCompression::new(level));
+    encoder.write_all(data)?;
+    Ok(encoder.finish()?)
+}
+
+// Decode a gzip stream held in memory into `writer` and return the decoded size. The output
+// is streamed, so a small input that expands a lot never has to fit in memory.
+pub fn decompress_bytes<W: Write>(data: &[u8], writer: &mut W) -> Result<u64, CompressionError> {
+    let mut decoder = flate2::read::MultiGzDecoder::new(data);
+    Ok(std::io::copy(&mut decoder, writer)?)
+}
+
+fn convert_tar_entries<R: Read>(mut archive: tar::Archive<R>, sink: &mut ConvertSink) -> Result<()> {
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let name = entry.path()?.to_string_lossy().replace('\\', "/");
+        let entry_type = entry.header().entry_type();
+        if !entry_type.is_dir() && !entry_type.is_file() {
+            tracing::warn!(entry = %name, "Skipping unsupported tar entry");
+            continue;
+        }
+
+        let info = ConvertEntry {
+            name,
+            is_dir: entry_type.is_dir(),
+            mode: entry.header().mode().ok(),
+            mtime: entry.header().mtime().ok().map(|m| (m as i64, 0)),
+            size: Some(entry.size()),
+        };
+        sink.add(&info, &mut entry)?;
+    }
+    Ok(())
+}
+
+// Metadata carried over for each entry during a conversion. The modification time is seconds
+// and nanoseconds since the epoch; the size is None when the source doesn't record it.
+struct ConvertEntry {
+    name: String,
+    is_dir: bool,
+    mode: Option<u32>,
+    mtime: Option<(i64, u32)>,
+    size: Option<u64>,
+}
+
+// Target side of a conversion
+enum ConvertSink {
+    // The deflate level for each entry
+    Zip(ZipWriter<BufWriter<File>>, Option<u32>),
+    Tar(TarBuilder<ConvertEncoder>),
+    // Single-stream formats hold exactly one file; the flag records whether it was written
+    Single(ConvertEncoder, bool),
+}
+
+// The stream under a tar or single-file target. Kept concrete rather than boxed so finish()
+// can write each encoder's trailer and report a failure to do so.
+enum ConvertEncoder {
+    Plain(BufWriter<File>),
+    Gz(GzEncoder<BufWriter<File>>),
+    Br(brotli::CompressorWriter<BufWriter<File>>),
+    Bzip2(bzip2::write::BzEncoder<BufWriter<File>>),
+}
+
+impl ConvertEncoder {
+    fn finish(self) -> Result<()> {
+        let mut file = match self {
+            ConvertEncoder::Plain(file) => file,
+            ConvertEncoder::Gz(encoder) => encoder.finish()?,
+            ConvertEncoder::Br(encoder) => encoder.into_inner(),
+            ConvertEncoder::Bzip2(encoder) => encoder.finish()?,
+        };
+        file.flush()?;
+        Ok(())
+    }
+}
+
+impl Write for ConvertEncoder {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            ConvertEncoder::Plain(file) => file.write(buf),
+            ConvertEncoder::Gz(encoder) => encoder.write(buf),
+            ConvertEncoder::Br(encoder) => encoder.write(buf),
+            ConvertEncoder::Bzip2(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            ConvertEncoder::Plain(file) => file.flush(),
+            ConvertEncoder::Gz(encoder) => encoder.flush(),
+            ConvertEncoder::Br(encoder) => encoder.flush(),
+            ConvertEncoder::Bzip2(encoder) => encoder.flush(),
+        }
+    }
+}
+
+impl ConvertSink {
+    fn create(output_path: &Path, target_type: &CompressionType, preset: CompressionPreset) -> Result<Self> {
+        let file = File::create(output_path)
+            .with_context(|| format!("Failed to create output file: {}", output_path.display()))?;
+        let file = BufWriter::new(file);
+
+        let level = preset.level(target_type).unwrap_or(DEFAULT_COMPRESSION_LEVEL);
+        let gz = |file| ConvertEncoder::Gz(GzEncoder::new(file, FlateCompression::new(level)));
+        let br = |file| ConvertEncoder::Br(brotli::CompressorWriter::new(file, 4096, level.min(BROTLI_MAX_QUALITY), BROTLI_WINDOW_BITS));
+
+        Ok(match target_type {
+            CompressionType::Zip => ConvertSink::Zip(ZipWriter::new(file), Some(level)),
+            CompressionType::Tar => ConvertSink::Tar(TarBuilder::new(ConvertEncoder::Plain(file))),
+            CompressionType::TarGz => ConvertSink::Tar(TarBuilder::new(gz(file))),
+            CompressionType::TarBr => ConvertSink::Tar(TarBuilder::new(br(file))),
+            CompressionType::Gz | CompressionType::Gzip => ConvertSink::Single(gz(file), false),
+            CompressionType::Br => ConvertSink::Single(br(file), false),
+            CompressionType::Bzip2 => ConvertSink::Single(ConvertEncoder::Bzip2(bzip2::write::BzEncoder::new(file, bzip2::Compression::new(level))), false),
+        })
+    }
+
+    fn needs_size(&self) -> bool {
+        matches!(self, ConvertSink::Tar(_))
+    }
+
+    fn add(&mut self, entry: &ConvertEntry, data: &mut dyn Read) -> Result<()> {
+        let default_mode = if entry.is_dir { 0o755 } else { 0o644 };
+        let mode = entry.mode.map(|m| m & 0o7777).unwrap_or(default_mode);
+
+        match self {
+            ConvertSink::Zip(zip, level) => {
+                // An unknown size may still pass 4 GiB, so it gets zip64 fields too
+                let mut options = FileOptions::default()
+                    .compression_method(zip::CompressionMethod::Deflated)
+                    .compression_level(level.map(|level| level as i32))
+                    .unix_permissions(mode)
+                    .large_file(entry.size.is_none_or(|size| size > u32::MAX as u64));
+                if let Some(mtime) = entry.mtime.and_then(|(seconds, _)| zip_datetime_from_unix(seconds)) {
+                    options = options.last_modified_time(mtime);
+                }
+
+                // The DOS time only has two-second resolution, so the full time goes in the
+                // extended timestamp fields as well
+                let precise = entry.mtime
+                    .and_then(|(seconds, nanos)| u64::try_from(seconds).ok().map(|seconds| std::time::Duration::new(seconds, nanos)));
+                if entry.is_dir {
+                    zip.add_directory(entry.name.as_str(), options)?;
+                } else if let Some(modified) = precise {
+                    zip.start_file_with_extra_data(entry.name.as_str(), options)?;
+                    zip.write_all(&timestamp_extra_fields(modified))?;
+                    zip.end_extra_data()?;
+                    std::io::copy(data, zip)?;
+                } else {
+                    zip.start_file(entry.name.as_str(), options)?;
+                    std::io::copy(data, zip)?;
+                }
+            },
+            ConvertSink::Tar(tar) => {
+                let mut header = tar::Header::new_gnu();
+                if entry.is_dir {
+                    header.set_entry_type(tar::EntryType::Directory);
+                    header.set_size(0);
+                } else {
+                    header.set_entry_type(tar::EntryType::Regular);
+                    header.set_size(entry.size.unwrap_or(0));
+                }
+                header.set_mode(mode);
+                header.set_mtime(entry.mtime.map(|(seconds, _)| seconds).unwrap_or(0).max(0) as u64);
+
+                if entry.is_dir {
+                    tar.append_data(&mut header, &entry.name, std::io::empty())?;
+                } else {
+                    tar.append_data(&mut header, &entry.name, data)?;
+                }
+            },
+            ConvertSink::Single(writer, written) => {
+                if entry.is_dir {
+                    return Ok(());
+                }
+                if *written {
+                    return Err(anyhow::anyhow!(
+                        "The source archive contains more than one file; choose zip, tar.gz or tar.br as the target"
+                    ));
+                }
+                std::io::copy(data, writer)?;
+                *written = true;
+            },
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<()> {
+        match self {
+            ConvertSink::Zip(mut zip, _) => {
+                zip.finish()?.flush()?;
+            },
+            ConvertSink::Tar(tar) => {
+                tar.into_inner()?.finish()?;
+            },
+            ConvertSink::Single(writer, written) => {
+                if !written {
+                    return Err(anyhow::anyhow!("The source archive contains no files"));
+                }
+                writer.finish()?;
+            },
+        }
+        Ok(())
+    }
+}
+
+fn zip_datetime_from_unix(seconds: i64) -> Option<zip::DateTime> {
+    time::OffsetDateTime::from_unix_timestamp(seconds).ok()
+        .and_then(|t| zip::DateTime::try_from(t).ok())
+}
+
+// Also true for the first volume of a split archive, which extracts as the whole archive
+pub fn is_compressed_file(path: &Path) -> bool {
+    archive_extension(first_volume_base(path).as_deref().unwrap_or(path)).is_some()
+}
+
+// The format of an archive judged by its first bytes rather than its name, for files whose
+// extension is missing or wrong. Brotli has no magic number, so there a .br or .tar.br name is
+// only believed if the start of the file decodes. None for missing, unreadable or unknown files.
+pub fn detect_archive_type(path: &Path) -> Option<CompressionType> {
+    let open = || VolumeReader::open(path).map(BufReader::new);
+    let is_tar = |head: &[u8]| head.len() >= 262 && &head[257..262] == b"ustar";
+    let mut head = Vec::new();
+    open().ok()?.take(512).read_to_end(&mut head).ok()?;
+
+    if head.starts_with(b"PK\x03\x04") || head.starts_with(b"PK\x05\x06") {
+        Some(CompressionType::Zip)
+    } else if head.starts_with(b"BZh") {
+        Some(CompressionType::Bzip2)
+    } else if head.starts_with(b"\x1f\x8b") {
+        // Whatever decodes before a short or damaged stream gives out is enough to spot a tar header
+        let mut decoded = Vec::new();
+        let _ = flate2::read::MultiGzDecoder::new(open().ok()?).take(512).read_to_end(&mut decoded);
+        Some(if is_tar(&decoded) { CompressionType::TarGz } else { CompressionType::Gz })
+    } else if is_tar(&head) {
+        Some(CompressionType::Tar)
+    } else {
+        let name = first_volume_base(path);
+        match CompressionType::from_file_name(name.as_deref().unwrap_or(path))? {
+            CompressionType::Br | CompressionType::TarBr => {
+                let mut decoded = Vec::new();
+                brotli::Decompressor::new(open().ok()?, 4096).take(512).read_to_end(&mut decoded).ok()?;
+                match decoded.is_empty() {
+                    true => None,
+                    false => Some(if is_tar(&decoded) { CompressionType::TarBr } else { CompressionType::Br }),
+                }
+            },
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{noise, read_tree, write_file, TempDir};
+
+    fn archive_name_of(path: &Path) -> String {
+        path.file_name().unwrap().to_string_lossy().into_owned()
+    }
+
+    fn no_cancel() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    async fn extract(archive: &Path, output_dir: &Path, options: &DecompressOptions) -> Result<ExtractSummary, CompressionError> {
+        decompress_files_with_progress(archive, output_dir, options, &no_cancel(), |_, _| {}).await
+    }
+
+    // A tar whose "d" links to `outside`, followed by a file stored as "d/evil.txt"
+    #[cfg(unix)]
+    fn write_symlink_escape_tar(path: &Path, outside: &Path) {
+        let mut builder = tar::Builder::new(File::create(path).unwrap());
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Symlink);
+        header.set_size(0);
+        header.set_mode(0o777);
+        builder.append_link(&mut header, "d", outside).unwrap();
+        let mut header = tar::Header::new_gnu();
+        header.set_size(4);
+        header.set_mode(0o644);
+        builder.append_data(&mut header, "d/evil.txt", &b"evil"[..]).unwrap();
+        builder.finish().unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn tar_entries_are_not_written_through_extracted_symlinks() {
+        let outside = TempDir::new();
+        let work = TempDir::new();
+        let archive = work.join("escape.tar");
+        write_symlink_escape_tar(&archive, outside.path());
+
+        let cases = [
+            DecompressOptions { conflict_policy: ConflictPolicy::Skip, ..Default::default() },
+            DecompressOptions { conflict_policy: ConflictPolicy::Rename, ..Default::default() },
+            DecompressOptions { backup_on_overwrite: Some(|_| Ok(())), ..Default::default() },
+            DecompressOptions { routes: vec![("*".to_string(), PathBuf::from("routed"))], ..Default::default() },
+        ];
+        for (index, options) in cases.iter().enumerate() {
+            let output_dir = work.join(format!("out{}", index));
+            extract(&archive, &output_dir, options).await.unwrap();
+            assert!(!outside.join("evil.txt").exists(), "case {} wrote through the link", index);
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn tar_hard_links_cannot_reach_outside_through_symlinks() {
+        let outside = TempDir::new();
+        write_file(&outside.join("id_rsa"), "secret");
+        let work = TempDir::new();
+        let archive = work.join("links.tar");
+        let mut builder = tar::Builder::new(File::create(&archive).unwrap());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(4);
+        header.set_mode(0o644);
+        builder.append_data(&mut header, "a.txt", &b"kept"[..]).unwrap();
+        for (entry_type, name, target) in [
+            (tar::EntryType::Symlink, "d", outside.path().to_path_buf()),
+            (tar::EntryType::Symlink, "key", outside.join("id_rsa")),
+            (tar::EntryType::Link, "loot", PathBuf::from("d/id_rsa")),
+            (tar::EntryType::Link, "loot2", PathBuf::from("key")),
+            (tar::EntryType::Link, "b.txt", PathBuf::from("a.txt")),
+        ] {
+            let mut header = tar::Header::new_gnu();
+            header.set_entry_type(entry_type);
+            header.set_size(0);
+            header.set_mode(0o644);
+            builder.append_link(&mut header, name, target).unwrap();
+        }
+        builder.finish().unwrap();
+        drop(builder);
+
+        for (index, options) in [
+            DecompressOptions::default(),
+            DecompressOptions { conflict_policy: ConflictPolicy::Rename, ..Default::default() },
+        ].iter().enumerate() {
+            let output_dir = work.join(format!("out{}", index));
+            extract(&archive, &output_dir, options).await.unwrap();
+            assert!(!output_dir.join("loot").exists(), "case {} linked a file through a symlinked folder", index);
+            assert!(!output_dir.join("loot2").exists(), "case {} linked a symlink's target", index);
+            assert_eq!(std::fs::read(output_dir.join("b.txt")).unwrap(), b"kept");
+        }
+        assert_eq!(std::fs::read(outside.join("id_rsa")).unwrap(), b"secret");
+    }
+
+    #[tokio::test]
+    async fn best_of_keeps_the_smallest_and_leaves_other_files_alone() {
+        let dir = TempDir::new();
+        let input = dir.join("notes.txt");
+        write_file(&input, "the same line over and over\n".repeat(2000));
+        // A file with a losing candidate's name that must survive even with overwrite set
+        write_file(&dir.join("backup.tar"), "mine");
+
+        let formats = [CompressionType::Tar, CompressionType::Zip, CompressionType::TarGz];
+        let options = CompressOptions { overwrite: true, ..Default::default() };
+        let best = compress_best_of(&[input.clone()], &dir.join("backup"), &formats, &options, &no_cancel(), |_, _, _| {}).await.unwrap();
+
+        assert_eq!(best.sizes.len(), formats.len());
+        let smallest = best.sizes.iter().map(|(_, size)| *size).min().unwrap();
+        assert_eq!(std::fs::metadata(&best.output_path).unwrap().len(), smallest);
+        assert!(!matches!(best.compression_type, CompressionType::Tar));
+        assert_eq!(std::fs::read(dir.join("backup.tar")).unwrap(), b"mine");
+
+        let mut names: Vec<String> = std::fs::read_dir(dir.path()).unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        let mut expected = vec!["backup.tar".to_string(), "notes.txt".to_string(), archive_name_of(&best.output_path)];
+        expected.sort();
+        assert_eq!(names, expected);
+    }
+
+    #[test]
+    fn every_gzip_and_bzip2_spelling_is_recognised() {
+        for (name, extension) in [("x.gz", "gz"), ("x.gzip", "gz"), ("x.bz2", "bz2"), ("x.bzip2", "bz2"), ("X.BZIP2", "bz2"), ("x.tgz", "tar.gz")] {
+            let path = Path::new(name);
+            assert_eq!(archive_extension(path), Some(extension), "{}", name);
+            assert!(is_compressed_file(path), "{}", name);
+            let from_name = CompressionType::from_file_name(path).map(|t| t.extension());
+            assert!(from_name.is_some(), "{}", name);
+            let spelling = name.split_once('.').unwrap().1;
+            assert_eq!(CompressionType::from_extension(spelling).map(|t| t.extension()), from_name, "{}", name);
+            assert_eq!(CompressionType::from_extension(&format!(".{}", spelling)).map(|t| t.extension()), from_name, "{}", name);
+        }
+        assert!(CompressionType::from_extension("rar").is_none());
+        assert!(CompressionType::from_extension("txt").is_none());
+        assert!(CompressionType::from_extension("x.gz").is_none());
+    }
+
+    #[tokio::test]
+    async fn gzip_and_bzip2_spellings_round_trip() {
+        let dir = TempDir::new();
+        let input = dir.join("data.txt");
+        write_file(&input, noise(10_000, 7));
+        for spelling in ["gz", "gzip", "bz2", "bzip2"] {
+            let archive = dir.join(format!("data.txt.{}", spelling));
+            let compression_type = CompressionType::from_file_name(&archive).unwrap();
+            let options = CompressOptions::default();
+            compress_files_with_progress(&[input.clone()], &archive, compression_type, &options, &no_cancel(), |_, _, _| {}).await.unwrap();
+
+            let output_dir = dir.join(format!("out-{}", spelling));
+            extract(&archive, &output_dir, &DecompressOptions::default()).await.unwrap();
+            assert_eq!(read_tree(&output_dir), vec![(PathBuf::from("data.txt"), noise(10_000, 7))], "{}", spelling);
+        }
+    }
+
+    #[tokio::test]
+    async fn extracted_size_is_read_from_the_archive_where_recorded() {
+        let dir = TempDir::new();
+        let input = dir.join("zeros.bin");
+        write_file(&input, vec![0u8; 300_000]);
+        let options = CompressOptions::default();
+
+        let gz = dir.join("zeros.bin.gz");
+        compress_files_with_progress(&[input.clone()], &gz, CompressionType::Gz, &options, &no_cancel(), |_, _, _| {}).await.unwrap();
+        assert_eq!(recorded_extracted_size(&gz).unwrap(), Some(300_000));
+
+        // Sized across the whole volume set, not just the first volume
+        let zip = dir.join("zeros.zip");
+        let split = CompressOptions { split_into: Some(3), ..Default::default() };
+        compress_files_with_progress(&[input.clone()], &zip, CompressionType::Zip, &split, &no_cancel(), |_, _, _| {}).await.unwrap();
+        assert_eq!(estimate_extracted_size(&volume_path(&zip, 1)).unwrap(), 300_000);
+
+        // Brotli records nothing, so only a guess is made, and a guess never fails the check
+        let br = dir.join("zeros.bin.br");
+        compress_files_with_progress(&[input.clone()], &br, CompressionType::Br, &options, &no_cancel(), |_, _, _| {}).await.unwrap();
+        assert_eq!(recorded_extracted_size(&br).unwrap(), None);
+        assert!(estimate_extracted_size(&br).unwrap() >= std::fs::metadata(&br).unwrap().len());
+        assert!(check_free_space(&br, dir.path(), false).is_ok());
+    }
+
+    #[tokio::test]
+    async fn output_limit_removes_what_it_wrote_even_into_an_existing_folder() {
+        let dir = TempDir::new();
+        write_file(&dir.join("in").join("a.txt"), "first");
+        write_file(&dir.join("in").join("sub").join("zeros.bin"), vec![0u8; 4 * 1024 * 1024]);
+        let inputs = [dir.join("in").join("a.txt"), dir.join("in").join("sub")];
+
+        for compression_type in [CompressionType::Zip, CompressionType::TarGz] {
+            let archive = dir.join(format!("bomb{}", compression_type.extension()));
+            compress_files_with_progress(&inputs, &archive, compression_type.clone(), &CompressOptions::default(), &no_cancel(), |_, _, _| {}).await.unwrap();
+            assert!(std::fs::metadata(&archive).unwrap().len() < 64 * 1024);
+
+            let output_dir = dir.join(format!("out{}", compression_type.extension()));
+            write_file(&output_dir.join("mine.txt"), "keep me");
+            let options = DecompressOptions { max_output_bytes: Some(1024 * 1024), ..Default::default() };
+            let result = extract(&archive, &output_dir, &options).await;
+
+            assert!(matches!(result, Err(CompressionError::DecompressionLimitExceeded(_))), "{:?}", compression_type);
+            assert_eq!(read_tree(&output_dir), vec![(PathBuf::from("mine.txt"), b"keep me".to_vec())], "{:?}", compression_type);
+            assert_eq!(std::fs::read_dir(&output_dir).unwrap().count(), 1, "{:?}", compression_type);
+        }
+    }
+
+    // A deflated local entry written the way streaming zip writers do: zero sizes in the header
+    // and the CRC and sizes in a data descriptor after the data
+    fn streamed_zip_entry(name: &str, data: &[u8]) -> Vec<u8> {
+        let mut encoder = flate2::write::DeflateEncoder::new(Vec::new(), FlateCompression::default());
+        encoder.write_all(data).unwrap();
+        let compressed = encoder.finish().unwrap();
+        let mut crc = flate2::Crc::new();
+        crc.update(data);
+
+        let mut entry = b"PK\x03\x04".to_vec();
+        for field in [20u16, ZIP_DATA_DESCRIPTOR_FLAG, ZIP_DEFLATED, 0, 0x21] {
+            entry.extend_from_slice(&field.to_le_bytes());
+        }
+        entry.extend_from_slice(&[0u8; 12]);
+        entry.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        entry.extend_from_slice(&0u16.to_le_bytes());
+        entry.extend_from_slice(name.as_bytes());
+        entry.extend_from_slice(&compressed);
+        entry.extend_from_slice(b"PK\x07\x08");
+        for field in [crc.sum(), compressed.len() as u32, data.len() as u32] {
+            entry.extend_from_slice(&field.to_le_bytes());
+        }
+        entry
+    }
+
+    #[test]
+    fn salvage_reads_entries_with_data_descriptors() {
+        let dir = TempDir::new();
+        let first = "hello ".repeat(1000);
+        let second = noise(50_000, 3);
+        let mut archive = streamed_zip_entry("a.txt", first.as_bytes());
+        archive.extend(streamed_zip_entry("docs/b.bin", &second));
+        let mut corrupt = streamed_zip_entry("bad.txt", b"the descriptor will not match");
+        let crc_at = corrupt.len() - 12;
+        corrupt[crc_at] ^= 0xff;
+        archive.extend(corrupt);
+        // Cut off partway through the last entry's data
+        let last = streamed_zip_entry("c.bin", &noise(50_000, 4));
+        archive.extend_from_slice(&last[..last.len() / 2]);
+        let path = dir.join("partial.zip");
+        write_file(&path, &archive);
+
+        let output_dir = dir.join("out");
+        let recovered = salvage_zip(&path, &output_dir).unwrap();
+        assert_eq!(recovered, vec!["a.txt".to_string(), "docs/b.bin".to_string()]);
+        assert_eq!(read_tree(&output_dir), vec![
+            (PathBuf::from("a.txt"), first.into_bytes()),
+            (PathBuf::from("docs").join("b.bin"), second),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn every_format_round_trips_through_split_volumes() {
+        let dir = TempDir::new();
+        let input = dir.join("data.bin");
+        let data = noise(3 * 1024 * 1024, 11);
+        write_file(&input, &data);
+        let options = CompressOptions { split_size: Some(1024 * 1024), ..Default::default() };
+
+        for compression_type in CompressionType::all() {
+            let archive = dir.join(format!("data.bin{}", compression_type.extension()));
+            compress_files_with_progress(&[input.clone()], &archive, compression_type.clone(), &options, &no_cancel(), |_, _, _| {}).await.unwrap();
+            assert!(!archive.exists());
+            assert!(volume_path(&archive, 3).exists() && !volume_path(&archive, 5).exists(), "{:?}", compression_type);
+
+            let output_dir = dir.join(format!("out{}", compression_type.extension()));
+            extract(&volume_path(&archive, 1), &output_dir, &DecompressOptions::default()).await.unwrap();
+            assert!(read_tree(&output_dir) == vec![(PathBuf::from("data.bin"), data.clone())], "{:?}", compression_type);
+        }
+    }
+
+    #[tokio::test]
+    async fn dedup_stores_one_copy_and_restores_every_alias() {
+        let dir = TempDir::new();
+        let input = dir.join("photos");
+        let same = noise(100_000, 5);
+        for name in ["a.jpg", "b.jpg", "c.jpg"] {
+            write_file(&input.join(name), &same);
+        }
+        write_file(&input.join("other.txt"), "different");
+        let archive = dir.join("photos.zip");
+        let options = CompressOptions { dedup: true, sort_entries: true, ..Default::default() };
+        compress_files_with_progress(&[input.clone()], &archive, CompressionType::Zip, &options, &no_cancel(), |_, _, _| {}).await.unwrap();
+
+        let mut zip = zip::ZipArchive::new(File::open(&archive).unwrap()).unwrap();
+        let aliases = read_dedup_manifest(&mut zip).unwrap().unwrap();
+        assert_eq!(aliases.len(), 2);
+        assert!(aliases.values().all(|original| original == "photos/a.jpg"));
+        assert_eq!(zip.by_name("photos/b.jpg").unwrap().size(), 0);
+        assert_eq!(zip.by_name("photos/c.jpg").unwrap().size(), 0);
+        assert!(std::fs::metadata(&archive).unwrap().len() < 150_000);
+
+        let output_dir = dir.join("out");
+        extract(&archive, &output_dir, &DecompressOptions::default()).await.unwrap();
+        assert_eq!(read_tree(&output_dir.join("photos")), read_tree(&input));
+
+        // The original is already there and kept, yet its aliases still get the archived contents
+        let skip_dir = dir.join("skip");
+        write_file(&skip_dir.join("photos").join("a.jpg"), "mine");
+        let options = DecompressOptions { conflict_policy: ConflictPolicy::Skip, ..Default::default() };
+        extract(&archive, &skip_dir, &options).await.unwrap();
+        assert_eq!(std::fs::read(skip_dir.join("photos").join("a.jpg")).unwrap(), b"mine");
+        assert_eq!(std::fs::read(skip_dir.join("photos").join("b.jpg")).unwrap(), same);
+        assert_eq!(std::fs::read(skip_dir.join("photos").join("c.jpg")).unwrap(), same);
+    }
+
+    #[tokio::test]
+    async fn parallel_zip_streams_large_files_instead_of_buffering_them() {
+        let large = PARALLEL_MAX_ENTRY_BYTES + 1;
+        assert!(estimate_memory_for_sizes(&[large, 1024], &CompressionType::Zip, 6, 4) < large);
+
+        let work = TempDir::new();
+        let input = work.join("input");
+        write_file(&input.join("small.bin"), noise(4096, 1));
+        File::create(input.join("large.bin")).unwrap().set_len(large).unwrap();
+        let archive = work.join("parallel.zip");
+        let options = CompressOptions { parallel: true, ..Default::default() };
+        compress_files_with_progress(&[input.clone()], &archive, CompressionType::Zip, &options, &no_cancel(), |_, _, _| {}).await.unwrap();
+
+        let output_dir = work.join("out");
+        extract(&archive, &output_dir, &DecompressOptions::default()).await.unwrap();
+        assert_eq!(read_tree(&output_dir.join("input")), read_tree(&input));
+    }
+
+    #[tokio::test]
+    async fn converted_archives_are_complete_in_every_target_format() {
+        let work = TempDir::new();
+        let input = work.join("input");
+        write_file(&input.join("data.bin"), noise(300_000, 7));
+        let source = work.join("source.zip");
+        compress_files_with_progress(&[input.clone()], &source, CompressionType::Zip, &CompressOptions::default(), &no_cancel(), |_, _, _| {}).await.unwrap();
+
+        for target_type in [CompressionType::Tar, CompressionType::TarGz, CompressionType::TarBr] {
+            let converted = work.join(format!("converted{}", target_type.extension()));
+            convert_archive(&source, &converted, target_type.clone(), CompressionPreset::Default, &no_cancel(), |_, _| {}).await.unwrap();
+            let output_dir = work.join(format!("out{}", target_type.extension()));
+            extract(&converted, &output_dir, &DecompressOptions::default()).await.unwrap();
+            assert_eq!(read_tree(&output_dir.join("input")), read_tree(&input), "{}", target_type.extension());
+        }
+
+        let single = work.join("single.zip");
+        compress_files_with_progress(&[input.join("data.bin")], &single, CompressionType::Zip, &CompressOptions::default(), &no_cancel(), |_, _, _| {}).await.unwrap();
+        for target_type in [CompressionType::Gz, CompressionType::Br, CompressionType::Bzip2] {
+            let converted = work.join(format!("data.bin{}", target_type.extension()));
+            convert_archive(&single, &converted, target_type.clone(), CompressionPreset::Default, &no_cancel(), |_, _| {}).await.unwrap();
+            let output_dir = work.join(format!("single{}", target_type.extension()));
+            extract(&converted, &output_dir, &DecompressOptions::default()).await.unwrap();
+            assert_eq!(std::fs::read(output_dir.join("data.bin")).unwrap(), noise(300_000, 7), "{}", target_type.extension());
+        }
+    }
+
+    #[tokio::test]
+    async fn dry_run_reports_planned_paths_and_totals_without_writing() {
+        let work = TempDir::new();
+        let input = work.join("input");
+        write_file(&input.join("a.txt"), "alpha");
+        write_file(&input.join("sub/b.txt"), "bravo!");
+        let archive = work.join("input.zip");
+        compress_files_with_progress(&[input.clone()], &archive, CompressionType::Zip, &CompressOptions::default(), &no_cancel(), |_, _, _| {}).await.unwrap();
+
+        let output_dir = work.join("out");
+        let mut reported = Vec::new();
+        let options = DecompressOptions { dry_run: true, ..Default::default() };
+        let summary = decompress_files_with_progress(&archive, &output_dir, &options, &no_cancel(), |_, name| reported.push(PathBuf::from(name))).await.unwrap();
+
+        assert_eq!(summary.planned_files, 2);
+        assert_eq!(summary.planned_size, 11);
+        reported.sort();
+        assert_eq!(reported, vec![output_dir.join("input/a.txt"), output_dir.join("input/sub/b.txt")]);
+        assert!(!output_dir.exists());
+    }
+
+    // Stands in for the trash: keeps the file next to where it was, as "<name>.bak"
+    fn backup_beside(path: &Path) -> std::io::Result<()> {
+        let mut backup = path.as_os_str().to_os_string();
+        backup.push(".bak");
+        std::fs::rename(path, backup)
+    }
+
+    #[tokio::test]
+    async fn files_are_backed_up_before_they_are_overwritten() {
+        let work = TempDir::new();
+        write_file(&work.join("input/notes.txt"), "new contents");
+        write_file(&work.join("input/fresh.txt"), "fresh");
+        let output_dir = work.join("out");
+        let overwritten = output_dir.join("input/notes.txt");
+
+        for compression_type in [CompressionType::Zip, CompressionType::TarGz] {
+            let archive = work.join(format!("input{}", compression_type.extension()));
+            compress_files_with_progress(&[work.join("input")], &archive, compression_type, &CompressOptions::default(), &no_cancel(), |_, _, _| {}).await.unwrap();
+            write_file(&overwritten, "old contents");
+            let _ = std::fs::remove_file(output_dir.join("input/fresh.txt"));
+
+            let options = DecompressOptions { backup_on_overwrite: Some(backup_beside), ..Default::default() };
+            let summary = extract(&archive, &output_dir, &options).await.unwrap();
+            assert_eq!(summary.trashed, 1);
+            assert_eq!(std::fs::read_to_string(&overwritten).unwrap(), "new contents");
+            assert_eq!(std::fs::read_to_string(output_dir.join("input/notes.txt.bak")).unwrap(), "old contents");
+            assert!(!output_dir.join("input/fresh.txt.bak").exists());
+        }
+
+        // A file that can't be backed up is left as it was
+        write_file(&overwritten, "old contents");
+        let archive = work.join("input.zip");
+        let options = DecompressOptions { backup_on_overwrite: Some(|_| Err(std::io::Error::other("no trash"))), ..Default::default() };
+        assert!(extract(&archive, &output_dir, &options).await.is_err());
+        assert_eq!(std::fs::read_to_string(&overwritten).unwrap(), "old contents");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn followed_symlinks_store_a_shared_directory_once() {
+        let work = TempDir::new();
+        let input = work.join("input");
+        write_file(&input.join("real/data.txt"), "shared");
+        std::os::unix::fs::symlink(input.join("real"), input.join("link1")).unwrap();
+        std::os::unix::fs::symlink(input.join("real"), input.join("link2")).unwrap();
+        std::os::unix::fs::symlink(&input, input.join("real/loop")).unwrap();
+
+        let archive = work.join("input.zip");
+        let options = CompressOptions { symlink_policy: SymlinkPolicy::Follow, sort_entries: true, ..Default::default() };
+        compress_files_with_progress(&[input.clone()], &archive, CompressionType::Zip, &options, &no_cancel(), |_, _, _| {}).await.unwrap();
+
+        let stored: Vec<String> = zip_entry_names(&archive).into_iter()
+            .filter(|name| name.ends_with("data.txt"))
+            .collect();
+        assert_eq!(stored, vec!["input/link1/data.txt"]);
+    }
+
+    #[cfg(feature = "age-support")]
+    #[tokio::test]
+    async fn age_encrypted_tars_round_trip_with_a_passphrase_or_identity() {
+        use age::secrecy::ExposeSecret;
+
+        let work = TempDir::new();
+        let input = work.join("input");
+        write_file(&input.join("secret.txt"), "top secret");
+        write_file(&input.join("nested/data.bin"), noise(10_000, 3));
+        let identity = age::x25519::Identity::generate();
+        let identity_file = work.join("key.txt");
+        write_file(&identity_file, identity.to_string().expose_secret());
+
+        let cases = [
+            (AgeKey::Passphrase("correct horse".to_string()), AgeKey::Passphrase("correct horse".to_string())),
+            (AgeKey::Recipient(identity.to_public().to_string()), AgeKey::IdentityFile(identity_file.clone())),
+        ];
+        for (index, (encrypt_key, decrypt_key)) in cases.iter().enumerate() {
+            let archive = work.join(format!("secret{}.tar.gz.age", index));
+            compress_tar_gz_age_with_progress(&[input.clone()], &archive, encrypt_key, DEFAULT_COMPRESSION_LEVEL, &PathFilter::default(), &no_cancel(), |_, _, _| {}).await.unwrap();
+            assert!(!std::fs::read(&archive).unwrap().windows(10).any(|window| window == b"top secret"));
+
+            let output_dir = work.join(format!("out{}", index));
+            decompress_tar_gz_age_with_progress(&archive, &output_dir, decrypt_key, &no_cancel(), |_, _| {}).await.unwrap();
+            assert_eq!(read_tree(&output_dir.join("input")), read_tree(&input));
+        }
+
+        let wrong = AgeKey::Passphrase("wrong".to_string());
+        assert!(decompress_tar_gz_age_with_progress(&work.join("secret0.tar.gz.age"), &work.join("wrong"), &wrong, &no_cancel(), |_, _| {}).await.is_err());
+
+        // The requested level reaches the gzip layer: level 0 only stores
+        let size_at = |level: u32| {
+            let archive = work.join(format!("level{}.tar.gz.age", level));
+            let key = cases[1].0.clone();
+            let input = input.clone();
+            async move {
+                compress_tar_gz_age_with_progress(&[input], &archive, &key, level, &PathFilter::default(), &no_cancel(), |_, _, _| {}).await.unwrap();
+                std::fs::metadata(&archive).unwrap().len()
+            }
+        };
+        let stored = size_at(0).await;
+        let compressed = size_at(9).await;
+        assert!(stored > compressed + 1024, "level 0 is {} bytes, level 9 is {}", stored, compressed);
+    }
+
+    fn modified_time(path: &Path) -> (i64, u32) {
+        let modified = filetime::FileTime::from_last_modification_time(&std::fs::metadata(path).unwrap());
+        (modified.unix_seconds(), modified.nanoseconds())
+    }
+
+    #[tokio::test]
+    async fn extraction_restores_archived_modification_times() {
+        let work = TempDir::new();
+        let input = work.join("input");
+        write_file(&input.join("old.txt"), "from 2001");
+        crate::file_utils::set_modified_time(&input.join("old.txt"), 1_000_000_000, 0).unwrap();
+        let archive = work.join("input.tar.gz");
+        compress_files_with_progress(&[input.clone()], &archive, CompressionType::TarGz, &CompressOptions::default(), &no_cancel(), |_, _, _| {}).await.unwrap();
+        extract(&archive, &work.join("from-tar"), &DecompressOptions::default()).await.unwrap();
+        assert_eq!(modified_time(&work.join("from-tar/input/old.txt")), (1_000_000_000, 0));
+
+        // Zip's DOS time has no timezone and is read back as UTC
+        let archive = work.join("dos.zip");
+        let mut zip = ZipWriter::new(File::create(&archive).unwrap());
+        let dos_time = zip::DateTime::from_date_and_time(2001, 9, 9, 1, 46, 40).unwrap();
+        zip.start_file("old.txt", FileOptions::default().last_modified_time(dos_time)).unwrap();
+        zip.write_all(b"from 2001").unwrap();
+        zip.finish().unwrap();
+        extract(&archive, &work.join("from-zip"), &DecompressOptions::default()).await.unwrap();
+        assert_eq!(modified_time(&work.join("from-zip/old.txt")), (1_000_000_000, 0));
+    }
+
+    #[tokio::test]
+    async fn precise_zip_timestamps_keep_sub_second_modification_times() {
+        let work = TempDir::new();
+        let input = work.join("input");
+        write_file(&input.join("a.txt"), "odd second");
+        crate::file_utils::set_modified_time(&input.join("a.txt"), 1_000_000_001, 123_456_700).unwrap();
+
+        let archive = work.join("precise.zip");
+        let options = CompressOptions { precise_timestamps: true, ..Default::default() };
+        compress_files_with_progress(&[input.clone()], &archive, CompressionType::Zip, &options, &no_cancel(), |_, _, _| {}).await.unwrap();
+        let mut zip = zip::ZipArchive::new(File::open(&archive).unwrap()).unwrap();
+        assert_eq!(extended_mtime(zip.by_name("input/a.txt").unwrap().extra_data()), Some((1_000_000_001, 123_456_700)));
+
+        extract(&archive, &work.join("out"), &DecompressOptions::default()).await.unwrap();
+        assert_eq!(modified_time(&work.join("out/input/a.txt")), (1_000_000_001, 123_456_700));
+    }
+
+    fn zip_entry_names(archive: &Path) -> Vec<String> {
+        let mut zip = zip::ZipArchive::new(File::open(archive).unwrap()).unwrap();
+        (0..zip.len()).map(|index| zip.by_index(index).unwrap().name().to_string()).collect()
+    }
+
+    #[cfg(windows)]
+    #[tokio::test]
+    async fn junctions_follow_the_symlink_policy() {
+        let work = TempDir::new();
+        let input = work.join("input");
+        write_file(&input.join("kept.txt"), "kept");
+        write_file(&work.join("outside/linked.txt"), "linked");
+        let status = std::process::Command::new("cmd")
+            .args(["/C", "mklink", "/J"])
+            .arg(input.join("junction"))
+            .arg(work.join("outside"))
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        for (policy, expected) in [
+            (SymlinkPolicy::Skip, vec!["input/kept.txt"]),
+            (SymlinkPolicy::Follow, vec!["input/junction/linked.txt", "input/kept.txt"]),
+        ] {
+            let archive = work.join(format!("{:?}.zip", policy));
+            let options = CompressOptions { symlink_policy: policy, sort_entries: true, ..Default::default() };
+            compress_files_with_progress(&[input.clone()], &archive, CompressionType::Zip, &options, &no_cancel(), |_, _, _| {}).await.unwrap();
+            let files: Vec<String> = zip_entry_names(&archive).into_iter().filter(|name| !name.ends_with('/')).collect();
+            assert_eq!(files, expected, "{:?}", policy);
+        }
+    }
+
+    #[test]
+    fn the_output_name_picks_the_compression_type() {
+        for (name, expected) in [
+            ("backup.zip", Some(".zip")),
+            ("v1.2-release.ZIP", Some(".zip")),
+            ("backup.tar", Some(".tar")),
+            ("backup.tar.gz", Some(".tar.gz")),
+            ("backup.TGZ", Some(".tar.gz")),
+            ("backup.tar.br", Some(".tar.br")),
+            ("notes.txt.gz", Some(".gz")),
+            ("notes.txt.gzip", Some(".gzip")),
+            ("notes.txt.br", Some(".br")),
+            ("notes.txt.bzip2", Some(".bz2")),
+            ("backup.7z", None),
+            ("backup", None),
+        ] {
+            let detected = CompressionType::from_file_name(Path::new(name));
+            assert_eq!(detected.as_ref().map(|t| t.extension()), expected, "{}", name);
+        }
+    }
+
+    #[tokio::test]
+    async fn every_member_of_a_concatenated_gzip_is_extracted() {
+        let work = TempDir::new();
+        let archive = work.join("log.txt.gz");
+        let mut joined = Vec::new();
+        for part in ["first member\n", "second member\n"] {
+            let mut encoder = GzEncoder::new(Vec::new(), FlateCompression::default());
+            encoder.write_all(part.as_bytes()).unwrap();
+            joined.extend(encoder.finish().unwrap());
+        }
+        std::fs::write(&archive, joined).unwrap();
+
+        let output_dir = work.join("out");
+        extract(&archive, &output_dir, &DecompressOptions::default()).await.unwrap();
+        assert_eq!(std::fs::read_to_string(output_dir.join("log.txt")).unwrap(), "first member\nsecond member\n");
+    }
+
+    // ZipCrypto ("traditional PKWARE") encryption, which the zip crate can read but not write
+    struct ZipCryptoKeys([u32; 3]);
+
+    impl ZipCryptoKeys {
+        fn new(password: &str) -> Self {
+            let mut keys = ZipCryptoKeys([0x1234_5678, 0x2345_6789, 0x3456_7890]);
+            for byte in password.bytes() {
+                keys.update(byte);
+            }
+            keys
+        }
+
+        fn crc32_byte(crc: u32, byte: u8) -> u32 {
+            let mut value = (crc ^ u32::from(byte)) & 0xff;
+            for _ in 0..8 {
+                value = if value & 1 != 0 { (value >> 1) ^ 0xEDB8_8320 } else { value >> 1 };
+            }
+            value ^ (crc >> 8)
+        }
+
+        fn update(&mut self, byte: u8) {
+            let [key0, key1, key2] = &mut self.0;
+            *key0 = Self::crc32_byte(*key0, byte);
+            *key1 = key1.wrapping_add(*key0 & 0xff).wrapping_mul(134_775_813).wrapping_add(1);
+            *key2 = Self::crc32_byte(*key2, (*key1 >> 24) as u8);
+        }
+
+        fn encrypt(&mut self, plain: u8) -> u8 {
+            let temp = (self.0[2] | 2) & 0xffff;
+            let cipher = plain ^ ((temp * (temp ^ 1)) >> 8) as u8;
+            self.update(plain);
+            cipher
+        }
+    }
+
+    // A zip of stored entries, each encrypted with its own password
+    fn zipcrypto_archive(entries: &[(&str, &[u8], &str)]) -> Vec<u8> {
+        let mut archive = Vec::new();
+        let mut central = Vec::new();
+        for (name, data, password) in entries {
+            let mut crc = flate2::Crc::new();
+            crc.update(data);
+            // Encryption header: 11 filler bytes, then the CRC's high byte for checking the password
+            let mut keys = ZipCryptoKeys::new(password);
+            let mut sealed: Vec<u8> = [0x5a; 11].iter().chain(&[(crc.sum() >> 24) as u8]).chain(data.iter())
+                .map(|&byte| keys.encrypt(byte))
+                .collect();
+
+            let mut fields = Vec::new();
+            for field in [ZIP_ENCRYPTED_FLAG, 0, 0, 0x21] {
+                fields.extend_from_slice(&field.to_le_bytes());
+            }
+            for field in [crc.sum(), sealed.len() as u32, data.len() as u32] {
+                fields.extend_from_slice(&field.to_le_bytes());
+            }
+            fields.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            fields.extend_from_slice(&0u16.to_le_bytes());
+
+            central.extend_from_slice(b"PK\x01\x02\x14\x00\x14\x00");
+            central.extend_from_slice(&fields);
+            central.extend_from_slice(&[0u8; 10]); // comment length, disk, internal and external attributes
+            central.extend_from_slice(&(archive.len() as u32).to_le_bytes());
+            central.extend_from_slice(name.as_bytes());
+
+            archive.extend_from_slice(b"PK\x03\x04\x14\x00");
+            archive.extend_from_slice(&fields);
+            archive.extend_from_slice(name.as_bytes());
+            archive.append(&mut sealed);
+        }
+        let central_offset = archive.len() as u32;
+        archive.extend_from_slice(&central);
+        archive.extend_from_slice(b"PK\x05\x06\x00\x00\x00\x00");
+        archive.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+        archive.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+        archive.extend_from_slice(&(central.len() as u32).to_le_bytes());
+        archive.extend_from_slice(&central_offset.to_le_bytes());
+        archive.extend_from_slice(&0u16.to_le_bytes());
+        archive
+    }
+
+    #[tokio::test]
+    async fn encrypted_zip_entries_use_the_password_resolved_for_each() {
+        let work = TempDir::new();
+        let archive = work.join("mixed.zip");
+        write_file(&archive, zipcrypto_archive(&[
+            ("public/a.txt", b"first secret", "alpha"),
+            ("private/b.txt", b"second secret", "bravo"),
+        ]));
+
+        let resolver = PasswordResolver::new(|entry| Some(if entry.starts_with("public/") { "alpha" } else { "bravo" }.to_string()));
+        let options = DecompressOptions { password: Some(resolver), ..Default::default() };
+        let output_dir = work.join("out");
+        extract(&archive, &output_dir, &options).await.unwrap();
+        assert_eq!(std::fs::read_to_string(output_dir.join("public/a.txt")).unwrap(), "first secret");
+        assert_eq!(std::fs::read_to_string(output_dir.join("private/b.txt")).unwrap(), "second secret");
+
+        let options = DecompressOptions { password: Some(PasswordResolver::single("alpha".to_string())), ..Default::default() };
+        let result = extract(&archive, &work.join("one-password"), &options).await;
+        assert!(matches!(result, Err(CompressionError::WrongPassword { ref entry }) if entry == "private/b.txt"), "{:?}", result);
+        let result = extract(&archive, &work.join("no-password"), &DecompressOptions::default()).await;
+        assert!(matches!(result, Err(CompressionError::PasswordRequired { .. })), "{:?}", result);
+    }
+
+    // Stored file names (not directories) of a zip made from `inputs` with `options`
+    async fn zipped_files(inputs: &[PathBuf], archive: &Path, options: &CompressOptions) -> Vec<String> {
+        compress_files_with_progress(inputs, archive, CompressionType::Zip, options, &no_cancel(), |_, _, _| {}).await.unwrap();
+        let mut files: Vec<String> = zip_entry_names(archive).into_iter().filter(|name| !name.ends_with('/')).collect();
+        files.sort();
+        files
+    }
+
+    #[tokio::test]
+    async fn include_and_exclude_globs_select_files_at_any_depth() {
+        let work = TempDir::new();
+        let project = work.join("project");
+        for file in ["src/main.rs", "src/lib.rs", "debug.log", "node_modules/pkg/index.js", "README.md"] {
+            write_file(&project.join(file), file);
+        }
+
+        let options = CompressOptions { exclude: vec!["node_modules".to_string(), "*.log".to_string()], ..Default::default() };
+        assert_eq!(
+            zipped_files(&[project.clone()], &work.join("excluded.zip"), &options).await,
+            vec!["project/README.md", "project/src/lib.rs", "project/src/main.rs"],
+        );
+
+        // Exclusions win over inclusions
+        let options = CompressOptions { include: vec!["*.rs".to_string()], exclude: vec!["lib.rs".to_string()], ..Default::default() };
+        assert_eq!(zipped_files(&[project.clone()], &work.join("included.zip"), &options).await, vec!["project/src/main.rs"]);
+    }
+
+    #[tokio::test]
+    async fn tauzipignore_uses_gitignore_rules_relative_to_its_folder() {
+        let work = TempDir::new();
+        let project = work.join("project");
+        write_file(&project.join(IGNORE_FILE_NAME), "target/\n*.tmp\n!keep.tmp\n/build.txt\n");
+        for file in ["src/main.rs", "target/debug/app", "scratch.tmp", "keep.tmp", "build.txt", "src/build.txt"] {
+            write_file(&project.join(file), file);
+        }
+
+        assert_eq!(
+            zipped_files(&[project.clone()], &work.join("ignored.zip"), &CompressOptions::default()).await,
+            vec!["project/.tauzipignore", "project/keep.tmp", "project/src/build.txt", "project/src/main.rs"],
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn symlinks_are_stored_as_links_or_skipped_by_policy() {
+        let work = TempDir::new();
+        let input = work.join("input");
+        write_file(&input.join("target.txt"), "pointed at");
+        std::os::unix::fs::symlink("target.txt", input.join("link.txt")).unwrap();
+
+        let options = CompressOptions { symlink_policy: SymlinkPolicy::Skip, ..Default::default() };
+        assert_eq!(zipped_files(&[input.clone()], &work.join("skip.zip"), &options).await, vec!["input/target.txt"]);
+
+        let options = CompressOptions { symlink_policy: SymlinkPolicy::Store, ..Default::default() };
+        let archive = work.join("store.zip");
+        compress_files_with_progress(&[input.clone()], &archive, CompressionType::Zip, &options, &no_cancel(), |_, _, _| {}).await.unwrap();
+        let mut zip = zip::ZipArchive::new(File::open(&archive).unwrap()).unwrap();
+        let mut link = zip.by_name("input/link.txt").unwrap();
+        assert_eq!(link.unix_mode().unwrap() & 0o170000, 0o120000);
+        let mut target = String::new();
+        link.read_to_string(&mut target).unwrap();
+        assert_eq!(target, "target.txt");
+
+        let archive = work.join("store.tar");
+        compress_files_with_progress(&[input.clone()], &archive, CompressionType::Tar, &options, &no_cancel(), |_, _, _| {}).await.unwrap();
+        let mut tar = tar::Archive::new(File::open(&archive).unwrap());
+        let link = tar.entries().unwrap()
+            .map(|entry| entry.unwrap())
+            .find(|entry| entry.path().unwrap() == Path::new("input/link.txt"))
+            .unwrap();
+        assert_eq!(link.header().entry_type(), tar::EntryType::Symlink);
+        assert_eq!(link.link_name().unwrap().unwrap(), Path::new("target.txt"));
+
+        let output_dir = work.join("out");
+        extract(&archive, &output_dir, &DecompressOptions::default()).await.unwrap();
+        assert_eq!(std::fs::read_link(output_dir.join("input/link.txt")).unwrap(), Path::new("target.txt"));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn per_item_archives_get_distinct_names_when_compressed_concurrently() {
+        let work = TempDir::new();
+        let inputs = vec![work.join("notes.txt"), work.join("notes"), work.join("photo.bin")];
+        write_file(&inputs[0], "a note");
+        write_file(&inputs[1].join("inside.txt"), "in a folder");
+        write_file(&inputs[2], noise(200_000, 5));
+
+        let results = compress_per_item(&inputs, CompressionType::Zip, &CompressOptions::default(), 2, &no_cancel(), |_, _, _| {}).await.unwrap();
+        let outputs: Vec<PathBuf> = results.into_iter().map(|item| item.result.unwrap()).collect();
+        assert_eq!(outputs, vec![work.join("notes.zip"), work.join("notes (2).zip"), work.join("photo.zip")]);
+
+        for (index, (input, output)) in inputs.iter().zip(&outputs).enumerate() {
+            let output_dir = work.join(format!("out{}", index));
+            extract(output, &output_dir, &DecompressOptions::default()).await.unwrap();
+            let extracted = output_dir.join(input.file_name().unwrap());
+            match input.is_dir() {
+                true => assert_eq!(read_tree(&extracted), read_tree(input)),
+                false => assert_eq!(std::fs::read(&extracted).unwrap(), std::fs::read(input).unwrap()),
+            }
+        }
+        let leftovers = std::fs::read_dir(work.path()).unwrap()
+            .filter(|entry| entry.as_ref().unwrap().file_name().to_string_lossy().ends_with(".tmp"))
+            .count();
+        assert_eq!(leftovers, 0);
+    }
+
+    #[cfg(feature = "slow-tests")]
+    #[tokio::test]
+    async fn zips_with_more_than_65535_entries_use_zip64() {
+        let work = TempDir::new();
+        let input = work.join("many");
+        let count = u16::MAX as usize + 2;
+        for folder in 0..count / 1000 + 1 {
+            std::fs::create_dir_all(input.join(folder.to_string())).unwrap();
+        }
+        for index in 0..count {
+            File::create(input.join((index / 1000).to_string()).join(index.to_string())).unwrap();
+        }
+
+        let archive = work.join("many.zip");
+        compress_files_with_progress(&[input.clone()], &archive, CompressionType::Zip, &CompressOptions::default(), &no_cancel(), |_, _, _| {}).await.unwrap();
+        let bytes = std::fs::read(&archive).unwrap();
+        assert!(bytes.windows(4).any(|window| window == b"PK\x06\x06"), "no zip64 end of central directory");
+        let files = zip_entry_names(&archive).into_iter().filter(|name| !name.ends_with('/')).count();
+        assert_eq!(files, count);
+    }
+
+    #[cfg(feature = "slow-tests")]
+    #[tokio::test]
+    async fn zips_of_files_past_4_gib_round_trip() {
+        // CRC-32 and length of a file, read in blocks
+        fn crc_of(path: &Path) -> (u32, u64) {
+            let mut file = File::open(path).unwrap();
+            let mut crc = flate2::Crc::new();
+            let mut buffer = vec![0u8; 1 << 20];
+            let mut len = 0u64;
+            loop {
+                match file.read(&mut buffer).unwrap() {
+                    0 => return (crc.sum(), len),
+                    n => {
+                        crc.update(&buffer[..n]);
+                        len += n as u64;
+                    },
+                }
+            }
+        }
+
+        let work = TempDir::new();
+        let input = work.join("input");
+        std::fs::create_dir_all(&input).unwrap();
+        // Sparse: only the data at either end takes up disk space
+        let large = input.join("large.bin");
+        let size = u32::MAX as u64 + 4096;
+        let mut file = File::create(&large).unwrap();
+        file.write_all(&noise(4096, 1)).unwrap();
+        file.set_len(size).unwrap();
+        {
+            use std::io::{Seek, SeekFrom};
+            file.seek(SeekFrom::End(-4096)).unwrap();
+        }
+        file.write_all(&noise(4096, 2)).unwrap();
+        drop(file);
+        let expected = crc_of(&large);
+        assert_eq!(expected.1, size);
+
+        let archive = work.join("large.zip");
+        compress_files_with_progress(&[input.clone()], &archive, CompressionType::Zip, &CompressOptions::default(), &no_cancel(), |_, _, _| {}).await.unwrap();
+        let mut zip = zip::ZipArchive::new(File::open(&archive).unwrap()).unwrap();
+        let entry = zip.by_name("input/large.bin").unwrap();
+        assert_eq!((entry.crc32(), entry.size()), expected);
+        drop(entry);
+        std::fs::remove_file(&large).unwrap();
+
+        let output = work.join("output");
+        extract(&archive, &output, &DecompressOptions::default()).await.unwrap();
+        assert_eq!(crc_of(&output.join("input/large.bin")), expected);
+    }
+
+    // (name, raw compressed bytes, crc) of every entry, in archive order
+    fn raw_zip_entries(archive: &Path) -> Vec<(String, Vec<u8>, u32)> {
+        let mut zip = zip::ZipArchive::new(File::open(archive).unwrap()).unwrap();
+        (0..zip.len()).map(|index| {
+            let mut entry = zip.by_index_raw(index).unwrap();
+            let mut raw = Vec::new();
+            entry.read_to_end(&mut raw).unwrap();
+            (entry.name().to_string(), raw, entry.crc32())
+        }).collect()
+    }
+
+    #[tokio::test]
+    async fn deleting_an_entry_leaves_the_others_byte_identical() {
+        let work = TempDir::new();
+        let input = work.join("input");
+        write_file(&input.join("a.txt"), "delete me");
+        write_file(&input.join("docs/b.txt"), "b ".repeat(500));
+        write_file(&input.join("docs/c.bin"), noise(20_000, 9));
+        write_file(&input.join("keep.bin"), noise(20_000, 10));
+        let archive = work.join("input.zip");
+        let options = CompressOptions { sort_entries: true, ..Default::default() };
+        compress_files_with_progress(&[input.clone()], &archive, CompressionType::Zip, &options, &no_cancel(), |_, _, _| {}).await.unwrap();
+        let before = raw_zip_entries(&archive);
+
+        assert_eq!(delete_entry(&archive, "input/a.txt").unwrap(), 1);
+        let expected: Vec<_> = before.iter().filter(|(name, _, _)| name != "input/a.txt").cloned().collect();
+        assert_eq!(raw_zip_entries(&archive), expected);
+
+        // A folder goes with everything under it
+        delete_entry(&archive, "input/docs/").unwrap();
+        let names: Vec<String> = raw_zip_entries(&archive).into_iter().map(|(name, _, _)| name).collect();
+        assert!(names.iter().all(|name| !name.starts_with("input/docs")), "{:?}", names);
+        assert!(names.contains(&"input/keep.bin".to_string()));
+
+        assert!(delete_entry(&archive, "input/missing.txt").is_err());
+    }
+
+    #[test]
+    fn configured_incompressible_extensions_extend_or_replace_the_defaults() {
+        let config: crate::config::Config = serde_json::from_str(r#"{ "incompressible_extensions": [".DCM", " nii "] }"#).unwrap();
+        let extensions = IncompressibleExtensions::from_config(&config);
+        for extension in ["dcm", ".dcm", "NII", "jpg", "mp4"] {
+            assert!(extensions.contains(extension), "{}", extension);
+        }
+        assert!(!extensions.contains("txt"));
+
+        let config = crate::config::Config { replace_incompressible_defaults: true, ..config };
+        let extensions = IncompressibleExtensions::from_config(&config);
+        assert!(extensions.contains("dcm"));
+        assert!(!extensions.contains("jpg"));
+    }
+
+    #[test]
+    fn memory_estimates_follow_the_documented_formulas() {
+        let buffers = STREAM_BUFFER_BYTES;
+        let deflate = (1 << 17) + (1 << 17);
+        let window = 1u64 << BROTLI_WINDOW_BITS;
+        for (compression_type, level, expected) in [
+            (CompressionType::Tar, 6, buffers),
+            (CompressionType::TarGz, 9, deflate + buffers),
+            (CompressionType::Br, 4, window + (4 << 16) + buffers),
+            (CompressionType::Br, 6, window + 4 * window + buffers),
+            (CompressionType::TarBr, 11, window + 4 * window + 16 * window + buffers),
+            (CompressionType::Bzip2, 9, 400_000 + 8 * 9 * 100_000 + buffers),
+        ] {
+            assert_eq!(estimate_memory_for_sizes(&[], &compression_type, level, 1), expected, "{:?} level {}", compression_type, level);
+        }
+
+        // Parallel zip workers also hold up to 4 finished entries each, largest first
+        let sizes = largest_first(vec![1_000; 20]);
+        assert_eq!(estimate_memory_for_sizes(&sizes, &CompressionType::Zip, 6, 1), deflate + buffers);
+        assert_eq!(estimate_memory_for_sizes(&sizes, &CompressionType::Zip, 6, 2), 2 * (deflate + buffers) + 8 * 1_000);
+
+        // Workers are cut to what fits the budget, but never below one
+        let budget = 3 * (deflate + buffers) + 12 * 1_000;
+        assert_eq!(threads_within_memory(&sizes, &CompressionType::Zip, 6, 8, budget), 3);
+        assert_eq!(threads_within_memory(&sizes, &CompressionType::Zip, 6, 8, 1), 1);
+    }
+
+    #[tokio::test]
+    async fn sorted_entries_follow_path_order_not_creation_order() {
+        let work = TempDir::new();
+        let first = work.join("input");
+        for name in ["zeta.txt", "beta/b.txt", "alpha.txt", "beta/a.txt", "beta.txt"] {
+            write_file(&first.join(name), name);
+        }
+        let second = work.join("extra.txt");
+        write_file(&second, "extra");
+        let options = CompressOptions { sort_entries: true, ..Default::default() };
+
+        let archive = work.join("sorted.zip");
+        compress_files_with_progress(&[first.clone(), second.clone()], &archive, CompressionType::Zip, &options, &no_cancel(), |_, _, _| {}).await.unwrap();
+        let names: Vec<PathBuf> = zip_entry_names(&archive).into_iter()
+            .filter(|name| !name.ends_with('/'))
+            .map(PathBuf::from)
+            .collect();
+        assert_eq!(names.len(), 6);
+        assert_eq!(names, sorted_inputs(&names));
+        assert_eq!(names[0], Path::new("extra.txt"));
+
+        let archive = work.join("sorted.tar");
+        compress_files_with_progress(&[first, second], &archive, CompressionType::Tar, &options, &no_cancel(), |_, _, _| {}).await.unwrap();
+        let mut tar = tar::Archive::new(File::open(&archive).unwrap());
+        let tar_names: Vec<PathBuf> = tar.entries().unwrap()
+            .map(|entry| entry.unwrap())
+            .filter(|entry| entry.header().entry_type().is_file())
+            .map(|entry| entry.path().unwrap().into_owned())
+            .collect();
+        assert_eq!(tar_names, names);
+    }
+
+    #[tokio::test]
+    async fn routed_entries_land_in_the_directory_of_the_first_matching_rule() {
+        let work = TempDir::new();
+        let input = work.join("input");
+        write_file(&input.join("photo.jpg"), "jpg");
+        write_file(&input.join("nested/notes.txt"), "txt");
+        write_file(&input.join("data.bin"), "bin");
+        let routes = vec![
+            ("*.jpg".to_string(), PathBuf::from("images")),
+            ("*.txt".to_string(), PathBuf::from("docs")),
+        ];
+
+        for compression_type in [CompressionType::Zip, CompressionType::TarGz] {
+            let archive = work.join(format!("mixed{}", compression_type.extension()));
+            compress_files_with_progress(&[input.clone()], &archive, compression_type, &CompressOptions::default(), &no_cancel(), |_, _, _| {}).await.unwrap();
+
+            let output = work.join("output");
+            let options = DecompressOptions { routes: routes.clone(), ..Default::default() };
+            extract(&archive, &output, &options).await.unwrap();
+            assert_eq!(std::fs::read_to_string(output.join("images/input/photo.jpg")).unwrap(), "jpg");
+            assert_eq!(std::fs::read_to_string(output.join("docs/input/nested/notes.txt")).unwrap(), "txt");
+            assert_eq!(std::fs::read_to_string(output.join("input/data.bin")).unwrap(), "bin");
+            assert!(!output.join("input/photo.jpg").exists());
+            assert!(!output.join("input/nested/notes.txt").exists());
+            std::fs::remove_dir_all(&output).unwrap();
+        }
+    }
+
+    #[test]
+    fn files_that_change_while_read_keep_later_tar_entries_aligned() {
+        // A file whose header said 10 bytes but only 4 were left to read, then one that grew to 14
+        let mut builder = tar::Builder::new(Vec::new());
+        for (name, contents) in [("shrank.log", &b"abcd"[..]), ("grew.log", &b"0123456789more"[..])] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(10);
+            header.set_mode(0o644);
+            let mut reader = DeclaredSizeReader::new(contents, 10);
+            builder.append_data(&mut header, name, &mut reader).unwrap();
+            assert_eq!(reader.read, contents.len().min(10) as u64);
+            assert_eq!(reader.grew, contents.len() > 10);
+        }
+        let mut header = tar::Header::new_gnu();
+        header.set_size(4);
+        header.set_mode(0o644);
+        builder.append_data(&mut header, "after.txt", &b"next"[..]).unwrap();
+
+        let bytes = builder.into_inner().unwrap();
+        let mut tar = tar::Archive::new(&bytes[..]);
+        let entries: Vec<(String, Vec<u8>)> = tar.entries().unwrap()
+            .map(|entry| {
+                let mut entry = entry.unwrap();
+                let mut data = Vec::new();
+                entry.read_to_end(&mut data).unwrap();
+                (entry.path().unwrap().display().to_string(), data)
+            })
+            .collect();
+        assert_eq!(entries, vec![
+            ("shrank.log".to_string(), b"abcd\0\0\0\0\0\0".to_vec()),
+            ("grew.log".to_string(), b"0123456789".to_vec()),
+            ("after.txt".to_string(), b"next".to_vec()),
+        ]);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn zip_round_trips_keep_each_files_own_mode() {
+        use std::os::unix::fs::PermissionsExt;
+        let work = TempDir::new();
+        let input = work.join("input");
+        write_file(&input.join("private.txt"), "secret");
+        write_file(&input.join("run.sh"), "#!/bin/sh\n");
+        let set_mode = |name: &str, mode: u32| std::fs::set_permissions(input.join(name), std::fs::Permissions::from_mode(mode)).unwrap();
+        set_mode("private.txt", 0o600);
+        set_mode("run.sh", 0o755);
+
+        let archive = work.join("modes.zip");
+        compress_files_with_progress(&[input.clone()], &archive, CompressionType::Zip, &CompressOptions::default(), &no_cancel(), |_, _, _| {}).await.unwrap();
+        let output = work.join("output");
+        extract(&archive, &output, &DecompressOptions::default()).await.unwrap();
+
+        let mode_of = |name: &str| std::fs::metadata(output.join("input").join(name)).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode_of("private.txt"), 0o600);
+        assert_eq!(mode_of("run.sh"), 0o755);
+    }
+
+    // A zip with a central directory sidecar, of a small tree with a nested folder
+    async fn sidecar_zip(work: &TempDir) -> (PathBuf, PathBuf) {
+        let input = work.join("input");
+        write_file(&input.join("a.txt"), "alpha");
+        write_file(&input.join("nested/b.bin"), noise(100_000, 5));
+        let archive = work.join("served.zip");
+        let options = CompressOptions { central_directory_sidecar: true, ..Default::default() };
+        compress_files_with_progress(&[input.clone()], &archive, CompressionType::Zip, &options, &no_cancel(), |_, _, _| {}).await.unwrap();
+        (input, archive)
+    }
+
+    #[tokio::test]
+    async fn central_directory_sidecars_mirror_the_end_of_a_standard_zip() {
+        let work = TempDir::new();
+        let (input, archive) = sidecar_zip(&work).await;
+
+        // The sidecar is the archive's tail from the start of its central directory
+        let bytes = std::fs::read(&archive).unwrap();
+        let sidecar = std::fs::read(central_directory_sidecar_path(&archive)).unwrap();
+        let (offset, size) = zip_central_directory_range(&mut File::open(&archive).unwrap()).unwrap();
+        assert_eq!(&bytes[offset as usize..], &sidecar[..]);
+        assert_eq!(&sidecar[..4], b"PK\x01\x02");
+        assert!(sidecar.len() as u64 > size);
+        // Local headers carry the sizes, so no entry needs a data descriptor
+        assert_eq!(le_u16(&bytes, 6) & 0x0008, 0);
+
+        let output = work.join("output");
+        extract(&archive, &output, &DecompressOptions::default()).await.unwrap();
+        assert_eq!(read_tree(&output.join("input")), read_tree(&input));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Info-ZIP unzip on the PATH; run with --ignored"]
+    async fn zips_with_a_central_directory_sidecar_stay_extractable_by_unzip() {
+        let work = TempDir::new();
+        let (input, archive) = sidecar_zip(&work).await;
+        let output = work.join("output");
+        let status = std::process::Command::new("unzip").arg("-q").arg(&archive).arg("-d").arg(&output).status()
+            .expect("unzip is not installed");
+        assert!(status.success());
+        assert_eq!(read_tree(&output.join("input")), read_tree(&input));
+    }
+
+    #[tokio::test]
+    async fn truncated_samples_of_each_format_are_incomplete() {
+        let work = TempDir::new();
+        let input = work.join("input");
+        write_file(&input.join("a.txt"), "alpha");
+        write_file(&input.join("b.bin"), noise(50_000, 11));
+        let single = work.join("data.bin");
+        write_file(&single, noise(50_000, 12));
+
+        for compression_type in [CompressionType::Zip, CompressionType::Tar, CompressionType::TarGz, CompressionType::TarBr, CompressionType::Gz, CompressionType::Br, CompressionType::Bzip2] {
+            let extension = compression_type.extension();
+            let source = if extension.starts_with(".tar") || extension == ".zip" { &input } else { &single };
+            let archive = work.join(format!("whole{}", extension));
+            compress_files_with_progress(&[source.clone()], &archive, compression_type, &CompressOptions::default(), &no_cancel(), |_, _, _| {}).await.unwrap();
+            assert!(is_complete_archive(&archive).unwrap(), "{}", extension);
+
+            let bytes = std::fs::read(&archive).unwrap();
+            for len in [bytes.len() - 1, bytes.len() / 2, 10] {
+                let truncated = work.join(format!("cut{}", extension));
+                std::fs::write(&truncated, &bytes[..len]).unwrap();
+                assert!(!is_complete_archive(&truncated).unwrap(), "{} cut to {} bytes", extension, len);
+            }
+        }
+    }
+
+    #[test]
+    fn listings_report_each_zip_entrys_method_and_ratio() {
+        let work = TempDir::new();
+        let archive = work.join("mixed.zip");
+        let text = "compressible ".repeat(1_000);
+        let mut zip = zip::ZipWriter::new(File::create(&archive).unwrap());
+        for (name, method) in [
+            ("stored.txt", zip::CompressionMethod::Stored),
+            ("deflated.txt", zip::CompressionMethod::Deflated),
+            ("bzip2.txt", zip::CompressionMethod::Bzip2),
+        ] {
+            zip.start_file(name, FileOptions::default().compression_method(method)).unwrap();
+            zip.write_all(text.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap();
+
+        let entries = list_archive(&archive).unwrap();
+        let listed: Vec<(&str, &str)> = entries.iter().map(|entry| (entry.name.as_str(), entry.method.as_str())).collect();
+        assert_eq!(listed, vec![("stored.txt", "Stored"), ("deflated.txt", "Deflated"), ("bzip2.txt", "Bzip2")]);
+        assert!(entries.iter().all(|entry| entry.size == text.len() as u64));
+        assert_eq!(entries[0].ratio, Some(1.0));
+        assert!(entries[1].ratio.unwrap() < 0.1);
+        assert!(entries[2].ratio.unwrap() < 0.1);
+    }
+
+    #[tokio::test]
+    async fn unicode_names_round_trip_with_or_without_the_utf8_flag() {
+        const NAME: &str = "тест_😀.txt";
+        const UTF8_FLAG: u16 = 1 << 11;
+        let work = TempDir::new();
+        let input = work.join("input");
+        write_file(&input.join(NAME), "unicode");
+
+        for compression_type in [CompressionType::Zip, CompressionType::TarGz] {
+            let archive = work.join(format!("names{}", compression_type.extension()));
+            compress_files_with_progress(&[input.clone()], &archive, compression_type, &CompressOptions::default(), &no_cancel(), |_, _, _| {}).await.unwrap();
+            let output = work.join("output");
+            extract(&archive, &output, &DecompressOptions::default()).await.unwrap();
+            assert_eq!(read_tree(&output), vec![(Path::new("input").join(NAME), b"unicode".to_vec())]);
+            std::fs::remove_dir_all(&output).unwrap();
+        }
+
+        // Our zips flag the name as UTF-8; many other tools store the same bytes unflagged
+        let mut bytes = std::fs::read(work.join("names.zip")).unwrap();
+        assert_ne!(le_u16(&bytes, 6) & UTF8_FLAG, 0);
+        let headers: Vec<usize> = (0..bytes.len() - 4)
+            .filter(|&at| bytes[at..at + 4] == *b"PK\x03\x04" || bytes[at..at + 4] == *b"PK\x01\x02")
+            .collect();
+        for at in headers {
+            let flags_at = if bytes[at + 2] == 3 { at + 6 } else { at + 8 };
+            let flags = le_u16(&bytes, flags_at) & !UTF8_FLAG;
+            bytes[flags_at..flags_at + 2].copy_from_slice(&flags.to_le_bytes());
+        }
+        let unflagged = work.join("unflagged.zip");
+        std::fs::write(&unflagged, &bytes).unwrap();
+
+        assert!(list_archive(&unflagged).unwrap().iter().any(|entry| entry.name == format!("input/{}", NAME)));
+        let output = work.join("output");
+        extract(&unflagged, &output, &DecompressOptions::default()).await.unwrap();
+        assert_eq!(read_tree(&output), vec![(Path::new("input").join(NAME), b"unicode".to_vec())]);
+    }
+
+    #[tokio::test]
+    async fn formats_sharing_walks_archive_the_entries_of_the_first_walk() {
+        let work = TempDir::new();
+        let input = work.join("input");
+        write_file(&input.join("a.txt"), "a");
+        write_file(&input.join("nested/b.txt"), "b");
+        let walks = Arc::new(SharedWalks::default());
+        let options = CompressOptions::default();
+
+        let zip_archive = work.join("walked.zip");
+        compress_files_with_walks(&[input.clone()], &zip_archive, CompressionType::Zip, &options, Some(walks.clone()), &no_cancel(), |_, _, _| {}).await.unwrap();
+        // Only a second walk could find this
+        write_file(&input.join("late.txt"), "late");
+        let tar_archive = work.join("walked.tar");
+        compress_files_with_walks(&[input.clone()], &tar_archive, CompressionType::Tar, &options, Some(walks), &no_cancel(), |_, _, _| {}).await.unwrap();
+
+        let mut tar = tar::Archive::new(File::open(&tar_archive).unwrap());
+        let tar_names: Vec<String> = tar.entries().unwrap()
+            .map(|entry| entry.unwrap())
+            .filter(|entry| entry.header().entry_type().is_file())
+            .map(|entry| entry.path().unwrap().to_string_lossy().replace('\\', "/"))
+            .collect();
+        assert_eq!(tar_names, zip_entry_names(&zip_archive));
+        assert_eq!(tar_names.len(), 2);
+        assert!(!tar_names.iter().any(|name| name.contains("late")));
+    }
+
+    #[tokio::test]
+    async fn per_item_archives_replace_existing_files_only_when_overwriting() {
+        let work = TempDir::new();
+        let inputs = vec![work.join("notes.txt"), work.join("notes"), work.join("photo.bin")];
+        write_file(&inputs[0], "a note");
+        write_file(&inputs[1].join("inside.txt"), "in a folder");
+        write_file(&inputs[2], "a photo");
+
+        for (overwrite, expected) in [
+            (false, vec!["notes (2).zip", "notes (3).zip", "photo (2).zip"]),
+            (true, vec!["notes.zip", "notes (4).zip", "photo.zip"]),
+        ] {
+            write_file(&work.join("notes.zip"), "old");
+            write_file(&work.join("photo.zip"), "old");
+            let options = CompressOptions { overwrite, ..Default::default() };
+            let results = compress_per_item(&inputs, CompressionType::Zip, &options, 2, &no_cancel(), |_, _, _| {}).await.unwrap();
+            let outputs: Vec<PathBuf> = results.into_iter().map(|item| item.result.unwrap()).collect();
+            let expected: Vec<PathBuf> = expected.iter().map(|name| work.join(name)).collect();
+            assert_eq!(outputs, expected, "overwrite: {}", overwrite);
+            assert!(is_complete_archive(&outputs[0]).unwrap());
+        }
+    }
+
+    #[tokio::test]
+    async fn conversions_use_the_preset_and_refuse_to_overwrite_their_source() {
+        let work = TempDir::new();
+        let input = work.join("input");
+        let text: Vec<u8> = noise(400_000, 11).iter().map(|byte| b"abcdefgh "[(byte % 9) as usize]).collect();
+        write_file(&input.join("text.txt"), &text);
+        let source = work.join("source.tar");
+        compress_files_with_progress(&[input.clone()], &source, CompressionType::Tar, &CompressOptions::default(), &no_cancel(), |_, _, _| {}).await.unwrap();
+
+        for target_type in [CompressionType::Zip, CompressionType::TarGz, CompressionType::TarBr] {
+            let mut sizes = Vec::new();
+            for preset in [CompressionPreset::Fastest, CompressionPreset::Best] {
+                let converted = work.join(format!("{:?}{}", preset, target_type.extension()));
+                convert_archive(&source, &converted, target_type.clone(), preset, &no_cancel(), |_, _| {}).await.unwrap();
+                sizes.push(std::fs::metadata(&converted).unwrap().len());
+            }
+            assert!(sizes[0] > sizes[1], "{}: fastest is {} bytes, best is {}", target_type.extension(), sizes[0], sizes[1]);
+        }
+
+        // A path that only matches once resolved must not truncate the source
+        std::fs::create_dir_all(work.join("sub")).unwrap();
+        let source_bytes = std::fs::read(&source).unwrap();
+        let same = work.join("sub/../source.tar");
+        assert!(convert_archive(&source, &same, CompressionType::Tar, CompressionPreset::Default, &no_cancel(), |_, _| {}).await.is_err());
+        assert_eq!(std::fs::read(&source).unwrap(), source_bytes);
+    }
+
+    #[tokio::test]
+    async fn converted_zips_keep_precise_modification_times() {
+        let work = TempDir::new();
+        let input = work.join("input");
+        write_file(&input.join("odd.txt"), "an odd second");
+        crate::file_utils::set_modified_time(&input.join("odd.txt"), 1_000_000_001, 123_456_700).unwrap();
+        let source = work.join("source.zip");
+        let options = CompressOptions { precise_timestamps: true, ..Default::default() };
+        compress_files_with_progress(&[input.clone()], &source, CompressionType::Zip, &options, &no_cancel(), |_, _, _| {}).await.unwrap();
+
+        let converted = work.join("converted.zip");
+        convert_archive(&source, &converted, CompressionType::Zip, CompressionPreset::Default, &no_cancel(), |_, _| {}).await.unwrap();
+        extract(&converted, &work.join("out"), &DecompressOptions::default()).await.unwrap();
+        assert_eq!(modified_time(&work.join("out/input/odd.txt")), (1_000_000_001, 123_456_700));
+
+        // A single stream has no recorded size, so its entry is written ready for zip64
+        let gz = work.join("odd.txt.gz");
+        compress_files_with_progress(&[input.join("odd.txt")], &gz, CompressionType::Gz, &CompressOptions::default(), &no_cancel(), |_, _, _| {}).await.unwrap();
+        let from_gz = work.join("from-gz.zip");
+        convert_archive(&gz, &from_gz, CompressionType::Zip, CompressionPreset::Default, &no_cancel(), |_, _| {}).await.unwrap();
+        extract(&from_gz, &work.join("out-gz"), &DecompressOptions::default()).await.unwrap();
+        assert_eq!(std::fs::read(work.join("out-gz/odd.txt")).unwrap(), b"an odd second");
+    }
+
+    // photos/a.jpg, b.jpg and c.jpg with the same contents, zipped with dedup so b.jpg and c.jpg
+    // are stored as aliases of a.jpg
+    async fn dedup_archive(dir: &TempDir) -> (PathBuf, PathBuf) {
+        let input = dir.join("photos");
+        for name in ["a.jpg", "b.jpg", "c.jpg"] {
+            write_file(&input.join(name), noise(100_000, 5));
+        }
+        write_file(&input.join("other.txt"), "different");
+        let archive = dir.join("photos.zip");
+        let options = CompressOptions { dedup: true, sort_entries: true, ..Default::default() };
+        compress_files_with_progress(&[input.clone()], &archive, CompressionType::Zip, &options, &no_cancel(), |_, _, _| {}).await.unwrap();
+        (input, archive)
+    }
+
+    #[tokio::test]
+    async fn converting_a_dedup_zip_writes_every_alias_in_full() {
+        let dir = TempDir::new();
+        let (input, archive) = dedup_archive(&dir).await;
+        for target_type in [CompressionType::TarGz, CompressionType::Zip] {
+            let converted = dir.join(format!("converted{}", target_type.extension()));
+            convert_archive(&archive, &converted, target_type.clone(), CompressionPreset::Default, &no_cancel(), |_, _| {}).await.unwrap();
+            let output_dir = dir.join(format!("out{}", target_type.extension()));
+            extract(&converted, &output_dir, &DecompressOptions::default()).await.unwrap();
+            assert_eq!(read_tree(&output_dir.join("photos")), read_tree(&input), "{}", target_type.extension());
+            assert!(!output_dir.join(DEDUP_MANIFEST_NAME).exists(), "{}", target_type.extension());
+        }
+    }
+
+    #[tokio::test]
+    async fn previewing_a_dedup_alias_reads_the_original() {
+        let dir = TempDir::new();
+        let (_, archive) = dedup_archive(&dir).await;
+        assert_eq!(preview_entry(&archive, "photos/c.jpg", 1000).unwrap(), &noise(100_000, 5)[..1000]);
+    }
+
+    #[tokio::test]
+    async fn deleting_a_dedup_original_moves_its_contents_to_an_alias() {
+        let dir = TempDir::new();
+        let (input, archive) = dedup_archive(&dir).await;
+
+        // The manifest can't go while aliases still depend on it
+        let before = std::fs::read(&archive).unwrap();
+        assert!(delete_entry(&archive, DEDUP_MANIFEST_NAME).is_err());
+        assert_eq!(std::fs::read(&archive).unwrap(), before);
+
+        assert_eq!(delete_entry(&archive, "photos/a.jpg").unwrap(), 1);
+        let mut zip = zip::ZipArchive::new(File::open(&archive).unwrap()).unwrap();
+        let aliases = read_dedup_manifest(&mut zip).unwrap().unwrap();
+        assert_eq!(aliases, BTreeMap::from([("photos/c.jpg".to_string(), "photos/b.jpg".to_string())]));
+        assert_eq!(zip.by_name("photos/b.jpg").unwrap().size(), 100_000);
+        drop(zip);
+
+        std::fs::remove_file(input.join("a.jpg")).unwrap();
+        let output_dir = dir.join("out");
+        extract(&archive, &output_dir, &DecompressOptions::default()).await.unwrap();
+        assert_eq!(read_tree(&output_dir.join("photos")), read_tree(&input));
+
+        // With no aliases left the manifest is dropped too
+        delete_entry(&archive, "photos/c.jpg").unwrap();
+        let mut zip = zip::ZipArchive::new(File::open(&archive).unwrap()).unwrap();
+        assert!(read_dedup_manifest(&mut zip).unwrap().is_none());
+    }
 }
\ No newline at end of file
@@ -0,0 +1,48 @@
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+const CONFIG_FILE_NAME: &str = "config.json";
+
+// User settings read from config.json; any field left out keeps its default
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct Config {
+    // Extra extensions (e.g. "dcm", ".nii") to treat as already compressed
+    pub incompressible_extensions: Vec<String>,
+    // Use only `incompressible_extensions`, dropping the built-in list
+    pub replace_incompressible_defaults: bool,
+    // Assumed ratio of extracted to archive size for formats that don't record it (gz, br, bz2
+    // and compressed tars), used by the free-space check before extraction
+    pub expansion_factor: Option<f64>,
+    // Show a desktop notification when a compression or extraction finishes while TauZip's
+    // window is in the background
+    pub notify_on_completion: bool,
+    // Format `compress` uses when neither --format nor the output name picks one, e.g. "tar.gz";
+    // the TAUZIP_DEFAULT_FORMAT environment variable takes precedence
+    pub default_format: Option<String>,
+}
+
+pub fn config_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("tauzip").join(CONFIG_FILE_NAME))
+}
+
+// A missing config file means defaults; a malformed one is reported and ignored
+pub fn load_config() -> Config {
+    let path = match config_path() {
+        Some(path) => path,
+        None => return Config::default(),
+    };
+
+    let content = match std::fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(_) => return Config::default(),
+    };
+
+    match serde_json::from_str(&content) {
+        Ok(config) => config,
+        Err(e) => {
+            tracing::warn!(path = %path.display(), error = %e, "Ignoring invalid config file");
+            Config::default()
+        }
+    }
+}
\ No newline at end of file
@@ -1,10 +1,80 @@
 use anyhow::Result;
 use std::path::PathBuf;
+#[cfg(any(not(target_os = "windows"), test))]
+use std::path::Path;
 
 pub struct ContextMenuManager {
     executable_path: PathBuf,
 }
 
+// Everything created during a single install run, so a partial failure can be undone
+#[derive(Default)]
+struct InstallRollback {
+    #[cfg(target_os = "windows")]
+    registry_keys: Vec<String>,
+    #[cfg(target_os = "macos")]
+    bundles: Vec<PathBuf>,
+    files: Vec<PathBuf>,
+}
+
+impl InstallRollback {
+    #[cfg(target_os = "windows")]
+    fn create_key(&mut self, root: &winreg::RegKey, path: &str) -> Result<winreg::RegKey> {
+        use winreg::enums::RegDisposition;
+
+        let (key, disposition) = root.create_subkey(path)?;
+        // Only keys we created ourselves are safe to delete again
+        let created = matches!(disposition, RegDisposition::REG_CREATED_NEW_KEY);
+        tracing::debug!(key = path, created, "Opened registry key");
+        if created {
+            self.registry_keys.push(path.to_string());
+        }
+        Ok(key)
+    }
+
+    #[cfg(any(target_os = "linux", test))]
+    fn write_file(&mut self, path: &Path, content: &str) -> Result<()> {
+        std::fs::write(path, content)?;
+        tracing::debug!(path = %path.display(), "Wrote integration file");
+        self.files.push(path.to_path_buf());
+        Ok(())
+    }
+
+    #[cfg(target_os = "macos")]
+    fn create_bundle(&mut self, path: &Path) -> Result<()> {
+        std::fs::create_dir_all(path.join("Contents"))?;
+        self.bundles.push(path.to_path_buf());
+        Ok(())
+    }
+
+    fn undo(&self) {
+        #[cfg(target_os = "windows")]
+        {
+            use winreg::{enums::*, RegKey};
+            let hkcr = RegKey::predef(HKEY_CLASSES_ROOT);
+            // Reverse order so child keys go before their parents
+            for key in self.registry_keys.iter().rev() {
+                if hkcr.delete_subkey_all(key).is_ok() {
+                    tracing::info!(key = %key, "Rolled back registry key");
+                }
+            }
+        }
+
+        for file in self.files.iter().rev() {
+            if std::fs::remove_file(file).is_ok() {
+                tracing::info!(path = %file.display(), "Rolled back file");
+            }
+        }
+
+        #[cfg(target_os = "macos")]
+        for bundle in self.bundles.iter().rev() {
+            if std::fs::remove_dir_all(bundle).is_ok() {
+                tracing::info!(path = %bundle.display(), "Rolled back bundle");
+            }
+        }
+    }
+}
+
 impl ContextMenuManager {
     pub fn new() -> Self {
         let executable_path = std::env::current_exe()
@@ -17,14 +87,26 @@ impl ContextMenuManager {
         // First, clean up any existing entries to avoid conflicts
         self.uninstall().await?;
         
+        let mut rollback = InstallRollback::default();
+        if let Err(e) = self.install_platform(&mut rollback).await {
+            // Don't leave a half-installed menu behind
+            tracing::error!(error = %e, "Context menu installation failed, rolling back");
+            rollback.undo();
+            return Err(e);
+        }
+        
+        Ok(())
+    }
+
+    async fn install_platform(&self, rollback: &mut InstallRollback) -> Result<()> {
         #[cfg(target_os = "windows")]
-        self.install_windows().await?;
+        self.install_windows(rollback).await?;
         
         #[cfg(target_os = "macos")]
-        self.install_macos().await?;
+        self.install_macos(rollback).await?;
         
         #[cfg(target_os = "linux")]
-        self.install_linux().await?;
+        self.install_linux(rollback).await?;
         
         Ok(())
     }
@@ -42,54 +124,109 @@ impl ContextMenuManager {
         Ok(())
     }
 
+    // Probes the same locations `install` writes to
+    pub fn is_installed(&self) -> bool {
+        #[cfg(target_os = "windows")]
+        let installed = self.is_installed_windows();
+        
+        #[cfg(target_os = "macos")]
+        let installed = self.is_installed_macos();
+        
+        #[cfg(target_os = "linux")]
+        let installed = self.is_installed_linux();
+        
+        #[cfg(not(any(target_os = "windows", target_os = "macos", target_os = "linux")))]
+        let installed = false;
+        
+        installed
+    }
+
+    #[cfg(target_os = "windows")]
+    fn is_installed_windows(&self) -> bool {
+        use winreg::{enums::*, RegKey};
+
+        let hkcr = RegKey::predef(HKEY_CLASSES_ROOT);
+        let required_keys = [
+            "*\\shell\\tauzip_compress\\command",
+            "*\\shell\\tauzip_decompress\\command",
+            "Directory\\shell\\tauzip_compress\\command",
+            "Directory\\shell\\tauzip_decompress\\command",
+        ];
+        
+        required_keys.iter().all(|key| hkcr.open_subkey(key).is_ok())
+    }
+
+    #[cfg(target_os = "macos")]
+    fn is_installed_macos(&self) -> bool {
+        let services_dir = macos_services_dir();
+        MACOS_WORKFLOWS.iter().all(|(name, _, _)| {
+            let contents = services_dir.join(format!("{}.workflow", name)).join("Contents");
+            contents.join("Info.plist").exists() && contents.join("document.wflow").exists()
+        })
+    }
+
+    #[cfg(target_os = "linux")]
+    fn is_installed_linux(&self) -> bool {
+        let home_dir = dirs::home_dir().unwrap_or_default();
+        let local_share = home_dir.join(".local/share");
+        let required_files = [
+            "applications/tauzip.desktop",
+            "file-manager/actions/tauzip-compress.desktop",
+            "file-manager/actions/tauzip-decompress.desktop",
+        ];
+        
+        required_files.iter().all(|file| local_share.join(file).exists())
+            && linux_integration_files(&local_share, detect_linux_file_manager()).iter().all(|file| file.exists())
+    }
+
     #[cfg(target_os = "windows")]
-    async fn install_windows(&self) -> Result<()> {
+    async fn install_windows(&self, rollback: &mut InstallRollback) -> Result<()> {
         use winreg::{enums::*, RegKey};
 
         let hkcr = RegKey::predef(HKEY_CLASSES_ROOT);
         
-        // Create direct compress menu item for all files - single instance will handle multiple files
-        let compress_direct = hkcr.create_subkey("*\\shell\\tauzip_compress")?;
-        compress_direct.0.set_value("", &"TauZip - Compress")?;
-        compress_direct.0.set_value("MUIVerb", &"TauZip - Compress")?;
-        compress_direct.0.set_value("Icon", &format!("{},0", self.executable_path.display()))?;
-        compress_direct.0.set_value("MultiSelectModel", &"Player")?;
-        let compress_direct_cmd = hkcr.create_subkey("*\\shell\\tauzip_compress\\command")?;
-        // Using regular gui-compress - single instance plugin handles multiple files automatically
-        compress_direct_cmd.0.set_value("", &format!("\"{}\" gui-compress \"%V\"", self.executable_path.display()))?;
+        // Create direct compress menu item for all files - each selected item launches separately
+        let compress_direct = rollback.create_key(&hkcr, "*\\shell\\tauzip_compress")?;
+        compress_direct.set_value("", &"TauZip - Compress")?;
+        compress_direct.set_value("MUIVerb", &"TauZip - Compress")?;
+        compress_direct.set_value("Icon", &format!("{},0", self.executable_path.display()))?;
+        compress_direct.set_value("MultiSelectModel", &"Player")?;
+        let compress_direct_cmd = rollback.create_key(&hkcr, "*\\shell\\tauzip_compress\\command")?;
+        // %V is one path per launch; gui-compress merges rapid launches into a single window
+        compress_direct_cmd.set_value("", &format!("\"{}\" gui-compress \"%V\"", self.executable_path.display()))?;
 
         // Create GUI decompress menu item for all files
-        let decompress_direct = hkcr.create_subkey("*\\shell\\tauzip_decompress")?;
-        decompress_direct.0.set_value("", &"TauZip - Decompress")?;
-        decompress_direct.0.set_value("MUIVerb", &"TauZip - Decompress")?;
-        decompress_direct.0.set_value("Icon", &format!("{},0", self.executable_path.display()))?;
-        decompress_direct.0.set_value("MultiSelectModel", &"Player")?;
-        let decompress_direct_cmd = hkcr.create_subkey("*\\shell\\tauzip_decompress\\command")?;
-        // Using regular gui-decompress - single instance plugin handles multiple files automatically
-        decompress_direct_cmd.0.set_value("", &format!("\"{}\" gui-decompress \"%V\"", self.executable_path.display()))?;
+        let decompress_direct = rollback.create_key(&hkcr, "*\\shell\\tauzip_decompress")?;
+        decompress_direct.set_value("", &"TauZip - Decompress")?;
+        decompress_direct.set_value("MUIVerb", &"TauZip - Decompress")?;
+        decompress_direct.set_value("Icon", &format!("{},0", self.executable_path.display()))?;
+        decompress_direct.set_value("MultiSelectModel", &"Player")?;
+        let decompress_direct_cmd = rollback.create_key(&hkcr, "*\\shell\\tauzip_decompress\\command")?;
+        // %V is one path per launch; gui-decompress merges rapid launches into a single window
+        decompress_direct_cmd.set_value("", &format!("\"{}\" gui-decompress \"%V\"", self.executable_path.display()))?;
 
         // Create direct compress menu item for directories
-        let dir_compress_direct = hkcr.create_subkey("Directory\\shell\\tauzip_compress")?;
-        dir_compress_direct.0.set_value("", &"TauzZip - Compress")?;
-        dir_compress_direct.0.set_value("MUIVerb", &"TauZip - Compress")?;
-        dir_compress_direct.0.set_value("Icon", &format!("{},0", self.executable_path.display()))?;
-        dir_compress_direct.0.set_value("MultiSelectModel", &"Player")?;
-        let dir_compress_direct_cmd = hkcr.create_subkey("Directory\\shell\\tauzip_compress\\command")?;
-        dir_compress_direct_cmd.0.set_value("", &format!("\"{}\" gui-compress \"%V\"", self.executable_path.display()))?;
+        let dir_compress_direct = rollback.create_key(&hkcr, "Directory\\shell\\tauzip_compress")?;
+        dir_compress_direct.set_value("", &"TauzZip - Compress")?;
+        dir_compress_direct.set_value("MUIVerb", &"TauZip - Compress")?;
+        dir_compress_direct.set_value("Icon", &format!("{},0", self.executable_path.display()))?;
+        dir_compress_direct.set_value("MultiSelectModel", &"Player")?;
+        let dir_compress_direct_cmd = rollback.create_key(&hkcr, "Directory\\shell\\tauzip_compress\\command")?;
+        dir_compress_direct_cmd.set_value("", &format!("\"{}\" gui-compress \"%V\"", self.executable_path.display()))?;
 
         // Also add decompress option for directories (for cases like extracting to folder)
-        let dir_decompress_direct = hkcr.create_subkey("Directory\\shell\\tauzip_decompress")?;
-        dir_decompress_direct.0.set_value("", &"TauZip - Decompress Here")?;
-        dir_decompress_direct.0.set_value("MUIVerb", &"TauZip - Decompress Here")?;
-        dir_decompress_direct.0.set_value("Icon", &format!("{},0", self.executable_path.display()))?;
-        dir_decompress_direct.0.set_value("MultiSelectModel", &"Player")?;
-        let dir_decompress_direct_cmd = hkcr.create_subkey("Directory\\shell\\tauzip_decompress\\command")?;
+        let dir_decompress_direct = rollback.create_key(&hkcr, "Directory\\shell\\tauzip_decompress")?;
+        dir_decompress_direct.set_value("", &"TauZip - Decompress Here")?;
+        dir_decompress_direct.set_value("MUIVerb", &"TauZip - Decompress Here")?;
+        dir_decompress_direct.set_value("Icon", &format!("{},0", self.executable_path.display()))?;
+        dir_decompress_direct.set_value("MultiSelectModel", &"Player")?;
+        let dir_decompress_direct_cmd = rollback.create_key(&hkcr, "Directory\\shell\\tauzip_decompress\\command")?;
         // Keep gui-decompress-here for directories since it has special logic for finding archives in directories
-        dir_decompress_direct_cmd.0.set_value("", &format!("\"{}\" gui-decompress-here \"%V\"", self.executable_path.display()))?;
+        dir_decompress_direct_cmd.set_value("", &format!("\"{}\" gui-decompress-here \"%V\"", self.executable_path.display()))?;
 
         println!("Windows context menu installed successfully!");
         println!("You should see 'TauZip - Compress' and 'TauZip - Decompress' options in the right-click menu.");
-        println!("Multiple file selection is now properly supported with single instance - only one window opens!");
+        println!("Selecting multiple files opens a single window with all of them.");
         println!("Decompression will show a progress bar!");
         
         Ok(())
@@ -132,11 +269,9 @@ impl ContextMenuManager {
 
         for entry in &entries_to_remove {
             match hkcr.delete_subkey_all(entry) {
-                Ok(_) => println!("Removed: {}", entry),
-                Err(_) => {
-                    // Ignore errors for entries that don't exist
-                    // println!("Note: Entry not found: {}", entry);
-                }
+                Ok(_) => tracing::info!(key = %entry, "Removed registry key"),
+                // Entries that don't exist are fine
+                Err(e) => tracing::trace!(key = %entry, error = %e, "Registry key not removed"),
             }
         }
         
@@ -156,105 +291,80 @@ impl ContextMenuManager {
             let _ = hkcr.delete_subkey_all(&ext_decompress_path);
         }
         
-        println!("Windows context menu cleanup completed!");
+        tracing::info!("Windows context menu cleanup completed");
         Ok(())
     }
 
     #[cfg(target_os = "macos")]
-    async fn install_macos(&self) -> Result<()> {
-        // Create an Automator service or AppleScript application
-        let home_dir = dirs::home_dir().unwrap_or_default();
-        let services_dir = home_dir.join("Library/Services");
+    async fn install_macos(&self, rollback: &mut InstallRollback) -> Result<()> {
+        // Finder only picks up Quick Actions that are real Automator bundles
+        let services_dir = macos_services_dir();
         std::fs::create_dir_all(&services_dir)?;
 
-        let service_content = format!(r#"
-<?xml version="1.0" encoding="UTF-8"?>
-<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
-<plist version="1.0">
-<dict>
-    <key>NSServices</key>
-    <array>
-        <dict>
-            <key>NSMenuItem</key>
-            <dict>
-                <key>default</key>
-                <string>TauZip - Compress</string>
-            </dict>
-            <key>NSMessage</key>
-            <string>gui-compress</string>
-            <key>NSPortName</key>
-            <string>tauzip</string>
-            <key>NSRequiredContext</key>
-            <dict>
-                <key>NSApplicationIdentifier</key>
-                <string>com.finder</string>
-            </dict>
-            <key>NSSendFileTypes</key>
-            <array>
-                <string>public.item</string>
-            </array>
-        </dict>
-        <dict>
-            <key>NSMenuItem</key>
-            <dict>
-                <key>default</key>
-                <string>TauZip - Decompress</string>
-            </dict>
-            <key>NSMessage</key>
-            <string>gui-decompress</string>
-            <key>NSPortName</key>
-            <string>tauzip</string>
-            <key>NSRequiredContext</key>
-            <dict>
-                <key>NSApplicationIdentifier</key>
-                <string>com.finder</string>
-            </dict>
-            <key>NSSendFileTypes</key>
-            <array>
-                <string>public.archive</string>
-            </array>
-        </dict>
-    </array>
-</dict>
-</plist>
-"#);
+        for (name, subcommand, file_type) in MACOS_WORKFLOWS {
+            let bundle = services_dir.join(format!("{}.workflow", name));
+            rollback.create_bundle(&bundle)?;
+
+            let contents = bundle.join("Contents");
+            std::fs::write(contents.join("Info.plist"), workflow_info_plist(name, file_type))?;
+
+            let command = format!("{} {} \"$@\"", shell_quote(&self.executable_path.display().to_string()), subcommand);
+            std::fs::write(contents.join("document.wflow"), workflow_document(&command))?;
+            tracing::info!(path = %bundle.display(), "Installed service");
+        }
+
+        // Ask the pasteboard server to rescan so the items show up without logging out
+        let _ = std::process::Command::new("/System/Library/CoreServices/pbs")
+            .arg("-update")
+            .output();
 
-        std::fs::write(services_dir.join("tauzip.plist"), service_content)?;
-        
-        // You would need to create a proper macOS service bundle here
-        // This is a simplified implementation
-        
         Ok(())
     }
 
     #[cfg(target_os = "macos")]
     async fn uninstall_macos(&self) -> Result<()> {
-        let home_dir = dirs::home_dir().unwrap_or_default();
-        let services_dir = home_dir.join("Library/Services");
+        let services_dir = macos_services_dir();
         
-        // Remove all possible tauzip service files
+        // Remove the bare plists written by older versions
         let service_files = [
             "tauzip.plist",
             "TauZip.plist", 
             "TAUZIP.plist",
-            "tauzip.workflow",
-            "TauZip.workflow",
-            "TAUZIP.workflow",
         ];
         
         for file in &service_files {
             let service_path = services_dir.join(file);
             if service_path.exists() {
                 std::fs::remove_file(service_path)?;
-                println!("Removed: {}", file);
+                tracing::info!(file = %file, "Removed service");
             }
         }
+
+        let bundles = MACOS_WORKFLOWS.iter()
+            .map(|(name, _, _)| format!("{}.workflow", name))
+            .chain(["tauzip.workflow", "TauZip.workflow", "TAUZIP.workflow"].iter().map(|name| name.to_string()));
+
+        let mut removed = false;
+        for bundle in bundles {
+            let bundle_path = services_dir.join(&bundle);
+            if bundle_path.exists() {
+                std::fs::remove_dir_all(&bundle_path)?;
+                tracing::info!(bundle = %bundle, "Removed service");
+                removed = true;
+            }
+        }
+
+        if removed {
+            let _ = std::process::Command::new("/System/Library/CoreServices/pbs")
+                .arg("-update")
+                .output();
+        }
         
         Ok(())
     }
 
     #[cfg(target_os = "linux")]
-    async fn install_linux(&self) -> Result<()> {
+    async fn install_linux(&self, rollback: &mut InstallRollback) -> Result<()> {
         let home_dir = dirs::home_dir().unwrap_or_default();
         let local_share = home_dir.join(".local/share");
         std::fs::create_dir_all(local_share.join("applications"))?;
@@ -271,7 +381,7 @@ NoDisplay=true
 MimeType=application/x-archive;application/zip;application/x-tar;application/x-gzip;application/x-bzip2;application/x-compress;application/x-compressed;application/x-cpio;application/x-deb;application/x-rar;
 "#, self.executable_path.display());
 
-        std::fs::write(local_share.join("applications/tauzip.desktop"), desktop_content)?;
+        rollback.write_file(&local_share.join("applications/tauzip.desktop"), &desktop_content)?;
 
         // Create file manager action for compression with single instance support
         let compress_action = format!(r#"[Desktop Entry]
@@ -288,7 +398,7 @@ MimeTypes=all/all;
 SelectionCount=>0;
 "#, self.executable_path.display());
 
-        std::fs::write(local_share.join("file-manager/actions/tauzip-compress.desktop"), compress_action)?;
+        rollback.write_file(&local_share.join("file-manager/actions/tauzip-compress.desktop"), &compress_action)?;
 
         // Create file manager action for decompression with GUI progress and single instance support
         let decompress_action = format!(r#"[Desktop Entry]
@@ -305,7 +415,15 @@ MimeTypes=all/all;
 SelectionCount=>0;
 "#, self.executable_path.display());
 
-        std::fs::write(local_share.join("file-manager/actions/tauzip-decompress.desktop"), decompress_action)?;
+        rollback.write_file(&local_share.join("file-manager/actions/tauzip-decompress.desktop"), &decompress_action)?;
+
+        // The actions above are only read by file managers that implement the
+        // Nautilus-Actions spec, so also set up the native mechanism of the one in use
+        match detect_linux_file_manager() {
+            LinuxFileManager::Nautilus => self.install_nautilus(&local_share, rollback)?,
+            LinuxFileManager::Nemo => self.install_nemo(&local_share, rollback)?,
+            LinuxFileManager::Other => {},
+        }
 
         // Try to update the desktop database to register the new entries
         let _ = std::process::Command::new("update-desktop-database")
@@ -315,31 +433,454 @@ SelectionCount=>0;
         Ok(())
     }
 
+    // A nautilus-python extension puts the items straight into the context menu; without
+    // nautilus-python, scripts are the only option and show up under "Scripts"
+    #[cfg(target_os = "linux")]
+    fn install_nautilus(&self, local_share: &Path, rollback: &mut InstallRollback) -> Result<()> {
+        let executable = self.executable_path.display().to_string();
+
+        if has_nautilus_python() {
+            let extension_dir = local_share.join("nautilus-python/extensions");
+            std::fs::create_dir_all(&extension_dir)?;
+            let extension = nautilus_extension(&executable);
+            rollback.write_file(&extension_dir.join("tauzip.py"), &extension)?;
+            tracing::info!(path = %extension_dir.join("tauzip.py").display(), "Installed Nautilus extension");
+            println!("Restart Files (nautilus -q) for the menu items to appear.");
+            return Ok(());
+        }
+
+        let scripts_dir = local_share.join("nautilus/scripts");
+        std::fs::create_dir_all(&scripts_dir)?;
+        for (name, subcommand) in LINUX_MENU_ITEMS {
+            // Nautilus runs scripts from the current folder with the selected names as arguments
+            let script = format!("#!/bin/sh\nexec {} {} \"$@\"\n", shell_quote(&executable), subcommand);
+            let path = scripts_dir.join(name);
+            rollback.write_file(&path, &script)?;
+            make_executable(&path)?;
+            tracing::info!(path = %path.display(), "Installed Nautilus script");
+        }
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    fn install_nemo(&self, local_share: &Path, rollback: &mut InstallRollback) -> Result<()> {
+        let actions_dir = local_share.join("nemo/actions");
+        std::fs::create_dir_all(&actions_dir)?;
+        for (name, subcommand) in LINUX_MENU_ITEMS {
+            let action = format!(r#"[Nemo Action]
+Name={}
+Comment={} with TauZip
+Exec={} {} %F
+Icon-Name=application-x-archive
+Selection=notnone
+Extensions=any;
+"#, name, if subcommand == "gui-compress" { "Compress files" } else { "Decompress archives" }, self.executable_path.display(), subcommand);
+            let path = actions_dir.join(format!("tauzip-{}.nemo_action", subcommand.trim_start_matches("gui-")));
+            rollback.write_file(&path, &action)?;
+            tracing::info!(path = %path.display(), "Installed Nemo action");
+        }
+        Ok(())
+    }
+
     #[cfg(target_os = "linux")]
     async fn uninstall_linux(&self) -> Result<()> {
         let home_dir = dirs::home_dir().unwrap_or_default();
         let local_share = home_dir.join(".local/share");
-        
-        let files_to_remove = [
-            "applications/tauzip.desktop",
-            "applications/TauZip.desktop",
-            "applications/TAUZIP.desktop",
-            "file-manager/actions/tauzip-compress.desktop",
-            "file-manager/actions/tauzip-decompress.desktop",
-            "file-manager/actions/TauZip-compress.desktop",
-            "file-manager/actions/TauZip-decompress.desktop",
-            "file-manager/actions/TAUZIP-compress.desktop", 
-            "file-manager/actions/TAUZIP-decompress.desktop",
-        ];
 
-        for file in &files_to_remove {
-            let path = local_share.join(file);
+        for path in linux_uninstall_files(&local_share) {
             if path.exists() {
                 std::fs::remove_file(&path)?;
-                println!("Removed: {}", path.display());
+                tracing::info!(path = %path.display(), "Removed integration file");
             }
         }
 
         Ok(())
     }
+}
+
+// Menu label and CLI subcommand for each item added to Linux file managers
+#[cfg(target_os = "linux")]
+const LINUX_MENU_ITEMS: [(&str, &str); 2] = [
+    ("TauZip - Compress", "gui-compress"),
+    ("TauZip - Decompress", "gui-decompress"),
+];
+
+#[cfg(target_os = "linux")]
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum LinuxFileManager {
+    Nautilus,
+    Nemo,
+    Other,
+}
+
+// Prefer a file manager that is actually running; otherwise go by the desktop session, since
+// GNOME and Cinnamon start theirs on demand
+#[cfg(target_os = "linux")]
+fn detect_linux_file_manager() -> LinuxFileManager {
+    let running = |name: &str| {
+        std::fs::read_dir("/proc")
+            .map(|entries| entries.flatten().any(|entry| {
+                std::fs::read_to_string(entry.path().join("comm"))
+                    .map(|comm| comm.trim() == name)
+                    .unwrap_or(false)
+            }))
+            .unwrap_or(false)
+    };
+    if running("nautilus") {
+        return LinuxFileManager::Nautilus;
+    }
+    if running("nemo") {
+        return LinuxFileManager::Nemo;
+    }
+
+    let desktop = std::env::var("XDG_CURRENT_DESKTOP").unwrap_or_default().to_lowercase();
+    if desktop.contains("cinnamon") {
+        LinuxFileManager::Nemo
+    } else if desktop.contains("gnome") || desktop.contains("unity") {
+        LinuxFileManager::Nautilus
+    } else {
+        LinuxFileManager::Other
+    }
+}
+
+// The file-manager specific files `install_linux` writes for `file_manager`
+#[cfg(target_os = "linux")]
+fn linux_integration_files(local_share: &Path, file_manager: LinuxFileManager) -> Vec<PathBuf> {
+    match file_manager {
+        LinuxFileManager::Nautilus => nautilus_integration_files(local_share, has_nautilus_python()),
+        LinuxFileManager::Nemo => LINUX_MENU_ITEMS.iter()
+            .map(|(_, subcommand)| local_share.join(format!("nemo/actions/tauzip-{}.nemo_action", subcommand.trim_start_matches("gui-"))))
+            .collect(),
+        LinuxFileManager::Other => Vec::new(),
+    }
+}
+
+// A python extension when nautilus-python can load it, plain scripts otherwise
+#[cfg(target_os = "linux")]
+fn nautilus_integration_files(local_share: &Path, python: bool) -> Vec<PathBuf> {
+    match python {
+        true => vec![local_share.join("nautilus-python/extensions/tauzip.py")],
+        false => LINUX_MENU_ITEMS.iter().map(|(name, _)| local_share.join("nautilus/scripts").join(name)).collect(),
+    }
+}
+
+// Every file any install may have left, whatever file manager and nautilus-python are present
+// now: either may have changed since, and uninstalling must still remove what was written then
+#[cfg(target_os = "linux")]
+fn linux_uninstall_files(local_share: &Path) -> Vec<PathBuf> {
+    let desktop_files = [
+        "applications/tauzip.desktop",
+        "applications/TauZip.desktop",
+        "applications/TAUZIP.desktop",
+        "file-manager/actions/tauzip-compress.desktop",
+        "file-manager/actions/tauzip-decompress.desktop",
+        "file-manager/actions/TauZip-compress.desktop",
+        "file-manager/actions/TauZip-decompress.desktop",
+        "file-manager/actions/TAUZIP-compress.desktop",
+        "file-manager/actions/TAUZIP-decompress.desktop",
+    ];
+    desktop_files.iter().map(|file| local_share.join(file))
+        .chain(nautilus_integration_files(local_share, true))
+        .chain(nautilus_integration_files(local_share, false))
+        .chain(linux_integration_files(local_share, LinuxFileManager::Nemo))
+        .collect()
+}
+
+// nautilus-python installs its loader into Nautilus' extension directory
+#[cfg(target_os = "linux")]
+fn has_nautilus_python() -> bool {
+    let lib_dirs = ["/usr/lib", "/usr/lib64", "/usr/lib/x86_64-linux-gnu", "/usr/lib/aarch64-linux-gnu"];
+    let extension_dirs = ["nautilus/extensions-4", "nautilus/extensions-3.0"];
+    lib_dirs.iter().any(|lib| extension_dirs.iter().any(|dir| {
+        Path::new(lib).join(dir).join("libnautilus-python.so").exists()
+    }))
+}
+
+#[cfg(target_os = "linux")]
+fn make_executable(path: &Path) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755))?;
+    Ok(())
+}
+
+// get_file_items takes (window, files) on Nautilus 3 and just (files) on Nautilus 4
+#[cfg(target_os = "linux")]
+fn nautilus_extension(executable: &str) -> String {
+    let items = LINUX_MENU_ITEMS.iter()
+        .map(|(name, subcommand)| format!("    ({:?}, {:?}),", name, subcommand))
+        .collect::<Vec<_>>()
+        .join("\n");
+    format!(r#"import subprocess
+
+from gi.repository import GObject, Nautilus
+
+TAUZIP = {executable:?}
+ITEMS = [
+{items}
+]
+
+
+class TauZipMenuProvider(GObject.GObject, Nautilus.MenuProvider):
+    def _launch(self, _menu, subcommand, files):
+        paths = [f.get_location().get_path() for f in files]
+        subprocess.Popen([TAUZIP, subcommand] + [path for path in paths if path])
+
+    def get_file_items(self, *args):
+        files = args[-1]
+        if not files:
+            return []
+        menu_items = []
+        for label, subcommand in ITEMS:
+            item = Nautilus.MenuItem(name="TauZip::" + subcommand, label=label)
+            item.connect("activate", self._launch, subcommand, files)
+            menu_items.append(item)
+        return menu_items
+"#, executable = executable, items = items)
+}
+
+// Service title, CLI subcommand and accepted file type for each Finder Quick Action
+#[cfg(target_os = "macos")]
+const MACOS_WORKFLOWS: [(&str, &str, &str); 2] = [
+    ("TauZip - Compress", "gui-compress", "public.item"),
+    ("TauZip - Decompress", "gui-decompress", "public.archive"),
+];
+
+#[cfg(target_os = "macos")]
+fn macos_services_dir() -> PathBuf {
+    dirs::home_dir().unwrap_or_default().join("Library/Services")
+}
+
+#[cfg(target_os = "macos")]
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// Single-quotes a value for /bin/sh, so spaces in the app path survive
+#[cfg(any(target_os = "macos", target_os = "linux"))]
+fn shell_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "'\\''"))
+}
+
+#[cfg(target_os = "macos")]
+fn workflow_info_plist(name: &str, file_type: &str) -> String {
+    format!(r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>NSServices</key>
+    <array>
+        <dict>
+            <key>NSBackgroundColorName</key>
+            <string>background</string>
+            <key>NSIconName</key>
+            <string>NSActionTemplate</string>
+            <key>NSMenuItem</key>
+            <dict>
+                <key>default</key>
+                <string>{name}</string>
+            </dict>
+            <key>NSMessage</key>
+            <string>runWorkflowAsService</string>
+            <key>NSRequiredContext</key>
+            <dict>
+                <key>NSApplicationIdentifier</key>
+                <string>com.apple.finder</string>
+            </dict>
+            <key>NSSendFileTypes</key>
+            <array>
+                <string>{file_type}</string>
+            </array>
+        </dict>
+    </array>
+</dict>
+</plist>
+"#, name = xml_escape(name), file_type = file_type)
+}
+
+// A single "Run Shell Script" action that receives the selection as arguments
+#[cfg(target_os = "macos")]
+fn workflow_document(command: &str) -> String {
+    format!(r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>AMApplicationBuild</key>
+    <string>523</string>
+    <key>AMApplicationVersion</key>
+    <string>2.10</string>
+    <key>AMDocumentVersion</key>
+    <string>2</string>
+    <key>actions</key>
+    <array>
+        <dict>
+            <key>action</key>
+            <dict>
+                <key>AMAccepts</key>
+                <dict>
+                    <key>Container</key>
+                    <string>List</string>
+                    <key>Optional</key>
+                    <true/>
+                    <key>Types</key>
+                    <array>
+                        <string>com.apple.cocoa.path</string>
+                    </array>
+                </dict>
+                <key>AMActionVersion</key>
+                <string>2.0.3</string>
+                <key>AMApplication</key>
+                <array>
+                    <string>Automator</string>
+                </array>
+                <key>AMParameterProperties</key>
+                <dict>
+                    <key>COMMAND_STRING</key>
+                    <dict/>
+                    <key>CheckedForUserDefaultShell</key>
+                    <dict/>
+                    <key>inputMethod</key>
+                    <dict/>
+                    <key>shell</key>
+                    <dict/>
+                    <key>source</key>
+                    <dict/>
+                </dict>
+                <key>AMProvides</key>
+                <dict>
+                    <key>Container</key>
+                    <string>List</string>
+                    <key>Types</key>
+                    <array>
+                        <string>com.apple.cocoa.string</string>
+                    </array>
+                </dict>
+                <key>ActionBundlePath</key>
+                <string>/System/Library/Automator/Run Shell Script.action</string>
+                <key>ActionName</key>
+                <string>Run Shell Script</string>
+                <key>ActionParameters</key>
+                <dict>
+                    <key>COMMAND_STRING</key>
+                    <string>{command}</string>
+                    <key>CheckedForUserDefaultShell</key>
+                    <true/>
+                    <key>inputMethod</key>
+                    <integer>1</integer>
+                    <key>shell</key>
+                    <string>/bin/sh</string>
+                    <key>source</key>
+                    <string></string>
+                </dict>
+                <key>BundleIdentifier</key>
+                <string>com.apple.RunShellScript</string>
+                <key>CFBundleVersion</key>
+                <string>2.0.3</string>
+                <key>CanShowSelectedItemsWhenRun</key>
+                <false/>
+                <key>CanShowWhenRun</key>
+                <true/>
+                <key>Category</key>
+                <array>
+                    <string>AMCategoryUtilities</string>
+                </array>
+                <key>Class Name</key>
+                <string>RunShellScriptAction</string>
+                <key>InputUUID</key>
+                <string>5A3F1C2E-7B8D-4E6F-9A0B-1C2D3E4F5A6B</string>
+                <key>Keywords</key>
+                <array>
+                    <string>Shell</string>
+                    <string>Script</string>
+                    <string>Command</string>
+                    <string>Run</string>
+                    <string>Unix</string>
+                </array>
+                <key>OutputUUID</key>
+                <string>6B4A2D3F-8C9E-4F70-AB1C-2D3E4F5A6B7C</string>
+                <key>UUID</key>
+                <string>7C5B3E4A-9DAF-4081-BC2D-3E4F5A6B7C8D</string>
+                <key>UnlocalizedApplications</key>
+                <array>
+                    <string>Automator</string>
+                </array>
+                <key>arguments</key>
+                <dict/>
+                <key>isViewVisible</key>
+                <integer>1</integer>
+            </dict>
+        </dict>
+    </array>
+    <key>connectors</key>
+    <dict/>
+    <key>workflowMetaData</key>
+    <dict>
+        <key>applicationBundleIDsByPath</key>
+        <dict/>
+        <key>applicationPaths</key>
+        <array/>
+        <key>inputTypeIdentifier</key>
+        <string>com.apple.Automator.fileSystemObject</string>
+        <key>outputTypeIdentifier</key>
+        <string>com.apple.Automator.nothing</string>
+        <key>presentationMode</key>
+        <integer>15</integer>
+        <key>processesInput</key>
+        <false/>
+        <key>serviceInputTypeIdentifier</key>
+        <string>com.apple.Automator.fileSystemObject</string>
+        <key>serviceOutputTypeIdentifier</key>
+        <string>com.apple.Automator.nothing</string>
+        <key>serviceProcessesInput</key>
+        <false/>
+        <key>systemImageName</key>
+        <string>NSActionTemplate</string>
+        <key>useAutomaticInputType</key>
+        <false/>
+        <key>workflowTypeIdentifier</key>
+        <string>com.apple.Automator.servicesMenu</string>
+    </dict>
+</dict>
+</plist>
+"#, command = xml_escape(command))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{write_file, TempDir};
+
+    #[test]
+    fn a_failed_install_removes_only_the_files_it_wrote() {
+        let dir = TempDir::new();
+        write_file(&dir.join("other.desktop"), "not ours");
+        let mut rollback = InstallRollback::default();
+        rollback.write_file(&dir.join("tauzip.desktop"), "first").unwrap();
+        rollback.write_file(&dir.join("tauzip-compress.desktop"), "second").unwrap();
+        // A missing folder makes a later step fail partway through the install
+        assert!(rollback.write_file(&dir.join("missing/tauzip-decompress.desktop"), "third").is_err());
+
+        rollback.undo();
+        let left: Vec<_> = std::fs::read_dir(dir.path()).unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(left, vec!["other.desktop"]);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn uninstall_covers_every_nautilus_integration_whatever_is_installed_now() {
+        let local_share = Path::new("/home/user/.local/share");
+        let removed = linux_uninstall_files(local_share);
+        for python in [true, false] {
+            for file in nautilus_integration_files(local_share, python) {
+                assert!(removed.contains(&file), "{} is not removed", file.display());
+            }
+        }
+        for file in linux_integration_files(local_share, LinuxFileManager::Nemo) {
+            assert!(removed.contains(&file), "{} is not removed", file.display());
+        }
+    }
 }
\ No newline at end of file
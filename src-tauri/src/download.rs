@@ -0,0 +1,185 @@
+use anyhow::{Context, Result};
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::compression::archive_extension;
+
+// Enough for mirrors and release-asset hops, and a redirect loop still fails quickly
+const MAX_REDIRECTS: usize = 10;
+// Downloaded chunks waiting for the extraction, so the two overlap without holding the whole
+// body in memory
+const STREAM_CHUNKS_IN_FLIGHT: usize = 16;
+
+// A downloaded archive in a temporary directory of its own, removed again when this is
+// dropped, whether or not the download or extraction got to the end
+pub struct TempDownload {
+    dir: PathBuf,
+    path: PathBuf,
+}
+
+impl TempDownload {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempDownload {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+// The file name at the end of the URL's path, which is also what the format is detected from
+fn archive_name(url: &reqwest::Url) -> Result<String> {
+    let name = url.path_segments()
+        .and_then(|segments| segments.last())
+        .filter(|name| !name.is_empty())
+        .ok_or_else(|| anyhow::anyhow!("'{}' does not end in a file name", url))?;
+    if archive_extension(Path::new(name)).is_none() {
+        return Err(anyhow::anyhow!(
+            "Cannot tell the archive format of '{}'; the URL must end in a supported extension such as .zip or .tar.gz",
+            url
+        ));
+    }
+    Ok(name.to_string())
+}
+
+// A downloadable archive URL and the file name its format is detected from
+fn parse_archive_url(url: &str) -> Result<(reqwest::Url, String)> {
+    let url = reqwest::Url::parse(url).with_context(|| format!("Invalid URL '{}'", url))?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(anyhow::anyhow!("Only http and https URLs can be downloaded, not '{}'", url.scheme()));
+    }
+    let name = archive_name(&url)?;
+    Ok((url, name))
+}
+
+// The archive file name of a URL, to pick how it is extracted before anything is downloaded
+pub fn archive_url_name(url: &str) -> Result<String> {
+    parse_archive_url(url).map(|(_, name)| name)
+}
+
+// Send the request, following up to MAX_REDIRECTS redirects, and turn any answer but a 2xx
+// into an error that names the status
+async fn fetch(url: &reqwest::Url) -> Result<reqwest::Response> {
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::limited(MAX_REDIRECTS))
+        .build()?;
+    let response = client.get(url.clone()).send().await.map_err(|e| {
+        if e.is_redirect() {
+            anyhow::anyhow!("Gave up on {} after more than {} redirects", url, MAX_REDIRECTS)
+        } else {
+            anyhow::Error::new(e).context(format!("Failed to download {}", url))
+        }
+    })?;
+    if response.url() != url {
+        tracing::info!(url = %response.url(), "Redirected");
+    }
+    let status = response.status();
+    if !status.is_success() {
+        return Err(anyhow::anyhow!("The server answered {} for {}", status, response.url()));
+    }
+    Ok(response)
+}
+
+// Download an http(s) URL to a temporary file. The body is written as it arrives, so the
+// archive never has to fit in memory. `progress_callback` gets the bytes received so far and
+// the total when the server sends a Content-Length.
+pub async fn download_archive<F>(url: &str, mut progress_callback: F) -> Result<TempDownload>
+where
+    F: FnMut(u64, Option<u64>),
+{
+    let (url, name) = parse_archive_url(url)?;
+
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or(0);
+    let dir = std::env::temp_dir().join(format!("tauzip-download-{}-{}", std::process::id(), nanos));
+    std::fs::create_dir_all(&dir)?;
+    // From here on the directory goes away with `download`, including on every error below
+    let download = TempDownload { path: dir.join(&name), dir };
+
+    let mut response = fetch(&url).await?;
+    let total = response.content_length();
+
+    let mut output = BufWriter::new(File::create(download.path())?);
+    let mut received = 0;
+    while let Some(chunk) = response.chunk().await.with_context(|| format!("Download of {} was interrupted", url))? {
+        output.write_all(&chunk)?;
+        received += chunk.len() as u64;
+        progress_callback(received, total);
+    }
+    output.flush()?;
+
+    if let Some(total) = total.filter(|&total| received < total) {
+        return Err(anyhow::anyhow!("Download of {} ended after {} of {} bytes", url, received, total));
+    }
+    Ok(download)
+}
+
+// An archive being downloaded, to be read front to back as it arrives
+pub struct ArchiveStream {
+    pub name: String,
+    // From Content-Length, when the server sends one
+    pub total: Option<u64>,
+    pub reader: BodyReader,
+}
+
+// Blocking reader over a response body that a background task keeps receiving. Read it from a
+// blocking thread (spawn_blocking), not from async code.
+pub struct BodyReader {
+    chunks: tokio::sync::mpsc::Receiver<Result<Vec<u8>, String>>,
+    current: Vec<u8>,
+    position: usize,
+}
+
+impl Read for BodyReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.position == self.current.len() {
+            match self.chunks.blocking_recv() {
+                Some(Ok(chunk)) => {
+                    self.current = chunk;
+                    self.position = 0;
+                },
+                Some(Err(e)) => return Err(std::io::Error::new(std::io::ErrorKind::Other, e)),
+                None => return Ok(0),
+            }
+        }
+        let count = buf.len().min(self.current.len() - self.position);
+        buf[..count].copy_from_slice(&self.current[self.position..self.position + count]);
+        self.position += count;
+        Ok(count)
+    }
+}
+
+// Start downloading an http(s) URL and hand back its body as a reader, without storing it
+pub async fn open_archive_stream(url: &str) -> Result<ArchiveStream> {
+    let (url, name) = parse_archive_url(url)?;
+    let mut response = fetch(&url).await?;
+    let total = response.content_length();
+
+    let (sender, chunks) = tokio::sync::mpsc::channel(STREAM_CHUNKS_IN_FLIGHT);
+    tokio::spawn(async move {
+        let mut received = 0;
+        loop {
+            let message = match response.chunk().await {
+                Ok(Some(chunk)) => {
+                    received += chunk.len() as u64;
+                    Ok(chunk.to_vec())
+                },
+                Ok(None) => match total.filter(|&total| received < total) {
+                    Some(total) => Err(format!("Download of {} ended after {} of {} bytes", url, received, total)),
+                    None => break,
+                },
+                Err(e) => Err(format!("Download of {} was interrupted: {}", url, e)),
+            };
+            let failed = message.is_err();
+            // A closed channel means the extraction stopped reading
+            if sender.send(message).await.is_err() || failed {
+                break;
+            }
+        }
+    });
+
+    Ok(ArchiveStream { name, total, reader: BodyReader { chunks, current: Vec::new(), position: 0 } })
+}
\ No newline at end of file
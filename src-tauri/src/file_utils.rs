@@ -1,50 +1,156 @@
-use std::path::Path;
+use filetime::FileTime;
+use std::path::{Path, PathBuf};
 
-pub fn preserve_file_attributes(source: &Path, _target: &Path) -> std::io::Result<()> {
+pub fn preserve_file_attributes(source: &Path, target: &Path) -> std::io::Result<()> {
     let metadata = std::fs::metadata(source)?;
     
     #[cfg(unix)]
     {
-        use std::os::unix::fs::PermissionsExt;
         let permissions = metadata.permissions();
-        std::fs::set_permissions(_target, permissions)?;
+        std::fs::set_permissions(target, permissions)?;
     }
     
     // Preserve timestamps
-    let _accessed = metadata.accessed()?;
-    let _modified = metadata.modified()?;
-    
-    // Note: Setting file times requires platform-specific code
-    // This is a simplified version
-    #[cfg(unix)]
-    {
-        use std::os::unix::fs::MetadataExt;
-        // You would use libc calls here to set the timestamps
-        // This is beyond the scope of this example
-    }
+    let accessed = FileTime::from_last_access_time(&metadata);
+    let modified = FileTime::from_last_modification_time(&metadata);
+    filetime::set_file_times(target, accessed, modified)?;
     
     Ok(())
 }
 
-pub fn get_unique_name(base_path: &Path) -> std::path::PathBuf {
-    let mut counter = 1;
-    let mut unique_path = base_path.to_path_buf();
+pub fn set_modified_time(path: &Path, unix_seconds: i64, nanos: u32) -> std::io::Result<()> {
+    filetime::set_file_mtime(path, FileTime::from_unix_time(unix_seconds, nanos))
+}
+
+// "name.ext", then "name (2).ext", "name (3).ext"... whichever is free first
+pub fn get_unique_name(base_path: &Path) -> PathBuf {
+    get_unique_name_among(base_path, &[])
+}
+
+// Like get_unique_name, but also avoids `reserved` paths that were promised to other outputs
+// and don't exist on disk yet
+pub fn get_unique_name_among(base_path: &Path, reserved: &[PathBuf]) -> PathBuf {
+    let parent = base_path.parent().unwrap_or(Path::new("."));
+    let stem = match base_path.file_stem() {
+        Some(stem) => stem.to_string_lossy(),
+        None => return base_path.to_path_buf(),
+    };
+    // Archive suffixes stay whole: "backup (2).tar.gz", not "backup.tar (2).gz"
+    let archive_stem = crate::compression::strip_archive_suffix(base_path);
+    let (stem, extension) = match (archive_stem.file_name(), base_path.file_name()) {
+        (Some(short), Some(full)) if archive_stem != base_path => {
+            let short = short.to_string_lossy().into_owned();
+            let suffix = full.to_string_lossy()[short.len()..].to_string();
+            (short.into(), Some(suffix))
+        },
+        _ => (stem, base_path.extension().map(|extension| format!(".{}", extension.to_string_lossy()))),
+    };
     
-    while unique_path.exists() {
+    first_unused_path(|counter| match (counter, &extension) {
+        (1, _) => base_path.to_path_buf(),
+        (_, Some(extension)) => parent.join(format!("{} ({}){}", stem, counter, extension)),
+        (_, None) => parent.join(format!("{} ({})", stem, counter)),
+    }, reserved)
+}
+
+// Tries candidate(1), candidate(2)... and returns the first path that neither exists yet nor is
+// reserved. Shared by file renames and extraction directory names so both number collisions
+// the same way.
+fn first_unused_path(candidate: impl Fn(usize) -> PathBuf, reserved: &[PathBuf]) -> PathBuf {
+    let mut counter = 1;
+    loop {
+        let path = candidate(counter);
+        if !path.exists() && !reserved.contains(&path) {
+            return path;
+        }
         counter += 1;
-        if let Some(stem) = base_path.file_stem() {
-            if let Some(extension) = base_path.extension() {
-                unique_path = base_path.parent().unwrap_or(Path::new("."))
-                    .join(format!("{} ({}).{}", 
-                        stem.to_string_lossy(), 
-                        counter, 
-                        extension.to_string_lossy()));
-            } else {
-                unique_path = base_path.parent().unwrap_or(Path::new("."))
-                    .join(format!("{} ({})", stem.to_string_lossy(), counter));
-            }
+    }
+}
+
+// Create `dir` if needed and check a file can be written there, so an unwritable target
+// fails before extraction starts rather than partway through a large archive
+pub fn ensure_writable_dir(dir: &Path) -> std::io::Result<()> {
+    std::fs::create_dir_all(dir)?;
+    let probe = dir.join(".tauzip-write-test");
+    std::fs::File::create(&probe)?;
+    std::fs::remove_file(&probe)
+}
+
+pub const DEFAULT_OUTPUT_DIR_TEMPLATE: &str = "{stem}";
+
+// Creates the directory an archive is extracted into, next to the archive, and returns it.
+// Placeholders: {stem} archive name without extension, {date} today as YYYY-MM-DD,
+// {n} collision counter starting at 1. Without {n}, " (2)", " (3)"... is appended on collision.
+//
+// Each candidate is claimed with create_dir rather than checked with exists(), so two
+// extractions of same-named archives running at once can't both pick the same directory.
+pub fn generate_output_dir(file: &Path, template: Option<&str>) -> std::io::Result<PathBuf> {
+    let candidate = output_dir_candidates(file, template);
+    let mut counter = 1;
+    loop {
+        let path = candidate(counter);
+        // Templates like "extracted/{stem}" need the folders above the last one; only the last
+        // is claimed
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        match std::fs::create_dir(&path) {
+            Ok(()) => return Ok(path),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => counter += 1,
+            Err(e) => return Err(e),
         }
     }
+}
+
+// The directory generate_output_dir would create right now, without creating it, for dry runs
+// and previews
+pub fn preview_output_dir(file: &Path, template: Option<&str>) -> PathBuf {
+    first_unused_path(output_dir_candidates(file, template), &[])
+}
+
+fn output_dir_candidates(file: &Path, template: Option<&str>) -> impl Fn(usize) -> PathBuf {
+    let template = template.filter(|t| !t.trim().is_empty()).unwrap_or(DEFAULT_OUTPUT_DIR_TEMPLATE);
+    // "backup.zip.001" extracts to the same folder as "backup.zip"
+    let joined = crate::compression::first_volume_base(file);
+    let file = joined.as_deref().unwrap_or(file);
+    let stem = file.file_stem().unwrap_or_default().to_string_lossy();
+    let parent = file.parent().unwrap_or_else(|| Path::new(".")).to_path_buf();
     
-    unique_path
+    let now = time::OffsetDateTime::now_local().unwrap_or_else(|_| time::OffsetDateTime::now_utc());
+    let date = format!("{:04}-{:02}-{:02}", now.year(), now.month() as u8, now.day());
+    let base_name = template.replace("{stem}", &stem).replace("{date}", &date);
+    
+    move |counter| {
+        if base_name.contains("{n}") {
+            return parent.join(base_name.replace("{n}", &counter.to_string()));
+        }
+        // Directories have no extension to keep, so the counter goes at the end of the whole name
+        match counter {
+            1 => parent.join(&base_name),
+            _ => parent.join(format!("{} ({})", base_name, counter)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{write_file, TempDir};
+
+    #[test]
+    fn output_dir_templates_may_contain_folders() {
+        let dir = TempDir::new();
+        let archive = dir.join("photos.zip");
+        write_file(&archive, "");
+
+        let first = generate_output_dir(&archive, Some("extracted/{stem}")).unwrap();
+        assert_eq!(first, dir.join("extracted").join("photos"));
+        assert!(first.is_dir());
+        let second = generate_output_dir(&archive, Some("extracted/{stem}")).unwrap();
+        assert_eq!(second, dir.join("extracted").join("photos (2)"));
+
+        let dated = generate_output_dir(&archive, Some("{date}/{stem}")).unwrap();
+        assert!(dated.is_dir());
+        assert_eq!(dated.file_name().unwrap(), "photos");
+    }
 }
\ No newline at end of file
@@ -1,11 +1,12 @@
-use super::compression::{compress_files, decompress_files_with_progress, CompressionType};
+use super::compression::{append_to_zip, available_memory, compress_files, compress_per_item, filter_compressible, CompressibilitySplit, is_complete_archive, estimate_memory, level_sweep, LevelResult, DEFAULT_COMPRESSION_LEVEL, convert_archive, delete_entry, detect_archive_type, extract_prefix, is_compressed_file, list_archive, move_to_trash, OverwriteBackup, preview_entry, preview_extraction, ArchiveEntry, CompressOptions, ConflictPolicy, DecompressOptions, ExtractionPreview, PasswordResolver, ProgressMode, CompressionError, CompressionPreset, ZipMethod, decompress_files_with_progress, verify_archive, CompressionType, ProgressCounts};
 use anyhow::Result;
 use std::ffi::c_void;
 use std::path::{PathBuf, Path};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::sync::{Arc, Mutex};
 use tauri::{Manager, App, AppHandle, generate_context, WebviewWindow, Emitter, Runtime, Window, Listener};
+use tauri_plugin_notification::NotificationExt;
 use serde::{Serialize, Deserialize};
 use serde_json::Value;
 use std::sync::atomic::{AtomicUsize, Ordering};
@@ -13,6 +14,27 @@ use std::sync::atomic::{AtomicUsize, Ordering};
 //use tauri_plugin_shell::ShellExt;
 use sysinfo::{System, Process, Signal};
 use crate::GuiState;
+use crate::context_menu::ContextMenuManager;
+use crate::file_utils::{ensure_writable_dir, generate_output_dir, preview_output_dir};
+use crate::history::{self, RecentEntry};
+use crate::manifest::{read_manifest, verify_extraction, ExtractionReport};
+
+#[derive(Clone, Serialize)]
+pub struct CompressionResult {
+    path: String,
+    original_size: u64,
+    compressed_size: u64,
+    ratio: f64, // compressed_size / original_size
+}
+
+// Outcome of a batch extraction: one archive failing doesn't stop the rest
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DecompressionBatchResult {
+    succeeded: Vec<String>, // output directories of the archives that extracted
+    failed: Vec<(String, String)>, // (archive path, error) for the ones that didn't
+    password_needed: bool, // some archive failed for want of the right password
+    message: String,
+}
 
 #[derive(Clone, Serialize)]
 pub struct CompressionProgressUpdate {
@@ -20,9 +42,56 @@ pub struct CompressionProgressUpdate {
     current_file: String,
     total_files: usize,
     current_file_index: usize,
+    files_done: usize,
+    files_total: usize,
+    bytes_done: u64,
+    bytes_total: u64,
+    bytes_per_second: f64,
+    eta_seconds: Option<u64>, // None until the rate is known
+    operation: String, // "compressing" or "extracting"
+}
+
+// Progress through a batch as a whole, emitted as "overall-progress": the item being worked
+// on is `index` of `total`, archives when extracting and input files when compressing
+#[derive(Clone, Serialize)]
+pub struct OverallProgressUpdate {
+    progress: f64,
+    index: usize, // 1-based
+    total: usize,
+    operation: String,
+}
+
+// Progress through the archive being read or written, emitted as "file-progress"
+#[derive(Clone, Serialize)]
+pub struct FileProgressUpdate {
+    progress: f64,
+    file: String,
+    bytes_done: u64,
+    bytes_total: u64,
+    operation: String,
+}
+
+// Emitted as "operation-complete" when a compression or extraction is done: the archives written
+// or the folders extracted to, for the UI to pass to open_file_location as they are
+#[derive(Clone, Serialize)]
+pub struct OperationComplete {
+    output_paths: Vec<String>,
     operation: String, // "compressing" or "extracting"
 }
 
+// Average rate since the operation started and the remaining time at that rate
+fn transfer_rate(bytes_done: u64, bytes_total: u64, started: Instant) -> (f64, Option<u64>) {
+    let elapsed = started.elapsed().as_secs_f64();
+    if bytes_done == 0 || elapsed <= 0.0 {
+        return (0.0, None);
+    }
+    
+    let bytes_per_second = bytes_done as f64 / elapsed;
+    let eta_seconds = (bytes_total > 0)
+        .then(|| (bytes_total.saturating_sub(bytes_done) as f64 / bytes_per_second).ceil() as u64);
+    (bytes_per_second, eta_seconds)
+}
+
 fn count_processes_by_name(name: &str) -> usize {
     let mut sys = System::new_all();
     sys.refresh_processes();
@@ -39,7 +108,7 @@ fn kill_processes_by_name(name: &str) {
 
     for process in sys.processes().values() {
         if process.name().eq_ignore_ascii_case(name) {
-            println!("Killing process: {} (PID: {})", process.name(), process.pid());
+            tracing::info!(name = process.name(), pid = %process.pid(), "Killing process");
             let _ = process.kill_with(Signal::Kill); // or Signal::Term
         }
     }
@@ -57,16 +126,75 @@ async fn count_now(count: usize, state: tauri::State<'_, Arc<GuiState>>) -> Resu
 	return Ok(());
 }
 
+// Stop the running compress_files_command or decompress_files_command; a window only runs one
+// at a time. The command notices between blocks and removes what it had written: the partial
+// archive, with a last progress event whose operation is "cancelled", or the extraction folder
+// of the archive being unpacked if the run created it.
+#[tauri::command]
+async fn cancel_operation(state: tauri::State<'_, Arc<GuiState>>) -> Result<(), String> {
+	state.cancel_token.store(true, Ordering::SeqCst);
+	Ok(())
+}
+
+// Last "compression-progress" event of a cancelled run, so the dialog reports the cancel instead
+// of "Complete"
+fn emit_cancelled(window: &tauri::Window, files_done: usize, files_total: usize) {
+    let _ = window.app_handle().emit("compression-progress", &CompressionProgressUpdate {
+        progress: 0.0,
+        current_file: "Cancelled".to_string(),
+        total_files: files_total,
+        current_file_index: files_done,
+        files_done,
+        files_total,
+        bytes_done: 0,
+        bytes_total: 0,
+        bytes_per_second: 0.0,
+        eta_seconds: None,
+        operation: "cancelled".to_string(),
+    });
+}
+
+fn parse_compression_type(compressiontype: &str) -> Result<CompressionType, String> {
+    match compressiontype {
+        "Zip" => Ok(CompressionType::Zip),
+        "Tar" => Ok(CompressionType::Tar),
+        "TarGz" => Ok(CompressionType::TarGz),
+        "TarBr" => Ok(CompressionType::TarBr),
+        "Gz" => Ok(CompressionType::Gz),
+        "Br" => Ok(CompressionType::Br),
+        "Gzip" => Ok(CompressionType::Gzip),
+        "Bzip2" => Ok(CompressionType::Bzip2),
+        _ => Err(format!("Unsupported compression type: {}", compressiontype)),
+    }
+}
+
+fn parse_preset(preset: Option<&str>) -> Result<CompressionPreset, String> {
+    match preset {
+        Some(name) => CompressionPreset::from_name(name).ok_or_else(|| format!("Unsupported compression preset: {}", name)),
+        None => Ok(CompressionPreset::default()),
+    }
+}
+
 #[tauri::command]
 async fn compress_files_command(
     window: tauri::Window,
     files: Vec<String>, 
     outputfile: String, 
     compressiontype: String,
+    include: Option<Vec<String>>,
+    exclude: Option<Vec<String>>,
+    method: Option<String>,
+    autostore: Option<bool>,
+    progressmode: Option<String>,
+    checksum: Option<bool>,
+    preset: Option<String>,
+    brotliquality: Option<u32>,
+    brotliwindow: Option<u32>,
+    overwrite: Option<bool>,
+    each: Option<bool>,
 	state: tauri::State<'_, Arc<GuiState>>
-) -> Result<String, String> {
-    println!("Compression request received - files: {:?}, output: {}, type: {}", 
-             files, outputfile, compressiontype);
+) -> Result<CompressionResult, String> {
+    tracing::info!(?files, output = %outputfile, format = %compressiontype, "Compression request received");
     
 	// let current = state.fetch_add(0, Ordering::SeqCst);
 	let count = count_processes_by_name("TauZip.exe");
@@ -75,16 +203,7 @@ async fn compress_files_command(
 	}
 	
     // Convert string to CompressionType enum
-    let compression_enum = match compressiontype.as_str() {
-        "Zip" => CompressionType::Zip,
-        "TarGz" => CompressionType::TarGz,
-        "TarBr" => CompressionType::TarBr,
-        "Gz" => CompressionType::Gz,
-        "Br" => CompressionType::Br,
-        "Gzip" => CompressionType::Gzip,
-        "Bzip2" => CompressionType::Bzip2,
-        _ => return Err(format!("Unsupported compression type: {}", compressiontype)),
-    };
+    let compression_enum = parse_compression_type(&compressiontype)?;
     
     // Convert string paths back to PathBuf
     let file_paths: Vec<PathBuf> = files.iter().map(|f| PathBuf::from(f)).collect();
@@ -105,58 +224,322 @@ async fn compress_files_command(
         }
     };
     
-    println!("Output path resolved to: {}", output_path.display());
+    tracing::debug!(output = %output_path.display(), "Output path resolved");
     
     // Use the new progress version
     use super::compression::compress_files_with_progress;
     
-    compress_files_with_progress(&file_paths, &output_path, compression_enum, |progress, current_filename| {
-        let progress_update = CompressionProgressUpdate {
-            progress,
-            current_file: current_filename,
-            total_files: file_paths.len(),
-            current_file_index: 1,
-            operation: "compressing".to_string(),
-        };
-        let _ = window.app_handle().emit("compression-progress", &progress_update);
+    // Fresh token per operation so a previous cancel doesn't abort this one
+    state.cancel_token.store(false, Ordering::SeqCst);
+    
+    let mut final_counts = ProgressCounts::default();
+    let started = Instant::now();
+    let zip_method = match method.as_deref() {
+        Some(name) => ZipMethod::from_name(name).ok_or_else(|| format!("Unsupported zip method: {}", name))?,
+        None => ZipMethod::default(),
+    };
+    let progress_mode = match progressmode.as_deref() {
+        Some(name) => ProgressMode::from_name(name).ok_or_else(|| format!("Unsupported progress mode: {}", name))?,
+        None => ProgressMode::default(),
+    };
+    let preset = parse_preset(preset.as_deref())?;
+    let compress_options = CompressOptions {
+        include: include.unwrap_or_default(),
+        exclude: exclude.unwrap_or_default(),
+        zip_method,
+        preset,
+        brotli_quality: brotliquality,
+        brotli_window: brotliwindow,
+        overwrite: overwrite.unwrap_or(false),
+        auto_store: autostore.unwrap_or(false),
+        progress_mode,
+        write_checksum: checksum.unwrap_or(false),
+        ..Default::default()
+    };
+    // Every input gets an archive of its own next to it and `outputfile` goes unused
+    if each.unwrap_or(false) {
+        return compress_each(window, file_paths, compression_enum, compress_options, &state).await;
+    }
+    let archive_name = output_path.file_name().unwrap_or_default().to_string_lossy().to_string();
+    let result = compress_files_with_progress(&file_paths, &output_path, compression_enum, &compress_options, &state.cancel_token, |progress, current_filename, counts| {
+        emit_compression_progress(&window, progress, current_filename, counts, started, &archive_name);
+        final_counts = counts.clone();
     })
-    .await
-    .map_err(|e| {
+    .await;
+    if matches!(result, Err(CompressionError::Cancelled)) {
+        emit_cancelled(&window, final_counts.files_done, final_counts.files_total);
+    }
+    let result = result.map_err(|e| {
         let error_msg = format!("Compression failed: {}", e);
-        println!("{}", error_msg);
+        tracing::error!("{}", error_msg);
         error_msg
-    })?;
+    });
     
-    // Final progress update
+    // An existing archive is numbered around rather than replaced unless `overwrite` is set
+    let written = result.as_ref().map(|path| path.as_path()).unwrap_or(output_path.as_path());
+    let _ = history::record_operation(&written.display().to_string(), "compress", result.as_ref().err());
+    let output_path = result?;
+    let archive_name = output_path.file_name().unwrap_or_default().to_string_lossy().to_string();
+    
+    emit_compression_complete(&window, &final_counts, started, &archive_name);
+    
+    // A pipe or device output has no meaningful length; report 0 rather than failing
+    let compressed_size = std::fs::metadata(&output_path)
+        .map(|metadata| if metadata.is_file() { metadata.len() } else { 0 })
+        .unwrap_or(0);
+    let original_size = final_counts.bytes_total;
+    let ratio = if original_size > 0 { compressed_size as f64 / original_size as f64 } else { 0.0 };
+    
+    tracing::info!(output = %output_path.display(), original_size, compressed_size, "Files compressed");
+    emit_operation_complete(&window, "compressing", vec![output_path.display().to_string()]);
+    notify_completion(&window, &format!("Archive created: {}", archive_name_of(&output_path)));
+    Ok(CompressionResult {
+        path: output_path.display().to_string(),
+        original_size,
+        compressed_size,
+        ratio,
+    })
+}
+
+// compress_files_command's "each" mode: compress_per_item with the dialog's progress events,
+// summed over all the archives. The result points at the first archive and adds up all their sizes.
+async fn compress_each(
+    window: tauri::Window,
+    file_paths: Vec<PathBuf>,
+    compression_enum: CompressionType,
+    compress_options: CompressOptions,
+    state: &GuiState,
+) -> Result<CompressionResult, String> {
+    let started = Instant::now();
+    let jobs = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+    let summary_name = format!("{} archives", file_paths.len());
+    let final_counts = Arc::new(Mutex::new(ProgressCounts::default()));
+
+    let progress_window = window.clone();
+    let progress_counts = final_counts.clone();
+    let progress_name = summary_name.clone();
+    let results = compress_per_item(&file_paths, compression_enum, &compress_options, jobs, &state.cancel_token, move |progress, current_filename, counts| {
+        emit_compression_progress(&progress_window, progress, current_filename, counts, started, &progress_name);
+        *progress_counts.lock().unwrap() = counts.clone();
+    })
+    .await;
+    // Cancelled items have already removed their partial archives
+    if state.cancel_token.load(Ordering::SeqCst) {
+        let counts = final_counts.lock().unwrap().clone();
+        emit_cancelled(&window, counts.files_done, counts.files_total);
+        return Err(format!("Compression failed: {}", CompressionError::Cancelled));
+    }
+    let results = results.map_err(|e| {
+        let error_msg = format!("Compression failed: {}", e);
+        tracing::error!("{}", error_msg);
+        error_msg
+    })?;
+
+    let mut outputs = Vec::new();
+    let mut failed = Vec::new();
+    for item in results {
+        match item.result {
+            Ok(output_path) => {
+                let _ = history::record_operation(&output_path.display().to_string(), "compress", None);
+                outputs.push(output_path);
+            },
+            Err(e) => {
+                let error_msg = format!("{}: {}", item.input.display(), e);
+                let _ = history::record_operation(&item.input.display().to_string(), "compress", Some(&error_msg));
+                failed.push(error_msg);
+            },
+        }
+    }
+    if !failed.is_empty() {
+        let error_msg = format!("Compression failed for {} of {} files: {}", failed.len(), file_paths.len(), failed.join("; "));
+        tracing::error!("{}", error_msg);
+        return Err(error_msg);
+    }
+
+    let final_counts = final_counts.lock().unwrap().clone();
+    emit_compression_complete(&window, &final_counts, started, &summary_name);
+
+    let compressed_size: u64 = outputs.iter()
+        .filter_map(|output_path| std::fs::metadata(output_path).ok())
+        .map(|metadata| metadata.len())
+        .sum();
+    let original_size = final_counts.bytes_total;
+    let ratio = if original_size > 0 { compressed_size as f64 / original_size as f64 } else { 0.0 };
+
+    tracing::info!(archives = outputs.len(), original_size, compressed_size, "Files compressed separately");
+    emit_operation_complete(&window, "compressing", outputs.iter().map(|output_path| output_path.display().to_string()).collect());
+    notify_completion(&window, &format!("{} archives created", outputs.len()));
+    Ok(CompressionResult {
+        path: outputs.first().map(|output_path| output_path.display().to_string()).unwrap_or_default(),
+        original_size,
+        compressed_size,
+        ratio,
+    })
+}
+
+// The three progress events of a running compression; `archive_name` is the archive being
+// written, or a summary of them when compressing each input separately
+fn emit_compression_progress(window: &tauri::Window, progress: f64, current_file: String, counts: &ProgressCounts, started: Instant, archive_name: &str) {
+    tracing::trace!(progress, file = %current_file, files_done = counts.files_done, files_total = counts.files_total, bytes_done = counts.bytes_done, bytes_total = counts.bytes_total, "Compression progress");
+    let (bytes_per_second, eta_seconds) = transfer_rate(counts.bytes_done, counts.bytes_total, started);
+    let progress_update = CompressionProgressUpdate {
+        progress,
+        current_file,
+        total_files: counts.files_total,
+        current_file_index: (counts.files_done + 1).min(counts.files_total),
+        files_done: counts.files_done,
+        files_total: counts.files_total,
+        bytes_done: counts.bytes_done,
+        bytes_total: counts.bytes_total,
+        bytes_per_second,
+        eta_seconds,
+        operation: "compressing".to_string(),
+    };
+    let _ = window.app_handle().emit("compression-progress", &progress_update);
+    let _ = window.app_handle().emit("overall-progress", &OverallProgressUpdate {
+        progress: if counts.files_total > 0 { counts.files_done as f64 / counts.files_total as f64 * 100.0 } else { progress },
+        index: (counts.files_done + 1).min(counts.files_total),
+        total: counts.files_total,
+        operation: "compressing".to_string(),
+    });
+    let _ = window.app_handle().emit("file-progress", &FileProgressUpdate {
+        progress: if counts.bytes_total > 0 { counts.bytes_done as f64 / counts.bytes_total as f64 * 100.0 } else { progress },
+        file: archive_name.to_string(),
+        bytes_done: counts.bytes_done,
+        bytes_total: counts.bytes_total,
+        operation: "compressing".to_string(),
+    });
+}
+
+// The same events at 100%, sent once the archive is finished
+fn emit_compression_complete(window: &tauri::Window, final_counts: &ProgressCounts, started: Instant, archive_name: &str) {
+    let (bytes_per_second, _) = transfer_rate(final_counts.bytes_done, final_counts.bytes_total, started);
     let final_progress = CompressionProgressUpdate {
         progress: 100.0,
         current_file: "Complete".to_string(),
-        total_files: 1,
-        current_file_index: 1,
+        total_files: final_counts.files_total,
+        current_file_index: final_counts.files_total,
+        files_done: final_counts.files_done,
+        files_total: final_counts.files_total,
+        bytes_done: final_counts.bytes_done,
+        bytes_total: final_counts.bytes_total,
+        bytes_per_second,
+        eta_seconds: Some(0),
         operation: "compressing".to_string(),
     };
     let _ = window.emit("compression-progress", &final_progress);
-    
-    let success_msg = format!("Files compressed successfully to: {}", output_path.display());
-    println!("{}", success_msg);
-    Ok(success_msg)
+    let _ = window.emit("overall-progress", &OverallProgressUpdate {
+        progress: 100.0,
+        index: final_counts.files_total,
+        total: final_counts.files_total,
+        operation: "compressing".to_string(),
+    });
+    let _ = window.emit("file-progress", &FileProgressUpdate {
+        progress: 100.0,
+        file: archive_name.to_string(),
+        bytes_done: final_counts.bytes_done,
+        bytes_total: final_counts.bytes_total,
+        operation: "compressing".to_string(),
+    });
+}
+
+fn emit_operation_complete(window: &tauri::Window, operation: &str, output_paths: Vec<String>) {
+    tracing::debug!(operation, ?output_paths, "Operation complete");
+    let _ = window.emit("operation-complete", &OperationComplete {
+        output_paths,
+        operation: operation.to_string(),
+    });
+}
+
+fn archive_name_of(path: &Path) -> String {
+    path.file_name().unwrap_or_default().to_string_lossy().to_string()
+}
+
+// A quiet OS notification once an operation is done, if notify_on_completion is set in
+// config.json. Skipped while the window has focus, since its own status line already says so.
+fn notify_completion(window: &tauri::Window, body: &str) {
+    if !crate::config::load_config().notify_on_completion || window.is_focused().unwrap_or(false) {
+        return;
+    }
+    if let Err(e) = window.app_handle().notification().builder().title("TauZip").body(body).show() {
+        tracing::warn!(error = %e, "Could not show a notification");
+    }
 }
 
 #[tauri::command]
 async fn decompress_files_command(
     window: tauri::Window,
-    files: Vec<String>
-) -> Result<String, String> {
-    println!("Decompression request received - files: {:?}", files);
+    files: Vec<String>,
+    conflictpolicy: Option<String>,
+    outputdirtemplate: Option<String>,
+    outputdir: Option<String>,
+    password: Option<String>,
+    routes: Option<Vec<(String, String)>>,
+    backup: Option<bool>,
+    flatten: Option<bool>,
+    skipspacecheck: Option<bool>,
+	state: tauri::State<'_, Arc<GuiState>>
+) -> Result<DecompressionBatchResult, String> {
+    tracing::info!(?files, conflict = ?conflictpolicy, template = ?outputdirtemplate, "Decompression request received");
+    
+    let options = DecompressOptions {
+        conflict_policy: match conflictpolicy {
+            Some(name) => ConflictPolicy::from_name(&name)
+                .ok_or_else(|| format!("Unsupported conflict policy: {}", name))?,
+            None => ConflictPolicy::default(),
+        },
+        backup_on_overwrite: backup.unwrap_or(false).then_some(move_to_trash as OverwriteBackup),
+        password: password.filter(|p| !p.is_empty()).map(PasswordResolver::single),
+        routes: routes.unwrap_or_default()
+            .into_iter()
+            .map(|(pattern, target)| (pattern, PathBuf::from(target)))
+            .collect(),
+        flatten: flatten.unwrap_or(false),
+        skip_space_check: skipspacecheck.unwrap_or(false),
+        ..Default::default()
+    };
+    
+    // An explicit target is checked up front so a read-only folder fails before any work is done
+    let explicit_dir = outputdir.filter(|dir| !dir.trim().is_empty()).map(PathBuf::from);
+    if let Some(dir) = &explicit_dir {
+        ensure_writable_dir(dir).map_err(|e| format!("Cannot extract to '{}': {}", dir.display(), e))?;
+    }
+    let mut skipped = 0;
+    let mut renamed = 0;
+    let mut trashed = 0;
+    
+    state.cancel_token.store(false, Ordering::SeqCst);
     
     let file_paths: Vec<PathBuf> = files.iter().map(|f| PathBuf::from(f)).collect();
     let total_files = file_paths.len();
     
+    // Extraction speed is measured in archive bytes read
+    let archive_sizes: Vec<u64> = file_paths.iter()
+        .map(|path| std::fs::metadata(path).map(|m| m.len()).unwrap_or(0))
+        .collect();
+    let bytes_total: u64 = archive_sizes.iter().sum();
+    let started = Instant::now();
+    
     let mut decompressed_to = Vec::new();
+    let mut failed = Vec::new();
+    let mut password_needed = false;
     
     for (index, file_path) in file_paths.iter().enumerate() {
         // Generate output directory for this file
-        let output_dir = generate_output_dir(file_path);
+        let output_dir = match explicit_dir.clone() {
+            Some(dir) => dir,
+            None => match generate_output_dir(file_path, outputdirtemplate.as_deref()) {
+                Ok(dir) => dir,
+                Err(e) => {
+                    let reason = format!("Cannot create a folder to extract into: {}", e);
+                    tracing::error!(archive = %file_path.display(), %reason, "Failed to decompress");
+                    failed.push((file_path.display().to_string(), reason));
+                    continue;
+                }
+            },
+        };
+        let bytes_before: u64 = archive_sizes[..index].iter().sum();
+        let (bytes_per_second, eta_seconds) = transfer_rate(bytes_before, bytes_total, started);
         
         // Update progress
         let progress = CompressionProgressUpdate {
@@ -167,81 +550,360 @@ async fn decompress_files_command(
                 .to_string(),
             total_files,
             current_file_index: index + 1,
+            files_done: index,
+            files_total: total_files,
+            bytes_done: bytes_before,
+            bytes_total,
+            bytes_per_second,
+            eta_seconds,
             operation: "extracting".to_string(),
         };
         
         let _ = window.emit("compression-progress", &progress);
+        let archive_name = progress.current_file.clone();
+        let _ = window.emit("overall-progress", &OverallProgressUpdate {
+            progress: progress.progress,
+            index: index + 1,
+            total: total_files,
+            operation: "extracting".to_string(),
+        });
         
         // Decompress the file
-        match decompress_files_with_progress(file_path, &output_dir, |file_progress, current_filename| {
+        match decompress_files_with_progress(file_path, &output_dir, &options, &state.cancel_token, |file_progress, current_filename| {
+            let bytes_done = bytes_before + (archive_sizes[index] as f64 * file_progress / 100.0) as u64;
+            let (bytes_per_second, eta_seconds) = transfer_rate(bytes_done, bytes_total, started);
             // Create a more detailed progress update
+            tracing::trace!(progress = file_progress, file = %current_filename, index, bytes_done, bytes_total, "Extraction progress");
             let detailed_progress = CompressionProgressUpdate {
                 progress: ((index as f64 + file_progress / 100.0) / total_files as f64) * 100.0,
                 current_file: current_filename,
                 total_files,
                 current_file_index: index + 1,
+                files_done: index,
+                files_total: total_files,
+                bytes_done,
+                bytes_total,
+                bytes_per_second,
+                eta_seconds,
                 operation: "extracting".to_string(),
             };
             let _ = window.emit("compression-progress", &detailed_progress);
+            let _ = window.emit("overall-progress", &OverallProgressUpdate {
+                progress: detailed_progress.progress,
+                index: index + 1,
+                total: total_files,
+                operation: "extracting".to_string(),
+            });
+            let _ = window.emit("file-progress", &FileProgressUpdate {
+                progress: file_progress,
+                file: archive_name.clone(),
+                bytes_done: bytes_done - bytes_before,
+                bytes_total: archive_sizes[index],
+                operation: "extracting".to_string(),
+            });
         }).await {
-            Ok(_) => {
+            Ok(summary) => {
+                skipped += summary.skipped;
+                renamed += summary.renamed;
+                trashed += summary.trashed;
+                let _ = history::record_operation(&file_path.display().to_string(), "decompress", None);
                 decompressed_to.push(output_dir.display().to_string());
-                println!("File decompressed to: {}", output_dir.display());
+                tracing::info!(archive = %file_path.display(), output = %output_dir.display(), "File decompressed");
             },
             Err(e) => {
-                let error_msg = format!("Failed to decompress '{}': {}", file_path.display(), e);
-                println!("{}", error_msg);
-                return Err(error_msg);
+                let reason = match &e {
+                    CompressionError::PasswordRequired { .. } | CompressionError::WrongPassword { .. } => {
+                        password_needed = true;
+                        e.to_string()
+                    },
+                    CompressionError::Corrupt(detail) => format!("The archive is damaged or incomplete ({})", detail),
+                    _ => e.to_string(),
+                };
+                let error_msg = format!("Failed to decompress '{}': {}", file_path.display(), reason);
+                tracing::error!("{}", error_msg);
+                let _ = history::record_operation(&file_path.display().to_string(), "decompress", Some(&error_msg));
+                // A cancel ends the whole batch, any other failure only skips this archive
+                if matches!(e, CompressionError::Cancelled) {
+                    emit_cancelled(&window, index, total_files);
+                    return Err(error_msg);
+                }
+                failed.push((file_path.display().to_string(), reason));
             }
         }
     }
     
     // Final progress update
+    let (bytes_per_second, _) = transfer_rate(bytes_total, bytes_total, started);
     let final_progress = CompressionProgressUpdate {
         progress: 100.0,
         current_file: "Complete".to_string(),
         total_files,
         current_file_index: total_files,
+        files_done: total_files,
+        files_total: total_files,
+        bytes_done: bytes_total,
+        bytes_total,
+        bytes_per_second,
+        eta_seconds: Some(0),
         operation: "extracting".to_string(),
     };
     let _ = window.app_handle().emit("compression-progress", &final_progress);
+    let _ = window.app_handle().emit("overall-progress", &OverallProgressUpdate {
+        progress: 100.0,
+        index: total_files,
+        total: total_files,
+        operation: "extracting".to_string(),
+    });
     
-    let success_msg = if decompressed_to.len() == 1 {
+    let message = if decompressed_to.is_empty() {
+        format!("No archives were decompressed. {} failed.", failed.len())
+    } else if decompressed_to.len() == 1 && failed.is_empty() {
         format!("File decompressed successfully to: {}", decompressed_to[0])
-    } else {
+    } else if failed.is_empty() {
         format!("Files decompressed successfully. {} archives processed.", decompressed_to.len())
+    } else {
+        format!("{} of {} archives decompressed, {} failed.", decompressed_to.len(), total_files, failed.len())
+    };
+    let message = if skipped > 0 || renamed > 0 {
+        format!("{} ({} existing files skipped, {} renamed)", message, skipped, renamed)
+    } else {
+        message
+    };
+    let message = if trashed > 0 {
+        format!("{} {} replaced files were moved to the trash.", message, trashed)
+    } else {
+        message
     };
     
-    println!("{}", success_msg);
+    tracing::info!("{}", message);
+    emit_operation_complete(&window, "extracting", decompressed_to.clone());
+    match (decompressed_to.len(), file_paths.first()) {
+        (0, _) => {},
+        (1, Some(file_path)) if failed.is_empty() => notify_completion(&window, &format!("Archive extracted: {}", archive_name_of(file_path))),
+        _ => notify_completion(&window, &message),
+    }
+    Ok(DecompressionBatchResult {
+        succeeded: decompressed_to,
+        failed,
+        password_needed,
+        message,
+    })
+}
+
+#[tauri::command]
+async fn convert_archive_command(
+    window: tauri::Window,
+    filepath: String,
+    outputfile: String,
+    compressiontype: String,
+    preset: Option<String>,
+	state: tauri::State<'_, Arc<GuiState>>
+) -> Result<String, String> {
+    tracing::info!(file = %filepath, output = %outputfile, format = %compressiontype, "Conversion request received");
+    
+    let compression_enum = parse_compression_type(&compressiontype)?;
+    let preset = parse_preset(preset.as_deref())?;
+    let input_path = PathBuf::from(&filepath);
+    let output_path = if Path::new(&outputfile).is_absolute() {
+        PathBuf::from(&outputfile)
+    } else {
+        input_path.parent().unwrap_or_else(|| Path::new(".")).join(&outputfile)
+    };
+    
+    state.cancel_token.store(false, Ordering::SeqCst);
+    
+    let bytes_total = std::fs::metadata(&input_path).map(|m| m.len()).unwrap_or(0);
+    let started = Instant::now();
+    let result = convert_archive(&input_path, &output_path, compression_enum, preset, &state.cancel_token, |progress, current_filename| {
+        let bytes_done = (bytes_total as f64 * progress / 100.0) as u64;
+        let (bytes_per_second, eta_seconds) = transfer_rate(bytes_done, bytes_total, started);
+        let progress_update = CompressionProgressUpdate {
+            progress,
+            current_file: current_filename,
+            total_files: 1,
+            current_file_index: 1,
+            files_done: 0,
+            files_total: 1,
+            bytes_done,
+            bytes_total,
+            bytes_per_second,
+            eta_seconds,
+            operation: "converting".to_string(),
+        };
+        let _ = window.app_handle().emit("compression-progress", &progress_update);
+    })
+    .await
+    .map_err(|e| {
+        let error_msg = format!("Conversion failed: {}", e);
+        tracing::error!("{}", error_msg);
+        error_msg
+    });
+    
+    let _ = history::record_operation(&output_path.display().to_string(), "convert", result.as_ref().err());
+    result?;
+    
+    let success_msg = format!("Archive converted successfully to: {}", output_path.display());
+    tracing::info!("{}", success_msg);
     Ok(success_msg)
 }
 
+#[tauri::command]
+async fn preview_extraction_command(filepath: String, outputdir: Option<String>, outputdirtemplate: Option<String>, flatten: Option<bool>) -> Result<ExtractionPreview, String> {
+    let file_path = PathBuf::from(&filepath);
+    // Without an explicit target, preview the directory decompress_files_command would pick
+    let output_dir = outputdir.map(PathBuf::from)
+        .unwrap_or_else(|| preview_output_dir(&file_path, outputdirtemplate.as_deref()));
+    
+    preview_extraction(&file_path, &output_dir, None, &[], flatten.unwrap_or(false)).map_err(|e| {
+        let error_msg = format!("Failed to preview '{}': {}", file_path.display(), e);
+        tracing::error!("{}", error_msg);
+        error_msg
+    })
+}
+
+// The files shown when an archive in the list is expanded
+#[tauri::command]
+async fn list_archive_command(filepath: String) -> Result<Vec<ArchiveEntry>, String> {
+    list_archive(Path::new(&filepath)).map_err(|e| {
+        let error_msg = format!("Failed to list '{}': {}", filepath, e);
+        tracing::error!("{}", error_msg);
+        error_msg
+    })
+}
+
+#[tauri::command]
+async fn preview_entry_command(filepath: String, entry: String, maxbytes: Option<usize>) -> Result<Vec<u8>, String> {
+    // 64 KiB covers the headers and embedded thumbnails of common image formats
+    let max_bytes = maxbytes.unwrap_or(64 * 1024);
+    
+    preview_entry(Path::new(&filepath), &entry, max_bytes).map_err(|e| {
+        let error_msg = format!("Failed to preview '{}' in '{}': {}", entry, filepath, e);
+        tracing::error!("{}", error_msg);
+        error_msg
+    })
+}
+
+// Whether the decompression dialog can take this path; a missing file or a folder is not an archive
+#[tauri::command]
+async fn is_supported_archive_command(path: String) -> bool {
+    let path = PathBuf::from(path);
+    path.is_file() && is_compressed_file(&path)
+}
+
+// The format found in the file's first bytes, named as compress_files_command's
+// compressiontype ("Zip", "TarGz", ...), for the dialog's format badge
+#[tauri::command]
+async fn detect_archive_type_command(path: String) -> Option<String> {
+    detect_archive_type(Path::new(&path)).map(|compression_type| format!("{:?}", compression_type))
+}
+
+// Pulls one folder (or file) of the tree view out of the archive
+#[tauri::command]
+async fn extract_prefix_command(filepath: String, outputdir: String, prefix: String, stripprefix: Option<bool>) -> Result<usize, String> {
+    extract_prefix(Path::new(&filepath), Path::new(&outputdir), &prefix, stripprefix.unwrap_or(false)).map_err(|e| {
+        let error_msg = format!("Failed to extract '{}' from '{}': {}", prefix, filepath, e);
+        tracing::error!("{}", error_msg);
+        error_msg
+    })
+}
+
+#[tauri::command]
+async fn delete_entry_command(filepath: String, entry: String) -> Result<usize, String> {
+    delete_entry(Path::new(&filepath), &entry).map_err(|e| {
+        let error_msg = format!("Failed to delete '{}' from '{}': {}", entry, filepath, e);
+        tracing::error!("{}", error_msg);
+        error_msg
+    })
+}
+
+#[tauri::command]
+async fn append_files_command(filepath: String, files: Vec<String>, conflictpolicy: Option<String>) -> Result<usize, String> {
+    let policy = match conflictpolicy {
+        Some(name) => ConflictPolicy::from_name(&name)
+            .ok_or_else(|| format!("Unsupported conflict policy: {}", name))?,
+        None => ConflictPolicy::default(),
+    };
+    let file_paths: Vec<PathBuf> = files.iter().map(PathBuf::from).collect();
+
+    append_to_zip(Path::new(&filepath), &file_paths, policy).map_err(|e| {
+        let error_msg = format!("Failed to add files to '{}': {}", filepath, e);
+        tracing::error!("{}", error_msg);
+        error_msg
+    })
+}
+
+#[tauri::command]
+async fn verify_extraction_command(outputdir: String) -> Result<ExtractionReport, String> {
+    let output_dir = PathBuf::from(&outputdir);
+    
+    let manifest = match read_manifest(&output_dir) {
+        Ok(Some(manifest)) => manifest,
+        Ok(None) => return Err(format!("No MANIFEST.json found in {}", output_dir.display())),
+        Err(e) => return Err(e.to_string()),
+    };
+    
+    verify_extraction(&output_dir, &manifest).map_err(|e| {
+        let error_msg = format!("Failed to verify '{}': {}", output_dir.display(), e);
+        tracing::error!("{}", error_msg);
+        error_msg
+    })
+}
+
 #[tauri::command]
 async fn get_compression_types() -> Vec<String> {
-    vec![
-        "Zip".to_string(),
-        "TarGz".to_string(),
-        "TarBr".to_string(),
-        "Gz".to_string(),
-        "Br".to_string(),
-        "Gzip".to_string(),
-        "Bzip2".to_string(),
-    ]
+    // The variant names are what parse_compression_type accepts back
+    CompressionType::all().iter().map(|compression_type| format!("{:?}", compression_type)).collect()
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MemoryEstimate {
+    pub estimated: u64,
+    pub available: u64,
+}
+
+// Lets the dialog warn before a run that is likely to exhaust memory
+#[tauri::command]
+async fn estimate_memory_command(files: Vec<String>, compressiontype: String, preset: Option<String>) -> Result<MemoryEstimate, String> {
+    let compression_enum = parse_compression_type(&compressiontype)?;
+    let file_paths: Vec<PathBuf> = files.iter().map(PathBuf::from).collect();
+    // Brotli's best quality needs far more memory than its default
+    let level = parse_preset(preset.as_deref())?.level(&compression_enum).unwrap_or(DEFAULT_COMPRESSION_LEVEL);
+
+    tokio::task::spawn_blocking(move || MemoryEstimate {
+        // The dialog compresses on a single thread
+        estimated: estimate_memory(&file_paths, &compression_enum, level, 1),
+        available: available_memory(),
+    })
+    .await
+    .map_err(|e| e.to_string())
+}
+
+// Which selected files are already compressed, so the dialog can suggest storing or excluding them
+#[tauri::command]
+async fn filter_compressible_command(files: Vec<String>, entropy: Option<bool>) -> Result<CompressibilitySplit, String> {
+    let file_paths: Vec<PathBuf> = files.iter().map(PathBuf::from).collect();
+
+    tokio::task::spawn_blocking(move || filter_compressible(&file_paths, entropy.unwrap_or(true)))
+        .await
+        .map_err(|e| e.to_string())
+}
+
+// Size/time per level on a sample of the selection, for the dialog's level chart
+#[tauri::command]
+async fn level_sweep_command(files: Vec<String>, compressiontype: String) -> Result<Vec<LevelResult>, String> {
+    let compression_enum = parse_compression_type(&compressiontype)?;
+    let file_paths: Vec<PathBuf> = files.iter().map(PathBuf::from).collect();
+
+    tokio::task::spawn_blocking(move || level_sweep(&file_paths, &compression_enum))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())
 }
 
 #[tauri::command]
 async fn validate_compression_type(files: Vec<String>, compressiontype: String) -> Result<bool, String> {
     // Convert string to CompressionType enum
-    let compression_enum = match compressiontype.as_str() {
-        "Zip" => CompressionType::Zip,
-        "TarGz" => CompressionType::TarGz,
-        "TarBr" => CompressionType::TarBr,
-        "Gz" => CompressionType::Gz,
-        "Br" => CompressionType::Br,
-        "Gzip" => CompressionType::Gzip,
-        "Bzip2" => CompressionType::Bzip2,
-        _ => return Err(format!("Unsupported compression type: {}", compressiontype)),
-    };
+    let compression_enum = parse_compression_type(&compressiontype)?;
     
     if !compression_enum.supports_multiple_files() && files.len() > 1 {
         return Ok(false);
@@ -263,11 +925,44 @@ fn close(app: tauri::AppHandle) -> Result<(), String> {
 	//return Err("Unable to close window".to_string());
 }
 
+#[tauri::command]
+async fn get_recent_operations() -> Vec<RecentEntry> {
+    history::recent_operations()
+}
+
+// False when the archive stops early, e.g. an unfinished download
+#[tauri::command]
+async fn check_complete_command(filepath: String) -> Result<bool, String> {
+    tokio::task::spawn_blocking(move || is_complete_archive(&PathBuf::from(filepath)))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn verify_archive_command(file_path: String) -> Result<String, String> {
+    let path = PathBuf::from(&file_path);
+    
+    match verify_archive(&path) {
+        Ok(_) => Ok(format!("Archive is OK: {}", path.display())),
+        Err(e) => {
+            let error_msg = format!("Archive verification failed for '{}': {:#}", path.display(), e);
+            tracing::error!("{}", error_msg);
+            Err(error_msg)
+        }
+    }
+}
+
+#[tauri::command]
+async fn is_context_menu_installed() -> bool {
+    ContextMenuManager::new().is_installed()
+}
+
 #[tauri::command]
 async fn open_file_location(file_path: String) -> Result<(), String> {
     let path = PathBuf::from(&file_path);
     
-    println!("Opening file location for: {}", file_path);
+    tracing::info!(path = %file_path, "Opening file location");
     
     #[cfg(target_os = "windows")]
     {
@@ -315,13 +1010,10 @@ async fn open_file_location(file_path: String) -> Result<(), String> {
 }
 
 pub fn run_app(app: &AppHandle, mut file_strings2: Vec<String>, argv: Vec<String>, gui_state: Arc<GuiState>) {
-	let log = false;
-	if log { std::fs::write("aa.txt", format!("run_app")); }
-	
 	let file_args: Vec<String> = argv.into_iter().skip(2).collect();
 	file_strings2.extend(file_args);
 	
-	if log { std::fs::write("aa.txt", format!("file_strings2 {:?}", file_strings2)); }
+	tracing::debug!(files = ?file_strings2, "Another launch passed files to the compression window");
 	
 	let c = *&file_strings2.len();
 	let app2 = app.clone();
@@ -354,15 +1046,9 @@ pub fn run_app(app: &AppHandle, mut file_strings2: Vec<String>, argv: Vec<String
 		match app2.emit("files-selected", file_strings2) {
 			Ok(_) => {
 				item2.fetch_add(c, Ordering::SeqCst);
-				//let s = Path::new(&c);
-				//if log { std::fs::write(format!("aa {}.txt", s.file_name().unwrap().to_string_lossy()), format!("emit success {:?}", s)); }
-				//println!("Successfully emitted files-selected event with {} files", file_strings3.len())
-			},
-			Err(e) => {
-				//let s = Path::new(&c);
-				//if log { std::fs::write(format!("aa {}.txt", s.file_name().unwrap().to_string_lossy()), format!("emit fail {:?}", file_strings4)); }
-				//println!("Failed to emit files-selected event: {}", e)
+				tracing::debug!(event = "files-selected", files = c, "Emitted");
 			},
+			Err(e) => tracing::error!(event = "files-selected", error = %e, "Failed to emit"),
 		}
 		// let mut count1 = item3.fetch_add(0, Ordering::SeqCst);
 		// while true {
@@ -395,13 +1081,10 @@ pub fn run_app(app: &AppHandle, mut file_strings2: Vec<String>, argv: Vec<String
 }
 
 pub fn run_decom_app(app: &AppHandle, mut file_strings2: Vec<String>, argv: Vec<String>, gui_state: Arc<GuiState>) {
-	let log = false;
-	if log { std::fs::write("aa.txt", "got main decom"); }
-		
 	let file_args: Vec<String> = argv.into_iter().skip(2).collect();
 	file_strings2.extend(file_args);
 		
-	if log { std::fs::write("b.txt", format!("file_strings2 decom 3 {:?}", &file_strings2)); }
+	tracing::debug!(files = ?file_strings2, "Another launch passed archives to the decompression window");
 	
 	let c = *&file_strings2.len();
 	let app2 = app.clone();
@@ -432,33 +1115,25 @@ pub fn run_decom_app(app: &AppHandle, mut file_strings2: Vec<String>, argv: Vec<
 		}
 		
 		match app2.emit("set-mode", "decompression") {
-			Ok(_) => println!("Successfully set decompression mode"),
-			Err(e) => println!("Failed to set decompression mode: {}", e),
+			Ok(_) => tracing::debug!(event = "set-mode", "Emitted"),
+			Err(e) => tracing::error!(event = "set-mode", error = %e, "Failed to emit"),
 		}
 		match app2.emit("archives-selected", file_strings2) {
 			Ok(_) => {
 				item2.fetch_add(c, Ordering::SeqCst);
-				//let s = Path::new(&c);
-				//if log { std::fs::write(format!("ba {}.txt", s.file_name().unwrap().to_string_lossy()), format!("emit decom success {:?}", s)); }
-				//println!("Successfully emitted files-selected event with {} files", file_strings3.len())
-			},
-			Err(e) => {
-				//let s = Path::new(&c);
-				//if log { std::fs::write(format!("ba {}.txt", s.file_name().unwrap().to_string_lossy()), format!("emit decom fail {:?}", file_strings4)); }
-				//println!("Failed to emit files-selected event: {}", e)
+				tracing::debug!(event = "archives-selected", files = c, "Emitted");
 			},
+			Err(e) => tracing::error!(event = "archives-selected", error = %e, "Failed to emit"),
 		}
 	});
 }
 
 
 pub async fn run_compression_dialog(file_strings: Vec<String>, files: Vec<PathBuf>, gui_state: Arc<GuiState>) -> Result<()> {
-    println!("Starting Tauri compression app with {} files", files.len());
+    tracing::info!(files = files.len(), args = file_strings.len(), "Starting Tauri compression app");
     
-	let log = false;
 	let file_strings2 = file_strings.clone();
 	let file_strings2b = file_strings.clone();
-    if log { std::fs::write("a.txt", "before"); }
 	
 	let item_clone = gui_state.item_count.clone();
 	let item_clone2 = gui_state.item_count.clone();
@@ -472,24 +1147,43 @@ pub async fn run_compression_dialog(file_strings: Vec<String>, files: Vec<PathBu
 	let arg_received_clone = gui_state.arg_received.clone();
 	let arg_received_clone2 = gui_state.arg_received.clone();
 	let arg_received_clone3 = gui_state.arg_received.clone();
+	let cancel_token_clone = gui_state.cancel_token.clone();
+	let cancel_token_clone2 = gui_state.cancel_token.clone();
 	
 	tauri::Builder::default()
 		.invoke_handler(tauri::generate_handler![
             compress_files_command,
             get_compression_types,
             validate_compression_type,
+            estimate_memory_command,
+            filter_compressible_command,
+            level_sweep_command,
             open_file_location,
 			close,
-			count_now
+			count_now,
+			cancel_operation,
+			is_context_menu_installed,
+			get_recent_operations,
+			verify_archive_command,
+			convert_archive_command,
+			preview_extraction_command,
+			verify_extraction_command,
+			list_archive_command,
+			preview_entry_command,
+			extract_prefix_command,
+			is_supported_archive_command,
+			detect_archive_type_command,
+			delete_entry_command,
+			append_files_command
         ])
 		.manage(gui_state.clone()) // store it in Tauri state
 		//.manage(item_clone.clone()) // store it in Tauri state
 		//.plugin(tauri_plugin_shell::init())
 		//.plugin(tauri_plugin_cli::init())
+		.plugin(tauri_plugin_notification::init())
         .plugin(tauri_plugin_single_instance::init(move |app, argv, _cwd| {
-			//println!("Tauri compression app setup started");
-			if log { std::fs::write("abc.txt", format!("{:?}", argv.clone())); }
-            run_app(app, file_strings2.clone(), argv.clone(), Arc::new(GuiState { window_count: window_count_clone2.clone(), item_count: item_clone.clone(), count_now: count_now_clone.clone(), arg_received: arg_received_clone.clone() }));
+			tracing::debug!(?argv, "Second compression launch");
+            run_app(app, file_strings2.clone(), argv.clone(), Arc::new(GuiState { window_count: window_count_clone2.clone(), item_count: item_clone.clone(), count_now: count_now_clone.clone(), arg_received: arg_received_clone.clone(), cancel_token: cancel_token_clone.clone() }));
 			//return Ok(());
 		}))
 		.setup(move |app| {
@@ -502,18 +1196,18 @@ pub async fn run_compression_dialog(file_strings: Vec<String>, files: Vec<PathBu
 			for x in files {
 				fb.push(x.display().to_string());
 			}
-			run_app(&app.app_handle(), file_strings2b.clone(), fb.clone(), Arc::new(GuiState { window_count: window_count_clone3.clone(), item_count: item_clone2.clone(), count_now: count_now_clone2.clone(), arg_received: arg_received_clone2.clone() }));
+			run_app(&app.app_handle(), file_strings2b.clone(), fb.clone(), Arc::new(GuiState { window_count: window_count_clone3.clone(), item_count: item_clone2.clone(), count_now: count_now_clone2.clone(), arg_received: arg_received_clone2.clone(), cancel_token: cancel_token_clone2.clone() }));
 			
 			let app3 = appx.clone();
 			{
-				tokio::spawn(async move {
+				tauri::async_runtime::spawn(async move {
 					while true {
 						let mut c = 0;
 						{
 							c = *count_now_clone3.lock().unwrap();
 						}
 						
-						tokio::time::sleep(Duration::from_millis(1000));
+						tokio::time::sleep(Duration::from_millis(1000)).await;
 						
 						let itemc = item_clone3.fetch_add(0, Ordering::SeqCst);
 						let total_arg = *arg_received_clone3.lock().unwrap();
@@ -534,9 +1228,8 @@ pub async fn run_compression_dialog(file_strings: Vec<String>, files: Vec<PathBu
 }
 
 pub async fn run_decompression_dialog(file_strings: Vec<String>, files: Vec<PathBuf>, gui_state: Arc<GuiState>) -> Result<()> {
-    println!("Starting Tauri decompression app with {} files", files.len());
+    tracing::info!(files = files.len(), args = file_strings.len(), "Starting Tauri decompression app");
     
-	let log = false;
     let file_strings2 = file_strings.clone();
 	let file_strings2b = file_strings.clone();
 	
@@ -552,21 +1245,39 @@ pub async fn run_decompression_dialog(file_strings: Vec<String>, files: Vec<Path
 	let arg_received_clone = gui_state.arg_received.clone();
 	let arg_received_clone2 = gui_state.arg_received.clone();
 	let arg_received_clone3 = gui_state.arg_received.clone();
+	let cancel_token_clone = gui_state.cancel_token.clone();
+	let cancel_token_clone2 = gui_state.cancel_token.clone();
 	
 	tauri::Builder::default()
 		.invoke_handler(tauri::generate_handler![
             decompress_files_command,
+            check_complete_command,
             open_file_location,
 			close,
-			count_now
+			count_now,
+			cancel_operation,
+			is_context_menu_installed,
+			get_recent_operations,
+			verify_archive_command,
+			convert_archive_command,
+			preview_extraction_command,
+			verify_extraction_command,
+			list_archive_command,
+			preview_entry_command,
+			extract_prefix_command,
+			is_supported_archive_command,
+			detect_archive_type_command,
+			delete_entry_command,
+			append_files_command
         ])
 		.manage(gui_state.clone()) // store it in Tauri state
 		//.manage(item_clone.clone()) // store it in Tauri state
 		//.plugin(tauri_plugin_shell::init())
 		//.plugin(tauri_plugin_cli::init())
+		.plugin(tauri_plugin_notification::init())
         .plugin(tauri_plugin_single_instance::init(move |app, argv, _cwd| {
-			if log { std::fs::write("def.txt", format!("{:?}", argv.clone())); }
-			run_decom_app(app, file_strings2.clone(), argv.clone(), Arc::new(GuiState { window_count: window_count_clone2.clone(), item_count: item_clone.clone(), count_now: count_now_clone.clone(), arg_received: arg_received_clone.clone()}));
+			tracing::debug!(?argv, "Second decompression launch");
+			run_decom_app(app, file_strings2.clone(), argv.clone(), Arc::new(GuiState { window_count: window_count_clone2.clone(), item_count: item_clone.clone(), count_now: count_now_clone.clone(), arg_received: arg_received_clone.clone(), cancel_token: cancel_token_clone.clone()}));
         }))
 		.setup(move |app| {
 			let appx = app.app_handle().clone();
@@ -578,18 +1289,18 @@ pub async fn run_decompression_dialog(file_strings: Vec<String>, files: Vec<Path
 			for x in files {
 				fb.push(x.display().to_string());
 			}
-			run_decom_app(&app.app_handle(), file_strings2b.clone(), fb.clone(), Arc::new(GuiState { window_count: window_count_clone3.clone(), item_count: item_clone2.clone(), count_now: count_now_clone2.clone(), arg_received: arg_received_clone2.clone()}));
+			run_decom_app(&app.app_handle(), file_strings2b.clone(), fb.clone(), Arc::new(GuiState { window_count: window_count_clone3.clone(), item_count: item_clone2.clone(), count_now: count_now_clone2.clone(), arg_received: arg_received_clone2.clone(), cancel_token: cancel_token_clone2.clone()}));
 			
 			let app3 = appx.clone();
 			{
-				tokio::spawn(async move {
+				tauri::async_runtime::spawn(async move {
 					while true {
 						let mut c = 0;
 						{
 							c = *count_now_clone3.lock().unwrap();
 						}
 						
-						tokio::time::sleep(Duration::from_millis(1000));
+						tokio::time::sleep(Duration::from_millis(1000)).await;
 						
 						let itemc = item_clone3.fetch_add(0, Ordering::SeqCst);
 						let total_arg = *arg_received_clone3.lock().unwrap();
@@ -607,19 +1318,4 @@ pub async fn run_decompression_dialog(file_strings: Vec<String>, files: Vec<Path
         .expect("error while running tauri application");
 		
 	Ok(())
-}
-
-fn generate_output_dir(file: &PathBuf) -> PathBuf {
-    let base_name = file.file_stem().unwrap_or_default().to_string_lossy();
-    let parent = file.parent().unwrap_or_else(|| std::path::Path::new("."));
-    
-    let mut counter = 1;
-    let mut output_dir = parent.join(base_name.as_ref());
-    
-    while output_dir.exists() {
-        counter += 1;
-        output_dir = parent.join(format!("{} ({})", base_name, counter));
-    }
-    
-    output_dir
 }
\ No newline at end of file
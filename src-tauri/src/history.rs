@@ -0,0 +1,52 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const MAX_RECENT_ENTRIES: usize = 20;
+const HISTORY_FILE_NAME: &str = "recent.json";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RecentEntry {
+    pub path: String,
+    pub operation: String, // "compress", "decompress" or "convert"
+    pub timestamp: u64,
+    pub result: String, // "success" or the error message
+}
+
+fn history_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("tauzip").join(HISTORY_FILE_NAME))
+}
+
+// Newest first; a missing or unreadable history file is treated as empty
+pub fn recent_operations() -> Vec<RecentEntry> {
+    let path = match history_path() {
+        Some(path) => path,
+        None => return Vec::new(),
+    };
+    
+    std::fs::read_to_string(path)
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default()
+}
+
+pub fn record_operation(path: &str, operation: &str, error: Option<&String>) -> Result<()> {
+    let history_path = history_path()
+        .ok_or_else(|| anyhow::anyhow!("Could not determine the config directory"))?;
+    
+    let mut entries = recent_operations();
+    entries.insert(0, RecentEntry {
+        path: path.to_string(),
+        operation: operation.to_string(),
+        timestamp: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+        result: error.cloned().unwrap_or_else(|| "success".to_string()),
+    });
+    entries.truncate(MAX_RECENT_ENTRIES);
+    
+    if let Some(parent) = history_path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(&history_path, serde_json::to_string_pretty(&entries)?)?;
+    Ok(())
+}
\ No newline at end of file
@@ -7,19 +7,29 @@
 #[allow(unused_imports)]
 use clap::{Arg, Command};
 use std::path::PathBuf;
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use std::fs::{File, OpenOptions};
 use std::io::{Read, Write, BufRead, BufReader};
 use serde::{Serialize, Deserialize};
 use std::sync::Mutex;
 use std::sync::Arc;
 use std::ffi::CString;
+use base64::{engine::general_purpose::STANDARD as BASE64_STANDARD, Engine};
+mod chunk_store;
 mod compression;
+mod config;
 mod context_menu;
+#[cfg(feature = "url-support")]
+mod download;
 mod file_utils;
 mod gui;
-use compression::{compress_files, decompress_files, CompressionType, is_compressed_file};
+mod history;
+mod manifest;
+#[cfg(test)]
+mod test_utils;
+use compression::{compress_files_with_progress, list_archive, preview_extraction, ArchiveEntry, CompressOptions, CompressionPreset, ConflictPolicy, SymlinkPolicy, ZipMethod, ProgressMode, DecompressOptions, ExtractSummary, convert_archive, decompress_files, decompress_files_with_progress, decompress_to_writer, verify_archive, CompressionType, PasswordResolver, is_compressed_file, CancellationToken, AgeKey};
 use context_menu::ContextMenuManager;
+use file_utils::{ensure_writable_dir, generate_output_dir, preview_output_dir};
 use tauri::{Manager, AppHandle};
 use std::sync::atomic::{AtomicUsize, Ordering};
 
@@ -31,13 +41,18 @@ struct FileCollectionSession {
 }
 
 const COLLECTION_TIMEOUT_MS: u64 = 500; // Wait 500ms for more files
+const SESSION_STALE_MS: u64 = COLLECTION_TIMEOUT_MS * 4; // Sessions untouched for 2 seconds were abandoned
 const SESSION_FILE_PREFIX: &str = "tauzip_session_";
+const DEFAULT_FORMAT_ENV: &str = "TAUZIP_DEFAULT_FORMAT";
+// --base64 holds the whole input in memory, so it is meant for small payloads only
+const BASE64_MAX_INPUT_BYTES: u64 = 64 * 1024 * 1024;
 
 pub struct GuiState {
 	pub window_count: Arc<AtomicUsize>,
 	pub item_count: Arc<AtomicUsize>,
 	pub count_now: Arc<Mutex<usize>>,
-	pub arg_received: Arc<Mutex<usize>>
+	pub arg_received: Arc<Mutex<usize>>,
+	pub cancel_token: CancellationToken
 }
 
 #[tokio::main]
@@ -45,14 +60,26 @@ async fn main() -> anyhow::Result<()> {
 	let window_count: Arc<AtomicUsize> = Arc::new(AtomicUsize::new(0));
 	let item_count: Arc<AtomicUsize> = Arc::new(AtomicUsize::new(0));
 	let ars = std::env::args().into_iter().collect::<Vec<String>>();
-	let gui_state = Arc::new(GuiState { window_count: Arc::new(AtomicUsize::new(0)), item_count: Arc::new(AtomicUsize::new(0)), count_now: Arc::new(Mutex::new(0)), arg_received: Arc::new(Mutex::new(0)) });
+	share_runtime_with_tauri();
+	
+	let gui_state = Arc::new(GuiState { window_count: Arc::new(AtomicUsize::new(0)), item_count: Arc::new(AtomicUsize::new(0)), count_now: Arc::new(Mutex::new(0)), arg_received: Arc::new(Mutex::new(0)), cancel_token: CancellationToken::default() });
 	if ars.len() > 2 && ars[1].to_string().to_lowercase() == "gui-compress".to_string() {
+		init_tracing(0);
 		let args: Vec<String> = std::env::args().into_iter().skip(2).collect::<Vec<String>>();
+		let args = match aggregate_gui_args(args, "compress").await? {
+			Some(args) => args,
+			None => return Ok(()),
+		};
 	
 		gui::run_compression_dialog(args, vec![], gui_state.clone()).await?;
 		return Ok(());
 	} else if ars.len() > 2 && ars[1].to_string().to_lowercase() == "gui-decompress".to_string() {
+		init_tracing(0);
 		let args: Vec<String> = std::env::args().into_iter().skip(2).collect::<Vec<String>>();
+		let args = match aggregate_gui_args(args, "decompress").await? {
+			Some(args) => args,
+			None => return Ok(()),
+		};
 	
 		gui::run_decompression_dialog(args, vec![], gui_state.clone()).await?;
 		return Ok(());
@@ -62,6 +89,12 @@ async fn main() -> anyhow::Result<()> {
     let matches = Command::new("tauzip")
         .version("0.1.0")
         .about("Cross-platform compression utility with context menu integration")
+        .arg(Arg::new("verbose")
+            .short('v')
+            .long("verbose")
+            .action(clap::ArgAction::Count)
+            .global(true)
+            .help("Log more detail to standard error: -v for debug, -vv for trace; RUST_LOG overrides this"))
         .subcommand(
             Command::new("install")
                 .about("Install context menu integration")
@@ -70,6 +103,10 @@ async fn main() -> anyhow::Result<()> {
             Command::new("uninstall")
                 .about("Remove context menu integration")
         )
+        .subcommand(
+            Command::new("status")
+                .about("Show whether context menu integration is installed")
+        )
         .subcommand(
             Command::new("compress")
                 .about("Compress files")
@@ -78,15 +115,430 @@ async fn main() -> anyhow::Result<()> {
                     .required(true)
                     .num_args(1..)
                     .value_parser(clap::value_parser!(PathBuf)))
+                .arg(Arg::new("output")
+                    .short('o')
+                    .long("output")
+                    .value_name("FILE")
+                    .value_parser(clap::value_parser!(PathBuf))
+                    .help("Output archive; unless --format is given, the format is inferred from its extension (e.g. .zip, .tar.gz, .tar.br)"))
+                .arg(Arg::new("parallel")
+                    .long("parallel")
+                    .action(clap::ArgAction::SetTrue)
+                    .help("Compress zip entries on all CPU cores"))
+                .arg(Arg::new("preserve-paths")
+                    .long("preserve-paths")
+                    .action(clap::ArgAction::SetTrue)
+                    .help("Store zip entries with their path relative to --root instead of just the selected names"))
+                .arg(Arg::new("root")
+                    .long("root")
+                    .value_name("DIR")
+                    .requires("preserve-paths")
+                    .value_parser(clap::value_parser!(PathBuf))
+                    .help("Directory that stored paths are relative to (default: filesystem root)"))
+                .arg(Arg::new("base-depth")
+                    .long("base-depth")
+                    .value_name("N")
+                    .value_parser(clap::value_parser!(usize))
+                    .help("Drop the first N components of each input's path from stored zip paths"))
+                .arg(Arg::new("split-into")
+                    .long("split-into")
+                    .value_name("N")
+                    .value_parser(clap::value_parser!(usize))
+                    .help("Split the archive into exactly N volumes of nearly equal size (.001, .002, ...)"))
+                .arg(Arg::new("split-size")
+                    .long("split-size")
+                    .value_name("SIZE")
+                    .value_parser(parse_size)
+                    .conflicts_with("split-into")
+                    .help("Split the archive into volumes of SIZE bytes each, e.g. 700M or 2G (.001, .002, ...); decompress the .001 file to join them again"))
+                .arg(Arg::new("include")
+                    .long("include")
+                    .value_name("GLOB")
+                    .action(clap::ArgAction::Append)
+                    .help("Only store files matching this pattern (repeatable)"))
+                .arg(Arg::new("exclude")
+                    .long("exclude")
+                    .value_name("GLOB")
+                    .action(clap::ArgAction::Append)
+                    .help("Skip files and folders matching this pattern, e.g. node_modules or '*.log' (repeatable); combined with any .tauzipignore in the compressed folders"))
+                .arg(Arg::new("symlinks")
+                    .long("symlinks")
+                    .value_name("POLICY")
+                    .value_parser(["follow", "store", "skip"])
+                    .default_value("follow")
+                    .help("Archive what symbolic links point to, the links themselves, or neither"))
+                .arg(Arg::new("method")
+                    .long("method")
+                    .value_name("METHOD")
+                    .value_parser(["deflated", "stored", "bzip2"])
+                    .default_value("deflated")
+                    .help("How zip entries are compressed; stored copies them as-is"))
+                .arg(Arg::new("preset")
+                    .long("preset")
+                    .value_name("PRESET")
+                    .value_parser(["fastest", "default", "best"])
+                    .default_value("default")
+                    .help("Trade speed for size: fastest and best use the lowest and highest level of the format (gzip and bzip2 1 and 9, brotli 0 and 11)"))
+                .arg(Arg::new("brotli-quality")
+                    .long("brotli-quality")
+                    .value_name("0-11")
+                    .value_parser(clap::value_parser!(u32).range(0..=11))
+                    .help("Brotli quality for .br and .tar.br, overriding --preset; 11 is much smaller for text but far slower"))
+                .arg(Arg::new("brotli-window")
+                    .long("brotli-window")
+                    .value_name("10-24")
+                    .value_parser(clap::value_parser!(u32).range(10..=24))
+                    .help("Brotli window size as a power of two for .br and .tar.br (default 22); larger finds repeats further apart but needs more memory to compress and decompress"))
+                .arg(Arg::new("auto-store")
+                    .long("auto-store")
+                    .action(clap::ArgAction::SetTrue)
+                    .help("Store already-compressed files (jpg, mp4, zip...) in zip archives without recompressing; extend the list with incompressible_extensions in config.json"))
+                .arg(Arg::new("precise-times")
+                    .long("precise-times")
+                    .action(clap::ArgAction::SetTrue)
+                    .help("Keep exact file modification times in zip archives (UT and NTFS extra fields)"))
+                .arg(Arg::new("dedup")
+                    .long("dedup")
+                    .action(clap::ArgAction::SetTrue)
+                    .help("Store identical files in a zip once; the copies are restored on extraction by tauzip only (other tools extract them empty)"))
+                .arg(Arg::new("manifest")
+                    .long("manifest")
+                    .action(clap::ArgAction::SetTrue)
+                    .conflicts_with_all(["age-passphrase", "age-recipient"])
+                    .help("Add a MANIFEST.json listing every file's size and SHA-256, for checking an extraction with decompress --verify-manifest"))
+                .arg(Arg::new("cd-sidecar")
+                    .long("cd-sidecar")
+                    .action(clap::ArgAction::SetTrue)
+                    .help("Also write a zip's central directory to <archive>.cd for clients that read it with range requests"))
+                .arg(Arg::new("checksum")
+                    .long("checksum")
+                    .action(clap::ArgAction::SetTrue)
+                    .help("Also write <archive>.sha256 with the archive's SHA-256, as sha256sum does"))
+                .arg(Arg::new("overwrite")
+                    .long("overwrite")
+                    .action(clap::ArgAction::SetTrue)
+                    .help("Replace an existing archive at the output path instead of writing \"name (2).zip\" next to it"))
+                .arg(Arg::new("sort-entries")
+                    .long("sort-entries")
+                    .action(clap::ArgAction::SetTrue)
+                    .help("Add entries in sorted path order for archives that list the same every time"))
+                .arg(Arg::new("best-of")
+                    .long("best-of")
+                    .value_name("FORMATS")
+                    .value_delimiter(',')
+                    .num_args(1..)
+                    .conflicts_with_all(["split-into", "age-passphrase", "age-recipient"])
+                    .help("Compress into each listed format (e.g. zip,tar.gz,tar.br) and keep only the smallest; --output names the archive without its extension"))
+                .arg(Arg::new("per-item")
+                    .long("per-item")
+                    .visible_alias("each")
+                    .action(clap::ArgAction::SetTrue)
+                    .conflicts_with_all(["output", "split-into", "split-size", "best-of", "age-passphrase", "age-recipient"])
+                    .help("Compress each input into its own archive next to it: notes.txt becomes notes.zip, or notes.txt.gz with a single-file format"))
+                .arg(Arg::new("format")
+                    .long("format")
+                    .value_name("EXT")
+                    .conflicts_with_all(["best-of", "age-passphrase", "age-recipient"])
+                    .help("Archive format, e.g. zip, tar.gz, tar.br (default: inferred from --output, otherwise TAUZIP_DEFAULT_FORMAT or default_format in config.json, falling back to zip)"))
+                .arg(Arg::new("jobs")
+                    .short('j')
+                    .long("jobs")
+                    .value_name("N")
+                    .requires("per-item")
+                    .value_parser(clap::value_parser!(usize))
+                    .help("How many --per-item archives to write at once (default: number of CPUs)"))
+                .arg(Arg::new("age-passphrase")
+                    .long("age-passphrase")
+                    .value_name("PASSPHRASE")
+                    .help("Create an age-encrypted .tar.gz.age using this passphrase"))
+                .arg(Arg::new("age-recipient")
+                    .long("age-recipient")
+                    .value_name("RECIPIENT")
+                    .conflicts_with("age-passphrase")
+                    .help("Create an age-encrypted .tar.gz.age for this age1... public key"))
+                .arg(Arg::new("base64")
+                    .long("base64")
+                    .action(clap::ArgAction::SetTrue)
+                    .conflicts_with_all(["output", "split-into", "split-size", "best-of", "per-item", "format", "cd-sidecar", "checksum", "manifest", "age-passphrase", "age-recipient"])
+                    .help("Gzip a single file in memory and print it to standard output as base64 text (up to 64 MiB)"))
         )
         .subcommand(
             Command::new("decompress")
                 .about("Decompress files (command line, no GUI)")
                 .arg(Arg::new("files")
-                    .help("Files to decompress")
+                    .help("Files to decompress; http(s) URLs are downloaded and extracted in one step (needs the 'url-support' feature)")
+                    .required_unless_present("base64")
+                    .num_args(1..)
+                    .value_parser(clap::value_parser!(PathBuf)))
+                .arg(Arg::new("output")
+                    .short('o')
+                    .long("output")
+                    .value_name("FILE")
+                    .value_parser(clap::value_parser!(PathBuf))
+                    .help("Exact output file for a single .gz, .br or .bz2 input"))
+                .arg(Arg::new("output-dir")
+                    .short('d')
+                    .long("output-dir")
+                    .value_name("DIR")
+                    .value_parser(clap::value_parser!(PathBuf))
+                    .conflicts_with_all(["output", "dir-template"])
+                    .help("Extract into this directory (created if needed) instead of next to the archive"))
+                .arg(Arg::new("on-conflict")
+                    .long("on-conflict")
+                    .value_name("POLICY")
+                    .value_parser(["overwrite", "skip", "rename"])
+                    .default_value("overwrite")
+                    .help("What to do with extracted files that already exist"))
+                .arg(Arg::new("backup-overwritten")
+                    .long("backup-overwritten")
+                    .action(clap::ArgAction::SetTrue)
+                    .help("Move files that extraction overwrites to the trash first, so they can be restored"))
+                .arg(Arg::new("stdout")
+                    .long("stdout")
+                    .action(clap::ArgAction::SetTrue)
+                    .conflicts_with_all(["output", "dry-run", "verify-manifest"])
+                    .help("Write the decompressed data of .gz, .br or .bz2 files to standard output"))
+                .arg(Arg::new("dry-run")
+                    .long("dry-run")
+                    .action(clap::ArgAction::SetTrue)
+                    .help("List the files that would be written without extracting anything"))
+                .arg(Arg::new("verify-manifest")
+                    .long("verify-manifest")
+                    .action(clap::ArgAction::SetTrue)
+                    .help("After extracting, check the files against the archive's MANIFEST.json"))
+                .arg(Arg::new("dir-template")
+                    .long("dir-template")
+                    .value_name("TEMPLATE")
+                    .help("Name of the extraction directory: {stem}, {date} and {n} are replaced (default \"{stem}\")"))
+                .arg(Arg::new("password")
+                    .long("password")
+                    .value_name("PASSWORD")
+                    .help("Password for encrypted zip entries"))
+                .arg(Arg::new("route")
+                    .long("route")
+                    .value_name("PATTERN=DIR")
+                    .action(clap::ArgAction::Append)
+                    .conflicts_with("output")
+                    .help("Extract entries matching a glob into DIR (relative to the output directory), e.g. --route '*.jpg=images'; the first matching rule wins"))
+                .arg(Arg::new("flatten")
+                    .long("flatten")
+                    .action(clap::ArgAction::SetTrue)
+                    .conflicts_with_all(["output", "stdout"])
+                    .help("Put every extracted file directly in the output directory, ignoring the archive's folders; files with the same name are numbered"))
+                .arg(Arg::new("recursive")
+                    .short('r')
+                    .long("recursive")
+                    .action(clap::ArgAction::SetTrue)
+                    .conflicts_with("stdout")
+                    .help("Also extract archives found inside the archive, each into a folder next to it"))
+                .arg(Arg::new("max-depth")
+                    .long("max-depth")
+                    .value_name("N")
+                    .requires("recursive")
+                    .value_parser(clap::value_parser!(usize))
+                    .help("How many levels of archives inside archives --recursive opens (default 3)"))
+                .arg(Arg::new("max-output")
+                    .long("max-output")
+                    .value_name("SIZE")
+                    .value_parser(parse_size)
+                    .help("Stop and remove the partial output once more than SIZE (e.g. 500M, 2G) has been extracted, and refuse zip entries with an implausible compression ratio; guards against decompression bombs"))
+                .arg(Arg::new("no-space-check")
+                    .long("no-space-check")
+                    .action(clap::ArgAction::SetTrue)
+                    .help("Extract even when the archive's contents look too big for the free space on the output volume"))
+                .arg(Arg::new("age-passphrase")
+                    .long("age-passphrase")
+                    .value_name("PASSPHRASE")
+                    .help("Passphrase for .tar.gz.age archives"))
+                .arg(Arg::new("age-identity")
+                    .long("age-identity")
+                    .value_name("FILE")
+                    .conflicts_with("age-passphrase")
+                    .value_parser(clap::value_parser!(PathBuf))
+                    .help("age identity file for .tar.gz.age archives"))
+                .arg(Arg::new("base64")
+                    .long("base64")
+                    .action(clap::ArgAction::SetTrue)
+                    .conflicts_with_all(["files", "output", "output-dir", "stdout", "dry-run", "verify-manifest", "recursive", "route", "flatten"])
+                    .help("Read base64-encoded gzip data (as written by compress --base64) from standard input and write the decompressed data to standard output"))
+        )
+        .subcommand(
+            Command::new("verify")
+                .about("Check archives for corruption without extracting them")
+                .arg(Arg::new("files")
+                    .help("Archives to verify")
+                    .required(true)
+                    .num_args(1..)
+                    .value_parser(clap::value_parser!(PathBuf)))
+                .arg(Arg::new("quick")
+                    .long("quick")
+                    .action(clap::ArgAction::SetTrue)
+                    .help("Only check that each archive isn't cut short, e.g. by an unfinished download"))
+                .arg(Arg::new("checksum")
+                    .long("checksum")
+                    .action(clap::ArgAction::SetTrue)
+                    .conflicts_with("quick")
+                    .help("Compare each archive with the SHA-256 in its .sha256 file instead of reading its entries"))
+        )
+        .subcommand(
+            Command::new("list")
+                .about("List the files in archives without extracting them")
+                .arg(Arg::new("files")
+                    .help("Archives to list")
+                    .required(true)
+                    .num_args(1..)
+                    .value_parser(clap::value_parser!(PathBuf)))
+                .arg(Arg::new("json")
+                    .long("json")
+                    .action(clap::ArgAction::SetTrue)
+                    .help("Print the listing as JSON"))
+        )
+        .subcommand(
+            Command::new("backup")
+                .about("Back up files into a chunk store, writing only the parts that changed since earlier backups")
+                .arg(Arg::new("files")
+                    .help("Files or folders to back up")
+                    .required(true)
+                    .num_args(1..)
+                    .value_parser(clap::value_parser!(PathBuf)))
+                .arg(Arg::new("store")
+                    .long("store")
+                    .value_name("DIR")
+                    .required(true)
+                    .value_parser(clap::value_parser!(PathBuf))
+                    .help("Chunk store folder; created on first use"))
+                .arg(Arg::new("name")
+                    .long("name")
+                    .value_name("NAME")
+                    .required(true)
+                    .help("Name of the new snapshot, e.g. 2024-05-01"))
+        )
+        .subcommand(
+            Command::new("restore")
+                .about("Restore a snapshot from a chunk store, or list the snapshots in it")
+                .arg(Arg::new("store")
+                    .long("store")
+                    .value_name("DIR")
+                    .required(true)
+                    .value_parser(clap::value_parser!(PathBuf))
+                    .help("Chunk store folder written by backup"))
+                .arg(Arg::new("name")
+                    .long("name")
+                    .value_name("NAME")
+                    .requires("output-dir")
+                    .help("Snapshot to restore; without it the store's snapshots are listed"))
+                .arg(Arg::new("output-dir")
+                    .short('o')
+                    .long("output-dir")
+                    .value_name("DIR")
+                    .value_parser(clap::value_parser!(PathBuf))
+                    .help("Folder to restore the snapshot into"))
+                .arg(Arg::new("overwrite")
+                    .long("overwrite")
+                    .action(clap::ArgAction::SetTrue)
+                    .help("Replace files that already exist in the output folder instead of stopping"))
+        )
+        .subcommand(
+            Command::new("levels")
+                .about("Compare output size and time across compression levels on a sample of the inputs")
+                .arg(Arg::new("files")
+                    .help("Files or folders to sample")
+                    .required(true)
+                    .num_args(1..)
+                    .value_parser(clap::value_parser!(PathBuf)))
+                .arg(Arg::new("format")
+                    .long("format")
+                    .value_name("EXT")
+                    .default_value("zip")
+                    .help("Archive format whose levels are compared, e.g. zip, tar.gz, tar.br, bz2"))
+        )
+        .subcommand(
+            Command::new("bench")
+                .about("Compress a file or folder into every format at the default level and compare time and size")
+                .arg(Arg::new("file")
+                    .help("File or folder to compress")
+                    .required(true)
+                    .value_parser(clap::value_parser!(PathBuf)))
+        )
+        .subcommand(
+            Command::new("delete")
+                .about("Remove entries (files or whole directories) from a zip archive")
+                .arg(Arg::new("archive")
+                    .help("Zip archive to edit")
+                    .required(true)
+                    .value_parser(clap::value_parser!(PathBuf)))
+                .arg(Arg::new("entries")
+                    .help("Entry paths inside the archive, e.g. docs/old.txt or docs/")
+                    .required(true)
+                    .num_args(1..))
+        )
+        .subcommand(
+            Command::new("add")
+                .about("Add files or directories to an existing zip archive")
+                .arg(Arg::new("archive")
+                    .help("Zip archive to edit")
+                    .required(true)
+                    .value_parser(clap::value_parser!(PathBuf)))
+                .arg(Arg::new("files")
+                    .help("Files to add")
                     .required(true)
                     .num_args(1..)
                     .value_parser(clap::value_parser!(PathBuf)))
+                .arg(Arg::new("on-conflict")
+                    .long("on-conflict")
+                    .value_name("POLICY")
+                    .value_parser(["overwrite", "skip", "rename"])
+                    .default_value("overwrite")
+                    .help("What to do with added names that are already in the archive"))
+        )
+        .subcommand(
+            Command::new("convert")
+                .about("Repackage an archive in another format (e.g. tar.gz to zip)")
+                .arg(Arg::new("file")
+                    .help("Archive to convert")
+                    .required(true)
+                    .value_parser(clap::value_parser!(PathBuf)))
+                .arg(Arg::new("output")
+                    .short('o')
+                    .long("output")
+                    .value_name("FILE")
+                    .required(true)
+                    .value_parser(clap::value_parser!(PathBuf))
+                    .help("Target archive; the format is inferred from its extension"))
+                .arg(Arg::new("preset")
+                    .long("preset")
+                    .value_name("PRESET")
+                    .value_parser(["fastest", "default", "best"])
+                    .default_value("default")
+                    .help("Trade speed for size in the target format, as for compress"))
+        )
+        .subcommand(
+            Command::new("salvage")
+                .about("Recover the intact entries of a damaged or partially downloaded zip")
+                .arg(Arg::new("file")
+                    .help("Zip archive to salvage")
+                    .required(true)
+                    .value_parser(clap::value_parser!(PathBuf)))
+                .arg(Arg::new("output-dir")
+                    .short('d')
+                    .long("output-dir")
+                    .value_name("DIR")
+                    .value_parser(clap::value_parser!(PathBuf))
+                    .help("Extract into this directory (created if needed) instead of \"<name> (salvaged)\" next to the archive"))
+        )
+        .subcommand(
+            Command::new("extract-url")
+                .about("Download an archive over http(s) and extract it (needs the 'url-support' feature)")
+                .arg(Arg::new("url")
+                    .help("Archive URL; its format is taken from the file name at the end")
+                    .required(true))
+                .arg(Arg::new("output-dir")
+                    .short('o')
+                    .short_alias('d')
+                    .long("output-dir")
+                    .value_name("DIR")
+                    .value_parser(clap::value_parser!(PathBuf))
+                    .help("Extract into this directory (created if needed) instead of one named after the archive in the current directory"))
         )
         .subcommand(
             Command::new("decompress-here")
@@ -95,6 +547,10 @@ async fn main() -> anyhow::Result<()> {
                     .help("Directory to decompress archives in")
                     .required(true)
                     .value_parser(clap::value_parser!(PathBuf)))
+                .arg(Arg::new("dir-template")
+                    .long("dir-template")
+                    .value_name("TEMPLATE")
+                    .help("Name of the extraction directory: {stem}, {date} and {n} are replaced (default \"{stem}\")"))
         )
         .subcommand(
             Command::new("gui-compress")
@@ -134,7 +590,7 @@ async fn main() -> anyhow::Result<()> {
 		)
         .subcommand(
             Command::new("gui-compress-selection")
-                .about("Compress currently selected files in Explorer")
+                .about("Compress the files currently selected in Explorer or Finder")
         )
         .subcommand(
             Command::new("gui-decompress-here")
@@ -151,6 +607,10 @@ async fn main() -> anyhow::Result<()> {
         .subcommand(
             Command::new("test-gui")
                 .about("Test GUI with sample files")
+                .arg(Arg::new("real")
+                    .long("real")
+                    .action(clap::ArgAction::SetTrue)
+                    .help("Compress the tauzip executable instead of a small generated text file"))
         )
         .subcommand(
             Command::new("debug")
@@ -169,6 +629,7 @@ async fn main() -> anyhow::Result<()> {
                     .value_parser(clap::value_parser!(PathBuf)))
         )
         .get_matches();
+    init_tracing(matches.get_count("verbose"));
 
     match matches.subcommand() {
         Some(("install", _)) => {
@@ -185,50 +646,803 @@ async fn main() -> anyhow::Result<()> {
             menu_manager.uninstall().await?;
             println!("Context menu integration removed successfully!");
         },
+        Some(("status", _)) => {
+            let menu_manager = ContextMenuManager::new();
+            if menu_manager.is_installed() {
+                println!("Context menu integration is installed.");
+            } else {
+                println!("Context menu integration is not installed. Run 'tauzip install' to add it.");
+            }
+        },
         Some(("compress", sub_matches)) => {
             let files: Vec<PathBuf> = sub_matches.get_many::<PathBuf>("files")
                 .unwrap()
                 .cloned()
                 .collect();
+            let output = sub_matches.get_one::<PathBuf>("output").cloned();
+            let include: Vec<String> = sub_matches.get_many::<String>("include").unwrap_or_default().cloned().collect();
+            let exclude: Vec<String> = sub_matches.get_many::<String>("exclude").unwrap_or_default().cloned().collect();
+            let symlink_policy = sub_matches.get_one::<String>("symlinks")
+                .and_then(|name| SymlinkPolicy::from_name(name))
+                .unwrap_or_default();
+            let zip_method = sub_matches.get_one::<String>("method")
+                .and_then(|name| ZipMethod::from_name(name))
+                .unwrap_or_default();
+            let preset = sub_matches.get_one::<String>("preset")
+                .and_then(|name| CompressionPreset::from_name(name))
+                .unwrap_or_default();
+            let brotli_quality = sub_matches.get_one::<u32>("brotli-quality").copied();
+            let brotli_window = sub_matches.get_one::<u32>("brotli-window").copied();
+            let auto_store = sub_matches.get_flag("auto-store");
+            let precise_timestamps = sub_matches.get_flag("precise-times");
+            let sort_entries = sub_matches.get_flag("sort-entries");
+            let central_directory_sidecar = sub_matches.get_flag("cd-sidecar");
+            let write_checksum = sub_matches.get_flag("checksum");
+            let dedup = sub_matches.get_flag("dedup");
+            let manifest = sub_matches.get_flag("manifest");
+            let overwrite = sub_matches.get_flag("overwrite");
+            
+            if sub_matches.get_flag("base64") {
+                if files.len() != 1 || !files[0].is_file() {
+                    eprintln!("Error: --base64 compresses exactly one file, not several files or a folder");
+                    std::process::exit(1);
+                }
+                let size = std::fs::metadata(&files[0])?.len();
+                if size > BASE64_MAX_INPUT_BYTES {
+                    eprintln!("Error: '{}' is {} bytes; --base64 is limited to {} bytes", files[0].display(), size, BASE64_MAX_INPUT_BYTES);
+                    std::process::exit(1);
+                }
+                let data = std::fs::read(&files[0])?;
+                let level = preset.level(&CompressionType::Gzip).unwrap_or(compression::DEFAULT_COMPRESSION_LEVEL);
+                let compressed = compression::compress_bytes(&data, level)?;
+                println!("{}", BASE64_STANDARD.encode(compressed));
+                return Ok(());
+            }
 				
-            // For CLI compression, default to zip
-            let output_path = generate_output_path(&files, CompressionType::Zip);
-            compress_files(&files, &output_path, CompressionType::Zip).await?;
-            println!("Files compressed to: {}", output_path.display());
+            let age_key = if let Some(passphrase) = sub_matches.get_one::<String>("age-passphrase") {
+                Some(AgeKey::Passphrase(passphrase.clone()))
+            } else {
+                sub_matches.get_one::<String>("age-recipient").map(|r| AgeKey::Recipient(r.clone()))
+            };
+            
+            if let Some(key) = age_key {
+                // Encryption wraps the tar.gz stream, so the output is always .tar.gz.age
+                let output_path = output.unwrap_or_else(|| {
+                    let mut output_path = generate_output_path(&files, CompressionType::TarGz).into_os_string();
+                    output_path.push(".age");
+                    PathBuf::from(output_path)
+                });
+                
+                #[cfg(feature = "age-support")]
+                {
+                    let cancel = CancellationToken::default();
+                    let files = if sort_entries { compression::sorted_inputs(&files) } else { files };
+                    let filter = compression::PathFilter::new(&include, &exclude)?
+                        .with_ignore_files(&files)?
+                        .with_symlink_policy(symlink_policy)
+                        .with_sorted_entries(sort_entries);
+                    let level = preset.level(&CompressionType::TarGz).unwrap_or(compression::DEFAULT_COMPRESSION_LEVEL);
+                    compression::compress_tar_gz_age_with_progress(&files, &output_path, &key, level, &filter, &cancel, |_, _, _| {}).await?;
+                    println!("Files compressed and encrypted to: {}", output_path.display());
+                    return Ok(());
+                }
+                
+                #[cfg(not(feature = "age-support"))]
+                {
+                    let _ = (key, output_path, include, exclude, symlink_policy, sort_entries);
+                    eprintln!("Error: age encryption requires building with the 'age-support' feature.");
+                    std::process::exit(1);
+                }
+            }
+            
+            // An explicit --format wins over whatever the output name suggests
+            let format = match sub_matches.get_one::<String>("format") {
+                Some(format) => Some(CompressionType::from_extension(format)
+                    .ok_or_else(|| anyhow::anyhow!("Unknown archive format '{}'. Supported: {}", format, CompressionType::supported_extensions().join(", ")))?),
+                None => None,
+            };
+            
+            if sub_matches.get_flag("per-item") {
+                let compression_type = format.unwrap_or_else(default_compression_type);
+                let jobs = sub_matches.get_one::<usize>("jobs").copied()
+                    .unwrap_or_else(|| std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1));
+                let options = CompressOptions {
+                    parallel: sub_matches.get_flag("parallel"),
+                    preserve_paths: sub_matches.get_flag("preserve-paths"),
+                    root: sub_matches.get_one::<PathBuf>("root").cloned(),
+                    input_base_depth: sub_matches.get_one::<usize>("base-depth").copied(),
+                    split_into: None,
+                    split_size: None,
+                    include,
+                    exclude,
+                    symlink_policy,
+                    zip_method,
+                    preset,
+                    brotli_quality,
+                    brotli_window,
+                    auto_store,
+                    precise_timestamps,
+                    sort_entries,
+                    progress_mode: ProgressMode::default(),
+                    central_directory_sidecar,
+                    write_checksum,
+                    dedup,
+                    manifest,
+                    overwrite,
+                };
+                
+                let cancel = CancellationToken::default();
+                let results = compression::compress_per_item(&files, compression_type, &options, jobs, &cancel, |_, _, _| {}).await?;
+                let mut failed = 0;
+                for item in &results {
+                    match &item.result {
+                        Ok(output_path) => println!("{} -> {}", item.input.display(), output_path.display()),
+                        Err(e) => {
+                            failed += 1;
+                            eprintln!("Failed to compress '{}': {:#}", item.input.display(), e);
+                        }
+                    }
+                }
+                if failed > 0 {
+                    eprintln!("{} of {} archives failed", failed, results.len());
+                    std::process::exit(1);
+                }
+                return Ok(());
+            }
+            
+            if let Some(formats) = sub_matches.get_many::<String>("best-of") {
+                let mut compression_types = Vec::new();
+                for format in formats {
+                    let compression_type = CompressionType::from_extension(format)
+                        .ok_or_else(|| anyhow::anyhow!("Unknown archive format '{}'. Supported: {}", format, CompressionType::supported_extensions().join(", ")))?;
+                    compression_types.push(compression_type);
+                }
+                // Any extension on --output is dropped; the winner's is added back
+                let output_stem = compression::strip_archive_suffix(&output
+                    .unwrap_or_else(|| generate_output_path(&files, CompressionType::Zip)));
+                let options = CompressOptions {
+                    parallel: sub_matches.get_flag("parallel"),
+                    preserve_paths: sub_matches.get_flag("preserve-paths"),
+                    root: sub_matches.get_one::<PathBuf>("root").cloned(),
+                    input_base_depth: sub_matches.get_one::<usize>("base-depth").copied(),
+                    split_into: None,
+                    split_size: None,
+                    include,
+                    exclude,
+                    symlink_policy,
+                    zip_method,
+                    preset,
+                    brotli_quality,
+                    brotli_window,
+                    auto_store,
+                    precise_timestamps,
+                    sort_entries,
+                    progress_mode: ProgressMode::default(),
+                    central_directory_sidecar,
+                    write_checksum,
+                    dedup,
+                    manifest,
+                    overwrite,
+                };
+                
+                let cancel = CancellationToken::default();
+                let best = compression::compress_best_of(&files, &output_stem, &compression_types, &options, &cancel, |_, _, _| {}).await?;
+                for (compression_type, size) in &best.sizes {
+                    println!("  {:<8} {} bytes", compression_type.extension(), size);
+                }
+                println!("Smallest is {}; files compressed to: {}", best.compression_type.extension(), best.output_path.display());
+                return Ok(());
+            }
+            
+            // Use --format if given, else infer it from the output name, otherwise default to zip
+            let (output_path, compression_type) = match (output, format) {
+                (Some(output_path), Some(compression_type)) => {
+                    // .gz and .gzip name the same format
+                    let canonical = |t: &CompressionType| match t {
+                        CompressionType::Gzip => CompressionType::Gz.extension(),
+                        t => t.extension(),
+                    };
+                    if let Some(named) = CompressionType::from_file_name(&output_path) {
+                        if canonical(&named) != canonical(&compression_type) {
+                            eprintln!(
+                                "Error: '{}' looks like a {} archive but --format is {}; rename the output or drop --format.",
+                                output_path.display(),
+                                named.extension(),
+                                compression_type.extension()
+                            );
+                            std::process::exit(1);
+                        }
+                    }
+                    (output_path, compression_type)
+                },
+                (None, Some(compression_type)) => (generate_output_path(&files, compression_type.clone()), compression_type),
+                (Some(output_path), None) => {
+                    let compression_type = CompressionType::from_file_name(&output_path)
+                        .ok_or_else(|| anyhow::anyhow!(
+                            "Cannot infer a compression format from '{}'. Supported extensions: {}",
+                            output_path.display(),
+                            CompressionType::supported_extensions().join(", ")
+                        ))?;
+                    (output_path, compression_type)
+                },
+                (None, None) => {
+                    let compression_type = default_compression_type();
+                    (generate_output_path(&files, compression_type.clone()), compression_type)
+                },
+            };
+            
+            if !compression_type.supports_multiple_files() && (files.len() > 1 || files[0].is_dir()) {
+                eprintln!(
+                    "Error: {} compresses a single file; use .zip, .tar, .tar.gz or .tar.br to bundle several inputs, or --each to compress every file on its own.",
+                    compression_type.extension()
+                );
+                std::process::exit(1);
+            }
+            
+            let options = CompressOptions {
+                parallel: sub_matches.get_flag("parallel"),
+                preserve_paths: sub_matches.get_flag("preserve-paths"),
+                root: sub_matches.get_one::<PathBuf>("root").cloned(),
+                input_base_depth: sub_matches.get_one::<usize>("base-depth").copied(),
+                split_into: sub_matches.get_one::<usize>("split-into").copied(),
+                split_size: sub_matches.get_one::<u64>("split-size").copied(),
+                include,
+                exclude,
+                symlink_policy,
+                zip_method,
+                preset,
+                brotli_quality,
+                brotli_window,
+                auto_store,
+                precise_timestamps,
+                sort_entries,
+                progress_mode: ProgressMode::default(),
+                central_directory_sidecar,
+                write_checksum,
+                dedup,
+                manifest,
+                overwrite,
+            };
+            let cancel = CancellationToken::default();
+            let output_path = compress_files_with_progress(&files, &output_path, compression_type, &options, &cancel, |_, _, _| {}).await?;
+            match (options.split_into, options.split_size) {
+                (Some(parts), _) => println!("Files compressed to {} volumes: {} ... {}", parts,
+                    compression::volume_path(&output_path, 1).display(),
+                    compression::volume_path(&output_path, parts).display()),
+                (None, Some(_)) => println!("Files compressed to volumes starting at: {}", compression::volume_path(&output_path, 1).display()),
+                (None, None) => println!("Files compressed to: {}", output_path.display()),
+            }
         },
         Some(("decompress", sub_matches)) => {
+            if sub_matches.get_flag("base64") {
+                let mut encoded = String::new();
+                std::io::stdin().take(BASE64_MAX_INPUT_BYTES + 1).read_to_string(&mut encoded)?;
+                if encoded.len() as u64 > BASE64_MAX_INPUT_BYTES {
+                    eprintln!("Error: --base64 input is larger than {} bytes", BASE64_MAX_INPUT_BYTES);
+                    std::process::exit(1);
+                }
+                // Wrapped output (e.g. from `base64` with its default 76 columns) is accepted too
+                let encoded: String = encoded.split_ascii_whitespace().collect();
+                let data = match BASE64_STANDARD.decode(encoded) {
+                    Ok(data) => data,
+                    Err(e) => {
+                        eprintln!("Error: standard input is not valid base64: {}", e);
+                        std::process::exit(1);
+                    }
+                };
+                let stdout = std::io::stdout();
+                let mut out = stdout.lock();
+                if let Err(e) = compression::decompress_bytes(&data, &mut out) {
+                    eprintln!("Failed to decompress standard input: {}", e);
+                    std::process::exit(1);
+                }
+                out.flush()?;
+                return Ok(());
+            }
+            
             let files: Vec<PathBuf> = sub_matches.get_many::<PathBuf>("files")
                 .unwrap()
                 .cloned()
                 .collect();
             
+            let age_key = if let Some(passphrase) = sub_matches.get_one::<String>("age-passphrase") {
+                Some(AgeKey::Passphrase(passphrase.clone()))
+            } else {
+                sub_matches.get_one::<PathBuf>("age-identity").map(|p| AgeKey::IdentityFile(p.clone()))
+            };
+            
+            let conflict_policy = sub_matches.get_one::<String>("on-conflict")
+                .and_then(|name| ConflictPolicy::from_name(name))
+                .unwrap_or_default();
+            let backup_on_overwrite = sub_matches.get_flag("backup-overwritten")
+                .then_some(compression::move_to_trash as compression::OverwriteBackup);
+            let verify_manifest = sub_matches.get_flag("verify-manifest");
+            let dir_template = sub_matches.get_one::<String>("dir-template").map(|t| t.as_str());
+            let explicit_dir = sub_matches.get_one::<PathBuf>("output-dir").cloned();
+            let password = sub_matches.get_one::<String>("password").cloned().map(PasswordResolver::single);
+            let flatten = sub_matches.get_flag("flatten");
+            let recursive = sub_matches.get_flag("recursive");
+            let max_nested_depth = sub_matches.get_one::<usize>("max-depth").copied();
+            let max_output_bytes = sub_matches.get_one::<u64>("max-output").copied();
+            let skip_space_check = sub_matches.get_flag("no-space-check");
+            let mut routes = Vec::new();
+            for rule in sub_matches.get_many::<String>("route").unwrap_or_default() {
+                match rule.split_once('=') {
+                    Some((pattern, target)) if !pattern.is_empty() && !target.is_empty() => {
+                        routes.push((pattern.to_string(), PathBuf::from(target)));
+                    },
+                    _ => {
+                        eprintln!("Error: invalid route '{}', expected PATTERN=DIR", rule);
+                        std::process::exit(1);
+                    }
+                }
+            }
+            let mut manifest_failures = 0;
+            
+            if sub_matches.get_flag("stdout") {
+                // Several inputs are concatenated, like zcat does
+                let stdout = std::io::stdout();
+                let mut out = stdout.lock();
+                for file in &files {
+                    if let Err(e) = decompress_to_writer(file, &mut out) {
+                        eprintln!("Failed to decompress '{}': {}", file.display(), e);
+                        std::process::exit(1);
+                    }
+                }
+                out.flush()?;
+                return Ok(());
+            }
+            
+            if sub_matches.get_flag("dry-run") {
+                let mut failed = 0;
+                let output_name = sub_matches.get_one::<PathBuf>("output")
+                    .and_then(|p| p.file_name())
+                    .and_then(|n| n.to_str());
+                for file in &files {
+                    let output_dir = match sub_matches.get_one::<PathBuf>("output") {
+                        Some(output) => output.parent().filter(|p| !p.as_os_str().is_empty())
+                            .unwrap_or_else(|| std::path::Path::new("."))
+                            .to_path_buf(),
+                        None => explicit_dir.clone().unwrap_or_else(|| preview_output_dir(file, dir_template)),
+                    };
+                    match preview_extraction(file, &output_dir, output_name, &routes, flatten) {
+                        Ok(preview) => {
+                            println!("{} would extract {} file(s), {} bytes:", file.display(), preview.entries.len(), preview.total_size);
+                            for entry in &preview.entries {
+                                let note = if preview.would_overwrite.contains(entry) { "  (exists)" } else { "" };
+                                println!("  {}{}", entry, note);
+                            }
+                        },
+                        Err(e) => {
+                            eprintln!("Failed to preview '{}': {}", file.display(), e);
+                            failed += 1;
+                        },
+                    }
+                }
+                if failed > 0 {
+                    std::process::exit(1);
+                }
+                return Ok(());
+            }
+            
+            if let Some(dir) = &explicit_dir {
+                if let Err(e) = ensure_writable_dir(dir) {
+                    eprintln!("Error: cannot extract to '{}': {}", dir.display(), e);
+                    std::process::exit(1);
+                }
+            }
+            
+            if let Some(output) = sub_matches.get_one::<PathBuf>("output") {
+                if files.len() != 1 {
+                    eprintln!("Error: --output can only be used with a single input file.");
+                    std::process::exit(1);
+                }
+                
+                // The explicit path is used as-is: its parent is the target directory, its name the file name
+                let output_dir = output.parent()
+                    .filter(|p| !p.as_os_str().is_empty())
+                    .unwrap_or_else(|| std::path::Path::new("."));
+                let output_name = output.file_name().and_then(|n| n.to_str())
+                    .ok_or_else(|| anyhow::anyhow!("Invalid output file name: {}", output.display()))?;
+                
+                let options = DecompressOptions {
+                    output_name: Some(output_name.to_string()),
+                    conflict_policy,
+                    backup_on_overwrite,
+                    password: password.clone(),
+                    recursive,
+                    max_nested_depth,
+                    max_output_bytes,
+                    skip_space_check,
+                    ..Default::default()
+                };
+                let cancel = CancellationToken::default();
+                match decompress_files_with_progress(&files[0], output_dir, &options, &cancel, |_, _| {}).await {
+                    Ok(summary) => println!("File decompressed to: {}{}", output.display(), conflict_note(&summary)),
+                    Err(e) => {
+                        eprintln!("Failed to decompress '{}': {}", files[0].display(), e);
+                        std::process::exit(1);
+                    },
+                }
+                return Ok(());
+            }
+            
+            let options = DecompressOptions {
+                conflict_policy,
+                backup_on_overwrite,
+                password: password.clone(),
+                routes: routes.clone(),
+                flatten,
+                recursive,
+                max_nested_depth,
+                max_output_bytes,
+                skip_space_check,
+                ..Default::default()
+            };
+            
+            let total = files.len();
+            let mut failed = 0;
             for file in files {
+                if is_url(&file) {
+                    #[cfg(feature = "url-support")]
+                    {
+                        match extract_url(&file.to_string_lossy(), explicit_dir.clone(), dir_template, &options).await {
+                            Ok((output_dir, summary)) => {
+                                println!("File decompressed to: {}{}", output_dir.display(), conflict_note(&summary));
+                                if verify_manifest && !check_manifest(&output_dir) {
+                                    manifest_failures += 1;
+                                }
+                            }
+                            Err(e) => {
+                                eprintln!("Failed to decompress '{}': {:#}", file.display(), e);
+                                failed += 1;
+                            },
+                        }
+                    }
+                    
+                    #[cfg(not(feature = "url-support"))]
+                    {
+                        eprintln!("Error: downloading '{}' needs the 'url-support' feature.", file.display());
+                        failed += 1;
+                    }
+                    continue;
+                }
+                
                 if !file.exists() {
                     eprintln!("Error: File does not exist: {}", file.display());
+                    failed += 1;
                     continue;
                 }
                 
                 if !is_compressed_file(&file) {
                     eprintln!("Error: '{}' is not a supported archive format.", file.display());
-                    eprintln!("Supported formats: .zip, .rar, .gz, .bz2, .tar, .7z, .gzip, .br, .tgz, .tar.gz, .tar.br");
+                    eprintln!("Supported formats: {}", compression::readable_extensions().join(", "));
+                    failed += 1;
                     continue;
                 }
                 
-                let output_dir = generate_output_dir(&file);
-                match decompress_files(&file, &output_dir).await {
-                    Ok(_) => println!("File decompressed to: {}", output_dir.display()),
-                    Err(e) => eprintln!("Failed to decompress '{}': {}", file.display(), e),
+                let output_dir = match explicit_dir.clone() {
+                    Some(dir) => dir,
+                    None => match generate_output_dir(&file, dir_template) {
+                        Ok(dir) => dir,
+                        Err(e) => {
+                            eprintln!("Error: cannot create a folder to extract '{}' into: {}", file.display(), e);
+                            failed += 1;
+                            continue;
+                        }
+                    },
+                };
+                
+                let is_age = file.to_string_lossy().ends_with(".tar.gz.age");
+                if let (true, Some(key)) = (is_age, &age_key) {
+                    #[cfg(feature = "age-support")]
+                    {
+                        let cancel = CancellationToken::default();
+                        match compression::decompress_tar_gz_age_with_progress(&file, &output_dir, key, &cancel, |_, _| {}).await {
+                            Ok(_) => println!("File decrypted and decompressed to: {}", output_dir.display()),
+                            Err(e) => {
+                                eprintln!("Failed to decompress '{}': {}", file.display(), e);
+                                failed += 1;
+                            },
+                        }
+                    }
+                    
+                    #[cfg(not(feature = "age-support"))]
+                    {
+                        let _ = key;
+                        eprintln!("Error: '{}' needs the 'age-support' feature to decrypt.", file.display());
+                        failed += 1;
+                    }
+                    continue;
+                }
+                
+                let cancel = CancellationToken::default();
+                match decompress_files_with_progress(&file, &output_dir, &options, &cancel, |_, _| {}).await {
+                    Ok(summary) => {
+                        println!("File decompressed to: {}{}", output_dir.display(), conflict_note(&summary));
+                        if verify_manifest && !check_manifest(&output_dir) {
+                            manifest_failures += 1;
+                        }
+                    }
+                    Err(e) => {
+                        eprintln!("Failed to decompress '{}': {}", file.display(), e);
+                        failed += 1;
+                    },
+                }
+            }
+            
+            if failed > 0 && total > 1 {
+                eprintln!("{} of {} archives failed to extract", failed, total);
+            }
+            if failed > 0 || manifest_failures > 0 {
+                std::process::exit(1);
+            }
+        },
+        Some(("verify", sub_matches)) => {
+            let files: Vec<PathBuf> = sub_matches.get_many::<PathBuf>("files")
+                .unwrap()
+                .cloned()
+                .collect();
+            
+            let quick = sub_matches.get_flag("quick");
+            let checksum = sub_matches.get_flag("checksum");
+            
+            let mut failed = 0;
+            for file in &files {
+                let result = if quick {
+                    compression::is_complete_archive(file).and_then(|complete| match complete {
+                        true => Ok(()),
+                        false => Err(anyhow::anyhow!("the archive is incomplete (truncated)").into()),
+                    })
+                } else if checksum {
+                    compression::verify_checksum(file).and_then(|matches| match matches {
+                        true => Ok(()),
+                        false => Err(anyhow::anyhow!("the SHA-256 does not match {}", compression::checksum_sidecar_path(file).display()).into()),
+                    })
+                } else {
+                    verify_archive(file)
+                };
+                match result {
+                    Ok(_) => println!("OK      {}", file.display()),
+                    Err(e) => {
+                        failed += 1;
+                        println!("FAILED  {}: {:#}", file.display(), e);
+                    }
+                }
+            }
+            
+            if failed > 0 {
+                eprintln!("{} of {} archives failed verification", failed, files.len());
+                std::process::exit(1);
+            }
+        },
+        Some(("list", sub_matches)) => {
+            let files: Vec<PathBuf> = sub_matches.get_many::<PathBuf>("files")
+                .unwrap()
+                .cloned()
+                .collect();
+            let as_json = sub_matches.get_flag("json");
+            
+            let mut failed = 0;
+            let mut listings = Vec::new();
+            for file in &files {
+                match list_archive(file) {
+                    Ok(entries) if as_json => listings.push(serde_json::json!({
+                        "archive": file.display().to_string(),
+                        "total_size": entries.iter().map(|entry| entry.size).sum::<u64>(),
+                        "entries": entries,
+                    })),
+                    Ok(entries) => {
+                        if files.len() > 1 {
+                            println!("Archive: {}", file.display());
+                        }
+                        print_listing(&entries);
+                    },
+                    Err(e) => {
+                        failed += 1;
+                        eprintln!("Failed to list '{}': {:#}", file.display(), e);
+                    }
+                }
+            }
+            
+            if as_json {
+                println!("{}", serde_json::to_string_pretty(&listings)?);
+            }
+            if failed > 0 {
+                std::process::exit(1);
+            }
+        },
+        Some(("levels", sub_matches)) => {
+            let files: Vec<PathBuf> = sub_matches.get_many::<PathBuf>("files")
+                .unwrap()
+                .cloned()
+                .collect();
+            let format = sub_matches.get_one::<String>("format").unwrap();
+            let compression_type = CompressionType::from_extension(format)
+                .ok_or_else(|| anyhow::anyhow!("Unknown archive format '{}'. Supported: {}", format, CompressionType::supported_extensions().join(", ")))?;
+            
+            let results = compression::level_sweep(&files, &compression_type)?;
+            if let Some(first) = results.first() {
+                println!("Sample of {} bytes:", first.sample_size);
+            }
+            println!("{:>5}  {:>12}  {:>7}  {:>9}", "Level", "Size", "Ratio", "Time");
+            for result in &results {
+                println!("{:>5}  {:>12}  {:>6.1}%  {:>8.3}s",
+                    result.level,
+                    result.size,
+                    result.size as f64 / result.sample_size.max(1) as f64 * 100.0,
+                    result.seconds);
+            }
+        },
+        Some(("bench", sub_matches)) => {
+            let file = sub_matches.get_one::<PathBuf>("file").unwrap();
+            if !file.exists() {
+                eprintln!("Error: File does not exist: {}", file.display());
+                std::process::exit(1);
+            }
+            
+            let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or(0);
+            let temp_dir = std::env::temp_dir().join(format!("tauzip-bench-{}-{}", std::process::id(), nanos));
+            std::fs::create_dir_all(&temp_dir)?;
+            let files = vec![file.clone()];
+            let cancel = CancellationToken::default();
+            
+            println!("{:<8}  {:>9}  {:>12}  {:>7}", "Format", "Time", "Size", "Ratio");
+            for compression_type in CompressionType::all() {
+                // .gzip writes the same stream as .gz
+                if matches!(compression_type, CompressionType::Gzip) {
+                    continue;
+                }
+                let format = compression_type.extension().trim_start_matches('.');
+                if file.is_dir() && !compression_type.supports_multiple_files() {
+                    println!("{:<8}  skipped: holds a single file, not a folder", format);
+                    continue;
+                }
+                
+                let output_path = temp_dir.join(format!("bench{}", compression_type.extension()));
+                let mut input_size = 0;
+                let started = Instant::now();
+                let result = compress_files_with_progress(&files, &output_path, compression_type.clone(), &CompressOptions::default(), &cancel, |_, _, counts| {
+                    input_size = counts.bytes_total;
+                }).await;
+                let seconds = started.elapsed().as_secs_f64();
+                
+                match result.and_then(|_| Ok(std::fs::metadata(&output_path)?.len())) {
+                    Ok(size) => println!("{:<8}  {:>8.3}s  {:>12}  {:>6.1}%",
+                        format,
+                        seconds,
+                        size,
+                        size as f64 / input_size.max(1) as f64 * 100.0),
+                    Err(e) => println!("{:<8}  failed: {}", format, e),
+                }
+                // Each archive goes as soon as it is measured, so at most one is on disk at a time
+                let _ = std::fs::remove_file(&output_path);
+            }
+            let _ = std::fs::remove_dir_all(&temp_dir);
+        },
+        Some(("delete", sub_matches)) => {
+            let archive = sub_matches.get_one::<PathBuf>("archive").unwrap();
+            for entry in sub_matches.get_many::<String>("entries").unwrap() {
+                match compression::delete_entry(archive, entry) {
+                    Ok(removed) => println!("Removed {} entr{} for '{}' from {}", removed, if removed == 1 { "y" } else { "ies" }, entry, archive.display()),
+                    Err(e) => {
+                        eprintln!("Failed to delete '{}': {:#}", entry, e);
+                        std::process::exit(1);
+                    }
+                }
+            }
+        },
+        Some(("add", sub_matches)) => {
+            let archive = sub_matches.get_one::<PathBuf>("archive").unwrap();
+            let files: Vec<PathBuf> = sub_matches.get_many::<PathBuf>("files").unwrap().cloned().collect();
+            let conflict_policy = sub_matches.get_one::<String>("on-conflict")
+                .and_then(|name| ConflictPolicy::from_name(name))
+                .unwrap_or_default();
+            match compression::append_to_zip(archive, &files, conflict_policy) {
+                Ok(added) => println!("Added {} entr{} to {}", added, if added == 1 { "y" } else { "ies" }, archive.display()),
+                Err(e) => {
+                    eprintln!("Failed to add to {}: {:#}", archive.display(), e);
+                    std::process::exit(1);
                 }
             }
         },
+        Some(("convert", sub_matches)) => {
+            let file = sub_matches.get_one::<PathBuf>("file").unwrap();
+            let output = sub_matches.get_one::<PathBuf>("output").unwrap();
+            
+            let target_type = CompressionType::from_file_name(output)
+                .ok_or_else(|| anyhow::anyhow!(
+                    "Cannot infer a compression format from '{}'. Supported extensions: {}",
+                    output.display(),
+                    CompressionType::supported_extensions().join(", ")
+                ))?;
+            
+            let preset = sub_matches.get_one::<String>("preset")
+                .and_then(|name| CompressionPreset::from_name(name))
+                .unwrap_or_default();
+
+            let cancel = CancellationToken::default();
+            convert_archive(file, output, target_type, preset, &cancel, |_, _| {}).await?;
+            println!("Archive converted to: {}", output.display());
+        },
+        Some(("salvage", sub_matches)) => {
+            let file = sub_matches.get_one::<PathBuf>("file").unwrap();
+            let output_dir = match sub_matches.get_one::<PathBuf>("output-dir") {
+                Some(dir) => dir.clone(),
+                None => {
+                    let stem = file.file_stem().unwrap_or_default().to_string_lossy();
+                    file.with_file_name(format!("{} (salvaged)", stem))
+                },
+            };
+            
+            let recovered = compression::salvage_zip(file, &output_dir)?;
+            for name in &recovered {
+                println!("Recovered {}", name);
+            }
+            println!("{} entries salvaged to: {}", recovered.len(), output_dir.display());
+        },
+        Some(("backup", sub_matches)) => {
+            let files: Vec<PathBuf> = sub_matches.get_many::<PathBuf>("files")
+                .unwrap()
+                .cloned()
+                .collect();
+            let store_dir = sub_matches.get_one::<PathBuf>("store").unwrap();
+            let name = sub_matches.get_one::<String>("name").unwrap();
+            
+            let store = chunk_store::ChunkStore::open(store_dir)?;
+            let stats = store.backup(&files, name)?;
+            println!(
+                "Snapshot '{}' saved: {} files, {} of {} chunks new ({} of {} bytes stored)",
+                name, stats.files, stats.new_chunks, stats.total_chunks, stats.new_bytes, stats.total_bytes
+            );
+        },
+        Some(("restore", sub_matches)) => {
+            let store_dir = sub_matches.get_one::<PathBuf>("store").unwrap();
+            let store = chunk_store::ChunkStore::open(store_dir)?;
+            
+            match sub_matches.get_one::<String>("name") {
+                Some(name) => {
+                    let output_dir = sub_matches.get_one::<PathBuf>("output-dir").unwrap();
+                    let restored = store.restore(name, output_dir, sub_matches.get_flag("overwrite"))?;
+                    println!("Restored {} files from '{}' to: {}", restored, name, output_dir.display());
+                },
+                None => {
+                    for snapshot in store.snapshots()? {
+                        let size: u64 = snapshot.files.iter().map(|file| file.size).sum();
+                        println!("{:<24} {:>6} files {:>12} bytes", snapshot.name, snapshot.files.len(), size);
+                    }
+                },
+            }
+        },
+        Some(("extract-url", sub_matches)) => {
+            let url = sub_matches.get_one::<String>("url").unwrap();
+            let explicit_dir = sub_matches.get_one::<PathBuf>("output-dir").cloned();
+            
+            #[cfg(feature = "url-support")]
+            match extract_url(url, explicit_dir, None, &DecompressOptions::default()).await {
+                Ok((output_dir, summary)) => println!("Archive extracted to: {}{}", output_dir.display(), conflict_note(&summary)),
+                Err(e) => {
+                    eprintln!("Failed to extract '{}': {:#}", url, e);
+                    std::process::exit(1);
+                },
+            }
+            
+            #[cfg(not(feature = "url-support"))]
+            {
+                let _ = (url, explicit_dir);
+                eprintln!("Error: extract-url needs the 'url-support' feature.");
+                std::process::exit(1);
+            }
+        },
         Some(("decompress-here", sub_matches)) => {
             let directory: PathBuf = sub_matches.get_one::<PathBuf>("directory")
                 .unwrap()
                 .clone();
+            let dir_template = sub_matches.get_one::<String>("dir-template").map(|t| t.as_str());
             
             if !directory.is_dir() {
                 eprintln!("Error: '{}' is not a directory", directory.display());
-                return Ok(());
+                std::process::exit(1);
             }
             
             println!("Looking for archives in: {}", directory.display());
@@ -247,7 +1461,7 @@ async fn main() -> anyhow::Result<()> {
             
             if archive_files.is_empty() {
                 println!("No supported archive files found in the directory.");
-                println!("Supported formats: .zip, .rar, .gz, .bz2, .tar, .7z, .gzip, .br, .tgz, .tar.gz, .tar.br");
+                println!("Supported formats: {}", compression::readable_extensions().join(", "));
                 return Ok(());
             }
             
@@ -257,16 +1471,31 @@ async fn main() -> anyhow::Result<()> {
             }
             
             // Extract each archive
+            let mut failed = 0;
             for file in archive_files {
-                let output_dir = generate_output_dir(&file);
+                let output_dir = match generate_output_dir(&file, dir_template) {
+                    Ok(dir) => dir,
+                    Err(e) => {
+                        eprintln!("✗ Failed to extract '{}': {}",
+                            file.file_name().unwrap_or_default().to_string_lossy(), e);
+                        failed += 1;
+                        continue;
+                    }
+                };
                 match decompress_files(&file, &output_dir).await {
                     Ok(_) => println!("✓ Extracted: {} -> {}", 
                         file.file_name().unwrap_or_default().to_string_lossy(),
                         output_dir.display()),
-                    Err(e) => eprintln!("✗ Failed to extract '{}': {}", 
-                        file.file_name().unwrap_or_default().to_string_lossy(), e),
+                    Err(e) => {
+                        eprintln!("✗ Failed to extract '{}': {}", 
+                            file.file_name().unwrap_or_default().to_string_lossy(), e);
+                        failed += 1;
+                    },
                 }
             }
+            if failed > 0 {
+                std::process::exit(1);
+            }
         },
         Some(("gui-compress", sub_matches)) => {
             let files: Vec<PathBuf> = sub_matches.get_many::<PathBuf>("files")
@@ -368,19 +1597,22 @@ async fn main() -> anyhow::Result<()> {
 			
 			if archive_files.is_empty() {
 				eprintln!("Error: No valid archive files found.");
-				eprintln!("Supported formats: .zip, .rar, .gz, .bz2, .tar, .7z, .gzip, .br, .tgz, .tar.gz, .tar.br");
+				eprintln!("Supported formats: {}", compression::readable_extensions().join(", "));
 				return Ok(());
 			}
 			
 			let x = gui::run_decompression_dialog(files2, archive_files, gui_state.clone()).await?;
 		},
         Some(("gui-compress-selection", _)) => {
-            println!("GUI Compress Selection - attempting to get selected files from Explorer...");
+            println!("GUI Compress Selection - attempting to get selected files from the file manager...");
             
-            // Try to get selected files from Windows Explorer using PowerShell
-            #[cfg(target_os = "windows")]
+            // Ask Explorer (through PowerShell) or Finder (through AppleScript) for its selection
+            #[cfg(any(target_os = "windows", target_os = "macos"))]
             {
-                let selected_files = get_selected_files_windows().await?;
+                #[cfg(target_os = "windows")]
+                let (selected_files, file_manager) = (get_selected_files_windows().await?, "Explorer");
+                #[cfg(target_os = "macos")]
+                let (selected_files, file_manager) = (get_selected_files_macos().await?, "Finder");
                 if !selected_files.is_empty() {
                     println!("Found {} selected files:", selected_files.len());
                     for (i, file) in selected_files.iter().enumerate() {
@@ -390,13 +1622,13 @@ async fn main() -> anyhow::Result<()> {
                     let x = gui::run_compression_dialog(files2, selected_files, gui_state.clone()).await?;
 					 
                 } else {
-                    eprintln!("No files are currently selected in Explorer.");
+                    eprintln!("No files are currently selected in {}.", file_manager);
                 }
             }
             
-            #[cfg(not(target_os = "windows"))]
+            #[cfg(not(any(target_os = "windows", target_os = "macos")))]
             {
-                eprintln!("Selection compression is currently only supported on Windows.");
+                eprintln!("Selection compression is currently only supported on Windows and macOS.");
             }
         },
         Some(("gui-decompress-here", sub_matches)) => {
@@ -425,7 +1657,7 @@ async fn main() -> anyhow::Result<()> {
             
             if archive_files.is_empty() {
                 eprintln!("Error: No supported archive files found in the directory.");
-                eprintln!("Supported formats: .zip, .rar, .gz, .bz2, .tar, .7z, .gzip, .br, .tgz, .tar.gz, .tar.br");
+                eprintln!("Supported formats: {}", compression::readable_extensions().join(", "));
                 return Ok(());
             }
             
@@ -472,14 +1704,27 @@ async fn main() -> anyhow::Result<()> {
             println!("3. Try running: tauzip uninstall && tauzip install");
             println!("4. Check if the executable path is correct");
         },
-        Some(("test-gui", _)) => {
+        Some(("test-gui", sub_matches)) => {
             println!("Testing GUI with sample files...");
-            let sample_files = vec![
-                std::env::current_exe()?,  // Use the executable itself as a test file
-            ];
-			let files2 = vec![std::env::current_exe()?.display().to_string()];
-            let x = gui::run_compression_dialog(files2, sample_files, gui_state.clone()).await?;
-			 
+            // The executable is tens of MB and slow to compress; a few KB of text keeps runs quick
+            let real = sub_matches.get_flag("real");
+            let sample = if real {
+                std::env::current_exe()?
+            } else {
+                let sample = std::env::temp_dir().join(format!("tauzip-test-gui-{}.txt", std::process::id()));
+                let text: String = (1..=100)
+                    .map(|i| format!("Line {} of the TauZip GUI test sample.\n", i))
+                    .collect();
+                std::fs::write(&sample, text)?;
+                sample
+            };
+            let sample_files = vec![sample.clone()];
+			let files2 = vec![sample.display().to_string()];
+            let result = gui::run_compression_dialog(files2, sample_files, gui_state.clone()).await;
+            if !real {
+                let _ = std::fs::remove_file(&sample);
+            }
+            result?;
         },
         Some(("debug", sub_matches)) => {
             println!("=== tauzip Debug Information ===");
@@ -578,93 +1823,173 @@ async fn main() -> anyhow::Result<()> {
     Ok(())
 }
 
-// async fn handle_file_aggregation(file: PathBuf, operation: &str) -> anyhow::Result<Option<Vec<PathBuf>>> {
-    // let current_time = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as u64;
-    // let temp_dir = std::env::temp_dir();
-    
-    // // Clean up old sessions first
-    // cleanup_old_sessions().await?;
+// Explorer starts one process per selected item (%V is a single path), so on Windows
+// the launches are merged through a session file before any window opens
+async fn aggregate_gui_args(args: Vec<String>, operation: &str) -> anyhow::Result<Option<Vec<String>>> {
+    if !cfg!(target_os = "windows") {
+        return Ok(Some(args));
+    }
     
-    // // Look for existing session within the timeout window
-    // let mut existing_session_file = None;
-    // let mut existing_session = None;
+    let files = args.iter().map(PathBuf::from).collect();
+    let collected = handle_file_aggregation(files, operation).await?;
+    Ok(collected.map(|files| files.iter().map(|file| file.display().to_string()).collect()))
+}
+
+// Returns the whole selection to the first launch and None to every launch that joined it
+async fn handle_file_aggregation(files: Vec<PathBuf>, operation: &str) -> anyhow::Result<Option<Vec<PathBuf>>> {
+    let temp_dir = std::env::temp_dir();
     
-    // if let Ok(entries) = std::fs::read_dir(&temp_dir) {
-        // for entry in entries.flatten() {
-            // let path = entry.path();
-            // if let Some(filename) = path.file_name().and_then(|n| n.to_str()) {
-                // if filename.starts_with(SESSION_FILE_PREFIX) && filename.ends_with(&format!("_{}.json", operation)) {
-                    // if let Ok(file_content) = std::fs::read_to_string(&path) {
-                        // if let Ok(session) = serde_json::from_str::<FileCollectionSession>(&file_content) {
-                            // if current_time - session.timestamp <= COLLECTION_TIMEOUT_MS {
-                                // existing_session_file = Some(path);
-                                // existing_session = Some(session);
-                                // break;
-                            // }
-                        // }
-                    // }
-                // }
-            // }
-        // }
-    // }
+    // Clean up old sessions first
+    cleanup_old_sessions().await?;
     
-    // if let Some(mut session) = existing_session {
-        // // Add this file to existing session
-        // session.files.push(file);
-        // session.timestamp = current_time;
-        
-        // // Write back the updated session
-        // if let Some(session_file) = existing_session_file {
-            // let updated_content = serde_json::to_string(&session)?;
-            // std::fs::write(session_file, updated_content)?;
-        // }
-        
-        // // This instance should exit - another instance will handle the GUI
-        // return Ok(None);
-    // } else {
-        // // Create new session
-        // let session = FileCollectionSession {
-            // timestamp: current_time,
-            // files: vec![file],
-            // operation: operation.to_string(),
-        // };
+    {
+        let _lock = SessionLock::acquire(&temp_dir, operation).await?;
+        let current_time = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as u64;
         
-        // let session_filename = format!("{}{}_{}_{}.json", SESSION_FILE_PREFIX, current_time, std::process::id(), operation);
-        // let session_path = temp_dir.join(session_filename);
-        
-        // let session_content = serde_json::to_string(&session)?;
-        // std::fs::write(&session_path, session_content)?;
+        // Join a session another launch is still collecting
+        if let Some((session_file, mut session)) = find_open_session(&temp_dir, operation, current_time) {
+            tracing::debug!(session = %session_file.display(), files = files.len(), "Joining an open selection session");
+            for file in files {
+                if !session.files.contains(&file) {
+                    session.files.push(file);
+                }
+            }
+            session.timestamp = current_time;
+            std::fs::write(session_file, serde_json::to_string(&session)?)?;
+            
+            // This instance should exit - the session owner will open the GUI
+            return Ok(None);
+        }
+    }
+    
+    let created = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as u64;
+    let session_filename = format!("{}{}_{}_{}.json", SESSION_FILE_PREFIX, created, std::process::id(), operation);
+    let session_path = temp_dir.join(session_filename);
+    let mut collected = FileCollectionSession {
+        timestamp: created,
+        files,
+        operation: operation.to_string(),
+    };
+    
+    {
+        let _lock = SessionLock::acquire(&temp_dir, operation).await?;
+        std::fs::write(&session_path, serde_json::to_string(&collected)?)?;
+    }
+    tracing::debug!(session = %session_path.display(), files = collected.files.len(), "Started a selection session");
+    
+    // Keep collecting until no launch has added files for COLLECTION_TIMEOUT_MS
+    loop {
+        tokio::time::sleep(Duration::from_millis(COLLECTION_TIMEOUT_MS)).await;
         
-        // // Wait for the timeout period to collect more files
-        // //tokio::time::sleep(Duration::from_millis(COLLECTION_TIMEOUT_MS)).await;
+        let _lock = SessionLock::acquire(&temp_dir, operation).await?;
+        let current_time = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as u64;
+        let session = std::fs::read_to_string(&session_path)
+            .ok()
+            .and_then(|content| serde_json::from_str::<FileCollectionSession>(&content).ok());
         
-        // // Read the final session
-        // let final_content = std::fs::read_to_string(&session_path)?;
-        // let final_session: FileCollectionSession = serde_json::from_str(&final_content)?;
+        match session {
+            Some(session) if current_time.saturating_sub(session.timestamp) < COLLECTION_TIMEOUT_MS => {
+                collected = session;
+            }
+            Some(session) => {
+                // Removing the file while holding the lock closes the session to late joiners
+                let _ = std::fs::remove_file(&session_path);
+                tracing::debug!(files = session.files.len(), "Selection session closed");
+                return Ok(Some(session.files));
+            }
+            // Cleaned up from under us; open what we have so far
+            None => {
+                tracing::warn!(session = %session_path.display(), "Selection session file disappeared; opening the files collected so far");
+                return Ok(Some(collected.files));
+            },
+        }
+    }
+}
+
+fn find_open_session(temp_dir: &std::path::Path, operation: &str, current_time: u64) -> Option<(PathBuf, FileCollectionSession)> {
+    let suffix = format!("_{}.json", operation);
+    let entries = std::fs::read_dir(temp_dir).ok()?;
+    
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let is_session = path.file_name()
+            .and_then(|n| n.to_str())
+            .is_some_and(|filename| filename.starts_with(SESSION_FILE_PREFIX) && filename.ends_with(&suffix));
+        if !is_session {
+            continue;
+        }
         
-        // // Clean up the session file
-        // let _ = std::fs::remove_file(&session_path);
+        let session = std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|content| serde_json::from_str::<FileCollectionSession>(&content).ok());
+        if let Some(session) = session {
+            if session.operation == operation && current_time.saturating_sub(session.timestamp) <= SESSION_STALE_MS {
+                return Some((path, session));
+            }
+        }
+    }
+    
+    None
+}
+
+// Serializes access to the session files of one operation across processes
+struct SessionLock {
+    path: PathBuf,
+}
+
+impl SessionLock {
+    async fn acquire(temp_dir: &std::path::Path, operation: &str) -> anyhow::Result<Self> {
+        let path = temp_dir.join(format!("{}{}.lock", SESSION_FILE_PREFIX, operation));
+        let started = SystemTime::now();
         
-        // return Ok(Some(final_session.files));
-    // }
-// }
+        loop {
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(_) => return Ok(Self { path }),
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                    // A lock this old belongs to a launch that died while holding it
+                    let abandoned = std::fs::metadata(&path)
+                        .and_then(|metadata| metadata.modified())
+                        .ok()
+                        .and_then(|modified| modified.elapsed().ok())
+                        .is_some_and(|age| age.as_millis() as u64 > SESSION_STALE_MS);
+                    if abandoned {
+                        let _ = std::fs::remove_file(&path);
+                    } else if started.elapsed().is_ok_and(|waited| waited.as_millis() as u64 > SESSION_STALE_MS * 2) {
+                        return Err(anyhow::anyhow!("Timed out waiting for {}", path.display()));
+                    }
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+}
+
+impl Drop for SessionLock {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
 
 async fn cleanup_old_sessions() -> anyhow::Result<()> {
     let current_time = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as u64;
     let temp_dir = std::env::temp_dir();
-    let max_age_ms = COLLECTION_TIMEOUT_MS * 4; // Clean up sessions older than 2 seconds
     
     if let Ok(entries) = std::fs::read_dir(&temp_dir) {
         for entry in entries.flatten() {
             let path = entry.path();
             if let Some(filename) = path.file_name().and_then(|n| n.to_str()) {
-                if filename.starts_with(SESSION_FILE_PREFIX) {
-                    // Try to extract timestamp from filename
-                    if let Some(timestamp_str) = filename.split('_').nth(2) {
-                        if let Ok(timestamp) = timestamp_str.parse::<u64>() {
-                            if current_time - timestamp > max_age_ms {
-                                let _ = std::fs::remove_file(&path);
-                            }
+                if filename.starts_with(SESSION_FILE_PREFIX) && filename.ends_with(".json") {
+                    // Sessions grow while files arrive, so age them by their last update,
+                    // falling back to the creation time in the filename
+                    let timestamp = std::fs::read_to_string(&path)
+                        .ok()
+                        .and_then(|content| serde_json::from_str::<FileCollectionSession>(&content).ok())
+                        .map(|session| session.timestamp)
+                        .or_else(|| filename.split('_').nth(2).and_then(|timestamp_str| timestamp_str.parse::<u64>().ok()));
+                    if let Some(timestamp) = timestamp {
+                        if current_time.saturating_sub(timestamp) > SESSION_STALE_MS {
+                            let _ = std::fs::remove_file(&path);
                         }
                     }
                 }
@@ -710,8 +2035,282 @@ async fn get_selected_files_windows() -> anyhow::Result<Vec<PathBuf>> {
     Ok(files)
 }
 
+// Finder's selection belongs to its frontmost window whether or not Finder itself is in front,
+// so this works from a keyboard shortcut or the Dock too. Finder isn't launched just to ask: if
+// it isn't running nothing can be selected.
+#[cfg(target_os = "macos")]
+async fn get_selected_files_macos() -> anyhow::Result<Vec<PathBuf>> {
+    use std::process::Command;
+    
+    let output = Command::new("osascript")
+        .args(&[
+            "-e",
+            r#"
+            if application "Finder" is not running then return ""
+            tell application "Finder"
+                set selectedItems to selection as alias list
+            end tell
+            set paths to ""
+            repeat with selectedItem in selectedItems
+                set paths to paths & POSIX path of selectedItem & linefeed
+            end repeat
+            return paths
+            "#
+        ])
+        .output()?;
+    
+    // Typically -1743 when TauZip hasn't been allowed to control Finder
+    if !output.status.success() {
+        return Err(anyhow::anyhow!(
+            "Could not read the Finder selection: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    
+    let output_str = String::from_utf8_lossy(&output.stdout);
+    let files: Vec<PathBuf> = output_str
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        // Folders come back with a trailing slash
+        .map(|line| PathBuf::from(match line.trim_end_matches('/') {
+            "" => "/",
+            path => path,
+        }))
+        .filter(|path| path.exists())
+        .collect();
+    
+    Ok(files)
+}
+
+// A byte count with an optional binary suffix: 1048576, 1024K, 1M, 1.5G, 700MB, 2GiB
+fn parse_size(text: &str) -> Result<u64, String> {
+    let text = text.trim();
+    let digits_end = text.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(text.len());
+    let (number, unit) = text.split_at(digits_end);
+    let multiplier: u64 = match unit.trim().to_ascii_uppercase().trim_end_matches("IB").trim_end_matches('B') {
+        "" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        "T" => 1 << 40,
+        _ => return Err(format!("unknown size unit '{}'; use K, M, G or T", unit.trim())),
+    };
+    let number: f64 = number.parse().map_err(|_| format!("'{}' is not a size", text))?;
+    let bytes = (number * multiplier as f64) as u64;
+    if bytes == 0 {
+        return Err("the size must be at least 1 byte".to_string());
+    }
+    Ok(bytes)
+}
+
+// An http(s) argument where a file is expected
+fn is_url(arg: &std::path::Path) -> bool {
+    arg.to_str().is_some_and(|arg| {
+        let arg = arg.to_ascii_lowercase();
+        arg.starts_with("http://") || arg.starts_with("https://")
+    })
+}
+
+// Download an archive and extract it into `explicit_dir`, or a folder named after the URL's
+// file name in the current directory. Tar and single-file formats are extracted as they
+// download; zip, 7z and rar (and --recursive runs) go through a temporary copy, which is
+// removed afterwards.
+#[cfg(feature = "url-support")]
+async fn extract_url(
+    url: &str,
+    explicit_dir: Option<PathBuf>,
+    dir_template: Option<&str>,
+    options: &DecompressOptions,
+) -> anyhow::Result<(PathBuf, ExtractSummary)> {
+    if let Some(dir) = &explicit_dir {
+        ensure_writable_dir(dir).map_err(|e| anyhow::anyhow!("cannot extract to '{}': {}", dir.display(), e))?;
+    }
+    let name = download::archive_url_name(url)?;
+    // Only created once the server has answered, so a failed request leaves no empty folder
+    let create_output_dir = || match &explicit_dir {
+        Some(dir) => Ok(dir.clone()),
+        None => generate_output_dir(std::path::Path::new(&name), dir_template),
+    };
+    let cancel = CancellationToken::default();
+    
+    if compression::is_streamable(std::path::Path::new(&name)) && !options.recursive {
+        let stream = download::open_archive_stream(url).await?;
+        let output_dir = create_output_dir()?;
+        let options = options.clone();
+        let stream_dir = output_dir.clone();
+        // The body is read with blocking calls, which can't happen on the async runtime
+        let summary = tokio::task::spawn_blocking(move || {
+            let mut shown_percent = None;
+            let summary = compression::decompress_stream(stream.reader, &stream.name, &stream_dir, &options, stream.total.unwrap_or(0), &cancel, |progress, _| {
+                let percent = progress as u64;
+                if shown_percent != Some(percent) {
+                    eprint!("\rDownloading and extracting: {}%", percent);
+                    shown_percent = Some(percent);
+                }
+            });
+            if shown_percent.is_some() {
+                eprintln!();
+            }
+            summary
+        }).await??;
+        return Ok((output_dir, summary));
+    }
+    
+    let mut shown_percent = None;
+    let download = download::download_archive(url, |received, total| {
+        if let Some(total) = total.filter(|&total| total > 0) {
+            let percent = received * 100 / total;
+            if shown_percent != Some(percent) {
+                eprint!("\rDownloading: {}%", percent);
+                shown_percent = Some(percent);
+            }
+        }
+    }).await;
+    if shown_percent.is_some() {
+        eprintln!();
+    }
+    // The temporary copy is removed when `download` goes out of scope
+    let download = download?;
+    let output_dir = create_output_dir()?;
+    let summary = decompress_files_with_progress(download.path(), &output_dir, options, &cancel, |_, _| {}).await?;
+    Ok((output_dir, summary))
+}
+
+fn conflict_note(summary: &ExtractSummary) -> String {
+    let mut notes = Vec::new();
+    if summary.skipped > 0 || summary.renamed > 0 {
+        notes.push(format!("{} skipped, {} renamed", summary.skipped, summary.renamed));
+    }
+    if summary.trashed > 0 {
+        notes.push(format!("{} overwritten moved to trash", summary.trashed));
+    }
+    if summary.nested > 0 {
+        notes.push(format!("{} nested archives extracted", summary.nested));
+    }
+    if notes.is_empty() {
+        String::new()
+    } else {
+        format!(" ({})", notes.join("; "))
+    }
+}
+
+// Prints the manifest comparison for an extracted tree; false when anything differs
+fn check_manifest(output_dir: &std::path::Path) -> bool {
+    let manifest = match manifest::read_manifest(output_dir) {
+        Ok(Some(manifest)) => manifest,
+        Ok(None) => {
+            println!("No MANIFEST.json in {}, nothing to verify", output_dir.display());
+            return true;
+        }
+        Err(e) => {
+            eprintln!("{:#}", e);
+            return false;
+        }
+    };
+    
+    match manifest::verify_extraction(output_dir, &manifest) {
+        Ok(report) => {
+            for path in &report.missing {
+                println!("MISSING   {}", path);
+            }
+            for path in &report.mismatched {
+                println!("MISMATCH  {}", path);
+            }
+            for path in &report.extra {
+                println!("EXTRA     {}", path);
+            }
+            if report.is_clean() {
+                println!("All {} files match MANIFEST.json", manifest.files.len());
+            }
+            report.is_clean()
+        }
+        Err(e) => {
+            eprintln!("Failed to verify '{}': {}", output_dir.display(), e);
+            false
+        }
+    }
+}
+
+// unzip -v style table: a size column right-aligned to the widest value, how each entry was
+// compressed, then the name. The ratio is the compressed size as a share of the original.
+fn print_listing(entries: &[ArchiveEntry]) {
+    let total_size: u64 = entries.iter().map(|entry| entry.size).sum();
+    let width = total_size.to_string().len().max("Length".len());
+    let method_width = entries.iter().map(|entry| entry.method.len()).max().unwrap_or(0).max("Method".len());
+    
+    println!("{:>width$}  {:<method_width$}  Ratio  {:<16}  Name", "Length", "Method", "Date (UTC)", width = width, method_width = method_width);
+    println!("{}  {}  -----  {}  ----", "-".repeat(width), "-".repeat(method_width), "-".repeat(16));
+    for entry in entries {
+        let date = entry.modified.map(format_unix_time).unwrap_or_default();
+        let ratio = entry.ratio.map(|ratio| format!("{:.0}%", ratio * 100.0)).unwrap_or_default();
+        println!("{:>width$}  {:<method_width$}  {:>5}  {:<16}  {}", entry.size, entry.method, ratio, date, entry.name, width = width, method_width = method_width);
+    }
+    println!("{}  {}  -----  {}  ----", "-".repeat(width), " ".repeat(method_width), " ".repeat(16));
+    let noun = if entries.len() == 1 { "file" } else { "files" };
+    println!("{:>width$}  {:<method_width$}  {:>5}  {:<16}  {} {}", total_size, "", "", "", entries.len(), noun, width = width, method_width = method_width);
+}
+
+// "2024-03-09 14:05", in UTC like the DOS times extraction restores
+fn format_unix_time(seconds: i64) -> String {
+    match time::OffsetDateTime::from_unix_timestamp(seconds) {
+        Ok(t) => format!("{:04}-{:02}-{:02} {:02}:{:02}", t.year(), u8::from(t.month()), t.day(), t.hour(), t.minute()),
+        Err(_) => String::new(),
+    }
+}
+
+// Hand our runtime to Tauri so its commands and our spawned tasks share one executor instead of
+// Tauri starting a second runtime inside this one. Must run inside the runtime, before any
+// Tauri app is built.
+fn share_runtime_with_tauri() {
+    tauri::async_runtime::set(tokio::runtime::Handle::current());
+}
+
+// Diagnostics go to standard error, so they never mix with archive data or listings on stdout.
+// Other crates only log warnings unless RUST_LOG (e.g. RUST_LOG=trace) says otherwise.
+fn init_tracing(verbosity: u8) {
+    let level = match verbosity {
+        0 => "info",
+        1 => "debug",
+        _ => "trace",
+    };
+    let filter = tracing_subscriber::EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new(format!("warn,{}={}", env!("CARGO_CRATE_NAME"), level)));
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr)
+        .with_target(false)
+        .init();
+}
+
+// The format compress uses when neither --format nor the output name gives one:
+// TAUZIP_DEFAULT_FORMAT if set, else default_format from config.json, else zip. A value that
+// names no format is reported and zip is used instead.
+fn default_compression_type() -> CompressionType {
+    std::env::var(DEFAULT_FORMAT_ENV).ok()
+        .and_then(|value| parse_default_format(&value, DEFAULT_FORMAT_ENV))
+        .or_else(|| config::load_config().default_format
+            .and_then(|value| parse_default_format(&value, "default_format in config.json")))
+        .unwrap_or(CompressionType::Zip)
+}
+
+// The format a default-format setting names, read from `source`; None for a blank value, which
+// leaves the choice to the next source
+fn parse_default_format(value: &str, source: &str) -> Option<CompressionType> {
+    let value = value.trim();
+    if value.is_empty() {
+        return None;
+    }
+    Some(CompressionType::from_extension(value).unwrap_or_else(|| {
+        eprintln!("Warning: {} is '{}', which is not a known format; using zip", source, value);
+        CompressionType::Zip
+    }))
+}
+
 fn generate_output_path(files: &[PathBuf], compression_type: CompressionType) -> PathBuf {
-    let base_name = if files.len() == 1 {
+    // Single-file formats keep the whole name (notes.txt.gz) so extraction can restore it
+    let base_name = if files.len() == 1 && !compression_type.supports_multiple_files() {
+        files[0].file_name().unwrap_or_default().to_string_lossy()
+    } else if files.len() == 1 {
         files[0].file_stem().unwrap_or_default().to_string_lossy()
     } else {
         "archive".into()
@@ -721,17 +2320,28 @@ fn generate_output_path(files: &[PathBuf], compression_type: CompressionType) ->
     parent.join(format!("{}{}", base_name, compression_type.extension()))
 }
 
-fn generate_output_dir(file: &PathBuf) -> PathBuf {
-    let base_name = file.file_stem().unwrap_or_default().to_string_lossy();
-    let parent = file.parent().unwrap_or_else(|| std::path::Path::new("."));
-    
-    let mut counter = 1;
-    let mut output_dir = parent.join(base_name.as_ref());
-    
-    while output_dir.exists() {
-        counter += 1;
-        output_dir = parent.join(format!("{} ({})", base_name, counter));
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_format_values_name_a_format_or_fall_back_to_zip() {
+        let format_of = |value: &str| parse_default_format(value, DEFAULT_FORMAT_ENV).map(|format| format.extension());
+        assert_eq!(format_of(" tar.gz "), Some(".tar.gz"));
+        assert_eq!(format_of("rar5"), Some(".zip"));
+        assert_eq!(format_of("  "), None);
+    }
+
+    // Tauri's own runtime would run this task on one of its worker threads; on the shared
+    // current-thread test runtime it can only run here
+    #[tokio::test]
+    async fn tauri_tasks_run_on_the_runtime_main_hands_over() {
+        share_runtime_with_tauri();
+        let test_thread = std::thread::current().id();
+        let task_thread = tauri::async_runtime::spawn(async {
+            tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+            std::thread::current().id()
+        }).await.unwrap();
+        assert_eq!(task_thread, test_thread);
     }
-    
-    output_dir
 }
\ No newline at end of file
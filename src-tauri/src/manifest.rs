@@ -0,0 +1,149 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+pub const MANIFEST_FILE_NAME: &str = "MANIFEST.json";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ManifestEntry {
+    pub path: String, // relative to the archive root, '/' separated
+    pub size: u64,
+    pub sha256: String, // lowercase hex
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Manifest {
+    pub files: Vec<ManifestEntry>,
+}
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct ExtractionReport {
+    pub missing: Vec<String>,
+    pub extra: Vec<String>,
+    pub mismatched: Vec<String>,
+}
+
+impl ExtractionReport {
+    pub fn is_clean(&self) -> bool {
+        self.missing.is_empty() && self.extra.is_empty() && self.mismatched.is_empty()
+    }
+}
+
+// None when the extracted tree has no MANIFEST.json at its root
+pub fn read_manifest(output_dir: &Path) -> Result<Option<Manifest>> {
+    let manifest_path = output_dir.join(MANIFEST_FILE_NAME);
+    if !manifest_path.is_file() {
+        return Ok(None);
+    }
+
+    let content = std::fs::read_to_string(&manifest_path)
+        .with_context(|| format!("Failed to read {}", manifest_path.display()))?;
+    let manifest = serde_json::from_str(&content)
+        .with_context(|| format!("Invalid manifest: {}", manifest_path.display()))?;
+    Ok(Some(manifest))
+}
+
+pub fn sha256_file(path: &Path) -> Result<String> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut hasher = Sha256::new();
+    let mut buffer = [0u8; 64 * 1024];
+
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+
+    Ok(hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+// Compares every file under output_dir with the manifest. The size is checked first so
+// truncated files are reported without hashing them.
+pub fn verify_extraction(output_dir: &Path, manifest: &Manifest) -> Result<ExtractionReport> {
+    let mut report = ExtractionReport::default();
+    let mut expected = HashSet::new();
+
+    for entry in &manifest.files {
+        expected.insert(entry.path.clone());
+        let path = output_dir.join(&entry.path);
+
+        let metadata = match std::fs::metadata(&path) {
+            Ok(metadata) if metadata.is_file() => metadata,
+            _ => {
+                report.missing.push(entry.path.clone());
+                continue;
+            }
+        };
+
+        if metadata.len() != entry.size || !sha256_file(&path)?.eq_ignore_ascii_case(&entry.sha256) {
+            report.mismatched.push(entry.path.clone());
+        }
+    }
+
+    let mut on_disk = Vec::new();
+    collect_relative_files(output_dir, output_dir, &mut on_disk)?;
+    report.extra = on_disk.into_iter()
+        .filter(|path| path != MANIFEST_FILE_NAME && !expected.contains(path))
+        .collect();
+    report.extra.sort();
+
+    Ok(report)
+}
+
+fn collect_relative_files(root: &Path, dir: &Path, files: &mut Vec<String>) -> Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_relative_files(root, &path, files)?;
+        } else if let Ok(relative) = path.strip_prefix(root) {
+            let parts: Vec<String> = relative.components()
+                .map(|component| component.as_os_str().to_string_lossy().into_owned())
+                .collect();
+            files.push(parts.join("/"));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compression::{compress_files_with_progress, decompress_files_with_progress, CancellationToken, CompressOptions, CompressionType, DecompressOptions};
+    use crate::test_utils::{write_file, TempDir};
+
+    #[tokio::test]
+    async fn verify_extraction_finds_tampered_files() {
+        let dir = TempDir::new();
+        let input = dir.join("docs");
+        write_file(&input.join("a.txt"), "alpha");
+        write_file(&input.join("sub").join("b.txt"), "bravo");
+        write_file(&input.join("c.txt"), "charlie");
+        let options = CompressOptions { manifest: true, ..Default::default() };
+        let cancel = CancellationToken::default();
+
+        for compression_type in [CompressionType::Zip, CompressionType::TarGz] {
+            let archive = dir.join(format!("docs{}", compression_type.extension()));
+            compress_files_with_progress(&[input.clone()], &archive, compression_type.clone(), &options, &cancel, |_, _, _| {}).await.unwrap();
+            let output_dir = dir.join(format!("out{}", compression_type.extension()));
+            decompress_files_with_progress(&archive, &output_dir, &DecompressOptions::default(), &cancel, |_, _| {}).await.unwrap();
+
+            let manifest = read_manifest(&output_dir).unwrap().expect("the archive has a manifest");
+            assert_eq!(manifest.files.len(), 3);
+            assert!(verify_extraction(&output_dir, &manifest).unwrap().is_clean(), "{:?}", compression_type);
+
+            write_file(&output_dir.join("docs").join("a.txt"), "alphA");
+            std::fs::remove_file(output_dir.join("docs").join("sub").join("b.txt")).unwrap();
+            write_file(&output_dir.join("docs").join("d.txt"), "delta");
+            let report = verify_extraction(&output_dir, &manifest).unwrap();
+            assert_eq!(report.mismatched, vec!["docs/a.txt"]);
+            assert_eq!(report.missing, vec!["docs/sub/b.txt"]);
+            assert_eq!(report.extra, vec!["docs/d.txt"]);
+        }
+    }
+}
\ No newline at end of file